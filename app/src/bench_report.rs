@@ -0,0 +1,379 @@
+use std::{collections::HashMap, fmt::Display, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchStatus {
+    Solved,
+    Unsolved,
+    ParseError,
+    Panicked,
+    Timeout,
+}
+
+impl BenchStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchStatus::Solved => "solved",
+            BenchStatus::Unsolved => "unsolved",
+            BenchStatus::ParseError => "parse_error",
+            BenchStatus::Panicked => "panicked",
+            BenchStatus::Timeout => "timeout",
+        }
+    }
+    pub fn from_name(name: &str) -> Result<BenchStatus, String> {
+        match name {
+            "solved" => Ok(BenchStatus::Solved),
+            "unsolved" => Ok(BenchStatus::Unsolved),
+            "parse_error" => Ok(BenchStatus::ParseError),
+            "panicked" => Ok(BenchStatus::Panicked),
+            "timeout" => Ok(BenchStatus::Timeout),
+            _ => Err(format!("Unknown bench status: {}", name)),
+        }
+    }
+}
+
+impl Display for BenchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// the result of routing one board
+#[derive(Debug, Clone)]
+pub struct BenchRecord {
+    pub board: String, // file name of the dsn file
+    pub algorithm: String,
+    pub status: BenchStatus,
+    pub total_connections: usize,
+    pub routed_connections: usize,
+    pub runtime_secs: f64,
+    pub samples: usize, // number of A* runs
+    pub wirelength: f64, // in dsn units
+    pub vias: usize,
+    pub drc_violations: usize,
+    pub length_violations: usize, // nets of length matching groups outside their tolerance
+}
+
+pub const CSV_HEADER: &str = "board,algorithm,status,total_connections,routed_connections,unrouted_connections,completion_rate,runtime_secs,samples,wirelength,vias,drc_violations,length_violations";
+
+impl BenchRecord {
+    pub fn unrouted_connections(&self) -> usize {
+        self.total_connections.saturating_sub(self.routed_connections)
+    }
+    pub fn completion_rate(&self) -> f64 {
+        if self.total_connections == 0 {
+            return 0.0;
+        }
+        self.routed_connections as f64 / self.total_connections as f64
+    }
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{:.4},{:.3},{},{:.3},{},{},{}",
+            escape_csv_field(&self.board),
+            escape_csv_field(&self.algorithm),
+            self.status,
+            self.total_connections,
+            self.routed_connections,
+            self.unrouted_connections(),
+            self.completion_rate(),
+            self.runtime_secs,
+            self.samples,
+            self.wirelength,
+            self.vias,
+            self.drc_violations,
+            self.length_violations
+        )
+    }
+    pub fn from_csv_row(row: &str) -> Result<BenchRecord, String> {
+        let fields = split_csv_row(row)?;
+        if fields.len() != 13 {
+            return Err(format!(
+                "Expected 13 fields in bench report row, found {}: {}",
+                fields.len(),
+                row
+            ));
+        }
+        fn parse_field<T: std::str::FromStr>(field: &str, name: &str) -> Result<T, String> {
+            field
+                .parse::<T>()
+                .map_err(|_| format!("Invalid value for {}: {}", name, field))
+        }
+        // unrouted_connections and completion_rate are derived, so they are not read back
+        Ok(BenchRecord {
+            board: fields[0].clone(),
+            algorithm: fields[1].clone(),
+            status: BenchStatus::from_name(&fields[2])?,
+            total_connections: parse_field(&fields[3], "total_connections")?,
+            routed_connections: parse_field(&fields[4], "routed_connections")?,
+            runtime_secs: parse_field(&fields[7], "runtime_secs")?,
+            samples: parse_field(&fields[8], "samples")?,
+            wirelength: parse_field(&fields[9], "wirelength")?,
+            vias: parse_field(&fields[10], "vias")?,
+            drc_violations: parse_field(&fields[11], "drc_violations")?,
+            length_violations: parse_field(&fields[12], "length_violations")?,
+        })
+    }
+    pub fn to_json_object(&self) -> String {
+        format!(
+            "{{\"board\": {}, \"algorithm\": {}, \"status\": \"{}\", \"total_connections\": {}, \"routed_connections\": {}, \"unrouted_connections\": {}, \"completion_rate\": {:.4}, \"runtime_secs\": {:.3}, \"samples\": {}, \"wirelength\": {:.3}, \"vias\": {}, \"drc_violations\": {}, \"length_violations\": {}}}",
+            escape_json_string(&self.board),
+            escape_json_string(&self.algorithm),
+            self.status,
+            self.total_connections,
+            self.routed_connections,
+            self.unrouted_connections(),
+            self.completion_rate(),
+            self.runtime_secs,
+            self.samples,
+            self.wirelength,
+            self.vias,
+            self.drc_violations,
+            self.length_violations
+        )
+    }
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn split_csv_row(row: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Unterminated quote in bench report row: {}", row));
+    }
+    fields.push(current);
+    Ok(fields)
+}
+
+fn escape_json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// overall numbers of a report, summed over all boards
+#[derive(Debug, Clone)]
+pub struct BenchSummary {
+    pub num_boards: usize,
+    pub num_solved_boards: usize,
+    pub total_connections: usize,
+    pub routed_connections: usize,
+    pub runtime_secs: f64,
+    pub samples: usize,
+    pub wirelength: f64,
+    pub vias: usize,
+    pub drc_violations: usize,
+    pub length_violations: usize,
+}
+
+impl BenchSummary {
+    pub fn from_records(records: &[BenchRecord]) -> BenchSummary {
+        BenchSummary {
+            num_boards: records.len(),
+            num_solved_boards: records
+                .iter()
+                .filter(|record| record.status == BenchStatus::Solved)
+                .count(),
+            total_connections: records.iter().map(|record| record.total_connections).sum(),
+            routed_connections: records.iter().map(|record| record.routed_connections).sum(),
+            runtime_secs: records.iter().map(|record| record.runtime_secs).sum(),
+            samples: records.iter().map(|record| record.samples).sum(),
+            wirelength: records.iter().map(|record| record.wirelength).sum(),
+            vias: records.iter().map(|record| record.vias).sum(),
+            drc_violations: records.iter().map(|record| record.drc_violations).sum(),
+            length_violations: records.iter().map(|record| record.length_violations).sum(),
+        }
+    }
+    pub fn completion_rate(&self) -> f64 {
+        if self.total_connections == 0 {
+            return 0.0;
+        }
+        self.routed_connections as f64 / self.total_connections as f64
+    }
+}
+
+impl Display for BenchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Boards solved: {}/{}, connections routed: {}/{} ({:.1}%), runtime: {:.1}s, samples: {}, wirelength: {:.1}, vias: {}, drc violations: {}, length violations: {}",
+            self.num_solved_boards,
+            self.num_boards,
+            self.routed_connections,
+            self.total_connections,
+            self.completion_rate() * 100.0,
+            self.runtime_secs,
+            self.samples,
+            self.wirelength,
+            self.vias,
+            self.drc_violations,
+            self.length_violations
+        )
+    }
+}
+
+pub fn write_csv_report(records: &[BenchRecord], path: &str) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    writeln!(file, "{}", CSV_HEADER)?;
+    for record in records {
+        writeln!(file, "{}", record.to_csv_row())?;
+    }
+    Ok(())
+}
+
+pub fn write_json_report(records: &[BenchRecord], path: &str) -> std::io::Result<()> {
+    let summary = BenchSummary::from_records(records);
+    let mut file = std::fs::File::create(path)?;
+    writeln!(file, "{{")?;
+    writeln!(
+        file,
+        "  \"summary\": {{\"num_boards\": {}, \"num_solved_boards\": {}, \"total_connections\": {}, \"routed_connections\": {}, \"completion_rate\": {:.4}, \"runtime_secs\": {:.3}, \"samples\": {}, \"wirelength\": {:.3}, \"vias\": {}, \"drc_violations\": {}, \"length_violations\": {}}},",
+        summary.num_boards,
+        summary.num_solved_boards,
+        summary.total_connections,
+        summary.routed_connections,
+        summary.completion_rate(),
+        summary.runtime_secs,
+        summary.samples,
+        summary.wirelength,
+        summary.vias,
+        summary.drc_violations,
+        summary.length_violations
+    )?;
+    writeln!(file, "  \"boards\": [")?;
+    for (i, record) in records.iter().enumerate() {
+        let separator = if i + 1 < records.len() { "," } else { "" };
+        writeln!(file, "    {}{}", record.to_json_object(), separator)?;
+    }
+    writeln!(file, "  ]")?;
+    writeln!(file, "}}")?;
+    Ok(())
+}
+
+pub fn read_csv_report(path: &str) -> Result<Vec<BenchRecord>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read bench report {}: {}", path, e))?;
+    let mut lines = content.lines();
+    match lines.next() {
+        Some(header) if header.trim() == CSV_HEADER => {}
+        _ => return Err(format!("{} is not a bench csv report", path)),
+    }
+    lines
+        .filter(|line| !line.trim().is_empty())
+        .map(BenchRecord::from_csv_row)
+        .collect()
+}
+
+/// how much worse a board may get before the diff reports it as a regression
+#[derive(Debug, Clone)]
+pub struct RegressionTolerance {
+    pub wirelength: f64, // relative, 0.05 means 5% longer
+    pub runtime: f64,    // relative, 1.0 means twice as slow
+}
+
+impl Default for RegressionTolerance {
+    fn default() -> Self {
+        RegressionTolerance {
+            wirelength: 0.05,
+            runtime: 1.0,
+        }
+    }
+}
+
+/// compares two reports board by board, returns one message per regression
+pub fn diff_reports(
+    old_records: &[BenchRecord],
+    new_records: &[BenchRecord],
+    tolerance: &RegressionTolerance,
+) -> Vec<String> {
+    let mut regressions: Vec<String> = Vec::new();
+    let new_by_board: HashMap<&str, &BenchRecord> = new_records
+        .iter()
+        .map(|record| (record.board.as_str(), record))
+        .collect();
+    for old in old_records {
+        let new = match new_by_board.get(old.board.as_str()) {
+            Some(new) => *new,
+            None => {
+                regressions.push(format!("{}: missing from the new report", old.board));
+                continue;
+            }
+        };
+        if old.status == BenchStatus::Solved && new.status != BenchStatus::Solved {
+            regressions.push(format!(
+                "{}: was solved, now {}",
+                old.board, new.status
+            ));
+        }
+        if new.routed_connections < old.routed_connections {
+            regressions.push(format!(
+                "{}: routed connections dropped from {} to {}",
+                old.board, old.routed_connections, new.routed_connections
+            ));
+        }
+        // wirelength and vias are only comparable when both runs routed the same connections
+        if old.status == BenchStatus::Solved && new.status == BenchStatus::Solved {
+            if new.wirelength > old.wirelength * (1.0 + tolerance.wirelength) {
+                regressions.push(format!(
+                    "{}: wirelength grew from {:.1} to {:.1}",
+                    old.board, old.wirelength, new.wirelength
+                ));
+            }
+            if new.drc_violations > old.drc_violations {
+                regressions.push(format!(
+                    "{}: drc violations grew from {} to {}",
+                    old.board, old.drc_violations, new.drc_violations
+                ));
+            }
+            if new.length_violations > old.length_violations {
+                regressions.push(format!(
+                    "{}: length violations grew from {} to {}",
+                    old.board, old.length_violations, new.length_violations
+                ));
+            }
+            if new.vias > old.vias {
+                regressions.push(format!(
+                    "{}: vias grew from {} to {}",
+                    old.board, old.vias, new.vias
+                ));
+            }
+        }
+        // small boards finish in milliseconds, ignore noise below one second
+        if new.runtime_secs > old.runtime_secs * (1.0 + tolerance.runtime)
+            && new.runtime_secs - old.runtime_secs > 1.0
+        {
+            regressions.push(format!(
+                "{}: runtime grew from {:.1}s to {:.1}s",
+                old.board, old.runtime_secs, new.runtime_secs
+            ));
+        }
+    }
+    regressions
+}
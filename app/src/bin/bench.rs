@@ -0,0 +1,531 @@
+//! Routes every dsn file in a directory and writes a report, or diffs two reports.
+//!
+//! usage:
+//!   bench [--dir app/examples] [--algorithm naive|bayesian|pathfinder] [--jobs N] [--timeout-secs S] [--time-budget-secs S] [--fanout] [--shove] [--diff-pair POSITIVE NEGATIVE]... [--inference heuristic|mean_field|bp] [--ordering STRATEGY] [--trace-cache-dir DIR] [--out bench_report]
+//!   bench diff <old.csv> <new.csv> [--wirelength-tolerance 0.05] [--runtime-tolerance 1.0]
+//!
+//! with --jobs, every board is routed in a child process of this binary, so boards run in parallel
+//! and a board that exceeds --timeout-secs can be killed.
+//! --time-budget-secs instead asks the router itself to stop and report the best partial result.
+//! --fanout fans the pads of grid array parts out to vias before routing.
+//! --shove lets a new trace push the fixed traces of other nets aside when they are in its way.
+//! --diff-pair routes the two nets as a differential pair in addition to the pairs in the dsn files, boards without the nets ignore it.
+//! --inference selects how the bayesian backtracker computes the posteriors of its candidate traces, heuristic by default.
+//! --ordering selects the order the naive backtracker routes the connections in: heap, shortest, longest,
+//! most_constrained, bbox_overlap, netclass[:class,...] or user:net,...
+//! --trace-cache-dir keeps the candidate traces of each board in DIR/<board>.tracecache, later runs reuse the legal ones.
+
+use std::{
+    io::Read,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::{exit, Child, Command, Stdio},
+    sync::{atomic::Ordering, Arc, Mutex, Once},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use app::bench_report::{
+    diff_reports, read_csv_report, write_csv_report, write_json_report, BenchRecord, BenchStatus,
+    BenchSummary, RegressionTolerance,
+};
+use parser::{
+    parse_end_to_end::{parse_start_to_dsn_struct, parse_struct_to_end_with_extra_info},
+    parse_to_display_format::ExtraInfo,
+};
+use router::{
+    command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL},
+    connection_ordering::OrderingStrategy,
+    drc::{check_pcb_solution, DrcOptions},
+    inference::InferenceEngine,
+    length_matching::check_lengths,
+    pcb_problem_solve::solve_pcb_problem,
+    router_config::{RouterConfig, RoutingAlgorithm},
+    shove::ShoveConfig,
+};
+use shared::{
+    hyperparameters::SAMPLE_CNT,
+    pcb_problem::{DiffPair, NetName},
+    pcb_render_model::PcbRenderModel,
+};
+
+// prefixes of the lines a child process prints for the parent
+const CONNECTIONS_LINE_PREFIX: &str = "BENCH_CONNECTIONS ";
+const RECORD_LINE_PREFIX: &str = "BENCH_RECORD ";
+
+struct BenchArgs {
+    dir: String,
+    config: RouterConfig,
+    diff_pairs: Vec<DiffPair>,
+    jobs: Option<usize>,
+    timeout_secs: Option<f64>,
+    trace_cache_dir: Option<PathBuf>,
+    out: String,
+}
+
+fn usage() -> ! {
+    println!(
+        "usage:\n  bench [--dir app/examples] [--algorithm naive|bayesian|pathfinder] [--jobs N] [--timeout-secs S] [--time-budget-secs S] [--fanout] [--shove] [--diff-pair POSITIVE NEGATIVE]... [--inference heuristic|mean_field|bp] [--ordering STRATEGY] [--trace-cache-dir DIR] [--out bench_report]\n  bench diff <old.csv> <new.csv> [--wirelength-tolerance 0.05] [--runtime-tolerance 1.0]"
+    );
+    exit(-1);
+}
+
+fn next_value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    let value = match args.next() {
+        Some(value) => value,
+        None => {
+            println!("Missing value for {}", flag);
+            usage();
+        }
+    };
+    match value.parse::<T>() {
+        Ok(value) => value,
+        Err(_) => {
+            println!("Invalid value for {}: {}", flag, value);
+            usage();
+        }
+    }
+}
+
+fn parse_inference_engine(name: &str) -> InferenceEngine {
+    match InferenceEngine::from_name(name) {
+        Ok(engine) => engine,
+        Err(e) => {
+            println!("{}", e);
+            usage();
+        }
+    }
+}
+
+fn parse_ordering(name: &str) -> OrderingStrategy {
+    match OrderingStrategy::from_name(name) {
+        Ok(ordering) => ordering,
+        Err(e) => {
+            println!("{}", e);
+            usage();
+        }
+    }
+}
+
+fn parse_algorithm(name: &str) -> RoutingAlgorithm {
+    match RoutingAlgorithm::from_name(name) {
+        Ok(algorithm) => algorithm,
+        Err(e) => {
+            println!("{}", e);
+            usage();
+        }
+    }
+}
+
+/// the two nets after --diff-pair, with the gap and skew of the nets
+fn next_diff_pair(args: &mut impl Iterator<Item = String>) -> DiffPair {
+    DiffPair {
+        positive_net: NetName(next_value(args, "--diff-pair")),
+        negative_net: NetName(next_value(args, "--diff-pair")),
+        gap: None,
+        max_skew: None,
+    }
+}
+
+fn parse_time_budget(secs: f64) -> Duration {
+    match Duration::try_from_secs_f64(secs) {
+        Ok(budget) => budget,
+        Err(e) => {
+            println!("Invalid value for --time-budget-secs: {}", e);
+            usage();
+        }
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let first = args.next();
+    match first.as_deref() {
+        Some("diff") => diff_main(args),
+        Some("--single") => {
+            // internal mode used by the child processes
+            let board: String = next_value(&mut args, "--single");
+            let mut config = RouterConfig::default();
+            let mut diff_pairs: Vec<DiffPair> = Vec::new();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--algorithm" => config.algorithm = parse_algorithm(&next_value::<String>(&mut args, "--algorithm")),
+                    "--time-budget-secs" => config.time_budget = Some(parse_time_budget(next_value(&mut args, "--time-budget-secs"))),
+                    "--fanout" => config.fanout = true,
+                    "--shove" => config.shove = ShoveConfig::enabled(),
+                    "--diff-pair" => diff_pairs.push(next_diff_pair(&mut args)),
+                    "--inference" => config.inference.engine = parse_inference_engine(&next_value::<String>(&mut args, "--inference")),
+                    "--ordering" => config.ordering = parse_ordering(&next_value::<String>(&mut args, "--ordering")),
+                    "--trace-cache" => config.trace_cache_path = Some(PathBuf::from(next_value::<String>(&mut args, "--trace-cache"))),
+                    _ => usage(),
+                }
+            }
+            let record = bench_board(Path::new(&board), &config, &diff_pairs);
+            println!("{}{}", RECORD_LINE_PREFIX, record.to_csv_row());
+        }
+        _ => {
+            let mut bench_args = BenchArgs {
+                dir: "app/examples".to_string(),
+                config: RouterConfig::default(),
+                diff_pairs: Vec::new(),
+                jobs: None,
+                timeout_secs: None,
+                trace_cache_dir: None,
+                out: "bench_report".to_string(),
+            };
+            let mut args = first.into_iter().chain(args);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--dir" => bench_args.dir = next_value(&mut args, "--dir"),
+                    "--algorithm" => bench_args.config.algorithm = parse_algorithm(&next_value::<String>(&mut args, "--algorithm")),
+                    "--jobs" => bench_args.jobs = Some(next_value(&mut args, "--jobs")),
+                    "--timeout-secs" => bench_args.timeout_secs = Some(next_value(&mut args, "--timeout-secs")),
+                    "--time-budget-secs" => bench_args.config.time_budget = Some(parse_time_budget(next_value(&mut args, "--time-budget-secs"))),
+                    "--fanout" => bench_args.config.fanout = true,
+                    "--shove" => bench_args.config.shove = ShoveConfig::enabled(),
+                    "--diff-pair" => bench_args.diff_pairs.push(next_diff_pair(&mut args)),
+                    "--inference" => bench_args.config.inference.engine = parse_inference_engine(&next_value::<String>(&mut args, "--inference")),
+                    "--ordering" => bench_args.config.ordering = parse_ordering(&next_value::<String>(&mut args, "--ordering")),
+                    "--trace-cache-dir" => bench_args.trace_cache_dir = Some(PathBuf::from(next_value::<String>(&mut args, "--trace-cache-dir"))),
+                    "--out" => bench_args.out = next_value(&mut args, "--out"),
+                    _ => usage(),
+                }
+            }
+            bench_main(bench_args);
+        }
+    }
+}
+
+fn bench_main(bench_args: BenchArgs) {
+    let mut boards: Vec<PathBuf> = match std::fs::read_dir(&bench_args.dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "dsn"))
+            .collect(),
+        Err(e) => {
+            println!("Failed to read directory {}: {}", bench_args.dir, e);
+            exit(-1);
+        }
+    };
+    boards.sort();
+    println!("Benchmarking {} boards in {}", boards.len(), bench_args.dir);
+    if let Some(trace_cache_dir) = bench_args.trace_cache_dir.as_ref()
+        && let Err(e) = std::fs::create_dir_all(trace_cache_dir)
+    {
+        println!("Failed to create directory {}: {}", trace_cache_dir.display(), e);
+        exit(-1);
+    }
+
+    let records: Vec<BenchRecord> = match bench_args.jobs {
+        Some(jobs) => bench_in_child_processes(&boards, &bench_args, jobs.max(1)),
+        None => {
+            if bench_args.timeout_secs.is_some() {
+                println!("Warning: --timeout-secs only takes effect together with --jobs");
+            }
+            boards
+                .iter()
+                .map(|board| bench_board(board, &board_config(board, &bench_args), &bench_args.diff_pairs))
+                .collect()
+        }
+    };
+
+    for record in records.iter() {
+        println!(
+            "{}: {}, routed {}/{}, {:.1}s",
+            record.board, record.status, record.routed_connections, record.total_connections, record.runtime_secs
+        );
+    }
+    println!("{}", BenchSummary::from_records(&records));
+
+    let csv_path = format!("{}.csv", bench_args.out);
+    let json_path = format!("{}.json", bench_args.out);
+    if let Err(e) = write_csv_report(&records, &csv_path) {
+        println!("Failed to write {}: {}", csv_path, e);
+        exit(-1);
+    }
+    if let Err(e) = write_json_report(&records, &json_path) {
+        println!("Failed to write {}: {}", json_path, e);
+        exit(-1);
+    }
+    println!("Report written to {} and {}", csv_path, json_path);
+}
+
+/// the config of the bench with the trace cache file of the board
+fn board_config(board: &Path, bench_args: &BenchArgs) -> RouterConfig {
+    let mut config = bench_args.config.clone();
+    if let Some(trace_cache_dir) = bench_args.trace_cache_dir.as_ref() {
+        config.trace_cache_path = Some(trace_cache_dir.join(format!("{}.tracecache", board_name(board))));
+    }
+    config
+}
+
+fn board_name(board: &Path) -> String {
+    board
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| board.to_string_lossy().to_string())
+}
+
+// started once per process, see release_display_waits
+static RELEASE_DISPLAY_WAITS: Once = Once::new();
+
+/// even at the auto level a failed A* shows its frontier and waits for the user to press enter,
+/// so keep pressing it for them like the command thread of the app would
+fn release_display_waits() {
+    RELEASE_DISPLAY_WAITS.call_once(|| {
+        std::thread::spawn(|| loop {
+            for cv in COMMAND_CVS.iter() {
+                cv.notify_all();
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        });
+    });
+}
+
+/// routes one board in this process
+fn bench_board(board: &Path, config: &RouterConfig, diff_pairs: &[DiffPair]) -> BenchRecord {
+    // never block on the display
+    COMMAND_LEVEL.store(CommandFlag::Auto.get_level(), Ordering::SeqCst);
+    release_display_waits();
+    SAMPLE_CNT.store(0, Ordering::SeqCst);
+    let mut record = BenchRecord {
+        board: board_name(board),
+        algorithm: config.algorithm.name().to_string(),
+        status: BenchStatus::ParseError,
+        total_connections: 0,
+        routed_connections: 0,
+        runtime_secs: 0.0,
+        samples: 0,
+        wirelength: 0.0,
+        vias: 0,
+        drc_violations: 0,
+        length_violations: 0,
+    };
+    println!("Routing {}", record.board);
+    let extra_info = ExtraInfo {
+        diff_pairs: diff_pairs.to_vec(),
+        ..ExtraInfo::default()
+    };
+    let pcb_problem = match std::fs::read_to_string(board)
+        .map_err(|e| e.to_string())
+        .and_then(parse_start_to_dsn_struct)
+        .and_then(|dsn_struct| parse_struct_to_end_with_extra_info(&dsn_struct, &extra_info))
+    {
+        Ok(problem) => problem,
+        Err(e) => {
+            println!("Failed to parse {}: {}", record.board, e);
+            return record;
+        }
+    };
+    record.total_connections = pcb_problem
+        .nets
+        .values()
+        .map(|net_info| net_info.connections.len())
+        .sum();
+    println!("{}{}", CONNECTIONS_LINE_PREFIX, record.total_connections);
+
+    let pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>> = Arc::new(Mutex::new(None));
+    let start_time = Instant::now();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        solve_pcb_problem(&pcb_problem, pcb_render_model, config)
+    }));
+    record.runtime_secs = start_time.elapsed().as_secs_f64();
+    record.samples = SAMPLE_CNT.load(Ordering::SeqCst);
+    let solution = match result {
+        Ok(Ok(solution)) => {
+            record.status = BenchStatus::Solved;
+            solution
+        }
+        Ok(Err(partial_solution)) => {
+            record.status = BenchStatus::Unsolved;
+            *partial_solution.solution
+        }
+        Err(_) => {
+            record.status = BenchStatus::Panicked;
+            return record;
+        }
+    };
+    record.routed_connections = solution.determined_traces.len();
+    record.wirelength = solution
+        .determined_traces
+        .values()
+        .map(|trace| trace.trace_path.total_length)
+        .sum::<f64>()
+        * solution.scale_down_factor as f64;
+    record.vias = solution
+        .determined_traces
+        .values()
+        .map(|trace| trace.trace_path.vias.len())
+        .sum();
+    record.drc_violations = check_pcb_solution(&pcb_problem, &solution, &DrcOptions::default()).len();
+    record.length_violations = check_lengths(&pcb_problem, &solution).violations();
+    record
+}
+
+struct RunningBoard {
+    board: PathBuf,
+    child: Child,
+    start_time: Instant,
+    stdout_reader: JoinHandle<String>,
+}
+
+/// routes each board in a child process, at most `jobs` at a time
+fn bench_in_child_processes(boards: &[PathBuf], bench_args: &BenchArgs, jobs: usize) -> Vec<BenchRecord> {
+    let config = &bench_args.config;
+    let timeout_secs = bench_args.timeout_secs;
+    let current_exe = std::env::current_exe().expect("Failed to get the path of the bench binary");
+    let mut pending = boards.iter();
+    let mut running: Vec<RunningBoard> = Vec::new();
+    let mut records: Vec<BenchRecord> = Vec::new();
+    loop {
+        while running.len() < jobs {
+            let Some(board) = pending.next() else {
+                break;
+            };
+            let mut command = Command::new(&current_exe);
+            command
+                .arg("--single")
+                .arg(board)
+                .arg("--algorithm")
+                .arg(config.algorithm.name());
+            if let Some(time_budget) = config.time_budget {
+                command.arg("--time-budget-secs").arg(time_budget.as_secs_f64().to_string());
+            }
+            if config.fanout {
+                command.arg("--fanout");
+            }
+            if config.shove.is_enabled() {
+                command.arg("--shove");
+            }
+            for diff_pair in bench_args.diff_pairs.iter() {
+                command.arg("--diff-pair").arg(&diff_pair.positive_net.0).arg(&diff_pair.negative_net.0);
+            }
+            command.arg("--inference").arg(config.inference.engine.name());
+            command.arg("--ordering").arg(config.ordering.to_string());
+            if let Some(trace_cache_path) = board_config(board, bench_args).trace_cache_path {
+                command.arg("--trace-cache").arg(trace_cache_path);
+            }
+            let mut child = command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|e| panic!("Failed to spawn bench process for {}: {}", board.display(), e));
+            // drain the pipe in a thread, the router prints a lot and would otherwise fill it
+            let mut stdout = child.stdout.take().unwrap();
+            let stdout_reader = std::thread::spawn(move || {
+                let mut output = String::new();
+                let _ = stdout.read_to_string(&mut output);
+                output
+            });
+            println!("Started {}", board.display());
+            running.push(RunningBoard {
+                board: board.clone(),
+                child,
+                start_time: Instant::now(),
+                stdout_reader,
+            });
+        }
+        if running.is_empty() {
+            break;
+        }
+        let mut i = 0;
+        while i < running.len() {
+            let elapsed = running[i].start_time.elapsed().as_secs_f64();
+            let finished = match running[i].child.try_wait() {
+                Ok(Some(_)) => true,
+                Ok(None) => false,
+                Err(_) => true,
+            };
+            let timed_out = !finished && timeout_secs.is_some_and(|timeout| elapsed > timeout);
+            if !finished && !timed_out {
+                i += 1;
+                continue;
+            }
+            let mut running_board = running.swap_remove(i);
+            if timed_out {
+                let _ = running_board.child.kill();
+                let _ = running_board.child.wait();
+            }
+            let output = running_board.stdout_reader.join().unwrap_or_default();
+            let record = record_from_child_output(&running_board.board, config, &output, timed_out, elapsed);
+            println!("Finished {}: {}", record.board, record.status);
+            records.push(record);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    records.sort_by(|a, b| a.board.cmp(&b.board));
+    records
+}
+
+fn record_from_child_output(
+    board: &Path,
+    config: &RouterConfig,
+    output: &str,
+    timed_out: bool,
+    elapsed: f64,
+) -> BenchRecord {
+    if !timed_out
+        && let Some(row) = output.lines().rev().find_map(|line| line.strip_prefix(RECORD_LINE_PREFIX))
+    {
+        match BenchRecord::from_csv_row(row) {
+            Ok(record) => return record,
+            Err(e) => println!("Failed to read the result of {}: {}", board.display(), e),
+        }
+    }
+    // the child was killed or crashed before printing its record
+    let total_connections = output
+        .lines()
+        .find_map(|line| line.strip_prefix(CONNECTIONS_LINE_PREFIX))
+        .and_then(|count| count.trim().parse::<usize>().ok());
+    BenchRecord {
+        board: board_name(board),
+        algorithm: config.algorithm.name().to_string(),
+        status: match (timed_out, total_connections) {
+            (true, _) => BenchStatus::Timeout,
+            (false, Some(_)) => BenchStatus::Panicked,
+            (false, None) => BenchStatus::ParseError,
+        },
+        total_connections: total_connections.unwrap_or(0),
+        routed_connections: 0,
+        runtime_secs: elapsed,
+        samples: 0,
+        wirelength: 0.0,
+        vias: 0,
+        drc_violations: 0,
+        length_violations: 0,
+    }
+}
+
+fn diff_main(mut args: impl Iterator<Item = String>) {
+    let old_path: String = next_value(&mut args, "diff");
+    let new_path: String = next_value(&mut args, "diff");
+    let mut tolerance = RegressionTolerance::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--wirelength-tolerance" => tolerance.wirelength = next_value(&mut args, "--wirelength-tolerance"),
+            "--runtime-tolerance" => tolerance.runtime = next_value(&mut args, "--runtime-tolerance"),
+            _ => usage(),
+        }
+    }
+    let read_report = |path: &str| match read_csv_report(path) {
+        Ok(records) => records,
+        Err(e) => {
+            println!("{}", e);
+            exit(-1);
+        }
+    };
+    let old_records = read_report(&old_path);
+    let new_records = read_report(&new_path);
+    println!("Old: {}", BenchSummary::from_records(&old_records));
+    println!("New: {}", BenchSummary::from_records(&new_records));
+    let regressions = diff_reports(&old_records, &new_records, &tolerance);
+    if regressions.is_empty() {
+        println!("No regressions");
+        return;
+    }
+    println!("{} regressions:", regressions.len());
+    for regression in regressions.iter() {
+        println!("  {}", regression);
+    }
+    exit(1);
+}
@@ -0,0 +1,211 @@
+//! Rips up some nets or connections of a routed board and routes them again, every other trace stays where it is.
+//!
+//! usage:
+//!   reroute <board.dsn> <routes.ses> [--net NAME]... [--connection NET START_PAD END_PAD]... [--connections FILE]
+//!           [--algorithm naive|bayesian|pathfinder] [--time-budget-secs S] [--fanout] [--out OUTPUT]
+//!
+//! the merged routes are written to OUTPUT.ses, by default the session file name with _rerouted appended,
+//! when connections are left unrouted they are listed in OUTPUT_unrouted.txt.
+//! --connections reads one "net" "start_pad" "end_pad" per line, the format of the _unrouted.txt files.
+//! connections the session file does not route are routed as well.
+
+use std::{
+    process::exit,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+
+use parser::{
+    parse_end_to_end::{parse_start_to_dsn_struct, parse_struct_to_end},
+    read_ses::read_ses,
+    write_ses::{write_partial_ses, write_ses},
+};
+use router::{
+    command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL},
+    drc::{check_pcb_solution, DrcOptions},
+    pcb_problem_solve::{reroute_pcb_solution, RipUp},
+    router_config::{RouterConfig, RoutingAlgorithm},
+};
+use shared::{
+    pcb_problem::{ConnectionID, NetName, PcbProblem},
+    pcb_render_model::PcbRenderModel,
+};
+
+fn usage() -> ! {
+    println!(
+        "usage:\n  reroute <board.dsn> <routes.ses> [--net NAME]... [--connection NET START_PAD END_PAD]... [--connections FILE]\n          [--algorithm naive|bayesian|pathfinder] [--time-budget-secs S] [--fanout] [--out OUTPUT]"
+    );
+    exit(-1);
+}
+
+fn next_value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    let value = match args.next() {
+        Some(value) => value,
+        None => {
+            println!("Missing value for {}", flag);
+            usage();
+        }
+    };
+    match value.parse::<T>() {
+        Ok(value) => value,
+        Err(_) => {
+            println!("Invalid value for {}: {}", flag, value);
+            usage();
+        }
+    }
+}
+
+/// the fields of a line of a connections file, quoted like "net" "start_pad" "end_pad" or separated by whitespace
+fn connection_fields(line: &str) -> Vec<String> {
+    if line.contains('"') {
+        line.split('"').skip(1).step_by(2).map(|field| field.to_string()).collect()
+    } else {
+        line.split_whitespace().map(|field| field.to_string()).collect()
+    }
+}
+
+/// the connection of the net between the two pads, in either direction
+fn find_connection(problem: &PcbProblem, net_name: &str, pad_a: &str, pad_b: &str) -> Result<ConnectionID, String> {
+    let net_info = problem
+        .nets
+        .get(&NetName(net_name.to_string()))
+        .ok_or(format!("Net {} is not on the board", net_name))?;
+    net_info
+        .connections
+        .values()
+        .find(|connection| {
+            (connection.start_pad.0 == pad_a && connection.end_pad.0 == pad_b)
+                || (connection.start_pad.0 == pad_b && connection.end_pad.0 == pad_a)
+        })
+        .map(|connection| connection.connection_id)
+        .ok_or(format!("Net {} has no connection between pads {} and {}", net_name, pad_a, pad_b))
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let board: String = next_value(&mut args, "<board.dsn>");
+    let session: String = next_value(&mut args, "<routes.ses>");
+    let mut config = RouterConfig::default();
+    let mut nets: Vec<String> = Vec::new();
+    let mut connections: Vec<Vec<String>> = Vec::new();
+    let mut out: Option<String> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--net" => nets.push(next_value(&mut args, "--net")),
+            "--connection" => connections.push(vec![
+                next_value(&mut args, "--connection"),
+                next_value(&mut args, "--connection"),
+                next_value(&mut args, "--connection"),
+            ]),
+            "--connections" => {
+                let path: String = next_value(&mut args, "--connections");
+                let content = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                    println!("Failed to read {}: {}", path, e);
+                    exit(-1);
+                });
+                connections.extend(
+                    content
+                        .lines()
+                        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+                        .map(connection_fields),
+                );
+            }
+            "--algorithm" => {
+                config.algorithm = RoutingAlgorithm::from_name(&next_value::<String>(&mut args, "--algorithm"))
+                    .unwrap_or_else(|e| {
+                        println!("{}", e);
+                        usage();
+                    })
+            }
+            "--time-budget-secs" => {
+                config.time_budget = Some(
+                    Duration::try_from_secs_f64(next_value(&mut args, "--time-budget-secs")).unwrap_or_else(|e| {
+                        println!("Invalid value for --time-budget-secs: {}", e);
+                        usage();
+                    }),
+                )
+            }
+            "--fanout" => config.fanout = true,
+            "--out" => out = Some(next_value(&mut args, "--out")),
+            _ => usage(),
+        }
+    }
+    let out = out.unwrap_or_else(|| format!("{}_rerouted", session.strip_suffix(".ses").unwrap_or(&session)));
+
+    let dsn_struct = std::fs::read_to_string(&board)
+        .map_err(|e| e.to_string())
+        .and_then(parse_start_to_dsn_struct)
+        .unwrap_or_else(|e| {
+            println!("Failed to parse DSN file {}: {}", board, e);
+            exit(-1);
+        });
+    let pcb_problem = parse_struct_to_end(&dsn_struct).unwrap_or_else(|e| {
+        println!("Failed to parse DSN file {}: {}", board, e);
+        exit(-1);
+    });
+    let solution = std::fs::read_to_string(&session)
+        .map_err(|e| e.to_string())
+        .and_then(|content| read_ses(&dsn_struct, &pcb_problem, &content))
+        .unwrap_or_else(|e| {
+            println!("Failed to read SES file {}: {}", session, e);
+            exit(-1);
+        });
+    println!("Read {} traces from {}", solution.determined_traces.len(), session);
+
+    let mut rip_up = RipUp::default();
+    rip_up.nets.extend(nets.into_iter().map(NetName));
+    for fields in connections.iter() {
+        let [net_name, pad_a, pad_b] = fields.as_slice() else {
+            println!("Expected a net and two pads for a connection, found: {:?}", fields);
+            usage();
+        };
+        match find_connection(&pcb_problem, net_name, pad_a, pad_b) {
+            Ok(connection_id) => {
+                rip_up.connections.insert(connection_id);
+            }
+            Err(e) => {
+                println!("{}", e);
+                exit(-1);
+            }
+        }
+    }
+
+    // never block on the display
+    COMMAND_LEVEL.store(CommandFlag::Auto.get_level(), Ordering::SeqCst);
+    std::thread::spawn(|| loop {
+        for cv in COMMAND_CVS.iter() {
+            cv.notify_all();
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    });
+    let pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>> = Arc::new(Mutex::new(None));
+    let result = reroute_pcb_solution(&pcb_problem, &solution, &rip_up, pcb_render_model, &config);
+    let solution = match result {
+        Ok(solution) => {
+            if let Err(e) = write_ses(&dsn_struct, &solution, &out) {
+                println!("Failed to write SES file: {}", e);
+                exit(-1);
+            }
+            println!("Rerouted board written to {}.ses", out);
+            solution
+        }
+        Err(partial_solution) => {
+            if let Err(e) = write_partial_ses(&dsn_struct, &partial_solution, &out) {
+                println!("Failed to write SES file: {}", e);
+                exit(-1);
+            }
+            println!(
+                "{} connections left unrouted, the routed part is written to {}.ses and the rest listed in {}_unrouted.txt",
+                partial_solution.unrouted_connections.len(),
+                out,
+                out
+            );
+            *partial_solution.solution
+        }
+    };
+    let violations = check_pcb_solution(&pcb_problem, &solution, &DrcOptions::default());
+    println!("DRC found {} violations", violations.len());
+    for violation in violations.iter() {
+        println!("  {}", violation);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod app;
+pub mod bench_report;
 pub mod camera_uniform;
 pub mod command_thread;
 pub mod context;
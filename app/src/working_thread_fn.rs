@@ -5,9 +5,11 @@ use std::{
 
 use cgmath::Deg;
 
-use parser::{parse_end_to_end::{parse_end_to_end, parse_start_to_dsn_struct, parse_struct_to_end}, write_ses::write_ses};
+use parser::{parse_end_to_end::{parse_end_to_end, parse_start_to_dsn_struct, parse_struct_to_end}, write_ses::{write_partial_ses, write_ses}};
 use router::{
-    naive_backtrack_algo::naive_backtrack, pcb_problem_solve::solve_pcb_problem
+    connectivity::check_connectivity,
+    drc::{check_pcb_solution, drc_to_pcb_render_model, DrcOptions},
+    naive_backtrack_algo::naive_backtrack, pcb_problem_solve::solve_pcb_problem, router_config::{RouterConfig, RoutingAlgorithm}
 };
 use shared::pcb_render_model::PcbRenderModel;
 
@@ -31,22 +33,44 @@ pub fn working_thread_fn(pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>) {
         }
     };
     // pcb_problem.num_layers = 1; // Set to 1 for single layer PCB
-    let result = solve_pcb_problem(&pcb_problem, pcb_render_model.clone(), false);
+    let result = solve_pcb_problem(&pcb_problem, pcb_render_model.clone(), &RouterConfig::new(RoutingAlgorithm::Naive));
     let result = match result {
         Ok(result) => {
             println!("PCB problem solved successfully");
+            match write_ses(&dsn_struct, &result, "a"){
+                Ok(_) => println!("SES file written successfully"),
+                Err(e) => {
+                    println!("Failed to write SES file: {}", e);
+                    exit(-1);
+                }
+            }
             result
         }
-        Err(e) => {
-            println!("Failed to solve PCB problem: {}", e);
-            exit(0);
+        Err(partial_solution) => {
+            println!("Failed to solve PCB problem: {}", partial_solution.reason);
+            println!("{} connections left unrouted", partial_solution.unrouted_connections.len());
+            match write_partial_ses(&dsn_struct, &partial_solution, "a"){
+                Ok(_) => println!("Partial SES file written successfully"),
+                Err(e) => {
+                    println!("Failed to write SES file: {}", e);
+                    exit(-1);
+                }
+            }
+            *partial_solution.solution
         }
     };
-    match write_ses(&dsn_struct, &result, "a"){
-        Ok(_) => println!("SES file written successfully"),
-        Err(e) => {
-            println!("Failed to write SES file: {}", e);
-            exit(-1);
-        }
+    let violations = check_pcb_solution(&pcb_problem, &result, &DrcOptions::default());
+    println!("DRC found {} violations", violations.len());
+    for violation in violations.iter() {
+        println!("  {}", violation);
     }
+    let connectivity_report = check_connectivity(&pcb_problem, &result);
+    print!("{}", connectivity_report);
+    // show the routed board with the violations, stubs and ratsnest marked
+    let mut render_model = drc_to_pcb_render_model(&pcb_problem, &result, &violations);
+    let marker_diameter = f32::max(pcb_problem.width, pcb_problem.height) / 50.0;
+    render_model
+        .other_shape_renderables
+        .extend(connectivity_report.to_renderables(marker_diameter));
+    *pcb_render_model.lock().unwrap() = Some(render_model);
 }
@@ -0,0 +1,62 @@
+//! boards of app/examples and a solver that never waits for the user, shared by the integration tests
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex, Once},
+    time::Duration,
+};
+
+use parser::{
+    dsn_struct::DsnStruct,
+    parse_end_to_end::{parse_start_to_dsn_struct, parse_struct_to_end},
+};
+use router::{
+    command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL},
+    pcb_problem_solve::solve_pcb_problem,
+    router_config::RouterConfig,
+};
+use shared::{
+    pcb_problem::{PcbProblem, PcbSolution},
+    pcb_render_model::PcbRenderModel,
+};
+
+// a small two layer board that the naive backtracker routes in well under a second
+pub const SMALL_BOARD: &str = "ex1_tutorial_file.dsn";
+
+pub fn example_path(file_name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join(file_name)
+}
+
+pub fn load_board(file_name: &str) -> (DsnStruct, PcbProblem) {
+    let content = std::fs::read_to_string(example_path(file_name)).expect("example board is readable");
+    let dsn_struct = parse_start_to_dsn_struct(content).expect("example board parses");
+    let problem = parse_struct_to_end(&dsn_struct).expect("example board converts to a problem");
+    (dsn_struct, problem)
+}
+
+// started once per test binary, see route
+static RELEASE_DISPLAY_WAITS: Once = Once::new();
+
+/// routes the problem with the default config
+pub fn route(problem: &PcbProblem) -> PcbSolution {
+    route_with(problem, &RouterConfig::default())
+}
+
+/// routes the problem, a failed A* waits for enter even at the auto level,
+/// so keep pressing it like the bench does
+pub fn route_with(problem: &PcbProblem, config: &RouterConfig) -> PcbSolution {
+    COMMAND_LEVEL.store(CommandFlag::Auto.get_level(), Ordering::SeqCst);
+    RELEASE_DISPLAY_WAITS.call_once(|| {
+        std::thread::spawn(|| loop {
+            for cv in COMMAND_CVS.iter() {
+                cv.notify_all();
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        });
+    });
+    let pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>> = Arc::new(Mutex::new(None));
+    match solve_pcb_problem(problem, pcb_render_model, config) {
+        Ok(solution) => solution,
+        Err(partial_solution) => panic!("The board is not routed: {}", partial_solution.reason),
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use router::connectivity::check_connectivity;
+
+use common::{load_board, route, SMALL_BOARD};
+
+#[test]
+fn routed_board_is_connected() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let report = check_connectivity(&problem, &solution);
+    assert!(report.is_connected(), "{}", report);
+    assert!(report.dangling_stubs.is_empty(), "{}", report);
+}
+
+#[test]
+fn missing_trace_leaves_its_net_open() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let mut solution = route(&problem);
+    let removed_connection_id = *solution.determined_traces.keys().min().unwrap();
+    let removed = solution.determined_traces.remove(&removed_connection_id).unwrap();
+
+    let report = check_connectivity(&problem, &solution);
+    assert!(!report.is_connected());
+    assert_eq!(report.open_nets.len(), 1, "{}", report);
+    let open_net = &report.open_nets[0];
+    assert_eq!(open_net.net_name, removed.net_name);
+    assert_eq!(open_net.num_islands, 2);
+    assert_eq!(open_net.ratsnest.len(), 1);
+}
+
+#[test]
+fn touching_copper_merges_the_nets() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let mut solution = route(&problem);
+    let mut fixed_traces: Vec<_> = solution.determined_traces.values().cloned().collect();
+    fixed_traces.sort_by_key(|fixed_trace| fixed_trace.connection_id);
+    let victim = fixed_traces[0].clone();
+    let aggressor = fixed_traces
+        .iter()
+        .find(|fixed_trace| fixed_trace.net_name != victim.net_name)
+        .expect("the board has more than one routed net");
+    solution.determined_traces.get_mut(&aggressor.connection_id).unwrap().trace_path = victim.trace_path.clone();
+
+    let report = check_connectivity(&problem, &solution);
+    assert!(!report.is_connected());
+    assert!(report.net_merges.iter().any(|merge| {
+        merge.net_names.contains(&victim.net_name) && merge.net_names.contains(&aggressor.net_name)
+    }));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use parser::{parse_end_to_end::parse_struct_to_end_with_extra_info, parse_to_display_format::ExtraInfo};
+use router::connectivity::check_connectivity;
+use shared::pcb_problem::{DiffPair, NetName};
+
+use common::{load_board, route, SMALL_BOARD};
+
+#[test]
+fn pair_that_cannot_be_coupled_is_reported_with_the_solution() {
+    let (dsn_struct, _) = load_board(SMALL_BOARD);
+    // both nets have more than two pads, a coupled pair runs between two pads only
+    let extra_info = ExtraInfo {
+        diff_pairs: vec![DiffPair {
+            positive_net: NetName("GNDREF".to_string()),
+            negative_net: NetName("Net-(J3-Pin_2)".to_string()),
+            gap: None,
+            max_skew: None,
+        }],
+        ..ExtraInfo::default()
+    };
+    let problem = parse_struct_to_end_with_extra_info(&dsn_struct, &extra_info).unwrap();
+    assert!(problem.nets[&NetName("GNDREF".to_string())].diff_pair.is_some());
+
+    let solution = route(&problem);
+    assert_eq!(solution.uncoupled_diff_pairs.len(), 1);
+    let uncoupled_pair = &solution.uncoupled_diff_pairs[0];
+    assert_eq!(uncoupled_pair.positive_net, NetName("GNDREF".to_string()));
+    assert_eq!(uncoupled_pair.negative_net, NetName("Net-(J3-Pin_2)".to_string()));
+    // the nets are routed separately instead
+    let report = check_connectivity(&problem, &solution);
+    assert!(report.is_connected(), "{}", report);
+}
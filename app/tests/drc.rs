@@ -0,0 +1,58 @@
+mod common;
+
+use router::drc::{check_pcb_solution, DrcObject, DrcOptions, DrcViolationKind};
+use shared::pcb_problem::NetName;
+
+use common::{load_board, route, SMALL_BOARD};
+
+#[test]
+fn routed_board_passes_drc() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let violations = check_pcb_solution(&problem, &solution, &DrcOptions::default());
+    assert!(
+        violations.is_empty(),
+        "Unexpected violations:\n{}",
+        violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("\n")
+    );
+}
+
+#[test]
+fn trace_on_top_of_another_net_is_a_short() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let mut solution = route(&problem);
+    let mut fixed_traces: Vec<_> = solution.determined_traces.values().cloned().collect();
+    fixed_traces.sort_by_key(|fixed_trace| fixed_trace.connection_id);
+    let victim = fixed_traces[0].clone();
+    let aggressor = fixed_traces
+        .iter()
+        .find(|fixed_trace| fixed_trace.net_name != victim.net_name)
+        .expect("the board has more than one routed net");
+    // the aggressor runs exactly where the victim is
+    solution.determined_traces.get_mut(&aggressor.connection_id).unwrap().trace_path = victim.trace_path.clone();
+
+    let violations = check_pcb_solution(&problem, &solution, &DrcOptions::default());
+    let is_trace_of = |object: &DrcObject, connection_id| {
+        matches!(object, DrcObject::Trace { connection_id: id, .. } if *id == connection_id)
+    };
+    assert!(violations.iter().any(|violation| {
+        violation.kind == DrcViolationKind::Short
+            && violation.objects.iter().any(|object| is_trace_of(object, victim.connection_id))
+            && violation.objects.iter().any(|object| is_trace_of(object, aggressor.connection_id))
+    }));
+}
+
+#[test]
+fn trace_of_an_unknown_net_is_reported() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let mut solution = route(&problem);
+    let connection_id = *solution.determined_traces.keys().min().unwrap();
+    let unknown_net = NetName("not on the board".to_string());
+    solution.determined_traces.get_mut(&connection_id).unwrap().net_name = unknown_net.clone();
+
+    let violations = check_pcb_solution(&problem, &solution, &DrcOptions::default());
+    assert!(violations.iter().any(|violation| {
+        violation.kind == DrcViolationKind::UnknownNet
+            && violation.objects == vec![DrcObject::Trace { net_name: unknown_net.clone(), connection_id }]
+    }));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use router::{
+    connectivity::check_connectivity,
+    pcb_problem_solve::{reroute_pcb_solution, RipUp},
+    router_config::RouterConfig,
+};
+use shared::pcb_render_model::PcbRenderModel;
+
+use common::{load_board, route, SMALL_BOARD};
+
+#[test]
+fn rerouted_net_leaves_the_other_traces_in_place() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    // route also keeps releasing the display waits of the reroute below
+    let solution = route(&problem);
+    let ripped_net = solution
+        .determined_traces
+        .values()
+        .map(|fixed_trace| fixed_trace.net_name.clone())
+        .min_by(|a, b| a.0.cmp(&b.0))
+        .unwrap();
+    let rip_up = RipUp {
+        nets: HashSet::from([ripped_net.clone()]),
+        ..RipUp::default()
+    };
+    let pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>> = Arc::new(Mutex::new(None));
+    let rerouted = match reroute_pcb_solution(&problem, &solution, &rip_up, pcb_render_model, &RouterConfig::default()) {
+        Ok(rerouted) => rerouted,
+        Err(partial_solution) => panic!("The net is not routed again: {}", partial_solution.reason),
+    };
+
+    assert_eq!(rerouted.determined_traces.len(), solution.determined_traces.len());
+    for (connection_id, fixed_trace) in solution.determined_traces.iter() {
+        let rerouted_trace = &rerouted.determined_traces[connection_id];
+        assert_eq!(rerouted_trace.net_name, fixed_trace.net_name);
+        if fixed_trace.net_name != ripped_net {
+            assert_eq!(
+                rerouted_trace.trace_path.anchors, fixed_trace.trace_path.anchors,
+                "connection {} moved",
+                connection_id.0
+            );
+        }
+    }
+    let report = check_connectivity(&problem, &rerouted);
+    assert!(report.is_connected(), "{}", report);
+}
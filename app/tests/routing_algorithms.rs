@@ -0,0 +1,45 @@
+mod common;
+
+use router::{
+    connectivity::check_connectivity,
+    drc::{check_pcb_solution, DrcOptions},
+    router_config::{RouterConfig, RoutingAlgorithm},
+};
+use shared::pcb_problem::{PcbProblem, PcbSolution};
+
+use common::{load_board, route, route_with, SMALL_BOARD};
+
+/// every connection is routed, the copper matches the netlist and keeps the design rules
+fn assert_routed(problem: &PcbProblem, solution: &PcbSolution) {
+    let num_connections: usize = problem.nets.values().map(|net_info| net_info.connections.len()).sum();
+    assert_eq!(solution.determined_traces.len(), num_connections);
+    let report = check_connectivity(problem, solution);
+    assert!(report.is_connected(), "{}", report);
+    let violations = check_pcb_solution(problem, solution, &DrcOptions::default());
+    assert!(
+        violations.is_empty(),
+        "Unexpected violations:\n{}",
+        violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("\n")
+    );
+}
+
+#[test]
+fn naive_backtrack_routes_the_board() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    assert_routed(&problem, &solution);
+}
+
+#[test]
+fn bayesian_backtrack_routes_the_board() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route_with(&problem, &RouterConfig::new(RoutingAlgorithm::Bayesian));
+    assert_routed(&problem, &solution);
+}
+
+#[test]
+fn pathfinder_routes_the_board() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route_with(&problem, &RouterConfig::new(RoutingAlgorithm::NegotiatedCongestion));
+    assert_routed(&problem, &solution);
+}
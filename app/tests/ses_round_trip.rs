@@ -0,0 +1,76 @@
+mod common;
+
+use std::collections::HashSet;
+
+use parser::{read_ses::read_ses, write_ses::write_ses};
+use router::{
+    connectivity::check_connectivity,
+    drc::{check_pcb_solution, DrcOptions},
+};
+use shared::{
+    collider::{CircleCollider, Collider},
+    pcb_problem::{FixedTrace, PcbProblem},
+};
+
+use common::{load_board, route, SMALL_BOARD};
+
+/// the anchor positions of the trace outside the copper of the pads of its connection,
+/// read_ses starts and ends a trace on the pad copper nearest to the pad centers, the rest has to come back
+fn anchors_outside_pads(problem: &PcbProblem, fixed_trace: &FixedTrace) -> HashSet<(i32, i32)> {
+    let net_info = &problem.nets[&fixed_trace.net_name];
+    let connection = &net_info.connections[&fixed_trace.connection_id];
+    let pad_colliders: Vec<Collider> = [&connection.start_pad, &connection.end_pad]
+        .into_iter()
+        .flat_map(|pad_name| net_info.pads[pad_name].to_shapes())
+        .map(|shape| Collider::from_prim_shape(&shape))
+        .collect();
+    fixed_trace
+        .trace_path
+        .anchors
+        .0
+        .iter()
+        .filter(|anchor| {
+            let point = Collider::Circle(CircleCollider {
+                position: anchor.position.to_float(),
+                diameter: 0.0,
+            });
+            !pad_colliders.iter().any(|collider| collider.collides_with(&point))
+        })
+        .map(|anchor| (anchor.position.x.to_bits(), anchor.position.y.to_bits()))
+        .collect()
+}
+
+#[test]
+fn written_session_reads_back_the_same_traces() {
+    let (dsn_struct, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let output = std::env::temp_dir().join(format!("{}_ses_round_trip", std::process::id()));
+    let output = output.to_str().unwrap();
+    write_ses(&dsn_struct, &solution, output).unwrap();
+    let content = std::fs::read_to_string(format!("{}.ses", output)).unwrap();
+    std::fs::remove_file(format!("{}.ses", output)).unwrap();
+
+    let read_solution = read_ses(&dsn_struct, &problem, &content).unwrap();
+    let connection_ids: HashSet<_> = solution.determined_traces.keys().collect();
+    let read_connection_ids: HashSet<_> = read_solution.determined_traces.keys().collect();
+    assert_eq!(read_connection_ids, connection_ids);
+    for (connection_id, fixed_trace) in solution.determined_traces.iter() {
+        let read_trace = &read_solution.determined_traces[connection_id];
+        assert_eq!(read_trace.net_name, fixed_trace.net_name);
+        let read_positions: HashSet<(i32, i32)> = read_trace
+            .trace_path
+            .anchors
+            .0
+            .iter()
+            .map(|anchor| (anchor.position.x.to_bits(), anchor.position.y.to_bits()))
+            .collect();
+        assert!(
+            anchors_outside_pads(&problem, fixed_trace).is_subset(&read_positions),
+            "connection {} lost anchors",
+            connection_id.0
+        );
+        assert!(read_trace.trace_path.total_length <= fixed_trace.trace_path.total_length + 1e-3);
+    }
+    assert!(check_connectivity(&problem, &read_solution).is_connected());
+    assert!(check_pcb_solution(&problem, &read_solution, &DrcOptions::default()).is_empty());
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::collections::HashSet;
+
+use router::{
+    connectivity::check_connectivity,
+    drc::{check_pcb_solution, DrcOptions},
+    router_config::RouterConfig,
+    shove::{shove_aside, ShoveConfig},
+};
+use shared::{
+    pcb_problem::{FixedTrace, PcbProblem},
+    trace_path::{TraceAnchor, TraceAnchors, TracePath},
+    vec2::{FixedPoint, FixedVec2},
+};
+
+use common::{load_board, route, route_with, SMALL_BOARD};
+
+// anchors are kept on even fixed point bits
+fn even(x: f32) -> FixedPoint {
+    FixedPoint::from_bits(FixedPoint::from_num(x).to_bits() & !1)
+}
+
+fn octilinear_step(x: FixedPoint) -> FixedPoint {
+    FixedPoint::from_bits((x.to_bits() / 3) & !1)
+}
+
+#[test]
+fn routed_board_with_shoving_is_connected_and_passes_drc() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let config = RouterConfig {
+        // a connection longer than the distance between its pads tries to shove the traces that were in the way of its search
+        shove: ShoveConfig {
+            detour_ratio: 1.0,
+            ..ShoveConfig::enabled()
+        },
+        ..RouterConfig::default()
+    };
+    let solution = route_with(&problem, &config);
+    let report = check_connectivity(&problem, &solution);
+    assert!(report.is_connected(), "{}", report);
+    let violations = check_pcb_solution(&problem, &solution, &DrcOptions::default());
+    assert!(
+        violations.is_empty(),
+        "Unexpected violations:\n{}",
+        violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("\n")
+    );
+}
+
+/// a short trace of another net right next to the middle of the longest segment of the victim
+fn trace_next_to(problem: &PcbProblem, victim: &FixedTrace, pusher_connection: &FixedTrace) -> FixedTrace {
+    let segment = victim
+        .trace_path
+        .segments
+        .iter()
+        .max_by(|a, b| (a.end - a.start).to_float().length().total_cmp(&(b.end - b.start).to_float().length()))
+        .unwrap();
+    let delta = segment.end - segment.start;
+    let step = FixedVec2::new(octilinear_step(delta.x), octilinear_step(delta.y));
+    let direction = delta.to_float() / delta.to_float().length();
+    let net_info = &problem.nets[&pusher_connection.net_name];
+    let offset = FixedVec2::new(
+        even(-direction.y * net_info.trace_width / 2.0),
+        even(direction.x * net_info.trace_width / 2.0),
+    );
+    let anchor = |position: FixedVec2| TraceAnchor {
+        position,
+        start_layer: segment.layer,
+        end_layer: segment.layer,
+        via_type: None,
+    };
+    FixedTrace {
+        net_name: pusher_connection.net_name.clone(),
+        connection_id: pusher_connection.connection_id,
+        trace_path: TracePath::from_anchors(
+            TraceAnchors(vec![
+                anchor(segment.start + step + offset),
+                anchor(segment.start + step + step + offset),
+            ]),
+            net_info.trace_width,
+            net_info.trace_clearance,
+            &net_info.via_types,
+        ),
+    }
+}
+
+#[test]
+fn shoving_frees_the_channel_of_a_new_trace() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let mut fixed_traces: Vec<&FixedTrace> = solution.determined_traces.values().collect();
+    fixed_traces.sort_by_key(|fixed_trace| fixed_trace.connection_id);
+    // some segments run too close to pads or other traces to move, at least one channel has to be freed
+    let mut num_freed = 0;
+    for victim in fixed_traces.iter() {
+        let pusher_connection = fixed_traces
+            .iter()
+            .find(|fixed_trace| fixed_trace.net_name != victim.net_name)
+            .unwrap();
+        let new_trace = trace_next_to(&problem, victim, pusher_connection);
+        assert!(victim.trace_path.collides_with(&new_trace.trace_path));
+        let Some(moved) = shove_aside(
+            &problem,
+            &solution.determined_traces,
+            &HashSet::new(),
+            &new_trace,
+            &ShoveConfig::enabled(),
+        ) else {
+            continue;
+        };
+        let shoved_victim = &moved[&victim.connection_id];
+        // it still runs between its pads
+        assert_eq!(shoved_victim.trace_path.anchors.0.first(), victim.trace_path.anchors.0.first());
+        assert_eq!(shoved_victim.trace_path.anchors.0.last(), victim.trace_path.anchors.0.last());
+        for fixed_trace in moved.values() {
+            assert!(!fixed_trace.trace_path.collides_with(&new_trace.trace_path));
+        }
+        num_freed += 1;
+    }
+    assert!(num_freed > 0);
+}
+
+#[test]
+fn shoving_is_off_by_default() {
+    assert!(!RouterConfig::default().shove.is_enabled());
+    assert!(ShoveConfig::enabled().is_enabled());
+}
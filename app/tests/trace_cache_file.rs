@@ -0,0 +1,69 @@
+mod common;
+
+use std::collections::HashMap;
+
+use router::bayesian_backtrack_algo::TraceCache;
+
+use common::{load_board, route, SMALL_BOARD};
+
+/// a file in the temporary directory, unique to this process and test
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}_{}.tracecache", std::process::id(), name))
+}
+
+#[test]
+fn saved_traces_load_back() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let trace_cache = TraceCache {
+        traces: solution
+            .determined_traces
+            .iter()
+            .map(|(connection_id, fixed_trace)| (*connection_id, vec![fixed_trace.trace_path.clone()]))
+            .collect(),
+    };
+    let path = temp_path("saved_traces_load_back");
+    trace_cache.save(&problem, &path).unwrap();
+    let mut loaded_cache = TraceCache { traces: HashMap::new() };
+    let num_loaded = loaded_cache.load(&problem, &path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(num_loaded, Ok(solution.determined_traces.len()));
+    for (connection_id, fixed_trace) in solution.determined_traces.iter() {
+        let loaded_traces = &loaded_cache.traces[connection_id];
+        assert_eq!(loaded_traces.len(), 1);
+        assert_eq!(loaded_traces[0].anchors, fixed_trace.trace_path.anchors);
+    }
+}
+
+#[test]
+fn trace_of_another_connection_is_not_loaded() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let solution = route(&problem);
+    let mut fixed_traces: Vec<_> = solution.determined_traces.values().cloned().collect();
+    fixed_traces.sort_by_key(|fixed_trace| fixed_trace.connection_id);
+    let trace_cache = TraceCache {
+        traces: HashMap::from([(fixed_traces[0].connection_id, vec![fixed_traces[1].trace_path.clone()])]),
+    };
+    let path = temp_path("trace_of_another_connection_is_not_loaded");
+    trace_cache.save(&problem, &path).unwrap();
+    let mut loaded_cache = TraceCache { traces: HashMap::new() };
+    let num_loaded = loaded_cache.load(&problem, &path);
+    std::fs::remove_file(&path).unwrap();
+
+    // it does not run between the pads of the connection
+    assert_eq!(num_loaded, Ok(0));
+}
+
+#[test]
+fn file_of_another_format_is_rejected() {
+    let (_, problem) = load_board(SMALL_BOARD);
+    let path = temp_path("file_of_another_format_is_rejected");
+    std::fs::write(&path, "trace_cache 0\n").unwrap();
+    let mut trace_cache = TraceCache { traces: HashMap::new() };
+    let result = trace_cache.load(&problem, &path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+    assert!(trace_cache.traces.is_empty());
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 
 use cgmath::Deg;
-use shared::vec2::FloatVec2;
+use shared::{
+    pcb_problem::{MeanderShape, PadEntryRules, PreferredDirection},
+    vec2::FloatVec2,
+};
 
 pub struct Resolution {
     pub unit: String,
@@ -10,13 +13,36 @@ pub struct Resolution {
 
 pub struct Layer {
     pub name: String,
+    pub direction: Option<PreferredDirection>, // (direction ...) of the layer or of its property list
 }
 
 pub struct Boundary(pub Vec<FloatVec2>);
 
+// (via_rules (drill_spacing <value>) (via_in_smd on|off) (annular_ring <value>) (no_via_under <component> ...)),
+// every item is optional, vias stay out of smd pads unless via_in_smd is on
+#[derive(Default)]
+pub struct ViaPlacementRules {
+    pub drill_spacing: Option<f32>,
+    pub via_in_smd: bool,
+    pub annular_ring: Option<f32>,
+    pub keepout_components: Vec<String>,
+}
+
+// (plane <net> (polygon <layer> <aperture_width> <x> <y> ...)), the area of a layer to fill with the copper of a net,
+// a rect shape is accepted as well, windows are ignored
+pub struct Plane {
+    pub net_name: String,
+    pub layer_name: String,
+    pub outline: Vec<FloatVec2>,
+}
+
 pub struct Structure {
     pub layers: Vec<Layer>,
     pub boundary: Boundary,
+    pub via_names: Vec<String>, // (via <padstack> ...), the vias of nets without use_via
+    pub via_rules: ViaPlacementRules,
+    pub pad_entry: PadEntryRules, // (pad_entry (off_center on|off) (long_axis_exit on|off)), both optional
+    pub planes: Vec<Plane>,
 }
 pub enum PlacementLayer {
     Front,
@@ -79,6 +105,7 @@ pub struct PadStack {
     pub name: String,
     pub shape: Shape,
     pub through_hole: bool,
+    pub layer_names: Vec<String>, // layers of the shapes, a via spans the layers between the outermost ones
 }
 
 pub struct Library {
@@ -86,12 +113,22 @@ pub struct Library {
     pub pad_stacks: HashMap<String, PadStack>,
 }
 
+// (circuit (use_layer <layer> ...) (max_length <value>) (max_vias <count>) (no_vias)) besides use_via
+#[derive(Default)]
+pub struct CircuitRules {
+    pub layer_names: Vec<String>, // empty allows every layer
+    pub max_length: Option<f32>,
+    pub max_vias: Option<usize>, // no_vias is stored as 0
+}
+
 pub struct Netclass {
     pub net_class_name: String,
     pub net_names: Vec<String>,
-    pub via_name: String,
+    pub via_name: String,       // the first via of use_via
+    pub via_names: Vec<String>, // every via of use_via
     pub width: f32,
     pub clearance: f32,
+    pub circuit_rules: CircuitRules,
 }
 
 pub struct Pin2 {
@@ -102,11 +139,32 @@ pub struct Pin2 {
 pub struct Net {
     pub name: String,
     pub pins: Vec<Pin2>,
+    pub circuit_rules: CircuitRules, // set rules override the ones of the netclass
+}
+
+// (pair (nets <positive> <negative>) (gap <value>) (skew <value>)), gap and skew are optional
+pub struct NetPair {
+    pub positive_net: String,
+    pub negative_net: String,
+    pub gap: Option<f32>,
+    pub max_skew: Option<f32>,
+}
+
+// (length_group <name> (nets <net> ...) (length <value>) (tolerance <value>) (meander accordion|trombone)),
+// length, tolerance and meander are optional
+pub struct NetLengthGroup {
+    pub name: String,
+    pub nets: Vec<String>,
+    pub target_length: Option<f32>,
+    pub tolerance: Option<f32>,
+    pub meander_shape: Option<MeanderShape>,
 }
 
 pub struct Network {
     pub nets: Vec<Net>,
     pub netclasses: HashMap<String, Netclass>,
+    pub net_pairs: Vec<NetPair>,
+    pub length_groups: Vec<NetLengthGroup>,
 }
 
 pub struct DsnStruct {
@@ -7,4 +7,5 @@ pub mod parse_to_s_expr;
 pub mod parse_to_struct;
 pub mod s_expr;
 pub mod write_ses;
-pub mod prim_mst;
\ No newline at end of file
+pub mod prim_mst;
+pub mod read_ses;
\ No newline at end of file
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use shared::pcb_problem::PcbProblem;
 
 use crate::{
@@ -12,11 +10,13 @@ use crate::{
 };
 
 pub fn parse_struct_to_end(dsn_struct: &DsnStruct) -> Result<PcbProblem, String> {
+    parse_struct_to_end_with_extra_info(dsn_struct, &ExtraInfo::default())
+}
+
+/// like parse_struct_to_end, with settings that are not in the dsn file, such as differential pairs
+pub fn parse_struct_to_end_with_extra_info(dsn_struct: &DsnStruct, extra_info: &ExtraInfo) -> Result<PcbProblem, String> {
     let display_format = dsn_to_display(dsn_struct)?;
-    let extra_info = ExtraInfo {
-        net_name_to_source_pad: HashMap::new(),
-    };
-    let pcb_problem = Converter::convert(&display_format, &extra_info)?;
+    let pcb_problem = Converter::convert(&display_format, extra_info)?;
     Ok(pcb_problem)
 }
 pub fn parse_start_to_dsn_struct(dsn_file_content: String) -> Result<DsnStruct, String> {
@@ -131,9 +131,7 @@ pub fn parse_end_to_end(dsn_file_content: String) -> Result<PcbProblem, String>
         }
     */
     let display_format = dsn_to_display(&dsn_struct)?;
-    let extra_info = ExtraInfo {
-        net_name_to_source_pad: HashMap::new(),
-    };
+    let extra_info = ExtraInfo::default();
     let pcb_problem = Converter::convert(&display_format, &extra_info)?;
     Ok(pcb_problem)
 }
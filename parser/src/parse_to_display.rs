@@ -1,5 +1,5 @@
 use crate::dsn_struct::{
-    Boundary, Component, ComponentInst, DsnStruct, Netclass, Network, PadStack, Pin, Pin2,
+    Boundary, Component, ComponentInst, DsnStruct, Net, Netclass, Network, PadStack, Pin, Pin2,
     Placement, PlacementLayer, Shape,
 };
 use crate::parse_to_display_format::{DisplayFormat, DisplayNetInfo, ExtraInfo};
@@ -8,8 +8,11 @@ use cgmath::{Deg, Matrix2, Rad, Vector2};
 use core::{f32, net};
 use shared::collider::PolygonCollider;
 use shared::pad::{Pad, PadLayer, PadName, PadShape};
-use shared::pcb_problem::{NetClassName, NetName};
-use shared::prim_shape::Line;
+use shared::pcb_problem::{
+    CopperPourRule, DiffPair, LengthGroup, NetClassName, NetName, RoutingConstraints, ViaKeepout, ViaRules,
+};
+use shared::prim_shape::{Line, PrimShape, RectangleShape};
+use shared::trace_path::ViaType;
 use shared::vec2::{FixedVec2, FloatVec2};
 use std::collections::HashMap;
 
@@ -416,39 +419,180 @@ fn pins_to_pads_and_scale(
 //         .ok_or_else(|| format!("Net '{}' doesn't belong to any netclass", net_name))
 // }
 
-fn parse_net_info_and_scale(
+// the rules of the net replace the ones of its netclass one by one, layer names become layer indices
+fn routing_constraints_and_scale(
     dsn: &DsnStruct,
+    net_class: &Netclass,
+    net: &Net,
     scale_down_factor: f32,
-) -> Result<HashMap<NetName, DisplayNetInfo>, String> {
-    let mut net_info: HashMap<NetName, DisplayNetInfo> = HashMap::new();
-    let mut net_to_net_class: HashMap<String, &Netclass> = HashMap::new();
-    for netclass in dsn.network.netclasses.values() {
-        for net_name in &netclass.net_names {
-            net_to_net_class.insert(net_name.clone(), netclass);
+) -> Result<RoutingConstraints, String> {
+    let layer_names = if net.circuit_rules.layer_names.is_empty() {
+        &net_class.circuit_rules.layer_names
+    } else {
+        &net.circuit_rules.layer_names
+    };
+    let allowed_layers = if layer_names.is_empty() {
+        None
+    } else {
+        let mut allowed_layers = layer_names
+            .iter()
+            .map(|layer_name| {
+                dsn.structure
+                    .layers
+                    .iter()
+                    .position(|layer| &layer.name == layer_name)
+                    .ok_or_else(|| format!("Unknown layer '{}' in the rules of net '{}'", layer_name, net.name))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+        allowed_layers.sort();
+        allowed_layers.dedup();
+        Some(allowed_layers)
+    };
+    Ok(RoutingConstraints {
+        allowed_layers,
+        max_vias: net.circuit_rules.max_vias.or(net_class.circuit_rules.max_vias),
+        max_length: net
+            .circuit_rules
+            .max_length
+            .or(net_class.circuit_rules.max_length)
+            .map(|max_length| max_length / scale_down_factor),
+    })
+}
+
+// kicad names its vias Via[<first>-<last>]_<diameter>:<drill>_<unit>
+fn drill_from_via_name(via_name: &str) -> Option<f32> {
+    let size = via_name.rsplit('_').nth(1)?;
+    let (_, drill) = size.split_once(':')?;
+    drill.parse::<f32>().ok()
+}
+
+// each listed component keeps vias out of the bounding rectangle of its pads on the layer it is mounted on
+fn via_keepouts(
+    dsn: &DsnStruct,
+    pad_map: &HashMap<String, TransformedPad>,
+) -> Vec<ViaKeepout> {
+    let num_layers = dsn.structure.layers.len();
+    let mut keepouts: Vec<ViaKeepout> = Vec::new();
+    for reference in dsn.structure.via_rules.keepout_components.iter() {
+        let Some(instance) = dsn
+            .placement
+            .components
+            .iter()
+            .flat_map(|component| component.instances.iter())
+            .find(|instance| &instance.reference == reference)
+        else {
+            println!("Warning: component {} in no_via_under is not placed, ignored", reference);
+            continue;
+        };
+        let mut min = FloatVec2::new(f32::MAX, f32::MAX);
+        let mut max = FloatVec2::new(f32::MIN, f32::MIN);
+        for pad in pad_map.values().filter(|pad| &pad.component_name == reference) {
+            // half the diagonal covers the pad at any rotation
+            let half_extent = match pad.shape {
+                PadShape::Circle { diameter } => diameter / 2.0,
+                PadShape::Rectangle { width, height } | PadShape::RoundRect { width, height, .. } => {
+                    (width * width + height * height).sqrt() / 2.0
+                }
+            };
+            min.x = f32::min(min.x, pad.position.x - half_extent);
+            min.y = f32::min(min.y, pad.position.y - half_extent);
+            max.x = f32::max(max.x, pad.position.x + half_extent);
+            max.y = f32::max(max.y, pad.position.y + half_extent);
+        }
+        if min.x > max.x {
+            println!("Warning: component {} in no_via_under has no pads, ignored", reference);
+            continue;
         }
+        let layer = match instance.placement_layer {
+            PlacementLayer::Front => 0,
+            PlacementLayer::Back => num_layers - 1,
+        };
+        keepouts.push(ViaKeepout {
+            component: reference.clone(),
+            layer,
+            shape: PrimShape::Rectangle(RectangleShape {
+                position: (min + max) / 2.0,
+                width: max.x - min.x,
+                height: max.y - min.y,
+                rotation: Deg(0.0),
+            }),
+        });
     }
-    let mut net_to_via_diameter_scaled: HashMap<String, f32> = HashMap::new();
-    for (net_name, netclass) in &net_to_net_class {
+    keepouts
+}
+
+// the vias of the netclass, or the vias of the structure when the netclass has no use_via,
+// each spans the layers between the outermost layers of its padstack shapes
+fn via_types_and_scale(
+    dsn: &DsnStruct,
+    net_class: &Netclass,
+    net_name: &str,
+    scale_down_factor: f32,
+) -> Result<Vec<ViaType>, String> {
+    let via_names = if net_class.via_names.is_empty() {
+        &dsn.structure.via_names
+    } else {
+        &net_class.via_names
+    };
+    let num_layers = dsn.structure.layers.len();
+    let mut via_types: Vec<ViaType> = Vec::new();
+    for via_name in via_names {
+        if via_types.iter().any(|via_type| &via_type.name == via_name) {
+            continue;
+        }
         let pad_stack = dsn
             .library
             .pad_stacks
-            .get(&netclass.via_name)
-            .ok_or_else(|| {
-                format!(
-                    "Via '{}' not found for net '{}'",
-                    netclass.via_name, net_name
-                )
-            })?;
-        let via_diameter = match &pad_stack.shape {
-            Shape::Circle { diameter } => *diameter as f32,
+            .get(via_name)
+            .ok_or_else(|| format!("Via '{}' not found for net '{}'", via_name, net_name))?;
+        let diameter = match &pad_stack.shape {
+            Shape::Circle { diameter } => *diameter,
             _ => {
                 return Err(format!(
                     "Invalid via '{}' for net '{}': not circular",
-                    netclass.via_name, net_name
+                    via_name, net_name
                 ));
             }
         };
-        net_to_via_diameter_scaled.insert(net_name.clone(), via_diameter / scale_down_factor);
+        let layers: Vec<usize> = pad_stack
+            .layer_names
+            .iter()
+            .filter_map(|layer_name| dsn.structure.layers.iter().position(|layer| &layer.name == layer_name))
+            .collect();
+        let (min_layer, max_layer) = match (layers.iter().min(), layers.iter().max()) {
+            (Some(min_layer), Some(max_layer)) => (*min_layer, *max_layer),
+            _ => (0, num_layers - 1), // shapes on unnamed layers are taken as a through via
+        };
+        via_types.push(ViaType {
+            name: via_name.clone(),
+            diameter: diameter / scale_down_factor,
+            drill: drill_from_via_name(via_name).map(|drill| drill / scale_down_factor),
+            min_layer,
+            max_layer,
+        });
+    }
+    if via_types.is_empty() {
+        return Err(format!("No via found for net '{}'", net_name));
+    }
+    // a via blocks every layer it spans, the ones spanning fewer layers are cheaper
+    via_types.sort_by(|a, b| {
+        a.num_spanned_layers()
+            .cmp(&b.num_spanned_layers())
+            .then(a.diameter.total_cmp(&b.diameter))
+    });
+    Ok(via_types)
+}
+
+fn parse_net_info_and_scale(
+    dsn: &DsnStruct,
+    scale_down_factor: f32,
+) -> Result<HashMap<NetName, DisplayNetInfo>, String> {
+    let mut net_info: HashMap<NetName, DisplayNetInfo> = HashMap::new();
+    let mut net_to_net_class: HashMap<String, &Netclass> = HashMap::new();
+    for netclass in dsn.network.netclasses.values() {
+        for net_name in &netclass.net_names {
+            net_to_net_class.insert(net_name.clone(), netclass);
+        }
     }
     for all_nets in dsn.network.nets.iter() {
         let net_class = net_to_net_class
@@ -456,9 +600,14 @@ fn parse_net_info_and_scale(
             .ok_or_else(|| format!("Net '{}' doesn't belong to any netclass", all_nets.name))?;
         let net_name = all_nets.name.clone();
         let pads = pins_to_pads_and_scale(&all_nets.pins, &dsn, scale_down_factor)?;
-        let via_diameter_scaled = *net_to_via_diameter_scaled
-            .get(&net_name)
-            .ok_or_else(|| format!("Via diameter not found for net '{}'", net_name))?;
+        let via_types = via_types_and_scale(dsn, net_class, &net_name, scale_down_factor)?;
+        // the first via of use_via is the default one
+        let via_diameter_scaled = via_types
+            .iter()
+            .find(|via_type| via_type.name == net_class.via_name)
+            .unwrap_or(&via_types[0])
+            .diameter;
+        let routing_constraints = routing_constraints_and_scale(dsn, net_class, all_nets, scale_down_factor)?;
         net_info.insert(
             NetName(net_name.clone()),
             DisplayNetInfo {
@@ -468,6 +617,8 @@ fn parse_net_info_and_scale(
                 default_trace_width: net_class.width / scale_down_factor,
                 default_trace_clearance: net_class.clearance / scale_down_factor,
                 via_diameter: via_diameter_scaled,
+                via_types,
+                routing_constraints,
             },
         );
     }
@@ -494,14 +645,85 @@ pub fn dsn_to_display(dsn: &DsnStruct) -> Result<DisplayFormat, String> {
     let net_info: HashMap<NetName, DisplayNetInfo> =
         parse_net_info_and_scale(&dsn, scale_down_factor)?;
 
+    let diff_pairs: Vec<DiffPair> = dsn
+        .network
+        .net_pairs
+        .iter()
+        .map(|net_pair| DiffPair {
+            positive_net: NetName(net_pair.positive_net.clone()),
+            negative_net: NetName(net_pair.negative_net.clone()),
+            gap: net_pair.gap,
+            max_skew: net_pair.max_skew,
+        })
+        .collect();
+
+    let length_groups: Vec<LengthGroup> = dsn
+        .network
+        .length_groups
+        .iter()
+        .map(|length_group| LengthGroup {
+            name: length_group.name.clone(),
+            nets: length_group.nets.iter().map(|net| NetName(net.clone())).collect(),
+            target_length: length_group.target_length,
+            tolerance: length_group.tolerance,
+            meander_shape: length_group.meander_shape,
+        })
+        .collect();
+
+    let dsn_via_rules = &dsn.structure.via_rules;
+    let pad_map = build_pad_map_and_scale(dsn, scale_down_factor)?;
+    let via_rules = ViaRules {
+        min_drill_spacing: dsn_via_rules.drill_spacing.map(|spacing| spacing / scale_down_factor),
+        allow_via_in_smd_pad: dsn_via_rules.via_in_smd,
+        min_annular_ring: dsn_via_rules.annular_ring.map(|annular_ring| annular_ring / scale_down_factor),
+        keepouts: via_keepouts(dsn, &pad_map),
+    };
+
+    let layer_names = dsn.get_layer_names();
+    let mut copper_pours: Vec<CopperPourRule> = Vec::new();
+    for plane in dsn.structure.planes.iter() {
+        let layer = layer_names
+            .iter()
+            .position(|layer_name| *layer_name == plane.layer_name)
+            .ok_or(format!(
+                "The plane of net {} is on the unknown layer {}",
+                plane.net_name, plane.layer_name
+            ))?;
+        copper_pours.push(CopperPourRule {
+            net_name: NetName(plane.net_name.clone()),
+            layers: vec![layer],
+            outline: Some(plane.outline.iter().map(|point| *point / scale_down_factor).collect()),
+            thermal_gap: None,
+            spoke_width: None,
+        });
+    }
+
     let display_format = DisplayFormat {
         width,
         height,
         center,
+        board_outline: dsn
+            .structure
+            .boundary
+            .0
+            .iter()
+            .map(|point| *point / scale_down_factor)
+            .collect(),
         num_layers,
         obstacle_lines,
         obstacle_polygons,
         nets: net_info,
+        diff_pairs,
+        length_groups,
+        via_rules,
+        layer_directions: dsn
+            .structure
+            .layers
+            .iter()
+            .map(|layer| layer.direction.unwrap_or_default())
+            .collect(),
+        pad_entry: dsn.structure.pad_entry,
+        copper_pours,
         scale_down_factor,
     };
     Ok(display_format)
@@ -3,8 +3,10 @@ use std::collections::HashMap;
 use shared::{
     collider::PolygonCollider,
     pad::{Pad, PadName},
-    pcb_problem::{NetClassName, NetName},
+    pcb_problem::{CopperPourRule, DiffPair, LengthGroup, NetClassName, NetName, PadEntryRules, PreferredDirection, RoutingConstraints, ViaRules},
     prim_shape::Line,
+    trace_path::ViaType,
+    trace_score::TraceScoreWeights,
     vec2::FloatVec2,
 };
 
@@ -17,20 +19,42 @@ pub struct DisplayNetInfo {
     pub default_trace_width: f32, // may be overridden by individual pads in the next pass
     pub default_trace_clearance: f32, // may be overridden by individual pads in the next pass
     pub via_diameter: f32,        // obtained from via name, and accessed through padstacks
+    pub via_types: Vec<ViaType>,  // every via the netclass may use, sorted from the cheapest
+    pub routing_constraints: RoutingConstraints, // from the netclass, overridden by the rules of the net
 }
 
 pub struct DisplayFormat {
     pub width: f32,                              // in specctra dsn units
     pub height: f32,                             // in specctra dsn units
     pub center: FloatVec2,                       // Center of the PCB, in specctra dsn units
+    pub board_outline: Vec<FloatVec2>,           // The boundary path of the PCB, in float units
     pub num_layers: usize,                       // 0: front, num_layers - 1: back
     pub obstacle_lines: Vec<Line>,               // Lines that represent obstacles in the PCB
     pub obstacle_polygons: Vec<PolygonCollider>, // Polygons that represent obstacles in the PCB
     pub nets: HashMap<NetName, DisplayNetInfo>,  // NetID to DisplayNetInfo
+    pub diff_pairs: Vec<DiffPair>,               // Differential pairs declared in the dsn file
+    pub length_groups: Vec<LengthGroup>,         // Length matching groups declared in the dsn file
+    pub via_rules: ViaRules,                     // Via placement rules declared in the dsn file, in float units
+    pub layer_directions: Vec<PreferredDirection>, // Preferred trace direction of every layer, by layer index
+    pub pad_entry: PadEntryRules,                // Where traces may land on their pads
+    pub copper_pours: Vec<CopperPourRule>,       // The planes declared in the dsn file, in float units
     pub scale_down_factor: f32, // Scale down factor to convert specctra dsn units to float units
 }
 
+#[derive(Default)]
 pub struct ExtraInfo {
     // for nets with 3 or more pads, choose the pad specified below as the source pad. If it's not specified, generate a warning and choose the first one.
     pub net_name_to_source_pad: HashMap<NetName, PadName>, // net name to source pad name
+    // differential pairs in addition to the ones in the dsn file, a pair here replaces a dsn pair with the same nets
+    pub diff_pairs: Vec<DiffPair>,
+    // length matching groups in addition to the ones in the dsn file, a group here replaces a dsn group with the same name
+    pub length_groups: Vec<LengthGroup>,
+    // preferred directions by layer index, replacing the ones in the dsn file
+    pub layer_directions: HashMap<usize, PreferredDirection>,
+    // extra cost per unit of length moved against the preferred direction, defaults to OFF_DIRECTION_PENALTY
+    pub off_direction_penalty: Option<f64>,
+    // nets to fill with copper after routing in addition to the planes in the dsn file, in float units, filled after the planes
+    pub copper_pours: Vec<CopperPourRule>,
+    // weights of the trace score of the probabilistic model, defaults to TraceScoreWeights::default
+    pub trace_score_weights: Option<TraceScoreWeights>,
 }
@@ -1,7 +1,12 @@
 use shared::{
     distinct_color_generator::DistinctColorGenerator,
+    hyperparameters::OFF_DIRECTION_PENALTY,
     pad::{Pad, PadName},
-    pcb_problem::{Connection, ConnectionID, NetInfo, NetName, PcbProblem},
+    pcb_problem::{
+        Connection, ConnectionID, CopperPourRule, DiffPair, DiffPairConstraint, LengthConstraint, LengthGroup, MeanderShape,
+        LayerDirections, NetInfo, NetName, PcbProblem,
+    },
+    trace_score::WeightedTraceScorer,
     vec2::FloatVec2,
 };
 
@@ -73,24 +78,160 @@ impl Converter {
             let color = distinct_color_generator.next().unwrap();
             let net_info = NetInfo {
                 net_name: net_name.clone(),
+                net_class_name: display_net.net_class_name.clone(),
                 color,
                 pads,
                 trace_width: display_net.default_trace_width,
                 trace_clearance: display_net.default_trace_clearance,
                 via_diameter: display_net.via_diameter,
+                via_types: display_net.via_types.clone(),
                 connections,
+                diff_pair: None,
+                length_constraint: None,
+                routing_constraints: display_net.routing_constraints.clone(),
             };
             nets.insert(net_name.clone(), net_info);
         }
+        // a pair from extra_info replaces any earlier pair that uses one of its nets
+        let mut diff_pairs: Vec<&DiffPair> = Vec::new();
+        for diff_pair in display_format.diff_pairs.iter().chain(extra_info.diff_pairs.iter()) {
+            let pair_nets = [&diff_pair.positive_net, &diff_pair.negative_net];
+            diff_pairs.retain(|other| {
+                !pair_nets.contains(&&other.positive_net) && !pair_nets.contains(&&other.negative_net)
+            });
+            diff_pairs.push(diff_pair);
+        }
+        for diff_pair in diff_pairs {
+            if diff_pair.positive_net == diff_pair.negative_net {
+                return Err(format!(
+                    "Differential pair uses net {} twice",
+                    diff_pair.positive_net.0
+                ));
+            }
+            let (Some(positive), Some(negative)) = (
+                nets.get(&diff_pair.positive_net),
+                nets.get(&diff_pair.negative_net),
+            ) else {
+                println!(
+                    "Warning: differential pair {} / {} refers to an unknown net, ignoring it",
+                    diff_pair.positive_net.0, diff_pair.negative_net.0
+                );
+                continue;
+            };
+            let gap = diff_pair
+                .gap
+                .map(|gap| gap / display_format.scale_down_factor)
+                .unwrap_or(f32::max(positive.trace_clearance, negative.trace_clearance));
+            let max_skew = diff_pair
+                .max_skew
+                .map(|max_skew| max_skew / display_format.scale_down_factor)
+                .unwrap_or(f32::max(positive.trace_width, negative.trace_width));
+            nets.get_mut(&diff_pair.positive_net).unwrap().diff_pair = Some(DiffPairConstraint {
+                partner: diff_pair.negative_net.clone(),
+                is_positive: true,
+                gap,
+                max_skew,
+            });
+            nets.get_mut(&diff_pair.negative_net).unwrap().diff_pair = Some(DiffPairConstraint {
+                partner: diff_pair.positive_net.clone(),
+                is_positive: false,
+                gap,
+                max_skew,
+            });
+        }
+        // a group from extra_info replaces the dsn group with the same name
+        let mut length_groups: Vec<&LengthGroup> = Vec::new();
+        for length_group in display_format.length_groups.iter().chain(extra_info.length_groups.iter()) {
+            length_groups.retain(|other| other.name != length_group.name);
+            length_groups.push(length_group);
+        }
+        for length_group in length_groups {
+            let mut group_nets: Vec<&NetName> = Vec::new();
+            for net_name in length_group.nets.iter() {
+                let Some(net_info) = nets.get(net_name) else {
+                    println!(
+                        "Warning: length group {} refers to the unknown net {}, ignoring it",
+                        length_group.name, net_name.0
+                    );
+                    continue;
+                };
+                if let Some(other) = &net_info.length_constraint {
+                    return Err(format!(
+                        "Net {} is in the length groups {} and {}",
+                        net_name.0, other.group, length_group.name
+                    ));
+                }
+                if !group_nets.contains(&net_name) {
+                    group_nets.push(net_name);
+                }
+            }
+            let tolerance = length_group
+                .tolerance
+                .map(|tolerance| tolerance / display_format.scale_down_factor)
+                .unwrap_or(
+                    group_nets
+                        .iter()
+                        .map(|net_name| nets[*net_name].trace_width)
+                        .fold(0.0, f32::max),
+                );
+            for net_name in group_nets {
+                nets.get_mut(net_name).unwrap().length_constraint = Some(LengthConstraint {
+                    group: length_group.name.clone(),
+                    target_length: length_group
+                        .target_length
+                        .map(|target_length| target_length / display_format.scale_down_factor),
+                    tolerance,
+                    meander_shape: length_group.meander_shape.unwrap_or(MeanderShape::Accordion),
+                });
+            }
+        }
+        let mut layer_directions = LayerDirections {
+            directions: display_format.layer_directions.clone(),
+            penalty: extra_info.off_direction_penalty.unwrap_or(OFF_DIRECTION_PENALTY),
+        };
+        for (&layer, &direction) in extra_info.layer_directions.iter() {
+            if layer >= display_format.num_layers {
+                println!("Warning: preferred direction for layer {} which does not exist, ignored", layer);
+                continue;
+            }
+            layer_directions.directions[layer] = direction;
+        }
+        // the planes of the dsn file are filled first, rules for unknown nets or layers are dropped
+        let mut copper_pours: Vec<CopperPourRule> = Vec::new();
+        for rule in display_format.copper_pours.iter().chain(extra_info.copper_pours.iter()) {
+            if !nets.contains_key(&rule.net_name) {
+                println!("Warning: copper pour of the unknown net {}, ignoring it", rule.net_name.0);
+                continue;
+            }
+            let mut rule = rule.clone();
+            rule.layers.retain(|&layer| {
+                if layer >= display_format.num_layers {
+                    println!(
+                        "Warning: copper pour of net {} on layer {} which does not exist, ignored",
+                        rule.net_name.0, layer
+                    );
+                }
+                layer < display_format.num_layers
+            });
+            if !rule.layers.is_empty() {
+                copper_pours.push(rule);
+            }
+        }
         let problem = PcbProblem {
             width: display_format.width,
             height: display_format.height,
             center: display_format.center,
+            board_outline: display_format.board_outline.clone(),
             num_layers: display_format.num_layers,
             obstacle_borders: Vec::new(),
             obstacle_border_outlines: Vec::new(),
             obstacle_polygons: Vec::new(),
             nets, // netname, netinfo
+            via_rules: display_format.via_rules.clone(),
+            layer_directions,
+            pad_entry: display_format.pad_entry,
+            copper_pours,
+            trace_scorer: Rc::new(WeightedTraceScorer::new(extra_info.trace_score_weights.unwrap_or_default())),
             //connection_id_generator: Box::new((0..).map(ConnectionID)),
             scale_down_factor: display_format.scale_down_factor,
         };
@@ -2,12 +2,16 @@
 use std::collections::HashMap;
 
 use cgmath::Deg;
-use shared::vec2::FloatVec2;
+use shared::{
+    pcb_problem::{MeanderShape, PadEntryRules, PreferredDirection},
+    vec2::FloatVec2,
+};
 
 use crate::{
     dsn_struct::{
-        Boundary, Component, ComponentInst, DsnStruct, Image, Layer, Library, Net, Netclass,
-        Network, PadStack, Pin, Pin2, Placement, PlacementLayer, Resolution, Shape, Structure,
+        Boundary, CircuitRules, Component, ComponentInst, DsnStruct, Image, Layer, Library, Net, NetLengthGroup,
+        NetPair, Netclass, Network, PadStack, Pin, Pin2, Placement, PlacementLayer, Plane, Resolution, Shape, Structure,
+        ViaPlacementRules,
     },
     s_expr::SExpr,
 };
@@ -31,11 +35,46 @@ fn parse_layer(s_expr: &Vec<SExpr>) -> Result<Layer, String> {
     let second_item = second_item
         .as_atom()
         .ok_or("Expected an atom as the second item in the layer scope")?;
+    // (direction <dir>) may be given in the layer itself or in its property list
+    let mut direction: Option<PreferredDirection> = None;
+    for item in s_expr.iter().skip(2) {
+        let Some(list) = item.as_list() else {
+            continue;
+        };
+        let items: Vec<&Vec<SExpr>> = match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("direction") => vec![list],
+            Some("property") => list.iter().skip(1).filter_map(|x| x.as_list()).collect(),
+            _ => continue,
+        };
+        for list in items {
+            if list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) != Some("direction") {
+                continue;
+            }
+            let value = list
+                .get(1)
+                .ok_or("Missing layer direction")?
+                .as_atom()
+                .ok_or("Layer direction must be an atom")?;
+            direction = Some(parse_preferred_direction(value)?);
+        }
+    }
     Ok(Layer {
         name: second_item.to_string(),
+        direction,
     })
 }
 
+fn parse_preferred_direction(value: &str) -> Result<PreferredDirection, String> {
+    match value {
+        "horizontal" | "hori" => Ok(PreferredDirection::Horizontal),
+        "vertical" | "vert" => Ok(PreferredDirection::Vertical),
+        "diagonal_45" | "positive_diagonal" => Ok(PreferredDirection::Diagonal45),
+        "diagonal_135" | "negative_diagonal" => Ok(PreferredDirection::Diagonal135),
+        "none" | "off" | "orthogonal" | "diagonal" => Ok(PreferredDirection::None),
+        _ => Err(format!("Unknown layer direction: {}", value)),
+    }
+}
+
 fn parse_boundary(s_expr: &Vec<SExpr>) -> Result<Boundary, String> {
     let first_item = s_expr
         .first()
@@ -111,6 +150,10 @@ fn parse_structure(s_expr: &Vec<SExpr>) -> Result<Structure, String> {
     }
     let mut layers: Vec<Layer> = Vec::new();
     let mut boundary: Option<Boundary> = None;
+    let mut via_names: Vec<String> = Vec::new();
+    let mut via_rules = ViaPlacementRules::default();
+    let mut pad_entry = PadEntryRules::default();
+    let mut planes: Vec<Plane> = Vec::new();
     for item in s_expr.iter().skip(1) {
         let expr_list = item.as_list().ok_or(format!(
             "Expected a list in the structure scope, found: {:?}",
@@ -131,13 +174,22 @@ fn parse_structure(s_expr: &Vec<SExpr>) -> Result<Structure, String> {
                 boundary = Some(parse_boundary(expr_list)?);
             }
             "via" => {
-                continue;
+                for name in expr_list.iter().skip(1) {
+                    let name = name.as_atom().ok_or("Expected via names to be atoms")?;
+                    via_names.push(name.to_string());
+                }
+            }
+            "via_rules" => {
+                via_rules = parse_via_rules(expr_list)?;
+            }
+            "pad_entry" => {
+                pad_entry = parse_pad_entry(expr_list)?;
             }
             "rule" => {
                 continue;
             }
             "plane" => {
-                continue;
+                planes.push(parse_plane(expr_list)?);
             }
             "keepout" => {
                 continue; // to do
@@ -148,7 +200,163 @@ fn parse_structure(s_expr: &Vec<SExpr>) -> Result<Structure, String> {
         }
     }
     let boundary = boundary.ok_or("Expected a boundary in the structure scope")?;
-    Ok(Structure { layers, boundary })
+    Ok(Structure {
+        layers,
+        boundary,
+        via_names,
+        via_rules,
+        pad_entry,
+        planes,
+    })
+}
+
+fn parse_via_rules(s_expr: &[SExpr]) -> Result<ViaPlacementRules, String> {
+    let mut via_rules = ViaPlacementRules::default();
+    for item in s_expr.iter().skip(1) {
+        let list = item
+            .as_list()
+            .ok_or(format!("Expected a list in the via rules scope, found: {:?}", item))?;
+        match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("drill_spacing") => {
+                let drill_spacing = list
+                    .get(1)
+                    .ok_or("Missing drill spacing value")?
+                    .as_atom()
+                    .ok_or("Drill spacing must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid drill spacing: {}", e))?;
+                via_rules.drill_spacing = Some(drill_spacing);
+            }
+            Some("via_in_smd") => {
+                via_rules.via_in_smd = parse_on_off(list, "via_in_smd")?;
+            }
+            Some("annular_ring") => {
+                let annular_ring = list
+                    .get(1)
+                    .ok_or("Missing annular ring value")?
+                    .as_atom()
+                    .ok_or("Annular ring must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid annular ring: {}", e))?;
+                via_rules.annular_ring = Some(annular_ring);
+            }
+            Some("no_via_under") => {
+                for component in list.iter().skip(1) {
+                    via_rules.keepout_components.push(
+                        component
+                            .as_atom()
+                            .ok_or("Component name in no_via_under must be an atom")?
+                            .to_string(),
+                    );
+                }
+            }
+            Some(other) => return Err(format!("Unknown via rule: {}", other)),
+            None => return Err("Empty via rule".into()),
+        }
+    }
+    Ok(via_rules)
+}
+
+fn parse_on_off(list: &[SExpr], name: &str) -> Result<bool, String> {
+    let switch = list
+        .get(1)
+        .ok_or(format!("Missing {} value", name))?
+        .as_atom()
+        .ok_or(format!("{} must be on or off", name))?;
+    match switch.as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Invalid {} value: {}, expected on or off", name, switch)),
+    }
+}
+
+fn parse_pad_entry(s_expr: &[SExpr]) -> Result<PadEntryRules, String> {
+    let mut pad_entry = PadEntryRules::default();
+    for item in s_expr.iter().skip(1) {
+        let list = item
+            .as_list()
+            .ok_or(format!("Expected a list in the pad entry scope, found: {:?}", item))?;
+        match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("off_center") => pad_entry.off_center = parse_on_off(list, "off_center")?,
+            Some("long_axis_exit") => pad_entry.long_axis_exit = parse_on_off(list, "long_axis_exit")?,
+            Some(other) => return Err(format!("Unknown pad entry rule: {}", other)),
+            None => return Err("Empty pad entry rule".into()),
+        }
+    }
+    Ok(pad_entry)
+}
+
+fn parse_plane(s_expr: &[SExpr]) -> Result<Plane, String> {
+    let net_name = s_expr
+        .get(1)
+        .ok_or("Missing plane net name")?
+        .as_atom()
+        .ok_or("Plane net name must be an atom")?
+        .to_string();
+    let shape = s_expr
+        .get(2)
+        .ok_or(format!("Missing shape of the plane of net {}", net_name))?
+        .as_list()
+        .ok_or(format!("The shape of the plane of net {} must be a list", net_name))?;
+    let shape_type = shape
+        .first()
+        .and_then(|item| item.as_atom())
+        .ok_or(format!("The shape of the plane of net {} must start with its type", net_name))?;
+    let layer_name = shape
+        .get(1)
+        .ok_or(format!("Missing layer of the plane of net {}", net_name))?
+        .as_atom()
+        .ok_or("Plane layer must be an atom")?
+        .to_string();
+    let numbers = shape
+        .iter()
+        .skip(2)
+        .map(|item| {
+            item.as_atom()
+                .ok_or("Plane coordinates must be numbers".to_string())?
+                .parse::<f32>()
+                .map_err(|e| format!("Invalid plane coordinate: {}", e))
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+    let outline = match shape_type.as_str() {
+        // (polygon <layer> <aperture_width> <x> <y> ...)
+        "polygon" => {
+            if numbers.len() < 7 || numbers.len() % 2 == 0 {
+                return Err(format!(
+                    "The polygon of the plane of net {} needs an aperture width and at least three vertices",
+                    net_name
+                ));
+            }
+            numbers[1..]
+                .chunks(2)
+                .map(|pair| FloatVec2::new(pair[0], pair[1]))
+                .collect()
+        }
+        // (rect <layer> <x_min> <y_min> <x_max> <y_max>)
+        "rect" => {
+            if numbers.len() != 4 {
+                return Err(format!("The rect of the plane of net {} needs 4 coordinates", net_name));
+            }
+            let (x_min, y_min, x_max, y_max) = (numbers[0], numbers[1], numbers[2], numbers[3]);
+            vec![
+                FloatVec2::new(x_min, y_min),
+                FloatVec2::new(x_max, y_min),
+                FloatVec2::new(x_max, y_max),
+                FloatVec2::new(x_min, y_max),
+            ]
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported shape {} of the plane of net {}",
+                shape_type, net_name
+            ));
+        }
+    };
+    Ok(Plane {
+        net_name,
+        layer_name,
+        outline,
+    })
 }
 
 fn parse_placement(s_expr: &Vec<SExpr>) -> Result<Placement, String> {
@@ -525,6 +733,7 @@ fn parse_padstack(s_expr: &Vec<SExpr>) -> Result<PadStack, String> {
 
     let mut shapes = None;
     let mut shape_num = 0;
+    let mut layer_names: Vec<String> = Vec::new();
     for item in s_expr.iter().skip(2) {
         let expr_list = item.as_list().ok_or(format!(
             "Expected a list in the padstack scope, found: {:?}",
@@ -539,6 +748,15 @@ fn parse_padstack(s_expr: &Vec<SExpr>) -> Result<PadStack, String> {
         match first_item.as_str() {
             "shape" => {
                 shape_num += 1;
+                // (shape (circle F.Cu diameter)), the layer follows the shape type
+                if let Some(layer_name) = expr_list
+                    .get(1)
+                    .and_then(|shape_type| shape_type.as_list())
+                    .and_then(|shape_type| shape_type.get(1))
+                    .and_then(|layer_name| layer_name.as_atom())
+                {
+                    layer_names.push(layer_name.to_string());
+                }
                 if shapes.is_none() {
                     shapes = Some(parse_shape(expr_list)?);
                 }
@@ -557,6 +775,7 @@ fn parse_padstack(s_expr: &Vec<SExpr>) -> Result<PadStack, String> {
         name: padstack_name,
         shape,
         through_hole,
+        layer_names,
     })
 }
 
@@ -669,12 +888,68 @@ fn parse_net(s_expr: &Vec<SExpr>) -> Result<Net, String> {
         });
     }
 
+    let mut circuit_rules = CircuitRules::default();
+    for item in s_expr.iter().skip(3) {
+        if let Some(list) = item.as_list()
+            && list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) == Some("circuit")
+        {
+            parse_circuit_rules(list, &mut circuit_rules)?;
+        }
+    }
+
     Ok(Net {
         name: net_name,
         pins,
+        circuit_rules,
     })
 }
 
+// the routing limits of a circuit scope, use_via is read by the netclass parser
+fn parse_circuit_rules(s_expr: &[SExpr], circuit_rules: &mut CircuitRules) -> Result<(), String> {
+    for item in s_expr.iter().skip(1) {
+        let Some(list) = item.as_list() else {
+            continue;
+        };
+        match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("use_layer") => {
+                for layer_name in list.iter().skip(1) {
+                    circuit_rules.layer_names.push(
+                        layer_name
+                            .as_atom()
+                            .ok_or("Layer name in use_layer must be an atom")?
+                            .to_string(),
+                    );
+                }
+            }
+            Some("max_length") => {
+                let temp_max_length = list
+                    .get(1)
+                    .ok_or("Missing max_length value")?
+                    .as_atom()
+                    .ok_or("Max length must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid max_length: {}", e))?;
+                circuit_rules.max_length = Some(temp_max_length);
+            }
+            Some("max_vias") => {
+                let temp_max_vias = list
+                    .get(1)
+                    .ok_or("Missing max_vias value")?
+                    .as_atom()
+                    .ok_or("Max vias must be a number")?
+                    .parse::<usize>()
+                    .map_err(|e| format!("Invalid max_vias: {}", e))?;
+                circuit_rules.max_vias = Some(temp_max_vias);
+            }
+            Some("no_vias") => {
+                circuit_rules.max_vias = Some(0);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 fn parse_netclass(s_expr: &Vec<SExpr>) -> Result<Netclass, String> {
     let first_item = s_expr
         .first()
@@ -710,14 +985,15 @@ fn parse_netclass(s_expr: &Vec<SExpr>) -> Result<Netclass, String> {
         }
     }
 
-    let mut via_name = String::new();
+    let mut via_names: Vec<String> = Vec::new();
     let mut width: Option<f32> = None;
     let mut clearance: Option<f32> = None;
+    let mut circuit_rules = CircuitRules::default();
     for item in s_expr.iter().skip(current_pos) {
         if let SExpr::List(list) = item {
             match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
                 Some("circuit") => {
-                    if let Some(use_via) = list.get(1) {
+                    for use_via in list.iter().skip(1) {
                         if let Some(use_via_list) = use_via.as_list() {
                             if use_via_list
                                 .first()
@@ -725,15 +1001,17 @@ fn parse_netclass(s_expr: &Vec<SExpr>) -> Result<Netclass, String> {
                                 .map(|s| s.as_str())
                                 == Some("use_via")
                             {
-                                via_name = use_via_list
-                                    .get(1)
-                                    .ok_or("Missing via name in use_via")?
-                                    .as_atom()
-                                    .ok_or("Via name must be an atom")?
-                                    .to_string();
+                                if use_via_list.len() < 2 {
+                                    return Err("Missing via name in use_via".into());
+                                }
+                                for name in use_via_list.iter().skip(1) {
+                                    let name = name.as_atom().ok_or("Via name must be an atom")?;
+                                    via_names.push(name.to_string());
+                                }
                             }
                         }
                     }
+                    parse_circuit_rules(list, &mut circuit_rules)?;
                 }
                 Some("rule") => {
                     for rule_item in list.iter().skip(1) {
@@ -774,12 +1052,146 @@ fn parse_netclass(s_expr: &Vec<SExpr>) -> Result<Netclass, String> {
     }
     let width = width.ok_or("Missing required field: width")?;
     let clearance = clearance.ok_or("Missing required field: clearance")?;
+    let via_name = via_names.first().cloned().unwrap_or_default();
     Ok(Netclass {
         net_class_name,
         net_names,
         via_name,
+        via_names,
         width,
         clearance,
+        circuit_rules,
+    })
+}
+
+fn parse_net_pair(s_expr: &[SExpr]) -> Result<NetPair, String> {
+    let mut net_names: Vec<String> = Vec::new();
+    let mut gap: Option<f32> = None;
+    let mut max_skew: Option<f32> = None;
+    for item in s_expr.iter().skip(1) {
+        let list = item
+            .as_list()
+            .ok_or(format!("Expected a list in the pair scope, found: {:?}", item))?;
+        match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("nets") => {
+                for net_name in list.iter().skip(1) {
+                    net_names.push(
+                        net_name
+                            .as_atom()
+                            .ok_or("Net name in pair must be an atom")?
+                            .to_string(),
+                    );
+                }
+            }
+            Some("gap") => {
+                let temp_gap = list
+                    .get(1)
+                    .ok_or("Missing gap value")?
+                    .as_atom()
+                    .ok_or("Gap must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid gap: {}", e))?;
+                gap = Some(temp_gap);
+            }
+            Some("skew") => {
+                let temp_skew = list
+                    .get(1)
+                    .ok_or("Missing skew value")?
+                    .as_atom()
+                    .ok_or("Skew must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid skew: {}", e))?;
+                max_skew = Some(temp_skew);
+            }
+            _ => {}
+        }
+    }
+    if net_names.len() != 2 {
+        return Err(format!(
+            "Expected exactly two nets in a pair, found: {:?}",
+            net_names
+        ));
+    }
+    let negative_net = net_names.pop().unwrap();
+    let positive_net = net_names.pop().unwrap();
+    Ok(NetPair {
+        positive_net,
+        negative_net,
+        gap,
+        max_skew,
+    })
+}
+
+fn parse_length_group(s_expr: &[SExpr]) -> Result<NetLengthGroup, String> {
+    let name = s_expr
+        .get(1)
+        .ok_or("Missing length group name")?
+        .as_atom()
+        .ok_or("Length group name must be an atom")?
+        .to_string();
+    let mut nets: Vec<String> = Vec::new();
+    let mut target_length: Option<f32> = None;
+    let mut tolerance: Option<f32> = None;
+    let mut meander_shape: Option<MeanderShape> = None;
+    for item in s_expr.iter().skip(2) {
+        let list = item
+            .as_list()
+            .ok_or(format!("Expected a list in the length group scope, found: {:?}", item))?;
+        match list.first().and_then(|x| x.as_atom()).map(|s| s.as_str()) {
+            Some("nets") => {
+                for net_name in list.iter().skip(1) {
+                    nets.push(
+                        net_name
+                            .as_atom()
+                            .ok_or("Net name in length group must be an atom")?
+                            .to_string(),
+                    );
+                }
+            }
+            Some("length") => {
+                let temp_length = list
+                    .get(1)
+                    .ok_or("Missing length value")?
+                    .as_atom()
+                    .ok_or("Length must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid length: {}", e))?;
+                target_length = Some(temp_length);
+            }
+            Some("tolerance") => {
+                let temp_tolerance = list
+                    .get(1)
+                    .ok_or("Missing tolerance value")?
+                    .as_atom()
+                    .ok_or("Tolerance must be a number")?
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid tolerance: {}", e))?;
+                tolerance = Some(temp_tolerance);
+            }
+            Some("meander") => {
+                let shape = list
+                    .get(1)
+                    .ok_or("Missing meander shape")?
+                    .as_atom()
+                    .ok_or("Meander shape must be an atom")?;
+                meander_shape = match shape.as_str() {
+                    "accordion" => Some(MeanderShape::Accordion),
+                    "trombone" => Some(MeanderShape::Trombone),
+                    _ => return Err(format!("Unknown meander shape: {}", shape)),
+                };
+            }
+            _ => {}
+        }
+    }
+    if nets.is_empty() {
+        return Err(format!("Length group {} has no nets", name));
+    }
+    Ok(NetLengthGroup {
+        name,
+        nets,
+        target_length,
+        tolerance,
+        meander_shape,
     })
 }
 
@@ -803,6 +1215,8 @@ fn parse_network(s_expr: &Vec<SExpr>) -> Result<Network, String> {
 
     let mut nets: Vec<Net> = Vec::new();
     let mut netclasses: HashMap<String, Netclass> = HashMap::new();
+    let mut net_pairs: Vec<NetPair> = Vec::new();
+    let mut length_groups: Vec<NetLengthGroup> = Vec::new();
 
     for item in s_expr.iter().skip(1) {
         let expr_list = item.as_list().ok_or(format!(
@@ -824,13 +1238,26 @@ fn parse_network(s_expr: &Vec<SExpr>) -> Result<Network, String> {
                 let netclass = parse_netclass(expr_list)?;
                 netclasses.insert(netclass.net_class_name.clone(), netclass);
             }
+            "pair" => {
+                let net_pair = parse_net_pair(expr_list)?;
+                net_pairs.push(net_pair);
+            }
+            "length_group" => {
+                let length_group = parse_length_group(expr_list)?;
+                length_groups.push(length_group);
+            }
             _ => {
                 return Err(format!("Unknown network item: {}", first_item));
             }
         }
     }
 
-    Ok(Network { nets, netclasses })
+    Ok(Network {
+        nets,
+        netclasses,
+        net_pairs,
+        length_groups,
+    })
 }
 
 pub fn parse_s_expr_to_struct(s_expr: &SExpr) -> Result<DsnStruct, String> {
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use shared::{
+    collider::{CircleCollider, Collider},
+    pad::PadName,
+    pcb_problem::{ConnectionID, FixedTrace, NetInfo, NetName, PcbProblem, PcbSolution},
+    trace_path::{TraceAnchor, TraceAnchors, TracePath},
+    vec2::{FixedPoint, FixedVec2, FloatVec2},
+};
+
+use crate::{dsn_struct::DsnStruct, parse_to_s_expr::parse_dsn_to_s_expr, s_expr::SExpr};
+
+// a point of a wire ending on a wire of the same layer within this fraction of its width joins that wire
+const ON_WIRE_TOLERANCE: f32 = 0.01;
+// in float units, a piece of wire this close to a multiple of 45 degrees is moved onto it, the file rounds the coordinates
+const OCTILINEAR_SNAP: f32 = 1e-3;
+
+// a position on a layer, the wires of a net join at these
+type CopperNode = (FixedVec2, usize);
+
+struct SesWire {
+    layer: usize,
+    width: f32,
+    points: Vec<FixedVec2>,
+}
+
+struct SesVia {
+    padstack_name: String,
+    position: FixedVec2,
+}
+
+#[derive(Default)]
+struct SesNet {
+    wires: Vec<SesWire>,
+    vias: Vec<SesVia>,
+}
+
+fn atom_at<'a>(list: &'a [SExpr], index: usize, scope: &str) -> Result<&'a String, String> {
+    list.get(index)
+        .and_then(|item| item.as_atom())
+        .ok_or(format!("Expected an atom at position {} in the {} scope", index, scope))
+}
+
+fn number_at(list: &[SExpr], index: usize, scope: &str) -> Result<f64, String> {
+    let atom = atom_at(list, index, scope)?;
+    atom.parse::<f64>()
+        .map_err(|e| format!("Failed to parse number {} in the {} scope: {}", atom, scope, e))
+}
+
+/// the lists among the items that start with the given name
+fn lists_named<'a>(items: &'a [SExpr], name: &'a str) -> impl Iterator<Item = &'a Vec<SExpr>> {
+    items
+        .iter()
+        .filter_map(|item| item.as_list())
+        .filter(move |list| list.first().and_then(|first| first.as_atom()).map(|s| s.as_str()) == Some(name))
+}
+
+fn parse_ses_net(s_expr: &[SExpr], layer_names: &[String], scale_down_factor: f32) -> Result<SesNet, String> {
+    // converted in double precision, the f32 coordinates written by write_ses then read back to the same fixed points,
+    // which matters for the traces routed right at the clearance
+    let scale_down_factor = scale_down_factor as f64;
+    let to_fixed = |x: f64, y: f64| FixedVec2 {
+        x: FixedPoint::from_num(x / scale_down_factor),
+        y: FixedPoint::from_num(y / scale_down_factor),
+    };
+    let mut ses_net = SesNet::default();
+    for wire in lists_named(s_expr, "wire") {
+        // the copper pours are filled again after routing, only the paths are traces
+        let Some(path) = lists_named(&wire[1..], "path").next() else {
+            continue;
+        };
+        let layer_name = atom_at(path, 1, "path")?;
+        let layer = layer_names
+            .iter()
+            .position(|name| name == layer_name)
+            .ok_or(format!("Unknown layer {} in the session file", layer_name))?;
+        let width = (number_at(path, 2, "path")? / scale_down_factor) as f32;
+        if path.len() % 2 != 1 || path.len() < 7 {
+            return Err(format!("Expected at least two points in the path on layer {}", layer_name));
+        }
+        let points = (3..path.len())
+            .step_by(2)
+            .map(|index| Ok(to_fixed(number_at(path, index, "path")?, number_at(path, index + 1, "path")?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        ses_net.wires.push(SesWire { layer, width, points });
+    }
+    for via in lists_named(s_expr, "via") {
+        ses_net.vias.push(SesVia {
+            padstack_name: atom_at(via, 1, "via")?.clone(),
+            position: to_fixed(number_at(via, 2, "via")?, number_at(via, 3, "via")?),
+        });
+    }
+    Ok(ses_net)
+}
+
+fn distance_to_segment(point: FloatVec2, start: FloatVec2, end: FloatVec2) -> f32 {
+    let segment = end - start;
+    let length2 = segment.magnitude2();
+    if length2 == 0.0 {
+        return (point - start).length();
+    }
+    let t = ((point - start).dot(segment) / length2).clamp(0.0, 1.0);
+    (point - (start + segment * t)).length()
+}
+
+/// the wires and vias of a net as a graph, each edge is a piece of wire with its width or a via with no width,
+/// a wire is split where another wire of the layer ends on it
+fn copper_graph(ses_net: &SesNet, net_info: &NetInfo, num_layers: usize) -> HashMap<CopperNode, Vec<(CopperNode, f32)>> {
+    let mut graph: HashMap<CopperNode, Vec<(CopperNode, f32)>> = HashMap::new();
+    let mut layer_points: HashMap<usize, HashSet<FixedVec2>> = HashMap::new();
+    for wire in ses_net.wires.iter() {
+        layer_points.entry(wire.layer).or_default().extend(wire.points.iter().cloned());
+    }
+    for wire in ses_net.wires.iter() {
+        let points_on_layer = &layer_points[&wire.layer];
+        for pair in wire.points.windows(2) {
+            let (start, end) = (pair[0].to_float(), pair[1].to_float());
+            let direction = end - start;
+            let mut points: Vec<(f32, FixedVec2)> = points_on_layer
+                .iter()
+                .filter(|point| distance_to_segment(point.to_float(), start, end) <= wire.width * ON_WIRE_TOLERANCE)
+                .map(|point| ((point.to_float() - start).dot(direction), *point))
+                .collect();
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for piece in points.windows(2) {
+                let (a, b) = ((piece[0].1, wire.layer), (piece[1].1, wire.layer));
+                if a != b {
+                    graph.entry(a).or_default().push((b, wire.width));
+                    graph.entry(b).or_default().push((a, wire.width));
+                }
+            }
+        }
+    }
+    for via in ses_net.vias.iter() {
+        let (min_layer, max_layer) = net_info
+            .via_types
+            .iter()
+            .find(|via_type| via_type.name == via.padstack_name)
+            .map(|via_type| (via_type.min_layer, via_type.max_layer))
+            .unwrap_or((0, num_layers - 1));
+        let nodes: Vec<CopperNode> = (min_layer..=max_layer)
+            .map(|layer| (via.position, layer))
+            .filter(|node| graph.contains_key(node))
+            .collect();
+        for a in nodes.iter() {
+            for b in nodes.iter().filter(|b| *b != a) {
+                graph.entry(*a).or_default().push((*b, 0.0));
+            }
+        }
+    }
+    graph
+}
+
+/// the path with the fewest pieces from a node on one set to a node on the other
+fn shortest_path(
+    graph: &HashMap<CopperNode, Vec<(CopperNode, f32)>>,
+    starts: &HashSet<CopperNode>,
+    ends: &HashSet<CopperNode>,
+) -> Option<Vec<CopperNode>> {
+    let mut previous: HashMap<CopperNode, Option<CopperNode>> = starts.iter().map(|node| (*node, None)).collect();
+    let mut queue: VecDeque<CopperNode> = starts.iter().cloned().collect();
+    while let Some(node) = queue.pop_front() {
+        if ends.contains(&node) {
+            let mut path = vec![node];
+            while let Some(Some(previous_node)) = previous.get(path.last().unwrap()) {
+                path.push(*previous_node);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (neighbor, _) in graph[&node].iter() {
+            if !previous.contains_key(neighbor) {
+                previous.insert(*neighbor, Some(node));
+                queue.push_back(*neighbor);
+            }
+        }
+    }
+    None
+}
+
+/// the nodes at the position nearest to the pad center, on every layer
+fn nearest_to_center(nodes: &HashSet<CopperNode>, center: FloatVec2) -> HashSet<CopperNode> {
+    let nearest = nodes
+        .iter()
+        .map(|(position, _)| *position)
+        .min_by(|a, b| (a.to_float() - center).length().total_cmp(&(b.to_float() - center).length()));
+    nodes.iter().filter(|(position, _)| Some(*position) == nearest).cloned().collect()
+}
+
+/// the anchors of a path, the nodes at one position make a single anchor that changes layers there,
+/// traces only run in multiples of 45 degrees, a piece of wire slightly off is snapped and any other gets a bend
+fn path_to_anchors(path: &[CopperNode], ses_net: &SesNet, net_info: &NetInfo) -> TraceAnchors {
+    let snap = FixedPoint::from_num(OCTILINEAR_SNAP);
+    let mut anchors: Vec<TraceAnchor> = Vec::new();
+    for (position, layer) in path.iter() {
+        let mut position = *position;
+        if let Some(previous) = anchors.last().map(|anchor| anchor.position) {
+            let (dx, dy) = (position.x - previous.x, position.y - previous.y);
+            if dx.abs() <= snap {
+                position.x = previous.x;
+            } else if dy.abs() <= snap {
+                position.y = previous.y;
+            } else if (dx.abs() - dy.abs()).abs() <= snap {
+                position.y = previous.y + dy.signum() * dx.abs();
+            } else {
+                // diagonal first, then straight
+                let diagonal = dx.abs().min(dy.abs());
+                let layer = anchors.last().unwrap().end_layer;
+                anchors.push(TraceAnchor {
+                    position: FixedVec2::new(previous.x + dx.signum() * diagonal, previous.y + dy.signum() * diagonal),
+                    start_layer: layer,
+                    end_layer: layer,
+                    via_type: None,
+                });
+            }
+        }
+        match anchors.last_mut() {
+            Some(anchor) if anchor.position == position => anchor.end_layer = *layer,
+            _ => anchors.push(TraceAnchor {
+                position,
+                start_layer: *layer,
+                end_layer: *layer,
+                via_type: None,
+            }),
+        }
+    }
+    for anchor in anchors.iter_mut().filter(|anchor| anchor.start_layer != anchor.end_layer) {
+        anchor.via_type = ses_net
+            .vias
+            .iter()
+            .find(|via| via.position == anchor.position)
+            .and_then(|via| net_info.via_types.iter().position(|via_type| via_type.name == via.padstack_name));
+    }
+    TraceAnchors(anchors)
+}
+
+/// reads the routes of a session file back into a solution of the problem,
+/// the wires of each net are split into one trace per connection, from a pad of the connection to the other,
+/// coordinates are in the units of the design file like write_ses writes them
+pub fn read_ses(dsn: &DsnStruct, problem: &PcbProblem, ses_file_content: &str) -> Result<PcbSolution, String> {
+    let s_expr = parse_dsn_to_s_expr(ses_file_content).map_err(|e| format!("Failed to parse SES: {}", e))?;
+    let session = s_expr.as_list().ok_or("Expected a list as the session scope")?;
+    if atom_at(session, 0, "session")? != "session" {
+        return Err("Expected 'session' as the first item of the session file".to_string());
+    }
+    let layer_names = dsn.get_layer_names();
+    let mut determined_traces: HashMap<ConnectionID, FixedTrace> = HashMap::new();
+    let nets = lists_named(session, "routes")
+        .flat_map(|routes| lists_named(routes, "network_out"))
+        .flat_map(|network_out| lists_named(network_out, "net"));
+    for net in nets {
+        let net_name = NetName(atom_at(net, 1, "net")?.clone());
+        let Some(net_info) = problem.nets.get(&net_name) else {
+            println!("Warning: net {} of the session file is not on the board, its wires are dropped", net_name.0);
+            continue;
+        };
+        let ses_net = parse_ses_net(&net[2..], &layer_names, problem.scale_down_factor)?;
+        let graph = copper_graph(&ses_net, net_info, problem.num_layers);
+        let nodes_on_pad = |pad_name: &PadName| -> HashSet<CopperNode> {
+            let pad = &net_info.pads[pad_name];
+            let pad_colliders: Vec<Collider> = pad.to_shapes().iter().map(Collider::from_prim_shape).collect();
+            let pad_layers: HashSet<usize> = pad.pad_layer.get_iter(problem.num_layers).collect();
+            graph
+                .keys()
+                .filter(|(position, layer)| {
+                    let point = Collider::Circle(CircleCollider {
+                        position: position.to_float(),
+                        diameter: 0.0,
+                    });
+                    pad_layers.contains(layer) && pad_colliders.iter().any(|collider| collider.collides_with(&point))
+                })
+                .cloned()
+                .collect()
+        };
+        let pad_nodes: HashMap<&PadName, HashSet<CopperNode>> =
+            net_info.pads.keys().map(|pad_name| (pad_name, nodes_on_pad(pad_name))).collect();
+        let mut connections: Vec<_> = net_info.connections.values().collect();
+        connections.sort_by_key(|connection| connection.connection_id);
+        let mut used_pieces: HashSet<(CopperNode, CopperNode)> = HashSet::new();
+        for connection in connections {
+            let (start_nodes, end_nodes) = (&pad_nodes[&connection.start_pad], &pad_nodes[&connection.end_pad]);
+            // the traces run from pad center to pad center, the nodes nearest to the centers are tried first
+            let path = shortest_path(
+                &graph,
+                &nearest_to_center(start_nodes, net_info.pads[&connection.start_pad].position),
+                &nearest_to_center(end_nodes, net_info.pads[&connection.end_pad].position),
+            )
+            .or_else(|| shortest_path(&graph, start_nodes, end_nodes));
+            let Some(path) = path.filter(|path| path.first().map(|node| node.0) != path.last().map(|node| node.0)) else {
+                println!(
+                    "Warning: the session file does not connect pads {} and {} of net {}",
+                    connection.start_pad.0, connection.end_pad.0, net_name.0
+                );
+                continue;
+            };
+            let mut trace_width = net_info.trace_width;
+            for piece in path.windows(2) {
+                let width = graph[&piece[0]].iter().find(|(node, _)| *node == piece[1]).map_or(0.0, |(_, width)| *width);
+                trace_width = trace_width.max(width);
+                used_pieces.insert((piece[0].min(piece[1]), piece[0].max(piece[1])));
+            }
+            let anchors = path_to_anchors(&path, &ses_net, net_info);
+            determined_traces.insert(
+                connection.connection_id,
+                FixedTrace {
+                    net_name: net_name.clone(),
+                    connection_id: connection.connection_id,
+                    trace_path: TracePath::from_anchors(anchors, trace_width, net_info.trace_clearance, &net_info.via_types),
+                },
+            );
+        }
+        let num_unused_pieces = graph
+            .iter()
+            .flat_map(|(a, neighbors)| neighbors.iter().map(move |(b, width)| (*a, *b, *width)))
+            .filter(|(a, b, width)| *width > 0.0 && a < b && !used_pieces.contains(&(*a, *b)))
+            // a trace may end anywhere on its pad, the copper inside a pad is covered by the pad
+            .filter(|(a, b, _)| !pad_nodes.values().any(|nodes| nodes.contains(a) && nodes.contains(b)))
+            .count();
+        if num_unused_pieces > 0 {
+            println!(
+                "Warning: {} pieces of wire of net {} are not on the way between the pads of a connection, they are dropped",
+                num_unused_pieces, net_name.0
+            );
+        }
+    }
+    Ok(PcbSolution {
+        determined_traces,
+        copper_pours: Vec::new(),
+        uncoupled_diff_pairs: Vec::new(),
+        scale_down_factor: problem.scale_down_factor,
+    })
+}
@@ -1,5 +1,5 @@
 use crate::dsn_struct::{DsnStruct, Library, Network, Shape};
-use shared::pcb_problem::{FixedTrace, PcbSolution};
+use shared::pcb_problem::{CopperPour, FixedTrace, PartialSolution, PcbSolution};
 use shared::trace_path::Via;
 use shared::vec2::FixedVec2;
 use std::collections::HashMap;
@@ -38,6 +38,7 @@ pub struct ViaSES {
     shape: String,
     through_hole: bool,
     diameter: f32,
+    layer_names: Vec<String>, // layers of the padstack shapes, blind and buried vias only cover some layers
 }
 
 impl ViaSES {
@@ -47,7 +48,10 @@ impl ViaSES {
         let mut s = format!("      (padstack \"{}\"\n", self.name);
 
         if self.through_hole {
-            for layer in layers {
+            let spanned_layers = layers.iter().filter(|layer| {
+                self.layer_names.is_empty() || self.layer_names.contains(layer)
+            });
+            for layer in spanned_layers {
                 s += &format!(
                     "        (shape\n          ({} {} {} 0 0)\n        )\n",
                     shape, layer, dia_int
@@ -79,6 +83,7 @@ fn via_info(dsn: &DsnStruct) -> Vec<ViaSES> {
                         shape: "circle".to_string(),
                         through_hole: pad.through_hole,
                         diameter,
+                        layer_names: pad.layer_names.clone(),
                     })
                 } else {
                     None
@@ -90,8 +95,10 @@ fn via_info(dsn: &DsnStruct) -> Vec<ViaSES> {
         .collect()
 }
 
-fn extract_fixed_vec2(v: &FixedVec2) -> (f32, f32) {
-    (v.x.to_num::<f32>(), v.y.to_num::<f32>())
+// in double precision, so reading the session back gives the same fixed points
+fn extract_fixed_vec2(v: &FixedVec2, scale_down_factor: f32) -> (f64, f64) {
+    let scale_down_factor = scale_down_factor as f64;
+    (v.x.to_num::<f64>() * scale_down_factor, v.y.to_num::<f64>() * scale_down_factor)
 }
 
 fn find_via_name(netname: &String, dsn: &DsnStruct) -> Option<String> {
@@ -114,33 +121,57 @@ fn generate_network<W: Write>(
     // The implementation will depend on the specific requirements of the network format
     let scale_down_factor = solution.scale_down_factor;
 
-    let mut nets: HashMap<&String, Vec<&FixedTrace>> = HashMap::new();
+    let mut nets: HashMap<&String, (Vec<&FixedTrace>, Vec<&CopperPour>)> = HashMap::new();
     for trace in solution.determined_traces.values() {
-        nets.entry(&trace.net_name.0).or_default().push(trace);
+        nets.entry(&trace.net_name.0).or_default().0.push(trace);
+    }
+    for pour in &solution.copper_pours {
+        nets.entry(&pour.net_name.0).or_default().1.push(pour);
     }
 
-    for (net_name, traces) in nets {
+    for (net_name, (traces, pours)) in nets {
         writeln!(file, "  (net \"{}\"", net_name).unwrap();
-        let via_name = find_via_name(&net_name, &dsn).unwrap_or("default_via".to_string());
+        let default_via_name = find_via_name(&net_name, &dsn).unwrap_or("default_via".to_string());
 
         for trace in traces {
             for via in &trace.trace_path.vias {
-                let (x, y) = extract_fixed_vec2(&via.position);
-                writeln!(file, "    (via {} {} {})", via_name, x * scale_down_factor, y * scale_down_factor)?;
+                let via_name = if via.padstack_name.is_empty() {
+                    &default_via_name
+                } else {
+                    &via.padstack_name
+                };
+                let (x, y) = extract_fixed_vec2(&via.position, scale_down_factor);
+                writeln!(file, "    (via {} {} {})", via_name, x, y)?;
             }
             for segment in &trace.trace_path.segments {
-                let (start_x, start_y) = extract_fixed_vec2(&segment.start);
-                let (end_x, end_y) = extract_fixed_vec2(&segment.end);
+                let (start_x, start_y) = extract_fixed_vec2(&segment.start, scale_down_factor);
+                let (end_x, end_y) = extract_fixed_vec2(&segment.end, scale_down_factor);
                 let layer_name = layers[segment.layer].as_str();
                 writeln!(
                     file,
                     "        (wire\n          (path {} {}\n            {} {}\n            {} {}))",
                     layer_name, // 0 = front, highest = back
                     segment.width * scale_down_factor,
-                    start_x * scale_down_factor,
-                    start_y * scale_down_factor,
-                    end_x * scale_down_factor,
-                    end_y * scale_down_factor
+                    start_x,
+                    start_y,
+                    end_x,
+                    end_y
+                )?;
+            }
+        }
+        // the pours are written as filled polygons with no aperture, one wire per polygon
+        for pour in pours {
+            let layer_name = layers[pour.layer].as_str();
+            for polygon in &pour.polygons {
+                let vertices = polygon
+                    .iter()
+                    .map(|vertex| format!("{} {}", vertex.x * scale_down_factor, vertex.y * scale_down_factor))
+                    .collect::<Vec<_>>()
+                    .join("\n            ");
+                writeln!(
+                    file,
+                    "        (wire\n          (polygon {} 0\n            {}))",
+                    layer_name, vertices
                 )?;
             }
         }
@@ -158,7 +189,7 @@ pub fn write_ses(dsn: &DsnStruct, solution: &PcbSolution, output: &str) -> Resul
 
     let layer_names: Vec<String> = dsn.get_layer_names();
 
-    writeln!(ses, "(session {}.ses)", output)?;
+    writeln!(ses, "(session {}.ses", output)?;
     writeln!(ses, "  (base_design {}.dsn)", output)?;
 
     generate_placement(&mut ses, &dsn)?;
@@ -193,3 +224,19 @@ pub fn write_ses(dsn: &DsnStruct, solution: &PcbSolution, output: &str) -> Resul
     writeln!(ses, ")")?;
     Ok(())
 }
+
+/// writes the routed part of a partial solution to `output`.ses,
+/// and the connections left for the engineer to `output`_unrouted.txt, one "net start_pad end_pad" per line
+pub fn write_partial_ses(dsn: &DsnStruct, partial_solution: &PartialSolution, output: &str) -> Result<()> {
+    write_ses(dsn, &partial_solution.solution, output)?;
+    let mut unrouted = File::create(output.to_string() + "_unrouted.txt")?;
+    writeln!(unrouted, "# {}", partial_solution.reason)?;
+    for connection in &partial_solution.unrouted_connections {
+        writeln!(
+            unrouted,
+            "\"{}\" \"{}\" \"{}\"",
+            connection.net_name.0, connection.start_pad.0, connection.end_pad.0
+        )?;
+    }
+    Ok(())
+}
@@ -11,22 +11,27 @@ use ordered_float::NotNan;
 
 use crate::post_process::optimize_path;
 use crate::{
+    blocking_traces::BlockingTraces,
+    cancellation::CancellationToken,
     command_flags::{COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES, CommandFlag},
+    congestion_map::CongestionCosts,
+    pad_entry::PadEntry,
     quad_tree::QuadTreeNode,
+    via_rules::ViaObstacles,
 };
 
 use shared::{
     binary_heap_item::BinaryHeapItem,
     collider::{BorderCollider, Collider},
     hyperparameters::{ASTAR_STRIDE, ESTIMATE_COEFFICIENT, MAX_TRIALS, SAMPLE_CNT, VIA_COST},
-    octile_distance::octile_distance_fixed,
     pad::PadLayer,
+    pcb_problem::{LayerDirections, RoutingConstraints},
     pcb_render_model::{
         self, PcbRenderModel, RenderableBatch, ShapeRenderable, UpdatePcbRenderModel,
     },
     prim_shape::{CircleShape, PrimShape, RectangleShape},
     trace_path::{
-        self, AStarNodeDirection, Direction, TraceAnchor, TraceAnchors, TracePath, TraceSegment, Via
+        self, AStarNodeDirection, Direction, TraceAnchor, TraceAnchors, TracePath, TraceSegment, Via, ViaType
     },
     vec2::{FixedPoint, FixedVec2, FloatVec2},
 };
@@ -39,19 +44,39 @@ pub struct AStarModel {
     pub obstacle_clearance_shapes: Rc<HashMap<usize, Vec<PrimShape>>>,
     pub obstacle_colliders: Rc<HashMap<usize, QuadTreeNode>>,
     pub obstacle_clearance_colliders: Rc<HashMap<usize, QuadTreeNode>>,
-    pub start: FixedVec2,
-    pub end: FixedVec2,
+    pub start: PadEntry, // the points inside the start pad the trace may leave from
+    pub end: PadEntry,   // the points inside the end pad the trace may reach
     pub start_layers: PadLayer,
     pub end_layers: PadLayer,
     pub num_layers: usize,
     pub trace_width: f32,
     pub trace_clearance: f32,
-    pub via_diameter: f32,
+    pub via_types: Vec<ViaType>, // sorted from the cheapest, the search picks the cheapest legal one for each layer change
     pub border_colliders_cache: RefCell<Option<Rc<Vec<Collider>>>>,
     pub border_shapes_cache: RefCell<Option<Rc<Vec<PrimShape>>>>,
+    pub congestion_costs: Option<Rc<CongestionCosts>>, // soft costs of regions used by other nets, only set by the negotiated congestion router
+    pub routing_constraints: RoutingConstraints, // layers, vias and length the trace may use
+    pub via_obstacles: Rc<ViaObstacles>, // drill spacing, via in pad, annular ring and component keepouts
+    pub layer_directions: LayerDirections, // moving against the preferred direction of a layer costs extra
+    pub blocking_traces: Option<Rc<BlockingTraces>>, // the fixed traces among the obstacles, to tell which of them blocked a failed search, only set by the naive backtracker
 }
 
 impl AStarModel {
+    // with a via limit, reaching a point with fewer vias is a different search state
+    fn via_count_key(&self, via_count: usize) -> usize {
+        if self.routing_constraints.max_vias.is_some() {
+            via_count
+        } else {
+            0
+        }
+    }
+    fn failure_reason(&self, reason: &str) -> String {
+        if self.routing_constraints.is_unconstrained() {
+            reason.to_string()
+        } else {
+            format!("{} within the net constraints: {}", reason, self.routing_constraints)
+        }
+    }
     fn in_layers(&self, layer_index: usize, layers: PadLayer) -> bool {
         assert!(
             layer_index < self.num_layers,
@@ -242,9 +267,11 @@ impl AStarModel {
         let obstacle_colliders = self.obstacle_colliders.get(&layer).unwrap();
         let obstacle_clearance_colliders = self.obstacle_clearance_colliders.get(&layer).unwrap();
         if obstacle_colliders.collides_with_set(trace_segment_clearance_colliders.iter()) {
+            self.record_blocking_traces(layer, &trace_segment_colliders, &trace_segment_clearance_colliders);
             return true; // collision with an obstacle clearance shape
         }
         if obstacle_clearance_colliders.collides_with_set(trace_segment_colliders.iter()) {
+            self.record_blocking_traces(layer, &trace_segment_colliders, &trace_segment_clearance_colliders);
             return true; // collision with an obstacle
         }
         if self.collides_with_border(trace_segment_colliders.iter()) {
@@ -252,6 +279,12 @@ impl AStarModel {
         }
         false // no collision
     }
+    // which fixed traces the colliding shape touches, for the conflict set of a failed search
+    fn record_blocking_traces(&self, layer: usize, colliders: &[Collider], clearance_colliders: &[Collider]) {
+        if let Some(blocking_traces) = &self.blocking_traces {
+            blocking_traces.record(layer, colliders, clearance_colliders);
+        }
+    }
     fn check_collision_for_via(
         &self,
         position: FixedVec2,
@@ -272,9 +305,11 @@ impl AStarModel {
         let obstacle_colliders = self.obstacle_colliders.get(&layer).unwrap();
         let obstacle_clearance_colliders = self.obstacle_clearance_colliders.get(&layer).unwrap();
         if obstacle_clearance_colliders.collides_with(&collider) {
+            self.record_blocking_traces(layer, std::slice::from_ref(&collider), std::slice::from_ref(&clearance_collider));
             return true; // collision with an obstacle clearance shape
         }
         if obstacle_colliders.collides_with(&clearance_collider) {
+            self.record_blocking_traces(layer, std::slice::from_ref(&collider), std::slice::from_ref(&clearance_collider));
             return true; // collision with an obstacle
         }
         if self.collides_with_border(std::iter::once(&collider)) {
@@ -574,28 +609,28 @@ impl AStarModel {
         );
         result
     }
+    /// the first even point outside the pad copper when leaving the entry point in the direction
+    fn exit_position(entry: &PadEntry, position: FixedVec2, direction: Direction) -> FixedVec2 {
+        let step_length = if direction.is_diagonal() { f32::sqrt(2.0) } else { 1.0 };
+        let scale = FixedPoint::from_num(entry.exit_length(position, direction) / step_length) + FixedPoint::DELTA * 2;
+        let scale = FixedPoint::from_bits(scale.to_bits() + (scale.to_bits() & 1)); // even, so the end stays on the even grid
+        position + direction.to_fixed_vec2(scale)
+    }
     /// 判断当前点是否与目标点对齐，返回对齐的方向
-    fn is_aligned_with_end(&self, position: FixedVec2, layer: usize) -> Option<Direction> {
-        let end_layers = self
-            .end_layers
-            .get_iter(self.num_layers)
-            .collect::<HashSet<_>>();
-        if !end_layers.contains(&layer) {
-            return None; // not aligned with end layer
-        }
-        assert_ne!(
-            position, self.end,
-            "调用该函数前应确保已经处理与end重合的情况"
-        );
-        match Direction::from_points(position, self.end) {
-            Ok(direction) => match direction{
-                Some(dir) => {
-                    Some(dir)
-                }
-                None => None, // not aligned
-            }
-            Err(_) => None,
+    /// every point of the end region the position is aligned with, with the direction towards it
+    fn is_aligned_with_end(&self, position: FixedVec2, layer: usize) -> Vec<(Direction, FixedVec2)> {
+        if !self.in_layers(layer, self.end_layers) {
+            return Vec::new(); // not aligned with end layer
         }
+        self.end
+            .points
+            .iter()
+            .filter(|end| **end != position)
+            .filter_map(|end| match Direction::from_points(position, *end) {
+                Ok(Some(direction)) if self.end.allows_direction(direction) => Some((direction, *end)),
+                _ => None, // not aligned, or reaching the end point against the exit directions of the pad
+            })
+            .collect()
     }
     /// line 1 is finite, line 2 is infinite
     fn line_intersection_infinite(
@@ -690,27 +725,16 @@ impl AStarModel {
 
     /// 获取与end对齐的交点，还是给定方向和线段长度，判断是否有交叉
     /// allow for the node to be in a different layer from end, but will return none in this case
+    /// end points the start position is already aligned with are reached directly and skipped here
     fn get_intersection_with_end_alignments(
         &self,
         start_pos: FixedVec2,
         end_pos: FixedVec2,
         layer: usize,
     ) -> Option<FixedVec2> {
-        let end_layers = self
-            .end_layers
-            .get_iter(self.num_layers)
-            .collect::<HashSet<_>>();
-        if !end_layers.contains(&layer) {
+        if !self.in_layers(layer, self.end_layers) {
             return None; // not aligned with end layer
         }
-        assert_ne!(
-            start_pos, self.end,
-            "调用该函数前应确保已经处理与end重合的情况"
-        );
-        assert!(
-            self.is_aligned_with_end(start_pos, layer).is_none(),
-            "调用该函数前应确保当前点不与end对齐"
-        );
         assert!(start_pos.is_sum_even());
         assert!(end_pos.is_sum_even());
 
@@ -726,29 +750,39 @@ impl AStarModel {
         end_directions.insert(Direction::Left);
         end_directions.insert(Direction::Right);
 
-        for end_direction in end_directions {
-            if end_direction == current_direction {
-                continue; // skip the current direction
-            }
-            if end_direction == current_direction.opposite() {
-                continue; // skip the opposite direction
+        for end in self.end.points.iter() {
+            if Direction::from_points(start_pos, *end).is_ok() {
+                continue; // coincides with or is aligned with the start position
             }
-            // assert_ne!(end_direction, current_direction);
-            // assert_ne!(end_direction, end_direction.opposite());
-            if let Some(intersection) = self.line_intersection_infinite(
-                start_pos,
-                end_pos,
-                self.end,
-                self.end + end_direction.to_fixed_vec2(FixedPoint::DELTA),
-            ) {
-                // assert!(intersection.is_sum_even());
-                let dx = intersection.x - start_pos.x;
-                let dy = intersection.y - start_pos.y;
-                let distance = FixedPoint::max(dx.abs(), dy.abs());
-                assert!(distance != FixedPoint::ZERO, "Distance should not be zero");
-                if distance < min_distance {
-                    min_distance = distance;
-                    best_intersection = Some(intersection);
+            for end_direction in end_directions.iter().copied() {
+                if end_direction == current_direction {
+                    continue; // skip the current direction
+                }
+                if end_direction == current_direction.opposite() {
+                    continue; // skip the opposite direction
+                }
+                if !self.end.allows_direction(end_direction) {
+                    continue; // the last move would reach the pad against its exit directions
+                }
+                if let Some(intersection) = self.line_intersection_infinite(
+                    start_pos,
+                    end_pos,
+                    *end,
+                    *end + end_direction.to_fixed_vec2(FixedPoint::DELTA),
+                ) {
+                    let dx = intersection.x - start_pos.x;
+                    let dy = intersection.y - start_pos.y;
+                    let distance = FixedPoint::max(dx.abs(), dy.abs());
+                    if distance == FixedPoint::ZERO
+                        || intersection == *end
+                        || !self.end.allows_segment(intersection, *end)
+                    {
+                        continue;
+                    }
+                    if distance < min_distance {
+                        min_distance = distance;
+                        best_intersection = Some(intersection);
+                    }
                 }
             }
         }
@@ -956,28 +990,28 @@ impl AStarModel {
             let renderables = astar_node.to_renderables(
                 self.trace_width,
                 self.trace_clearance,
-                self.via_diameter,
+                &self.via_types,
                 color,
             );
             render_model.trace_shape_renderables.extend(renderables);
         }
         // render the start and end nodes
-        let start_renderable = ShapeRenderable {
+        let start_renderables = self.start.points.iter().map(|position| ShapeRenderable {
             shape: PrimShape::Circle(CircleShape {
-                position: self.start.to_float(),
+                position: position.to_float(),
                 diameter: self.trace_width,
             }),
             color: [0.0, 0.0, 1.0, 1.0], // blue start node
-        };
-        let end_renderable = ShapeRenderable {
+        });
+        let end_renderables = self.end.points.iter().map(|position| ShapeRenderable {
             shape: PrimShape::Circle(CircleShape {
-                position: self.end.to_float(),
+                position: position.to_float(),
                 diameter: self.trace_width,
             }),
             color: [0.0, 1.0, 0.0, 1.0], // green end node
-        };
-        render_model.other_shape_renderables.push(start_renderable);
-        render_model.other_shape_renderables.push(end_renderable);
+        });
+        render_model.other_shape_renderables.extend(start_renderables);
+        render_model.other_shape_renderables.extend(end_renderables);
         render_model
     }
 
@@ -1104,30 +1138,38 @@ impl AStarModel {
     pub fn run(
         &self,
         pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+        cancellation_token: &CancellationToken,
     ) -> Result<AStarResult, String> {
         println!("Running A*");
         SAMPLE_CNT.fetch_add(1, Ordering::SeqCst);
         println!("Sample count: {}", SAMPLE_CNT.load(Ordering::SeqCst));
-        assert!(self.start.is_sum_even());
-        assert!(self.end.is_sum_even());
-        assert!(!self.start.is_x_odd_y_odd());
-        assert!(!self.end.is_x_odd_y_odd());
+        for position in self.start.points.iter().chain(self.end.points.iter()) {
+            assert!(position.is_sum_even());
+            assert!(!position.is_x_odd_y_odd());
+        }
 
         // frontier is a min heap
         let mut frontier: BinaryHeap<BinaryHeapItem<Reverse<NotNan<f64>>, Rc<AstarNode>>> =
             BinaryHeap::new();
 
-        let start_estimated_cost =
-            octile_distance_fixed(self.start, self.end) * ESTIMATE_COEFFICIENT;
-        for layer in self.start_layers.get_iter(self.num_layers) {
+        for (position, layer) in self.start.points.iter().flat_map(|position| {
+            self.start_layers
+                .get_iter(self.num_layers)
+                .filter(|layer| self.routing_constraints.allows_layer(*layer))
+                .map(move |layer| (*position, layer))
+        }) {
+            let start_actual_cost = self.start.landing_cost(position);
+            let start_estimated_cost = self.end.estimated_cost(position) * ESTIMATE_COEFFICIENT;
             let start_node = AstarNode {
-                position: self.start,
+                position,
                 layer,
+                via_count: 0,
+                via_type: None,
                 direction: AStarNodeDirection::None, // no direction for the start node
-                actual_cost: 0.0,
+                actual_cost: start_actual_cost,
                 actual_length: 0.0, // no length for the start node
                 estimated_cost: start_estimated_cost,
-                total_cost: start_estimated_cost,
+                total_cost: start_actual_cost + start_estimated_cost,
                 prev_node: None, // no previous node for the start node
             };
             frontier.push(BinaryHeapItem {
@@ -1143,7 +1185,7 @@ impl AStarModel {
             let item = frontier.pop().unwrap();
 
             let current_node = item.value.clone();
-            if current_node.position == self.end {
+            if self.end.contains(current_node.position) && self.in_layers(current_node.layer, self.end_layers) {
                 frontier.push(item); // push the current node back to the frontier, so that it can be displayed
 
                 self.display_when_necessary(
@@ -1156,11 +1198,17 @@ impl AStarModel {
                 let trace_path = current_node.to_trace_path(
                     self.trace_width,
                     self.trace_clearance,
-                    self.via_diameter,
+                    &self.via_types,
                 );
+                // shortcuts must keep to the exit directions of the pads as well
                 let check_collision_for_trace =
                     |start: FixedVec2, end: FixedVec2, width: f32, clearance: f32, layer: usize| {
                         self.check_collision_for_trace(start, end, width, clearance, layer)
+                            || !self.start.allows_segment(start, end)
+                            || !self.end.allows_segment(start, end)
+                            || self.congestion_costs.as_ref().is_some_and(|congestion_costs| {
+                                congestion_costs.crosses_other_nets(start, end, width / 2.0 + clearance / 2.0, layer)
+                            })
                     };
                 // let check_collision_for_via =
                 //     |position: FixedVec2, diameter: f32, clearance: f32, min_layer: usize, max_layer: usize| {
@@ -1178,14 +1226,38 @@ impl AStarModel {
                 }
                 println!();
                 self.display_final_trace(pcb_render_model.clone(), &trace_path, CommandFlag::AstarInOut);         
-                let trace_path = optimize_path(
-                    &trace_path,
-                    &check_collision_for_trace,
-                    //  &check_collision_for_via,
-                    self.trace_width,
-                    self.trace_clearance,
-                    self.via_diameter,
-                );    
+                // the segments leaving and reaching pads with exit directions are kept as found,
+                // the optimization only straightens the trace between them
+                let anchors = &trace_path.anchors.0;
+                let first = if self.start.exit_directions.is_empty() { 0 } else { 1 };
+                let last = if self.end.exit_directions.is_empty() { anchors.len() - 1 } else { anchors.len().saturating_sub(2) };
+                let trace_path = if first < last {
+                    let inner_trace_path = TracePath::from_anchors(
+                        TraceAnchors(anchors[first..=last].to_vec()),
+                        self.trace_width,
+                        self.trace_clearance,
+                        &self.via_types,
+                    );
+                    let inner_trace_path = optimize_path(
+                        &inner_trace_path,
+                        &check_collision_for_trace,
+                        //  &check_collision_for_via,
+                        self.trace_width,
+                        self.trace_clearance,
+                        &self.via_types,
+                    );
+                    let mut optimized_anchors = anchors[..first].to_vec();
+                    optimized_anchors.extend(inner_trace_path.anchors.0);
+                    optimized_anchors.extend(anchors[last + 1..].iter().cloned());
+                    TracePath::from_anchors(
+                        TraceAnchors(optimized_anchors),
+                        self.trace_width,
+                        self.trace_clearance,
+                        &self.via_types,
+                    )
+                } else {
+                    trace_path
+                };
                 println!("Finished one iteration of optimization");
                 self.display_final_trace(pcb_render_model.clone(), &trace_path, CommandFlag::AstarInOut);                
                 return Ok(AStarResult { trace_path });
@@ -1195,6 +1267,7 @@ impl AStarModel {
             let current_key = AstarNodeKey {
                 position: current_node.position,
                 layer: current_node.layer,
+                via_count: self.via_count_key(current_node.via_count),
             };
             if visited.contains(&current_key) {
                 continue; // already visited this node
@@ -1203,7 +1276,10 @@ impl AStarModel {
             trial_count += 1;
             if trial_count > MAX_TRIALS {
                 self.display_when_necessary(pcb_render_model.clone(), &frontier, CommandFlag::Auto);
-                return Err("A* search exceeded maximum trials".to_string());
+                return Err(self.failure_reason("A* search exceeded maximum trials"));
+            }
+            if cancellation_token.is_cancelled() {
+                return Err(cancellation_token.reason());
             }
             visited.insert(current_key.clone());
             // expand
@@ -1211,7 +1287,7 @@ impl AStarModel {
             // new:
             // hoist the closure out of the directions loop for the aligned_with_end condition
             let mut try_push_node_to_frontier =
-                |direction: AStarNodeDirection, end_position: FixedVec2, end_layer: usize| {
+                |direction: AStarNodeDirection, end_position: FixedVec2, end_layer: usize, via_type: Option<usize>| {
                     assert!(
                         !matches!(direction, AStarNodeDirection::None),
                         "Direction should not be None"
@@ -1229,9 +1305,32 @@ impl AStarModel {
                         end_position.x, end_position.y, direction
                     );
 
+                    if !self.routing_constraints.allows_layer(end_layer) {
+                        return;
+                    }
+                    // planar moves leaving the start pad or reaching the end pad keep to their exit directions
+                    if matches!(direction, AStarNodeDirection::Planar(_))
+                        && !(self.start.allows_segment(current_node.position, end_position)
+                            && self.end.allows_segment(current_node.position, end_position))
+                    {
+                        return;
+                    }
+                    let via_count = if let AStarNodeDirection::Vertical { .. } = direction {
+                        current_node.via_count + 1
+                    } else {
+                        current_node.via_count
+                    };
+                    if self
+                        .routing_constraints
+                        .max_vias
+                        .is_some_and(|max_vias| via_count > max_vias)
+                    {
+                        return;
+                    }
                     let astar_node_key = AstarNodeKey {
                         position: end_position,
                         layer: end_layer,
+                        via_count: self.via_count_key(via_count),
                     };
                     // check if the new position is already visited
                     if visited.contains(&astar_node_key) {
@@ -1239,19 +1338,48 @@ impl AStarModel {
                     }
                     // let length: f64 = (direction.to_fixed_vec2().length() * length).to_num();
                     let length: f64 = (end_position - current_node.position).length().to_num();
-                    let via_cost = if let AStarNodeDirection::Vertical { .. } = direction {
-                        VIA_COST // vertical movement has a via cost
-                    } else {
-                        0.0 // no via cost for planar movements
+                    // a via blocks every layer it spans, so the cost grows with the spanned layers
+                    let via_cost = match via_type {
+                        Some(via_type) => {
+                            VIA_COST * self.via_types[via_type].num_spanned_layers() as f64 / self.num_layers as f64
+                        }
+                        None => 0.0, // no via cost for planar movements
+                    };
+                    let congestion_cost = match (&self.congestion_costs, via_type) {
+                        (None, _) => 0.0,
+                        (Some(congestion_costs), Some(via_type)) => {
+                            let via_type = &self.via_types[via_type];
+                            congestion_costs.via_cost(end_position, via_type.min_layer, via_type.max_layer, via_type.diameter)
+                        }
+                        (Some(congestion_costs), None) => {
+                            congestion_costs.segment_cost(current_node.position, end_position, end_layer)
+                        }
                     };
-                    let actual_cost = current_node.actual_cost + length + via_cost;
+                    let delta = end_position - current_node.position;
+                    let off_direction_cost =
+                        self.layer_directions.off_direction_cost(end_layer, delta.x.to_num(), delta.y.to_num());
+                    let reaches_end = self.end.contains(end_position) && self.in_layers(end_layer, self.end_layers);
+                    let landing_cost = if reaches_end { self.end.landing_cost(end_position) } else { 0.0 };
+                    let actual_cost =
+                        current_node.actual_cost + length + via_cost + congestion_cost + off_direction_cost + landing_cost;
                     let actual_length = current_node.actual_length + length;
-                    let estimated_cost =
-                        octile_distance_fixed(end_position, self.end) * ESTIMATE_COEFFICIENT;
+                    // the straight distance to the end is a lower bound of the remaining length
+                    if self.routing_constraints.max_length.is_some_and(|max_length| {
+                        actual_length + self.end.distance(end_position) > max_length as f64
+                    }) {
+                        return;
+                    }
+                    let estimated_cost = if reaches_end {
+                        0.0 // the landing cost is already paid
+                    } else {
+                        self.end.estimated_cost(end_position) * ESTIMATE_COEFFICIENT
+                    };
                     let total_cost = actual_cost + estimated_cost;
                     let new_node = AstarNode {
                         position: end_position,
                         layer: end_layer,
+                        via_count,
+                        via_type,
                         direction,
                         actual_cost,
                         actual_length,
@@ -1278,29 +1406,25 @@ impl AStarModel {
             let mut condition_count = 0;
 
             // attempt a planar movement to reach the end
-            let end_direction = self.is_aligned_with_end(current_node.position, current_node.layer);
-            if let Some(end_direction) = end_direction {
-                assert_ne!(current_node.position, self.end, "assert 3");
+            for (end_direction, end) in self.is_aligned_with_end(current_node.position, current_node.layer) {
+                assert_ne!(current_node.position, end, "assert 3");
                 if !self.check_collision_for_trace(
                     current_node.position,
-                    self.end,
+                    end,
                     self.trace_width,
                     self.trace_clearance,
                     current_node.layer,
                 ) {
-                    // println!(
-                    //     "is_aligned_with_end: ({}, {}) ({}, {})",
-                    //     current_node.position.x, current_node.position.y, self.end.x, self.end.y
-                    // );
                     assert!(
-                        Direction::from_points(current_node.position, self.end).unwrap().unwrap()
+                        Direction::from_points(current_node.position, end).unwrap().unwrap()
                             == end_direction
                     );
                     condition_count = condition_count + 1;
                     try_push_node_to_frontier(
                         AStarNodeDirection::Planar(end_direction),
-                        self.end,
+                        end,
                         current_node.layer,
+                        None,
                     );
                     println!("Successfully pushed an end node to the frontier");
                 }else{
@@ -1309,44 +1433,44 @@ impl AStarModel {
             }
 
             // this will call try_push_node_to_frontier multiple times
-            let mut try_place_vias = |position: FixedVec2,
-                                      via_diameter: f32,
-                                      clearance: f32,
-                                      layer: usize| {
-                if self.check_collision_for_via(position, via_diameter, clearance, layer) {
-                    return;
-                }
-                for lower_layer in (0..layer).rev() {
-                    if self.check_collision_for_via(position, via_diameter, clearance, lower_layer)
-                    {
-                        break;
+            // each layer is reached with the cheapest via type that spans it and is clear on every layer it spans
+            let mut try_place_vias = |position: FixedVec2, clearance: f32, layer: usize| {
+                let mut reached_layers: Vec<usize> = vec![layer];
+                for (index, via_type) in self.via_types.iter().enumerate() {
+                    if !via_type.spans(layer, layer) {
+                        continue;
                     }
-                    try_push_node_to_frontier(
-                        AStarNodeDirection::Vertical { from_layer: layer },
-                        position,
-                        lower_layer,
-                    );
-                }
-                for upper_layer in (layer + 1)..self.num_layers {
-                    if self.check_collision_for_via(position, via_diameter, clearance, upper_layer)
-                    {
-                        break;
+                    if !self.via_obstacles.allows(position, via_type) {
+                        if let Some(blocking_traces) = &self.blocking_traces {
+                            blocking_traces.record_drills(position, via_type);
+                        }
+                        continue;
+                    }
+                    if (via_type.min_layer..=via_type.max_layer).any(|spanned_layer| {
+                        self.check_collision_for_via(position, via_type.diameter, clearance, spanned_layer)
+                    }) {
+                        continue;
+                    }
+                    for to_layer in via_type.min_layer..=via_type.max_layer {
+                        if reached_layers.contains(&to_layer) {
+                            continue;
+                        }
+                        reached_layers.push(to_layer);
+                        try_push_node_to_frontier(
+                            AStarNodeDirection::Vertical { from_layer: layer },
+                            position,
+                            to_layer,
+                            Some(index),
+                        );
                     }
-                    try_push_node_to_frontier(
-                        AStarNodeDirection::Vertical { from_layer: layer },
-                        position,
-                        upper_layer,
-                    );
                 }
             };
-            // new: try place a via if the current node is at a grid point
-            if self.is_grid_point(&current_node.position) {
-                try_place_vias(
-                    current_node.position,
-                    self.via_diameter,
-                    self.trace_clearance,
-                    current_node.layer,
-                );
+            // new: try place a via if the current node is at a grid point,
+            // one via per position, so that each layer change of the trace has one via type
+            if self.is_grid_point(&current_node.position)
+                && !matches!(current_node.direction, AStarNodeDirection::Vertical { .. })
+            {
+                try_place_vias(current_node.position, self.trace_clearance, current_node.layer);
             }
 
             // process grid points or one-step-to-grid-points
@@ -1381,24 +1505,24 @@ impl AStarModel {
                     AStarNodeDirection::Planar(direction),
                     end_position,
                     current_node.layer,
+                    None,
                 );
-                if let None = self.is_aligned_with_end(current_node.position, current_node.layer) {
-                    if let Some(intersection) = self.get_intersection_with_end_alignments(
-                        current_node.position,
-                        end_position,
+                if let Some(intersection) = self.get_intersection_with_end_alignments(
+                    current_node.position,
+                    end_position,
+                    current_node.layer,
+                ) {
+                    condition_count = condition_count + 1;
+                    assert!(
+                        Direction::from_points(current_node.position, end_position).unwrap().unwrap()
+                            == direction
+                    );
+                    try_push_node_to_frontier(
+                        AStarNodeDirection::Planar(direction),
+                        intersection,
                         current_node.layer,
-                    ) {
-                        condition_count = condition_count + 1;
-                        assert!(
-                            Direction::from_points(current_node.position, end_position).unwrap().unwrap()
-                                == direction
-                        );
-                        try_push_node_to_frontier(
-                            AStarNodeDirection::Planar(direction),
-                            intersection,
-                            current_node.layer,
-                        );
-                    }
+                        None,
+                    );
                 }
             }
 
@@ -1436,22 +1560,43 @@ impl AStarModel {
                     AStarNodeDirection::Planar(direction),
                     end_position,
                     current_node.layer,
+                    None,
                 );
-                if let None = self.is_aligned_with_end(current_node.position, current_node.layer) {
-                    if let Some(intersection) = self.get_intersection_with_end_alignments(
+                if let Some(intersection) = self.get_intersection_with_end_alignments(
+                    current_node.position,
+                    end_position,
+                    current_node.layer,
+                ) {
+                    condition_count = condition_count + 1;
+                    assert!(
+                        Direction::from_points(current_node.position, end_position).unwrap().unwrap()
+                            == direction
+                    );
+                    try_push_node_to_frontier(
+                        AStarNodeDirection::Planar(direction),
+                        intersection,
+                        current_node.layer,
+                        None,
+                    );
+                }
+            }
+
+            // a pad with exit directions is always left along them, the moves above may all run across them
+            if self.start.contains(current_node.position) && !self.start.exit_directions.is_empty() {
+                for direction in self.start.exit_directions.iter().copied() {
+                    let end_position = Self::exit_position(&self.start, current_node.position, direction);
+                    if let Some(end_position) = self.clamp_by_collision(
                         current_node.position,
                         end_position,
                         current_node.layer,
                     ) {
-                        condition_count = condition_count + 1;
-                        assert!(
-                            Direction::from_points(current_node.position, end_position).unwrap().unwrap()
-                                == direction
-                        );
+                        current_node_handled = true;
+                        condition_count += 1;
                         try_push_node_to_frontier(
                             AStarNodeDirection::Planar(direction),
-                            intersection,
+                            end_position,
                             current_node.layer,
+                            None,
                         );
                     }
                 }
@@ -1482,6 +1627,7 @@ impl AStarModel {
                             AStarNodeDirection::Planar(direction),
                             end_position,
                             current_node.layer,
+                            None,
                         );
                         found_point = true;
                         break;
@@ -1517,6 +1663,7 @@ impl AStarModel {
                             AStarNodeDirection::Planar(direction),
                             end_position,
                             current_node.layer,
+                            None,
                         );
                     } else {
                         // remove the tried direction
@@ -1545,6 +1692,7 @@ impl AStarModel {
                                     AStarNodeDirection::Planar(direction),
                                     end_position,
                                     current_node.layer,
+                                    None,
                                 );
                                 found_point = true;
                                 break; // only try one direction
@@ -1573,7 +1721,7 @@ impl AStarModel {
             ); // display the initial state of the frontier
         }
         self.display_when_necessary(pcb_render_model.clone(), &frontier, CommandFlag::Auto);
-        Err("No path found".to_string()) // no path found
+        Err(self.failure_reason("No path found")) // no path found
     }
 }
 
@@ -1581,11 +1729,14 @@ impl AStarModel {
 pub struct AstarNodeKey {
     pub position: FixedVec2,
     pub layer: usize,
+    pub via_count: usize, // always 0 when the number of vias is not limited
 }
 
 pub struct AstarNode {
     pub position: FixedVec2,
     pub layer: usize,
+    pub via_count: usize, // vias from the start node to this node
+    pub via_type: Option<usize>, // index into the via types of the model, for nodes reached with a via
     pub direction: AStarNodeDirection, // the direction from the previous node to this node
     pub actual_cost: f64,              // the actual cost to reach this node from the start node
     pub actual_length: f64,
@@ -1661,7 +1812,7 @@ impl AstarNode {
         self: Rc<Self>,
         width: f32,
         clearance: f32,
-        via_diameter: f32,
+        via_types: &[ViaType],
     ) -> TracePath {
         let mut current_node: Option<Rc<AstarNode>> = Some(self.clone());
         let mut next_node: Option<Rc<AstarNode>> = None;
@@ -1681,6 +1832,7 @@ impl AstarNode {
                         position: node.position,
                         start_layer: node.layer,
                         end_layer: pending_anchor.end_layer,
+                        via_type: pending_anchor.via_type,
                     })
                 } else {
                     anchors.push(pending_anchor);
@@ -1688,6 +1840,7 @@ impl AstarNode {
                         position: node.position,
                         start_layer: node.layer,
                         end_layer: node.layer,
+                        via_type: node.via_type,
                     })
                 }
             } else {
@@ -1695,6 +1848,7 @@ impl AstarNode {
                     position: node.position,
                     start_layer: node.layer,
                     end_layer: node.layer,
+                    via_type: node.via_type,
                 })
             };
             next_node = current_node.clone();
@@ -1728,14 +1882,7 @@ impl AstarNode {
             segments.push(segment);
             if start_anchor.start_layer != start_anchor.end_layer {
                 // if the start and end layers are different, we need to add a via
-                let via = Via {
-                    position: start_anchor.position,
-                    clearance,
-                    diameter: via_diameter,
-                    min_layer: usize::min(start_anchor.start_layer, start_anchor.end_layer),
-                    max_layer: usize::max(start_anchor.start_layer, start_anchor.end_layer),
-                };
-                vias.push(via);
+                vias.push(Via::from_anchor(start_anchor, clearance, via_types));
             }
         }
         let anchors = TraceAnchors(anchors);
@@ -1754,7 +1901,7 @@ impl AstarNode {
         &self,
         width: f32,
         clearance: f32,
-        via_diameter: f32,
+        via_types: &[ViaType],
         color: [f32; 3],
     ) -> Vec<RenderableBatch> {
         // This function is used to convert the AstarNode to a TraceSegment
@@ -1806,6 +1953,10 @@ impl AstarNode {
             }
             AStarNodeDirection::Vertical { from_layer } => {
                 // draw a via
+                let via_diameter = self
+                    .via_type
+                    .and_then(|via_type| via_types.get(via_type))
+                    .map_or(0.0, |via_type| via_type.diameter);
                 let shape_renderable = ShapeRenderable {
                     shape: PrimShape::Circle(CircleShape {
                         position: self.position.to_float(),
@@ -1,5 +1,5 @@
 use std::{
-    collections::{BinaryHeap, HashMap},
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet},
     rc::Rc,
     sync::{Arc, Mutex},
 };
@@ -9,9 +9,12 @@ use shared::{
     binary_heap_item::BinaryHeapItem,
     pcb_problem::{ConnectionID, FixedTrace, PcbProblem},
     pcb_render_model::PcbRenderModel,
+    trace_path::TraceAnchors,
 };
 
-use crate::{bayesian_backtrack_algo::TraceCache, proba_model::{ProbaModel, ProbaTrace, Traces}};
+use crate::{bayesian_backtrack_algo::TraceCache, cancellation::CancellationToken, proba_model::{ProbaModel, ProbaTrace, Traces}, router_config::RouterConfig, shove::{shove_aside, ShoveConfig}};
+
+type RankedCandidate = BinaryHeapItem<NotNan<f64>, Rc<ProbaTrace>>;
 
 #[derive(Debug, Clone)]
 pub struct BacktrackNode {
@@ -19,6 +22,9 @@ pub struct BacktrackNode {
     pub fixed_traces: HashMap<ConnectionID, FixedTrace>,
     pub fix_sequence: Vec<ConnectionID>, // The sequence of connections that have been fixed in this node
     pub prob_up_to_date: bool, // Whether the probabilistic model is up to date
+    pub branch_mass: f64, // The posterior mass of this branch relative to fixing the most probable candidate of every connection
+    pub best_posteriors: HashMap<ConnectionID, f64>, // The posterior of the most probable candidate of each connection
+    pub rejected_traces: BTreeSet<(ConnectionID, TraceAnchors)>, // Candidates that collided or led to a dead end, left out when the node is re-sampled
 }
 
 impl BacktrackNode {
@@ -27,6 +33,7 @@ impl BacktrackNode {
         let mut remaining_trace_candidates: BinaryHeap<
             BinaryHeapItem<NotNan<f64>, Rc<ProbaTrace>>,
         > = BinaryHeap::new();
+        let mut best_posteriors: HashMap<ConnectionID, f64> = HashMap::new();
         for (connection_id, traces) in proba_model.connection_to_traces.iter() {
             match traces {
                 Traces::Fixed(fixed_trace) => {
@@ -37,6 +44,8 @@ impl BacktrackNode {
                         let posterior = proba_trace.get_posterior_with_fallback();
                         let not_nan_proba =
                             NotNan::new(posterior).expect("Probability must be non-NaN");
+                        let best_posterior = best_posteriors.entry(*connection_id).or_insert(0.0);
+                        *best_posterior = f64::max(*best_posterior, posterior);
                         remaining_trace_candidates.push(BinaryHeapItem {
                             key: not_nan_proba,
                             value: proba_trace.clone(),
@@ -50,6 +59,23 @@ impl BacktrackNode {
             fixed_traces,
             fix_sequence: proba_model.fix_sequence.clone(), // Use the fixed sequence from the probabilistic model
             prob_up_to_date: true, // Initially, the probabilistic model is up to date
+            branch_mass: 1.0,
+            best_posteriors,
+            rejected_traces: BTreeSet::new(),
+        }
+    }
+    fn reject(&mut self, proba_trace: &ProbaTrace) {
+        self.rejected_traces
+            .insert((proba_trace.connection_id, proba_trace.trace_path.anchors.clone()));
+    }
+    /// remembers the candidate that led from this node to the child, after the child turned out to be a dead end
+    pub fn reject_child(&mut self, child: &BacktrackNode) {
+        let Some(connection_id) = child.fix_sequence.get(self.fix_sequence.len()) else {
+            return;
+        };
+        if let Some(fixed_trace) = child.fixed_traces.get(connection_id) {
+            self.rejected_traces
+                .insert((*connection_id, fixed_trace.trace_path.anchors.clone()));
         }
     }
     fn fix_trace(&mut self, connection_id: ConnectionID, fixed_trace: FixedTrace) {
@@ -72,15 +98,22 @@ impl BacktrackNode {
     }
     /// If an attemp fails, return none; it will pop the priority queue in both scenarios
     /// assume there are still candidates in the priority queue
+    /// candidates that would leave less than min_branch_mass to the new branch are skipped
+    /// a candidate that collides with fixed traces of other nets is fixed when they can be shoved aside, the rigid ones stay put
     pub fn try_fix_top_k_ranked_trace(
         &mut self,
         display_and_block: impl Fn(&BacktrackNode),
         k: usize,
+        min_branch_mass: f64,
+        problem: &PcbProblem,
+        shove: &ShoveConfig,
+        rigid: &HashSet<ConnectionID>,
     ) -> Option<Self> {
         // for self, peek from the priority queue
         // if succeed, remove all traces from the same connection, and generate a new node with the same priority queue and a fixed trace
         // if fail, return error
-        let mut result_candidate: Option<BinaryHeapItem<NotNan<f64>, Rc<ProbaTrace>>> = None;
+        let mut result_candidate: Option<(RankedCandidate, f64)> = None; // with the branch mass it leaves
+        let mut shoved_traces: HashMap<ConnectionID, FixedTrace> = HashMap::new(); // the fixed traces moved for it
         for i in 0..k {
             let top_ranked_candidate = self.remaining_trace_candidates.pop();
             let top_ranked_candidate = match top_ranked_candidate {
@@ -92,6 +125,20 @@ impl BacktrackNode {
             };
             let top_ranked_trace_path = &top_ranked_candidate.value.trace_path;
             let top_ranked_trace_net = &top_ranked_candidate.value.net_name;
+            let best_posterior = self.best_posteriors[&top_ranked_candidate.value.connection_id];
+            let branch_mass = if best_posterior > 0.0 {
+                self.branch_mass * top_ranked_candidate.key.into_inner() / best_posterior
+            } else {
+                self.branch_mass
+            };
+            if branch_mass < min_branch_mass {
+                println!(
+                    "Trial {}: Top ranked trace {} leaves a branch mass of {:.4}, below {}, skipping it",
+                    i, top_ranked_trace_net.0, branch_mass, min_branch_mass
+                );
+                self.reject(&top_ranked_candidate.value);
+                continue;
+            }
             // check if the trace collides with any fixed trace
             let filtered_fixed_traces: Vec<_> = self
                 .fixed_traces
@@ -121,12 +168,24 @@ impl BacktrackNode {
                     break;
                 }
             }
-            if !collision_found {
-                result_candidate = Some(top_ranked_candidate);
+            if collision_found {
+                let candidate_trace = FixedTrace {
+                    net_name: top_ranked_trace_net.clone(),
+                    connection_id: top_ranked_candidate.value.connection_id,
+                    trace_path: top_ranked_trace_path.clone(),
+                };
+                if let Some(shoved) = shove_aside(problem, &self.fixed_traces, rigid, &candidate_trace, shove) {
+                    shoved_traces = shoved;
+                    result_candidate = Some((top_ranked_candidate, branch_mass));
+                    break;
+                }
+                self.reject(&top_ranked_candidate.value);
+            } else {
+                result_candidate = Some((top_ranked_candidate, branch_mass));
                 break;
             }
         }
-        if let Some(result_candidate) = result_candidate {
+        if let Some((result_candidate, branch_mass)) = result_candidate {
             // If it does not collide, we can fix this trace
             let connection_id = result_candidate.value.connection_id;
             // Create a new fixed trace
@@ -137,7 +196,9 @@ impl BacktrackNode {
             };
             // delete all trace candidates for this connection in the new node
             let mut new_node = self.clone();
+            new_node.fixed_traces.extend(shoved_traces);
             new_node.fix_trace(connection_id, fixed_trace);
+            new_node.branch_mass = branch_mass;
             Some(new_node) // Return the new node with the fixed trace
         } else {
             None // No more candidates to fix
@@ -149,23 +210,34 @@ impl BacktrackNode {
         fix_sequence: Vec<ConnectionID>,
         pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
         trace_cache: &mut TraceCache,
+        config: &RouterConfig,
+        cancellation_token: &CancellationToken,
     ) -> Self {
-        let proba_model = ProbaModel::create_and_solve(problem, fixed_traces, fix_sequence, pcb_render_model, trace_cache);
+        let proba_model = ProbaModel::create_and_solve(problem, fixed_traces, fix_sequence, pcb_render_model, trace_cache, config, cancellation_token);
         BacktrackNode::from_proba_model(&proba_model)
     }
     /// if self is already up to date, return none
+    /// the branch mass and the rejected candidates of self are kept, the rejected candidates are not tried again
     pub fn try_update_proba_model(
         &mut self,
         problem: &PcbProblem,
         pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
         trace_cache: &mut TraceCache,
+        config: &RouterConfig,
+        cancellation_token: &CancellationToken,
     ) -> Result<(), String> {
         if self.prob_up_to_date {
             return Err("Probabilistic model is already up to date".to_string()); // If the probabilistic model is already up to date, do nothing
         }
         let fixed_traces = &self.fixed_traces;
         let fix_sequence = self.fix_sequence.clone();
-        let new_node = BacktrackNode::from_fixed_traces(problem, fixed_traces, fix_sequence, pcb_render_model, trace_cache);
+        let mut new_node = BacktrackNode::from_fixed_traces(problem, fixed_traces, fix_sequence, pcb_render_model, trace_cache, config, cancellation_token);
+        new_node.branch_mass = self.branch_mass;
+        new_node.rejected_traces = std::mem::take(&mut self.rejected_traces);
+        let rejected_traces = &new_node.rejected_traces;
+        new_node.remaining_trace_candidates.retain(|candidate| {
+            !rejected_traces.contains(&(candidate.value.connection_id, candidate.value.trace_path.anchors.clone()))
+        });
         *self = new_node; // Update self with the new node
         Ok(())
     }
@@ -7,16 +7,22 @@ use std::{
 
 use shared::{
     color_float3::ColorFloat3,
-    hyperparameters::{NUM_TOP_RANKED_TO_TRY, SAMPLE_CNT, UPDATE_PROBA_SKIP_STRIDE},
-    pcb_problem::{ConnectionID, NetName, PcbProblem, PcbSolution},
+    hyperparameters::{
+        BAYESIAN_MAX_BACKTRACKS, BAYESIAN_MIN_BRANCH_MASS, NUM_TOP_RANKED_TO_TRY, SAMPLE_CNT,
+        UPDATE_PROBA_SKIP_STRIDE,
+    },
+    pcb_problem::{ConnectionID, FixedTrace, NetName, PartialSolution, PcbProblem, PcbSolution},
     pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable, UpdatePcbRenderModel},
     prim_shape::PrimShape, trace_path::TracePath,
 };
 
 use crate::{
     backtrack_node::BacktrackNode,
+    best_partial_solution::BestPartialSolution,
     block_or_sleep,
+    cancellation::CancellationToken,
     command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES}, naive_backtrack_algo::naive_backtrack,
+    router_config::RouterConfig,
 };
 
 
@@ -24,12 +30,31 @@ pub struct TraceCache{
     pub traces: HashMap<ConnectionID, Vec<TracePath>>,
 }
 
+/// bounds the search of the bayesian backtracker, the naive backtracker takes over when it is used up
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktrackBudget {
+    pub max_backtracks: usize, // dead ends that re-sample or pop a node before falling back to the naive backtracker
+    pub min_branch_mass: f64, // branches less probable than this, relative to the most probable branch, are not explored
+}
+
+impl Default for BacktrackBudget {
+    fn default() -> Self {
+        BacktrackBudget {
+            max_backtracks: BAYESIAN_MAX_BACKTRACKS,
+            min_branch_mass: BAYESIAN_MIN_BRANCH_MASS,
+        }
+    }
+}
+
 
 pub fn bayesian_backtrack(
     pcb_problem: &PcbProblem,
     pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
     trace_cache: &mut TraceCache,
-) -> Result<PcbSolution, String> {
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+    config: &RouterConfig,
+    cancellation_token: &CancellationToken,
+) -> Result<PcbSolution, PartialSolution> {
     let connections = pcb_problem.nets.iter()
         .flat_map(|(_, net_info)| net_info.connections.keys().cloned())
         .collect::<Vec<_>>();
@@ -125,14 +150,27 @@ pub fn bayesian_backtrack(
         }
     }
 
+    // the prerouted traces are fixed in the first node, so the model only samples the other connections
+    let mut prerouted_sequence: Vec<ConnectionID> = prerouted_traces.keys().cloned().collect();
+    prerouted_sequence.sort();
+    // the prerouted traces are never shoved aside
+    let rigid_traces: HashSet<ConnectionID> = prerouted_traces.keys().cloned().collect();
     let first_node =
-        BacktrackNode::from_fixed_traces(pcb_problem, &HashMap::new(), Vec::new(), pcb_render_model.clone(), trace_cache);
+        BacktrackNode::from_fixed_traces(pcb_problem, prerouted_traces, prerouted_sequence, pcb_render_model.clone(), trace_cache, config, cancellation_token);
     // assume the first node has trace candidates
     node_stack.push(first_node);
 
     let mut heuristics: Option<Vec<ConnectionID>> = None;
+    let mut best_partial_solution = BestPartialSolution::new();
+    // the naive backtracker starts from the deepest fix sequence when the budget runs out
+    let mut deepest_fix_sequence: Vec<ConnectionID> = node_stack[0].fix_sequence.clone();
+    let mut num_backtracks: usize = 0;
 
     while node_stack.len() > 0 {
+        if cancellation_token.is_cancelled() {
+            SAMPLE_CNT.store(0, Ordering::SeqCst);
+            return Err(best_partial_solution.into_partial_solution(pcb_problem, cancellation_token.reason()));
+        }
         print_current_stack(&node_stack);
         display_when_necessary(
             node_stack.last().unwrap(),
@@ -147,6 +185,8 @@ pub fn bayesian_backtrack(
             let fixed_traces = top_node.fixed_traces.clone();
             let solution = PcbSolution {
                 determined_traces: fixed_traces,
+                copper_pours: Vec::new(),
+                uncoupled_diff_pairs: Vec::new(),
                 scale_down_factor: pcb_problem.scale_down_factor,
             };
             println!("Successfully found a solution with sample count {}", shared::hyperparameters::SAMPLE_CNT.load(Ordering::SeqCst));
@@ -160,7 +200,7 @@ pub fn bayesian_backtrack(
             display_when_necessary(node, pcb_problem, pcb_render_model.clone());
         };
         let new_node =
-            top_node.try_fix_top_k_ranked_trace(display_and_block_closure, NUM_TOP_RANKED_TO_TRY);
+            top_node.try_fix_top_k_ranked_trace(display_and_block_closure, NUM_TOP_RANKED_TO_TRY, config.backtrack_budget.min_branch_mass, pcb_problem, &config.shove, &rigid_traces);
         if new_node.is_some(){
             println!(
                 "Successfully fixed the top ranked trace, pushing new node onto the stack"
@@ -168,36 +208,90 @@ pub fn bayesian_backtrack(
             // assert!(new_node.prob_up_to_date, "New node must be up to date");
             let mut new_node = new_node.unwrap();
             if node_stack.len() % UPDATE_PROBA_SKIP_STRIDE == 0 {
-                let result = new_node.try_update_proba_model(pcb_problem, pcb_render_model.clone(), trace_cache);
+                let result = new_node.try_update_proba_model(pcb_problem, pcb_render_model.clone(), trace_cache, config, cancellation_token);
                 if let Err(err) = result {
                     println!("Failed to update the probabilistic model: {}", err);
-                    panic!("Failed to update the probabilistic model");
+                    best_partial_solution.consider(&new_node.fixed_traces);
+                    SAMPLE_CNT.store(0, Ordering::SeqCst);
+                    return Err(best_partial_solution.into_partial_solution(
+                        pcb_problem,
+                        format!("Failed to update the probabilistic model: {}", err),
+                    ));
                 }
             }
+            best_partial_solution.consider(&new_node.fixed_traces);
+            if new_node.fix_sequence.len() > deepest_fix_sequence.len() {
+                deepest_fix_sequence = new_node.fix_sequence.clone();
+            }
             node_stack.push(new_node);
             continue; // Continue to the next iteration
-        }else{
-            let mut connections_set: HashSet<ConnectionID> = connections.iter().cloned().collect();
-            let mut temp_heuristics: Vec<ConnectionID> = Vec::new();
-            assert!(top_node.fixed_traces.len() == top_node.fix_sequence.len(), "Fixed traces and fix sequence must have the same length");
-            for connection_id in top_node.fix_sequence.iter(){
-                temp_heuristics.push(*connection_id);
-                connections_set.remove(connection_id);
+        }
+        // the top node is a dead end
+        if num_backtracks >= config.backtrack_budget.max_backtracks {
+            println!(
+                "Bayesian backtrack used up its budget of {} backtracks, bringing the heuristics to naive backtrack",
+                config.backtrack_budget.max_backtracks
+            );
+            heuristics = Some(ordering_heuristics(&connections, &deepest_fix_sequence));
+            break;
+        }
+        num_backtracks += 1;
+        if !top_node.prob_up_to_date {
+            // traces were fixed since the candidates were sampled, the failing connections may fit with new ones
+            println!("Dead end at the top node, re-sampling the candidates of the unfixed connections around the fixed traces");
+            let result = top_node.try_update_proba_model(pcb_problem, pcb_render_model.clone(), trace_cache, config, cancellation_token);
+            if let Err(err) = result {
+                // a budgeted or cancelled re-sample ends the search with the best traces found so far
+                println!("Failed to update the probabilistic model: {}", err);
+                SAMPLE_CNT.store(0, Ordering::SeqCst);
+                return Err(best_partial_solution.into_partial_solution(
+                    pcb_problem,
+                    format!("Failed to update the probabilistic model: {}", err),
+                ));
             }
-            for connection_id in connections_set.iter() {
-                temp_heuristics.push(*connection_id);
+            continue;
+        }
+        let dead_end = node_stack.pop().unwrap();
+        let dead_end_index = node_stack.len();
+        match node_stack.last_mut() {
+            Some(parent) => {
+                println!(
+                    "Dead end at node {}, backtracking ({} of {} backtracks)",
+                    dead_end_index,
+                    num_backtracks,
+                    config.backtrack_budget.max_backtracks
+                );
+                parent.reject_child(&dead_end);
             }
-            assert!(temp_heuristics.len() == connections.len(), "Heuristics must contain all connections");
-            println!("Failed to find a solution in Bayesian backtrack, bringing the heuristics to naive backtrack");
-            heuristics = Some(temp_heuristics);
-            break;
-        }       
+            None => {
+                println!("Bayesian backtrack exhausted its search tree, bringing the heuristics to naive backtrack");
+                heuristics = Some(ordering_heuristics(&connections, &deepest_fix_sequence));
+                break;
+            }
+        }
     }
     println!("Number of samples taken by Bayesian backtrack: {}", SAMPLE_CNT.load(Ordering::SeqCst));
     SAMPLE_CNT.store(0, Ordering::SeqCst);
     assert!(heuristics.is_some(), "Heuristics must be set before calling naive backtrack");
-    let result = naive_backtrack(pcb_problem, pcb_render_model, trace_cache, heuristics);
+    let result = naive_backtrack(pcb_problem, pcb_render_model, trace_cache, heuristics, prerouted_traces, config, cancellation_token);
     println!("Number of samples taken by Naive backtrack: {}", SAMPLE_CNT.load(Ordering::SeqCst));
     SAMPLE_CNT.store(0, Ordering::SeqCst);
-    result
-}
\ No newline at end of file
+    result.map_err(|partial_solution| {
+        // the bayesian search may have got further than the naive one
+        best_partial_solution.consider(&partial_solution.solution.determined_traces);
+        best_partial_solution.into_partial_solution(pcb_problem, partial_solution.reason)
+    })
+}
+
+/// the fix sequence followed by the remaining connections, the order the naive backtracker tries them in
+fn ordering_heuristics(connections: &[ConnectionID], fix_sequence: &[ConnectionID]) -> Vec<ConnectionID> {
+    let mut connections_set: HashSet<ConnectionID> = connections.iter().cloned().collect();
+    let mut heuristics: Vec<ConnectionID> = Vec::new();
+    for connection_id in fix_sequence.iter() {
+        heuristics.push(*connection_id);
+        connections_set.remove(connection_id);
+    }
+    heuristics.extend(connections.iter().filter(|connection_id| connections_set.contains(connection_id)));
+    assert!(heuristics.len() == connections.len(), "Heuristics must contain all connections");
+    heuristics
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use shared::pcb_problem::{ConnectionID, FixedTrace, PartialSolution, PcbProblem};
+
+/// remembers the most complete set of fixed traces seen during a search:
+/// the most connections routed first, then the lowest total length
+pub struct BestPartialSolution {
+    fixed_traces: HashMap<ConnectionID, FixedTrace>,
+    total_length: f64,
+}
+
+impl Default for BestPartialSolution {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BestPartialSolution {
+    pub fn new() -> Self {
+        BestPartialSolution {
+            fixed_traces: HashMap::new(),
+            total_length: 0.0,
+        }
+    }
+    pub fn num_routed(&self) -> usize {
+        self.fixed_traces.len()
+    }
+    /// replaces the best with the given traces if they are better, returns whether they were
+    pub fn consider(&mut self, fixed_traces: &HashMap<ConnectionID, FixedTrace>) -> bool {
+        let total_length: f64 = fixed_traces
+            .values()
+            .map(|fixed_trace| fixed_trace.trace_path.total_length)
+            .sum();
+        let is_better = fixed_traces.len() > self.fixed_traces.len()
+            || (fixed_traces.len() == self.fixed_traces.len()
+                && !fixed_traces.is_empty()
+                && total_length < self.total_length);
+        if is_better {
+            self.fixed_traces = fixed_traces.clone();
+            self.total_length = total_length;
+        }
+        is_better
+    }
+    pub fn into_partial_solution(self, problem: &PcbProblem, reason: String) -> PartialSolution {
+        println!(
+            "Returning the best partial solution with {} routed connections: {}",
+            self.fixed_traces.len(),
+            reason
+        );
+        PartialSolution::from_fixed_traces(problem, self.fixed_traces, reason)
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+};
+
+use shared::{
+    collider::Collider,
+    pcb_problem::{ConnectionID, PcbProblem},
+    trace_path::{TracePath, ViaType},
+    vec2::{FixedVec2, FloatVec2},
+};
+
+use crate::via_rules::{drill_gap, via_drill};
+
+struct BlockingTrace {
+    connection_id: ConnectionID,
+    colliders: HashMap<usize, Vec<Collider>>,           // by layer
+    clearance_colliders: HashMap<usize, Vec<Collider>>, // by layer
+    drills: Vec<(FloatVec2, f32, usize, usize)>,        // position, drill, min layer and max layer of the vias
+}
+
+/// the fixed traces among the obstacles of a search, by connection, so a failed search can tell which of them
+/// were in its way, every collision with an obstacle is attributed to the traces it touches
+pub struct BlockingTraces {
+    traces: Vec<BlockingTrace>,
+    min_drill_spacing: Option<f32>,
+    blocking: RefCell<BTreeSet<ConnectionID>>,
+}
+
+impl BlockingTraces {
+    pub fn new<'a>(
+        problem: &PcbProblem,
+        traces: impl IntoIterator<Item = (ConnectionID, &'a TracePath)>,
+    ) -> Self {
+        let traces = traces
+            .into_iter()
+            .map(|(connection_id, trace_path)| BlockingTrace {
+                connection_id,
+                colliders: trace_path.to_colliders(problem.num_layers),
+                clearance_colliders: trace_path.to_clearance_colliders(problem.num_layers),
+                drills: trace_path
+                    .vias
+                    .iter()
+                    .map(|via| (via.position.to_float(), via_drill(via), via.min_layer, via.max_layer))
+                    .collect(),
+            })
+            .collect();
+        BlockingTraces {
+            traces,
+            min_drill_spacing: problem.via_rules.min_drill_spacing,
+            blocking: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// records the traces on the layer whose copper touches the clearance colliders or whose clearance touches the colliders
+    pub(crate) fn record(&self, layer: usize, colliders: &[Collider], clearance_colliders: &[Collider]) {
+        let mut blocking = self.blocking.borrow_mut();
+        for trace in self.traces.iter() {
+            if blocking.contains(&trace.connection_id) {
+                continue;
+            }
+            let touches = |trace_colliders: &HashMap<usize, Vec<Collider>>, probes: &[Collider]| {
+                trace_colliders.get(&layer).is_some_and(|trace_colliders| {
+                    trace_colliders
+                        .iter()
+                        .any(|trace_collider| probes.iter().any(|probe| trace_collider.collides_with(probe)))
+                })
+            };
+            if touches(&trace.colliders, clearance_colliders) || touches(&trace.clearance_colliders, colliders) {
+                blocking.insert(trace.connection_id);
+            }
+        }
+    }
+
+    /// records the traces with a via closer than the drill spacing to a via of the type at the position
+    pub(crate) fn record_drills(&self, position: FixedVec2, via_type: &ViaType) {
+        let Some(min_drill_spacing) = self.min_drill_spacing else {
+            return;
+        };
+        let position = position.to_float();
+        let drill = via_type.drill.unwrap_or(via_type.diameter);
+        let mut blocking = self.blocking.borrow_mut();
+        for trace in self.traces.iter() {
+            if trace.drills.iter().any(|(other_position, other_drill, min_layer, max_layer)| {
+                via_type.min_layer <= *max_layer
+                    && *min_layer <= via_type.max_layer
+                    && drill_gap(position, drill, *other_position, *other_drill) < min_drill_spacing
+            }) {
+                blocking.insert(trace.connection_id);
+            }
+        }
+    }
+
+    /// the connections whose traces got in the way of the search so far
+    pub fn blocking_connections(&self) -> BTreeSet<ConnectionID> {
+        self.blocking.borrow().clone()
+    }
+}
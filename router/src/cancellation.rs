@@ -0,0 +1,48 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// cooperative cancellation shared between the solver and whoever started it,
+/// the solvers poll it and return the best result found so far once it fires
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+    /// a token that also fires once the budget has passed, clones keep sharing the cancel flag
+    pub fn with_budget(&self, budget: Option<Duration>) -> Self {
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        let deadline = match (self.deadline, deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        CancellationToken {
+            cancelled: self.cancelled.clone(),
+            deadline,
+        }
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+    /// the reason reported with the partial solution when the token fired
+    pub fn reason(&self) -> String {
+        if self.cancelled.load(Ordering::Relaxed) {
+            "Routing was cancelled".to_string()
+        } else {
+            "Routing time budget exhausted".to_string()
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::{HashMap, HashSet};
+
+use shared::{
+    hyperparameters::CONGESTION_GRID_MAX_CELLS_PER_SIDE,
+    pcb_problem::{ConnectionID, NetName, PcbProblem},
+    trace_path::TracePath,
+    vec2::{FixedVec2, FloatVec2},
+};
+
+// (layer, cell index)
+type CellKey = (usize, usize);
+
+/// the geometry of the coarse grid the congestion is counted on
+#[derive(Debug, Clone, Copy)]
+pub struct CongestionGrid {
+    x_min: f32,
+    y_min: f32,
+    cell_size: f32,
+    num_columns: usize,
+    num_rows: usize,
+}
+
+impl CongestionGrid {
+    pub fn new(problem: &PcbProblem) -> Self {
+        // a cell is about half of the tightest trace pitch, so two legal neighbouring traces rarely share one
+        let min_pitch = problem
+            .nets
+            .values()
+            .map(|net_info| net_info.trace_width + net_info.trace_clearance)
+            .fold(f32::MAX, f32::min);
+        let max_side = f32::max(problem.width, problem.height);
+        let min_cell_size = max_side / CONGESTION_GRID_MAX_CELLS_PER_SIDE as f32;
+        let cell_size = if min_pitch == f32::MAX {
+            min_cell_size
+        } else {
+            f32::max(min_pitch / 2.0, min_cell_size)
+        };
+        let num_columns = (problem.width / cell_size).ceil() as usize + 1;
+        let num_rows = (problem.height / cell_size).ceil() as usize + 1;
+        CongestionGrid {
+            x_min: problem.center.x - problem.width / 2.0,
+            y_min: problem.center.y - problem.height / 2.0,
+            cell_size,
+            num_columns,
+            num_rows,
+        }
+    }
+    fn cell_of(&self, position: FloatVec2) -> Option<(usize, usize)> {
+        let column = ((position.x - self.x_min) / self.cell_size).floor();
+        let row = ((position.y - self.y_min) / self.cell_size).floor();
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (column, row) = (column as usize, row as usize);
+        if column >= self.num_columns || row >= self.num_rows {
+            return None;
+        }
+        Some((column, row))
+    }
+    fn cell_index(&self, position: FloatVec2) -> Option<usize> {
+        self.cell_of(position)
+            .map(|(column, row)| row * self.num_columns + column)
+    }
+    fn cell_center(&self, column: usize, row: usize) -> FloatVec2 {
+        FloatVec2::new(
+            self.x_min + (column as f32 + 0.5) * self.cell_size,
+            self.y_min + (row as f32 + 0.5) * self.cell_size,
+        )
+    }
+    /// the cells claimed by a segment of the given radius, the cell of every point on the segment is always included
+    fn cells_near_segment(&self, start: FloatVec2, end: FloatVec2, radius: f32) -> Vec<usize> {
+        let mut cells: HashSet<usize> = HashSet::new();
+        let length = (end - start).length();
+        let num_steps = (length / (self.cell_size / 2.0)).ceil().max(1.0) as usize;
+        for step in 0..=num_steps {
+            let t = step as f32 / num_steps as f32;
+            let point = start + (end - start) * t;
+            if let Some(cell_index) = self.cell_index(point) {
+                cells.insert(cell_index);
+            }
+        }
+        let min_corner = FloatVec2::new(start.x.min(end.x) - radius, start.y.min(end.y) - radius);
+        let max_corner = FloatVec2::new(start.x.max(end.x) + radius, start.y.max(end.y) + radius);
+        let column_min = ((min_corner.x - self.x_min) / self.cell_size).floor().max(0.0) as usize;
+        let row_min = ((min_corner.y - self.y_min) / self.cell_size).floor().max(0.0) as usize;
+        let column_max = (((max_corner.x - self.x_min) / self.cell_size).floor().max(0.0) as usize)
+            .min(self.num_columns - 1);
+        let row_max = (((max_corner.y - self.y_min) / self.cell_size).floor().max(0.0) as usize)
+            .min(self.num_rows - 1);
+        for row in row_min..=row_max {
+            for column in column_min..=column_max {
+                let center = self.cell_center(column, row);
+                if distance_to_segment(center, start, end) <= radius {
+                    cells.insert(row * self.num_columns + column);
+                }
+            }
+        }
+        cells.into_iter().collect()
+    }
+    /// each trace claims its copper plus half of its clearance, widened by half a cell diagonal,
+    /// so two traces that violate the clearance always share at least one cell
+    fn trace_cells(&self, trace_path: &TracePath) -> Vec<CellKey> {
+        let mut cells: HashSet<CellKey> = HashSet::new();
+        let cell_margin = self.cell_size * std::f32::consts::FRAC_1_SQRT_2;
+        for segment in trace_path.segments.iter() {
+            let radius = segment.width / 2.0 + segment.clearance / 2.0 + cell_margin;
+            for cell_index in
+                self.cells_near_segment(segment.start.to_float(), segment.end.to_float(), radius)
+            {
+                cells.insert((segment.layer, cell_index));
+            }
+        }
+        for via in trace_path.vias.iter() {
+            let position = via.position.to_float();
+            let radius = via.diameter / 2.0 + via.clearance / 2.0 + cell_margin;
+            for cell_index in self.cells_near_segment(position, position, radius) {
+                for layer in via.min_layer..=via.max_layer {
+                    cells.insert((layer, cell_index));
+                }
+            }
+        }
+        cells.into_iter().collect()
+    }
+}
+
+fn distance_to_segment(point: FloatVec2, start: FloatVec2, end: FloatVec2) -> f32 {
+    let segment = end - start;
+    let length2 = segment.magnitude2();
+    if length2 == 0.0 {
+        return (point - start).length();
+    }
+    let t = ((point - start).dot(segment) / length2).clamp(0.0, 1.0);
+    (point - (start + segment * t)).length()
+}
+
+/// counts which nets use every cell of the board and remembers how congested each cell has been,
+/// used by the negotiated congestion router to price overlaps between nets
+pub struct CongestionMap {
+    grid: CongestionGrid,
+    occupancy: HashMap<CellKey, HashMap<NetName, usize>>, // number of connections of each net in the cell
+    history: HashMap<CellKey, f64>, // accumulated cost of the cells that were overused in earlier iterations
+    connection_cells: HashMap<ConnectionID, (NetName, Vec<CellKey>)>,
+}
+
+impl CongestionMap {
+    pub fn new(problem: &PcbProblem) -> Self {
+        CongestionMap {
+            grid: CongestionGrid::new(problem),
+            occupancy: HashMap::new(),
+            history: HashMap::new(),
+            connection_cells: HashMap::new(),
+        }
+    }
+    pub fn add_trace(&mut self, connection_id: ConnectionID, net_name: &NetName, trace_path: &TracePath) {
+        self.remove_trace(connection_id);
+        let cells = self.grid.trace_cells(trace_path);
+        for cell in cells.iter() {
+            *self
+                .occupancy
+                .entry(*cell)
+                .or_default()
+                .entry(net_name.clone())
+                .or_insert(0) += 1;
+        }
+        self.connection_cells
+            .insert(connection_id, (net_name.clone(), cells));
+    }
+    pub fn remove_trace(&mut self, connection_id: ConnectionID) {
+        let Some((net_name, cells)) = self.connection_cells.remove(&connection_id) else {
+            return;
+        };
+        for cell in cells {
+            let nets = self.occupancy.get_mut(&cell).unwrap();
+            let count = nets.get_mut(&net_name).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                nets.remove(&net_name);
+            }
+            if nets.is_empty() {
+                self.occupancy.remove(&cell);
+            }
+        }
+    }
+    /// number of cells used by more than one net
+    pub fn num_overused_cells(&self) -> usize {
+        self.occupancy.values().filter(|nets| nets.len() > 1).count()
+    }
+    /// adds to the history cost of the cells shared by each pair of overlapping connections,
+    /// cells shared by traces that keep their clearance are left alone
+    pub fn update_history(&mut self, overlaps: &[(ConnectionID, ConnectionID)], history_factor: f64) {
+        for (connection_a, connection_b) in overlaps.iter() {
+            let (Some((_, cells_a)), Some((_, cells_b))) = (
+                self.connection_cells.get(connection_a),
+                self.connection_cells.get(connection_b),
+            ) else {
+                continue;
+            };
+            let cells_a: HashSet<&CellKey> = cells_a.iter().collect();
+            for cell in cells_b.iter().filter(|cell| cells_a.contains(cell)) {
+                *self.history.entry(*cell).or_insert(0.0) += history_factor;
+            }
+        }
+    }
+    /// the extra cost per unit length for routing the given net through each cell:
+    /// (1 + history) * (1 + present_factor * number of other nets) - 1
+    pub fn costs_for_net(&self, net_name: &NetName, present_factor: f64) -> CongestionCosts {
+        let mut costs: HashMap<CellKey, f64> = HashMap::new();
+        let mut occupied_by_other_nets: HashSet<CellKey> = HashSet::new();
+        for (cell, history) in self.history.iter() {
+            costs.insert(*cell, *history);
+        }
+        for (cell, nets) in self.occupancy.iter() {
+            let num_other_nets = nets.keys().filter(|other| *other != net_name).count();
+            if num_other_nets == 0 {
+                continue;
+            }
+            let history = self.history.get(cell).copied().unwrap_or(0.0);
+            let cost = (1.0 + history) * (1.0 + present_factor * num_other_nets as f64) - 1.0;
+            costs.insert(*cell, cost);
+            occupied_by_other_nets.insert(*cell);
+        }
+        CongestionCosts {
+            grid: self.grid,
+            costs,
+            occupied_by_other_nets,
+        }
+    }
+}
+
+/// congestion costs seen by one net, queried by the A* search
+#[derive(Debug, Clone)]
+pub struct CongestionCosts {
+    grid: CongestionGrid,
+    costs: HashMap<CellKey, f64>, // extra cost per unit length, missing cells cost nothing
+    occupied_by_other_nets: HashSet<CellKey>,
+}
+
+impl CongestionCosts {
+    /// costs that steer a search away from the given traces, every cell they claim costs the penalty per unit length,
+    /// used to sample candidates of a connection that differ from the earlier ones
+    pub fn around_traces<'a>(
+        problem: &PcbProblem,
+        trace_paths: impl IntoIterator<Item = &'a TracePath>,
+        penalty: f64,
+    ) -> Self {
+        let grid = CongestionGrid::new(problem);
+        let mut costs: HashMap<CellKey, f64> = HashMap::new();
+        for trace_path in trace_paths {
+            for cell in grid.trace_cells(trace_path) {
+                costs.insert(cell, penalty);
+            }
+        }
+        CongestionCosts {
+            grid,
+            costs,
+            occupied_by_other_nets: HashSet::new(),
+        }
+    }
+    fn cell_cost(&self, position: FloatVec2, layer: usize) -> f64 {
+        match self.grid.cell_index(position) {
+            Some(cell_index) => self.costs.get(&(layer, cell_index)).copied().unwrap_or(0.0),
+            None => 0.0,
+        }
+    }
+    pub fn segment_cost(&self, start: FixedVec2, end: FixedVec2, layer: usize) -> f64 {
+        if self.costs.is_empty() {
+            return 0.0;
+        }
+        let start = start.to_float();
+        let end = end.to_float();
+        let length = (end - start).length();
+        let num_steps = (length / (self.grid.cell_size / 2.0)).ceil().max(1.0) as usize;
+        let step_length = (length / num_steps as f32) as f64;
+        (0..num_steps)
+            .map(|step| {
+                let t = (step as f32 + 0.5) / num_steps as f32;
+                self.cell_cost(start + (end - start) * t, layer) * step_length
+            })
+            .sum()
+    }
+    /// whether the segment, with the given radius, claims a cell that another net uses,
+    /// the path optimization treats this as a collision so it does not undo the detours of the search
+    pub fn crosses_other_nets(&self, start: FixedVec2, end: FixedVec2, radius: f32, layer: usize) -> bool {
+        if self.occupied_by_other_nets.is_empty() {
+            return false;
+        }
+        self.grid
+            .cells_near_segment(start.to_float(), end.to_float(), radius)
+            .into_iter()
+            .any(|cell_index| self.occupied_by_other_nets.contains(&(layer, cell_index)))
+    }
+    pub fn via_cost(&self, position: FixedVec2, from_layer: usize, to_layer: usize, via_diameter: f32) -> f64 {
+        if self.costs.is_empty() {
+            return 0.0;
+        }
+        let position = position.to_float();
+        (from_layer.min(to_layer)..=from_layer.max(to_layer))
+            .map(|layer| self.cell_cost(position, layer) * via_diameter as f64)
+            .sum()
+    }
+}
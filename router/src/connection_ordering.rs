@@ -0,0 +1,335 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use ordered_float::NotNan;
+use shared::{
+    binary_heap_item::BinaryHeapItem,
+    collider::Collider,
+    hyperparameters::ORDERING_FREE_CELL_RADIUS,
+    pad::Pad,
+    pcb_problem::{ConnectionID, NetClassName, NetName, PcbProblem},
+    prim_shape::{CircleShape, PrimShape},
+    vec2::FloatVec2,
+};
+
+/// decides the order in which the sequential router routes the connections, earlier connections get the free space
+pub trait ConnectionOrdering {
+    /// every connection of initial_lengths in routing order, initial_lengths holds the length of a trace
+    /// routed around the pads of the other nets only
+    fn order(&self, problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID>;
+}
+
+/// the ordering strategy of the naive backtracker, selected by name in the config and on the command line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OrderingStrategy {
+    #[default]
+    LengthHeap, // a min-heap of the initial lengths in storage order, the shortest first
+    ShortestFirst,
+    LongestFirst,
+    MostConstrainedFirst, // fewest free grid cells around the pads first
+    BoundingBoxOverlap, // fewest bounding boxes of other nets overlapping the connection first
+    NetclassPriority(Vec<NetClassName>), // the given netclasses in order, then the others from the widest traces
+    UserOrder(Vec<NetName>), // the given nets in order, then the others shortest first
+}
+
+impl OrderingStrategy {
+    /// one of heap, shortest, longest, most_constrained, bbox_overlap, netclass[:class,...] or user:net,...
+    pub fn from_name(name: &str) -> Result<OrderingStrategy, String> {
+        let (kind, list) = match name.trim().split_once(':') {
+            Some((kind, list)) => (kind, Some(list)),
+            None => (name.trim(), None),
+        };
+        let names = || -> Vec<String> {
+            list.unwrap_or("")
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+                .collect()
+        };
+        let strategy = match kind.to_lowercase().as_str() {
+            "heap" | "length_heap" => OrderingStrategy::LengthHeap,
+            "shortest" | "shortest_first" => OrderingStrategy::ShortestFirst,
+            "longest" | "longest_first" => OrderingStrategy::LongestFirst,
+            "most_constrained" | "most-constrained" => OrderingStrategy::MostConstrainedFirst,
+            "bbox_overlap" | "bbox-overlap" => OrderingStrategy::BoundingBoxOverlap,
+            "netclass" => OrderingStrategy::NetclassPriority(names().into_iter().map(NetClassName).collect()),
+            "user" => {
+                let nets: Vec<NetName> = names().into_iter().map(NetName).collect();
+                if nets.is_empty() {
+                    return Err("The user ordering needs a list of nets, e.g. user:GND,VCC".to_string());
+                }
+                OrderingStrategy::UserOrder(nets)
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown connection ordering: {}, expected one of: heap, shortest, longest, most_constrained, bbox_overlap, netclass[:class,...], user:net,...",
+                    name
+                ));
+            }
+        };
+        if list.is_some() && !matches!(strategy, OrderingStrategy::NetclassPriority(_) | OrderingStrategy::UserOrder(_)) {
+            return Err(format!("The connection ordering {} does not take a list", kind));
+        }
+        Ok(strategy)
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            OrderingStrategy::LengthHeap => "heap",
+            OrderingStrategy::ShortestFirst => "shortest",
+            OrderingStrategy::LongestFirst => "longest",
+            OrderingStrategy::MostConstrainedFirst => "most_constrained",
+            OrderingStrategy::BoundingBoxOverlap => "bbox_overlap",
+            OrderingStrategy::NetclassPriority(_) => "netclass",
+            OrderingStrategy::UserOrder(_) => "user",
+        }
+    }
+    pub fn strategy(&self) -> Box<dyn ConnectionOrdering> {
+        match self {
+            OrderingStrategy::LengthHeap => Box::new(LengthHeap),
+            OrderingStrategy::ShortestFirst => Box::new(ShortestFirst),
+            OrderingStrategy::LongestFirst => Box::new(LongestFirst),
+            OrderingStrategy::MostConstrainedFirst => Box::new(MostConstrainedFirst),
+            OrderingStrategy::BoundingBoxOverlap => Box::new(BoundingBoxOverlap),
+            OrderingStrategy::NetclassPriority(classes) => Box::new(NetclassPriority { classes: classes.clone() }),
+            OrderingStrategy::UserOrder(nets) => Box::new(UserOrder { nets: nets.clone() }),
+        }
+    }
+}
+
+/// the name followed by the list, in the form from_name reads
+impl Display for OrderingStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = match self {
+            OrderingStrategy::NetclassPriority(classes) => classes.iter().map(|class| class.0.as_str()).collect(),
+            OrderingStrategy::UserOrder(nets) => nets.iter().map(|net| net.0.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if names.is_empty() {
+            write!(f, "{}", self.name())
+        } else {
+            write!(f, "{}:{}", self.name(), names.join(","))
+        }
+    }
+}
+
+/// sorts the connections by the key, ties go to the shorter connection and then to the lower id
+fn sort_by_key_then_length<K: PartialOrd>(
+    initial_lengths: &HashMap<ConnectionID, f64>,
+    key: impl Fn(ConnectionID) -> K,
+) -> Vec<ConnectionID> {
+    let mut connections: Vec<(K, f64, ConnectionID)> = initial_lengths
+        .iter()
+        .map(|(connection_id, length)| (key(*connection_id), *length, *connection_id))
+        .collect();
+    connections.sort_by(|a, b| {
+        a.0.partial_cmp(&b.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.1.total_cmp(&b.1))
+            .then(a.2.cmp(&b.2))
+    });
+    connections.into_iter().map(|(_, _, connection_id)| connection_id).collect()
+}
+
+fn connection_net_names(problem: &PcbProblem) -> HashMap<ConnectionID, &NetName> {
+    problem
+        .nets
+        .iter()
+        .flat_map(|(net_name, net_info)| net_info.connections.keys().map(move |connection_id| (*connection_id, net_name)))
+        .collect()
+}
+
+/// the order the naive backtracker has always used, the shortest connection first and the others loosely by length,
+/// as they are laid out in a min-heap of the initial lengths
+pub struct LengthHeap;
+
+impl ConnectionOrdering for LengthHeap {
+    fn order(&self, _problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        let mut connection_heap: BinaryHeap<BinaryHeapItem<Reverse<NotNan<f64>>, ConnectionID>> = BinaryHeap::new();
+        for (connection_id, length) in initial_lengths.iter() {
+            connection_heap.push(BinaryHeapItem::new(Reverse(NotNan::new(*length).unwrap()), *connection_id));
+        }
+        connection_heap.drain().map(|item| item.value).collect()
+    }
+}
+
+pub struct ShortestFirst;
+
+impl ConnectionOrdering for ShortestFirst {
+    fn order(&self, _problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        sort_by_key_then_length(initial_lengths, |_| 0)
+    }
+}
+
+pub struct LongestFirst;
+
+impl ConnectionOrdering for LongestFirst {
+    fn order(&self, _problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        sort_by_key_then_length(initial_lengths, |connection_id| -initial_lengths[&connection_id])
+    }
+}
+
+/// the connections whose pads have the fewest free cells around them go first, a cell is one trace pitch wide
+/// and free when a trace through its centre keeps clear of the pads of the other nets and of the board edge
+pub struct MostConstrainedFirst;
+
+impl MostConstrainedFirst {
+    fn free_cells(problem: &PcbProblem, net_name: &NetName, pad: &Pad, pitch: f32, trace_width: f32) -> usize {
+        let radius = ORDERING_FREE_CELL_RADIUS as i32;
+        let neighbourhood = Collider::from_prim_shape(&PrimShape::Circle(CircleShape {
+            position: pad.position,
+            diameter: 2.0 * (radius as f32 + 1.0) * pitch,
+        }));
+        let x_min = problem.center.x - problem.width / 2.0;
+        let x_max = problem.center.x + problem.width / 2.0;
+        let y_min = problem.center.y - problem.height / 2.0;
+        let y_max = problem.center.y + problem.height / 2.0;
+        let mut num_free_cells = 0;
+        for layer in pad.pad_layer.get_iter(problem.num_layers) {
+            // only the pads near this one can take its cells
+            let nearby_colliders: Vec<Collider> = problem
+                .nets
+                .iter()
+                .filter(|(other_net_name, _)| *other_net_name != net_name)
+                .flat_map(|(_, net_info)| net_info.pads.values())
+                .filter(|other_pad| other_pad.pad_layer.get_iter(problem.num_layers).any(|other_layer| other_layer == layer))
+                .flat_map(|other_pad| other_pad.to_clearance_shapes())
+                .map(|shape| Collider::from_prim_shape(&shape))
+                .filter(|collider| collider.collides_with(&neighbourhood))
+                .collect();
+            for column in -radius..=radius {
+                for row in -radius..=radius {
+                    let position = pad.position + FloatVec2::new(column as f32 * pitch, row as f32 * pitch);
+                    if position.x - trace_width / 2.0 < x_min
+                        || position.x + trace_width / 2.0 > x_max
+                        || position.y - trace_width / 2.0 < y_min
+                        || position.y + trace_width / 2.0 > y_max
+                    {
+                        continue;
+                    }
+                    let probe = Collider::from_prim_shape(&PrimShape::Circle(CircleShape {
+                        position,
+                        diameter: trace_width,
+                    }));
+                    if !nearby_colliders.iter().any(|collider| collider.collides_with(&probe)) {
+                        num_free_cells += 1;
+                    }
+                }
+            }
+        }
+        num_free_cells
+    }
+}
+
+impl ConnectionOrdering for MostConstrainedFirst {
+    fn order(&self, problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        let mut connection_to_free_cells: HashMap<ConnectionID, usize> = HashMap::new();
+        for (net_name, net_info) in problem.nets.iter() {
+            let pitch = net_info.trace_width + net_info.trace_clearance;
+            for connection in net_info.connections.values() {
+                if !initial_lengths.contains_key(&connection.connection_id) {
+                    continue;
+                }
+                let num_free_cells = [&connection.start_pad, &connection.end_pad]
+                    .into_iter()
+                    .map(|pad_name| {
+                        let pad = &net_info.pads[pad_name];
+                        Self::free_cells(problem, net_name, pad, pitch, net_info.trace_width)
+                    })
+                    .sum();
+                connection_to_free_cells.insert(connection.connection_id, num_free_cells);
+            }
+        }
+        sort_by_key_then_length(initial_lengths, |connection_id| connection_to_free_cells[&connection_id])
+    }
+}
+
+/// the connections whose pad bounding box overlaps the fewest boxes of connections of other nets go first,
+/// they take the least room from the others
+pub struct BoundingBoxOverlap;
+
+impl ConnectionOrdering for BoundingBoxOverlap {
+    fn order(&self, problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        // (net, min corner, max corner), widened by half a trace pitch
+        let mut boxes: Vec<(&NetName, ConnectionID, FloatVec2, FloatVec2)> = Vec::new();
+        for (net_name, net_info) in problem.nets.iter() {
+            let margin = (net_info.trace_width + net_info.trace_clearance) / 2.0;
+            for connection in net_info.connections.values() {
+                let start = net_info.pads[&connection.start_pad].position;
+                let end = net_info.pads[&connection.end_pad].position;
+                let min_corner = FloatVec2::new(start.x.min(end.x) - margin, start.y.min(end.y) - margin);
+                let max_corner = FloatVec2::new(start.x.max(end.x) + margin, start.y.max(end.y) + margin);
+                boxes.push((net_name, connection.connection_id, min_corner, max_corner));
+            }
+        }
+        let mut connection_to_overlaps: HashMap<ConnectionID, usize> = HashMap::new();
+        for (net_name, connection_id, min_corner, max_corner) in boxes.iter() {
+            if !initial_lengths.contains_key(connection_id) {
+                continue;
+            }
+            let num_overlaps = boxes
+                .iter()
+                .filter(|(other_net_name, _, other_min_corner, other_max_corner)| {
+                    other_net_name != net_name
+                        && min_corner.x <= other_max_corner.x
+                        && other_min_corner.x <= max_corner.x
+                        && min_corner.y <= other_max_corner.y
+                        && other_min_corner.y <= max_corner.y
+                })
+                .count();
+            connection_to_overlaps.insert(*connection_id, num_overlaps);
+        }
+        sort_by_key_then_length(initial_lengths, |connection_id| connection_to_overlaps[&connection_id])
+    }
+}
+
+/// the connections of the given netclasses go first, in the order of the list,
+/// the other netclasses follow from the one with the widest traces
+pub struct NetclassPriority {
+    pub classes: Vec<NetClassName>,
+}
+
+impl ConnectionOrdering for NetclassPriority {
+    fn order(&self, problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        let known_classes: HashSet<&NetClassName> = problem.nets.values().map(|net_info| &net_info.net_class_name).collect();
+        for class in self.classes.iter().filter(|class| !known_classes.contains(class)) {
+            println!("Warning: netclass {} of the connection ordering has no nets", class.0);
+        }
+        let mut connection_to_rank: HashMap<ConnectionID, (usize, f32)> = HashMap::new();
+        for net_info in problem.nets.values() {
+            let class_rank = self
+                .classes
+                .iter()
+                .position(|class| *class == net_info.net_class_name)
+                .unwrap_or(self.classes.len());
+            for connection_id in net_info.connections.keys() {
+                connection_to_rank.insert(*connection_id, (class_rank, -net_info.trace_width));
+            }
+        }
+        sort_by_key_then_length(initial_lengths, |connection_id| connection_to_rank[&connection_id])
+    }
+}
+
+/// the connections of the given nets go first, in the order of the list, the other nets follow shortest first
+pub struct UserOrder {
+    pub nets: Vec<NetName>,
+}
+
+impl ConnectionOrdering for UserOrder {
+    fn order(&self, problem: &PcbProblem, initial_lengths: &HashMap<ConnectionID, f64>) -> Vec<ConnectionID> {
+        for net_name in self.nets.iter().filter(|net_name| !problem.nets.contains_key(net_name)) {
+            println!("Warning: net {} of the connection ordering is not on the board", net_name.0);
+        }
+        let connection_to_net_name = connection_net_names(problem);
+        sort_by_key_then_length(initial_lengths, |connection_id| {
+            let net_name = connection_to_net_name[&connection_id];
+            self.nets
+                .iter()
+                .position(|other| other == net_name)
+                .unwrap_or(self.nets.len())
+        })
+    }
+}
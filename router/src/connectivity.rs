@@ -0,0 +1,396 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use shared::{
+    collider::{CircleCollider, Collider},
+    pad::{Pad, PadName},
+    pcb_problem::{ConnectionID, NetName, PcbProblem, PcbSolution},
+    pcb_render_model::ShapeRenderable,
+    prim_shape::{CircleShape, Line, PrimShape},
+    trace_path::TraceAnchor,
+    vec2::FloatVec2,
+};
+
+use crate::drc::{any_collision, bounding_box, closest_location, collect_items, DrcItem, DrcObject};
+
+/// an unrouted pad pair of an open net
+#[derive(Debug, Clone)]
+pub struct RatsnestLine {
+    pub net_name: NetName,
+    pub start_pad: PadName,
+    pub end_pad: PadName,
+    pub start: FloatVec2,
+    pub end: FloatVec2,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenNet {
+    pub net_name: NetName,
+    pub num_islands: usize, // number of separate copper groups the pads of the net fall into
+    pub ratsnest: Vec<RatsnestLine>,
+}
+
+/// copper that ends without touching a pad or other copper of its net
+#[derive(Debug, Clone)]
+pub struct DanglingStub {
+    pub object: DrcObject,
+    pub location: FloatVec2,
+    pub layers: Vec<usize>,
+}
+
+/// copper of different nets that touches, so the nets are electrically one
+#[derive(Debug, Clone)]
+pub struct NetMerge {
+    pub net_names: Vec<NetName>, // sorted
+    pub location: FloatVec2,     // one of the places where the nets touch
+}
+
+/// a trace whose first or last anchor is not inside the pad of its connection
+#[derive(Debug, Clone)]
+pub struct EndpointOffPad {
+    pub connection_id: ConnectionID,
+    pub pad_name: PadName,
+    pub position: FloatVec2,
+    pub layers: Vec<usize>, // layers of the anchor
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectivityReport {
+    pub open_nets: Vec<OpenNet>,
+    pub dangling_stubs: Vec<DanglingStub>,
+    pub net_merges: Vec<NetMerge>,
+    pub endpoints_off_pad: Vec<EndpointOffPad>,
+}
+
+impl ConnectivityReport {
+    /// every net is in one piece, no net touches another and every trace lands on its pads
+    pub fn is_connected(&self) -> bool {
+        self.open_nets.is_empty() && self.net_merges.is_empty() && self.endpoints_off_pad.is_empty()
+    }
+    pub fn ratsnest(&self) -> impl Iterator<Item = &RatsnestLine> {
+        self.open_nets.iter().flat_map(|open_net| open_net.ratsnest.iter())
+    }
+    pub fn to_renderables(&self, marker_diameter: f32) -> Vec<ShapeRenderable> {
+        let mut renderables: Vec<ShapeRenderable> = self
+            .ratsnest()
+            .map(|line| ShapeRenderable {
+                shape: PrimShape::Line(Line {
+                    start: line.start,
+                    end: line.end,
+                }),
+                color: [1.0, 1.0, 0.0, 1.0], // yellow ratsnest
+            })
+            .collect();
+        let markers = self
+            .dangling_stubs
+            .iter()
+            .map(|stub| (stub.location, [1.0, 0.5, 0.0, 0.6])) // orange stubs
+            .chain(self.net_merges.iter().map(|merge| (merge.location, [1.0, 0.0, 0.0, 0.6]))) // red merges
+            .chain(
+                self.endpoints_off_pad
+                    .iter()
+                    .map(|endpoint| (endpoint.position, [1.0, 0.0, 1.0, 0.6])), // magenta endpoints
+            );
+        for (position, color) in markers {
+            renderables.push(ShapeRenderable {
+                shape: PrimShape::Circle(CircleShape {
+                    position,
+                    diameter: marker_diameter,
+                }),
+                color,
+            });
+        }
+        renderables
+    }
+}
+
+impl Display for ConnectivityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Connectivity: {} open nets, {} dangling stubs, {} net merges, {} trace endpoints off pad",
+            self.open_nets.len(),
+            self.dangling_stubs.len(),
+            self.net_merges.len(),
+            self.endpoints_off_pad.len()
+        )?;
+        for open_net in self.open_nets.iter() {
+            writeln!(f, "  open net {} in {} islands", open_net.net_name.0, open_net.num_islands)?;
+            for line in open_net.ratsnest.iter() {
+                writeln!(f, "    unrouted {} - {}", line.start_pad.0, line.end_pad.0)?;
+            }
+        }
+        for stub in self.dangling_stubs.iter() {
+            writeln!(
+                f,
+                "  dangling {} at ({:.3}, {:.3}) on layers {:?}",
+                stub.object, stub.location.x, stub.location.y, stub.layers
+            )?;
+        }
+        for merge in self.net_merges.iter() {
+            let net_names = merge
+                .net_names
+                .iter()
+                .map(|net_name| net_name.0.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "  nets {} merged at ({:.3}, {:.3})",
+                net_names, merge.location.x, merge.location.y
+            )?;
+        }
+        for endpoint in self.endpoints_off_pad.iter() {
+            writeln!(
+                f,
+                "  connection {} ends at ({:.3}, {:.3}) outside pad {}",
+                endpoint.connection_id.0, endpoint.position.x, endpoint.position.y, endpoint.pad_name.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parents: (0..size).collect(),
+        }
+    }
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // path compression
+        let mut current = index;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+        root
+    }
+    fn union(&mut self, a: usize, b: usize) {
+        let root_a = self.find(a);
+        let root_b = self.find(b);
+        if root_a != root_b {
+            self.parents[root_a] = root_b;
+        }
+    }
+}
+
+fn items_touch(item: &DrcItem, other: &DrcItem) -> bool {
+    item.bounding_boxes_overlap(other)
+        && !item.common_layers(other).is_empty()
+        && any_collision(&item.colliders, &other.colliders)
+}
+
+fn disc_touches(position: FloatVec2, diameter: f32, layer: usize, item: &DrcItem) -> bool {
+    if !item.layers.contains(&layer) {
+        return false;
+    }
+    let disc = Collider::Circle(CircleCollider { position, diameter });
+    let (disc_min, disc_max) = bounding_box(std::slice::from_ref(&disc));
+    let (item_min, item_max) = item.bounding_box;
+    if disc_min.x > item_max.x || item_min.x > disc_max.x || disc_min.y > item_max.y || item_min.y > disc_max.y {
+        return false;
+    }
+    item.colliders.iter().any(|collider| collider.collides_with(&disc))
+}
+
+/// builds the copper graph of every net and checks that it matches the netlist
+pub fn check_connectivity(problem: &PcbProblem, solution: &PcbSolution) -> ConnectivityReport {
+    let items = collect_items(problem, solution);
+    let mut union_find = UnionFind::new(items.len());
+    let mut merges: HashMap<(NetName, NetName), FloatVec2> = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        for (j, other) in items.iter().enumerate().skip(i + 1) {
+            if !items_touch(item, other) {
+                continue;
+            }
+            if item.net_name == other.net_name {
+                union_find.union(i, j);
+            } else {
+                // different nets are kept apart in the graph and reported instead
+                let key = if item.net_name < other.net_name {
+                    (item.net_name.clone(), other.net_name.clone())
+                } else {
+                    (other.net_name.clone(), item.net_name.clone())
+                };
+                merges
+                    .entry(key)
+                    .or_insert_with(|| closest_location(item.skeleton, other.skeleton));
+            }
+        }
+    }
+    let mut report = ConnectivityReport::default();
+    let mut merge_list: Vec<((NetName, NetName), FloatVec2)> = merges.into_iter().collect();
+    merge_list.sort_by(|a, b| a.0.cmp(&b.0));
+    report.net_merges = merge_list
+        .into_iter()
+        .map(|((net_a, net_b), location)| NetMerge {
+            net_names: vec![net_a, net_b],
+            location,
+        })
+        .collect();
+
+    // open nets: pads of one net that ended up in different islands
+    let mut net_names: Vec<&NetName> = problem.nets.keys().collect();
+    net_names.sort();
+    for net_name in net_names {
+        let net_info = &problem.nets[net_name];
+        let mut islands: HashMap<usize, Vec<(PadName, FloatVec2)>> = HashMap::new();
+        for (index, item) in items.iter().enumerate() {
+            if let DrcObject::Pad {
+                net_name: pad_net_name,
+                pad_name,
+            } = &item.object
+            {
+                if pad_net_name != net_name {
+                    continue;
+                }
+                let root = union_find.find(index);
+                islands
+                    .entry(root)
+                    .or_default()
+                    .push((pad_name.clone(), net_info.pads[pad_name].position));
+            }
+        }
+        if islands.len() <= 1 {
+            continue;
+        }
+        let mut islands: Vec<Vec<(PadName, FloatVec2)>> = islands.into_values().collect();
+        islands.sort_by(|a, b| a[0].0.cmp(&b[0].0));
+        report.open_nets.push(OpenNet {
+            net_name: net_name.clone(),
+            num_islands: islands.len(),
+            ratsnest: island_ratsnest(net_name, &islands),
+        });
+    }
+
+    // dangling stubs: segment ends and vias that touch nothing else of their net
+    for (index, item) in items.iter().enumerate() {
+        match &item.object {
+            DrcObject::Trace { .. } => {
+                let layer = item.layers[0];
+                let (start, end) = item.skeleton;
+                // the copper width is the diameter of the end circles of the segment
+                let width = match item.colliders.first() {
+                    Some(Collider::Circle(circle)) => circle.diameter,
+                    _ => 0.0,
+                };
+                for point in [start, end] {
+                    let touches = items.iter().enumerate().any(|(other_index, other)| {
+                        other_index != index
+                            && other.net_name == item.net_name
+                            && disc_touches(point, width, layer, other)
+                    });
+                    if !touches {
+                        report.dangling_stubs.push(DanglingStub {
+                            object: item.object.clone(),
+                            location: point,
+                            layers: item.layers.clone(),
+                        });
+                    }
+                }
+            }
+            DrcObject::Via { .. } => {
+                let num_touching = items
+                    .iter()
+                    .enumerate()
+                    .filter(|(other_index, other)| {
+                        *other_index != index && other.net_name == item.net_name && items_touch(item, other)
+                    })
+                    .count();
+                if num_touching < 2 {
+                    report.dangling_stubs.push(DanglingStub {
+                        object: item.object.clone(),
+                        location: item.skeleton.0,
+                        layers: item.layers.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // trace endpoints must land inside the pads of their connection
+    let mut connection_ids: Vec<&ConnectionID> = solution.determined_traces.keys().collect();
+    connection_ids.sort();
+    for connection_id in connection_ids {
+        let fixed_trace = &solution.determined_traces[connection_id];
+        let net_info = &problem.nets[&fixed_trace.net_name];
+        let connection = &net_info.connections[connection_id];
+        let anchors = &fixed_trace.trace_path.anchors.0;
+        let (Some(first), Some(last)) = (anchors.first(), anchors.last()) else {
+            continue;
+        };
+        for (anchor, pad_name) in [(first, &connection.start_pad), (last, &connection.end_pad)] {
+            if !anchor_on_pad(anchor, &net_info.pads[pad_name], problem.num_layers) {
+                report.endpoints_off_pad.push(EndpointOffPad {
+                    connection_id: *connection_id,
+                    pad_name: pad_name.clone(),
+                    position: anchor.position.to_float(),
+                    layers: (anchor.start_layer..=anchor.end_layer).collect(),
+                });
+            }
+        }
+    }
+    report
+}
+
+/// whether the end anchor of a trace lies inside the copper of the pad, on one of its layers
+pub(crate) fn anchor_on_pad(anchor: &TraceAnchor, pad: &Pad, num_layers: usize) -> bool {
+    let point = Collider::Circle(CircleCollider {
+        position: anchor.position.to_float(),
+        diameter: 0.0,
+    });
+    let pad_layers: BTreeSet<usize> = pad.pad_layer.get_iter(num_layers).collect();
+    let on_pad_layer = (anchor.start_layer..=anchor.end_layer).any(|layer| pad_layers.contains(&layer));
+    on_pad_layer
+        && pad
+            .to_shapes()
+            .iter()
+            .map(Collider::from_prim_shape)
+            .any(|collider| collider.collides_with(&point))
+}
+
+/// connects the islands of an open net with the shortest pad pairs, prim's algorithm over islands
+fn island_ratsnest(net_name: &NetName, islands: &[Vec<(PadName, FloatVec2)>]) -> Vec<RatsnestLine> {
+    let mut connected: Vec<usize> = vec![0];
+    let mut remaining: Vec<usize> = (1..islands.len()).collect();
+    let mut ratsnest: Vec<RatsnestLine> = Vec::new();
+    while !remaining.is_empty() {
+        let mut best: Option<(f32, usize, RatsnestLine)> = None;
+        for &from_island in connected.iter() {
+            for (remaining_index, &to_island) in remaining.iter().enumerate() {
+                for (from_pad, from_position) in islands[from_island].iter() {
+                    for (to_pad, to_position) in islands[to_island].iter() {
+                        let distance = (*from_position - *to_position).length();
+                        if best.as_ref().is_none_or(|(best_distance, _, _)| distance < *best_distance) {
+                            let line = RatsnestLine {
+                                net_name: net_name.clone(),
+                                start_pad: from_pad.clone(),
+                                end_pad: to_pad.clone(),
+                                start: *from_position,
+                                end: *to_position,
+                            };
+                            best = Some((distance, remaining_index, line));
+                        }
+                    }
+                }
+            }
+        }
+        let (_, remaining_index, line) = best.unwrap();
+        ratsnest.push(line);
+        connected.push(remaining.swap_remove(remaining_index));
+    }
+    ratsnest
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use shared::{
+    collider::{CircleCollider, Collider},
+    pad::{Pad, PadShape},
+    pcb_problem::{CopperPour, CopperPourRule, NetName, PcbProblem, PcbSolution},
+    prim_shape::PrimShape,
+    vec2::FloatVec2,
+};
+
+// side of the square cells the fill is made of, in float units
+const POUR_CELL_SIZE: f32 = 0.05;
+// the cells get larger on big boards, so a layer never has more than this many cells per side
+const POUR_MAX_CELLS_PER_SIDE: usize = 2000;
+// extra distance kept on top of every clearance, so that rounding never brings the fill too close
+const POUR_CLEARANCE_MARGIN: f32 = 1e-3;
+
+// a piece of copper the fill keeps away from or connects to
+enum Copper {
+    Disc {
+        center: FloatVec2,
+        radius: f32,
+    },
+    Capsule {
+        start: FloatVec2,
+        end: FloatVec2,
+        radius: f32,
+    },
+    Box {
+        center: FloatVec2,
+        half_width: f32,
+        half_height: f32,
+        rotation: f32, // counterclockwise, in radians
+    },
+}
+
+impl Copper {
+    fn from_shape(shape: &PrimShape) -> Self {
+        match shape {
+            PrimShape::Circle(circle) => Copper::Disc {
+                center: circle.position,
+                radius: circle.diameter / 2.0,
+            },
+            PrimShape::Rectangle(rectangle) => Copper::Box {
+                center: rectangle.position,
+                half_width: rectangle.width / 2.0,
+                half_height: rectangle.height / 2.0,
+                rotation: rectangle.rotation.0.to_radians(),
+            },
+            PrimShape::Line(line) => Copper::Capsule {
+                start: line.start,
+                end: line.end,
+                radius: 0.0,
+            },
+        }
+    }
+
+    /// distance from the point to the outline, negative inside
+    fn distance(&self, point: FloatVec2) -> f32 {
+        match self {
+            Copper::Disc { center, radius } => (point - *center).length() - radius,
+            Copper::Capsule { start, end, radius } => {
+                let segment = *end - *start;
+                let length2 = segment.magnitude2();
+                let t = if length2 <= f32::EPSILON {
+                    0.0
+                } else {
+                    ((point - *start).dot(segment) / length2).clamp(0.0, 1.0)
+                };
+                (point - (*start + segment * t)).length() - radius
+            }
+            Copper::Box {
+                center,
+                half_width,
+                half_height,
+                rotation,
+            } => {
+                let local = to_local(point - *center, *rotation);
+                let dx = local.x.abs() - half_width;
+                let dy = local.y.abs() - half_height;
+                let outside = FloatVec2::new(dx.max(0.0), dy.max(0.0)).length();
+                outside + dx.max(dy).min(0.0)
+            }
+        }
+    }
+
+    /// min and max corners of the copper grown by the distance
+    fn bounding_box(&self, grow: f32) -> (FloatVec2, FloatVec2) {
+        let (min, max) = match self {
+            Copper::Disc { center, radius } => (
+                *center - FloatVec2::new(*radius, *radius),
+                *center + FloatVec2::new(*radius, *radius),
+            ),
+            Copper::Capsule { start, end, radius } => (
+                FloatVec2::new(start.x.min(end.x) - radius, start.y.min(end.y) - radius),
+                FloatVec2::new(start.x.max(end.x) + radius, start.y.max(end.y) + radius),
+            ),
+            Copper::Box {
+                center,
+                half_width,
+                half_height,
+                rotation,
+            } => {
+                let (cos, sin) = (rotation.cos().abs(), rotation.sin().abs());
+                let extent = FloatVec2::new(
+                    half_width * cos + half_height * sin,
+                    half_width * sin + half_height * cos,
+                );
+                (*center - extent, *center + extent)
+            }
+        };
+        (min - FloatVec2::new(grow, grow), max + FloatVec2::new(grow, grow))
+    }
+}
+
+fn to_local(offset: FloatVec2, rotation: f32) -> FloatVec2 {
+    let (cos, sin) = (rotation.cos(), rotation.sin());
+    FloatVec2::new(offset.x * cos + offset.y * sin, -offset.x * sin + offset.y * cos)
+}
+
+fn pad_copper(pad: &Pad) -> Vec<Copper> {
+    pad.to_shapes().iter().map(Copper::from_shape).collect()
+}
+
+// the square cells of one layer, a cell is filled when its whole square may be copper of the pour
+struct PourGrid {
+    origin: FloatVec2, // bottom left corner of the first cell
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    filled: Vec<bool>, // row by row, from the bottom
+}
+
+impl PourGrid {
+    fn new(problem: &PcbProblem) -> Self {
+        let cell_size = POUR_CELL_SIZE.max(problem.width.max(problem.height) / POUR_MAX_CELLS_PER_SIDE as f32);
+        let columns = (problem.width / cell_size).ceil().max(1.0) as usize;
+        let rows = (problem.height / cell_size).ceil().max(1.0) as usize;
+        PourGrid {
+            origin: problem.center - FloatVec2::new(problem.width / 2.0, problem.height / 2.0),
+            cell_size,
+            columns,
+            rows,
+            filled: vec![true; columns * rows],
+        }
+    }
+
+    /// the distance from the centre of a cell to its corners, a cell is clear of copper
+    /// when its centre is at least this much further than the clearance
+    fn half_diagonal(&self) -> f32 {
+        self.cell_size * std::f32::consts::FRAC_1_SQRT_2
+    }
+
+    fn center(&self, column: usize, row: usize) -> FloatVec2 {
+        self.origin
+            + FloatVec2::new(
+                (column as f32 + 0.5) * self.cell_size,
+                (row as f32 + 0.5) * self.cell_size,
+            )
+    }
+
+    /// the cells whose centre may lie between the corners
+    fn cells_in(&self, (min, max): (FloatVec2, FloatVec2)) -> impl Iterator<Item = (usize, usize)> + use<> {
+        let first = |value: f32, origin: f32| ((value - origin) / self.cell_size - 0.5).floor().max(0.0) as usize;
+        let last = |value: f32, origin: f32, count: usize| {
+            (((value - origin) / self.cell_size - 0.5).ceil().max(0.0) as usize).min(count.saturating_sub(1))
+        };
+        let (first_column, last_column) = (first(min.x, self.origin.x), last(max.x, self.origin.x, self.columns));
+        let (first_row, last_row) = (first(min.y, self.origin.y), last(max.y, self.origin.y, self.rows));
+        (first_row..=last_row).flat_map(move |row| (first_column..=last_column).map(move |column| (column, row)))
+    }
+
+    /// empties the cells outside the polygon or closer to its edges than the distance
+    fn keep_inside(&mut self, polygon: &[FloatVec2], distance: f32) {
+        for row in 0..self.rows {
+            let y = self.center(0, row).y;
+            // where the row crosses the edges, a cell is inside when an odd number of crossings is on its right
+            let crossings: Vec<f32> = (0..polygon.len())
+                .map(|i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+                .filter(|(a, b)| (a.y > y) != (b.y > y))
+                .map(|(a, b)| a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x))
+                .collect();
+            for column in 0..self.columns {
+                let x = self.center(column, row).x;
+                if crossings.iter().filter(|crossing| **crossing > x).count() % 2 == 0 {
+                    self.filled[row * self.columns + column] = false;
+                }
+            }
+        }
+        for i in 0..polygon.len() {
+            let edge = Copper::Capsule {
+                start: polygon[i],
+                end: polygon[(i + 1) % polygon.len()],
+                radius: 0.0,
+            };
+            self.clear_around(&edge, distance);
+        }
+    }
+
+    /// empties the cells closer to the copper than the distance
+    fn clear_around(&mut self, copper: &Copper, distance: f32) {
+        let keep_out = distance + self.half_diagonal() + POUR_CLEARANCE_MARGIN;
+        for (column, row) in self.cells_in(copper.bounding_box(keep_out)) {
+            if copper.distance(self.center(column, row)) < keep_out {
+                self.filled[row * self.columns + column] = false;
+            }
+        }
+    }
+}
+
+/// fills the copper pours of the problem around the routed solution, in the order of the rules
+pub fn fill_copper_pours(problem: &PcbProblem, solution: &PcbSolution) -> Vec<CopperPour> {
+    let mut pours: Vec<CopperPour> = Vec::new();
+    for rule in problem.copper_pours.iter() {
+        for &layer in rule.layers.iter() {
+            let pour = fill_copper_pour(problem, solution, rule, layer, &pours);
+            let area = pour
+                .polygons
+                .iter()
+                .map(|polygon| (polygon[2].x - polygon[0].x) * (polygon[2].y - polygon[0].y))
+                .fold(0.0, |area, rectangle_area| area + rectangle_area);
+            println!(
+                "Copper pour of net {} on layer {}: {} polygons, area {:.2}",
+                rule.net_name.0,
+                layer,
+                pour.polygons.len(),
+                area
+            );
+            pours.push(pour);
+        }
+    }
+    pours
+}
+
+fn fill_copper_pour(
+    problem: &PcbProblem,
+    solution: &PcbSolution,
+    rule: &CopperPourRule,
+    layer: usize,
+    earlier_pours: &[CopperPour],
+) -> CopperPour {
+    let net_info = &problem.nets[&rule.net_name];
+    let clearance = net_info.trace_clearance;
+    let thermal_gap = rule.thermal_gap.unwrap_or(clearance);
+    let spoke_width = rule.spoke_width.unwrap_or(net_info.trace_width);
+    let mut grid = PourGrid::new(problem);
+    let half_diagonal = grid.half_diagonal();
+
+    // inside the board, away from its edges, and inside the outline of the rule
+    let board_outline = if problem.board_outline.len() >= 3 {
+        problem.board_outline.clone()
+    } else {
+        let half_size = FloatVec2::new(problem.width / 2.0, problem.height / 2.0);
+        let (min, max) = (problem.center - half_size, problem.center + half_size);
+        vec![min, FloatVec2::new(max.x, min.y), max, FloatVec2::new(min.x, max.y)]
+    };
+    grid.keep_inside(&board_outline, clearance);
+    if let Some(outline) = &rule.outline {
+        grid.keep_inside(outline, 0.0);
+    }
+    let edge_distance = clearance + half_diagonal + POUR_CLEARANCE_MARGIN;
+    for border in problem.obstacle_borders.iter() {
+        for row in 0..grid.rows {
+            for column in 0..grid.columns {
+                let center = grid.center(column, row);
+                if -(center - border.point_on_border).dot(border.normal.normalize()) < edge_distance {
+                    grid.filled[row * grid.columns + column] = false;
+                }
+            }
+        }
+    }
+    for keepout in problem.obstacle_polygons.iter() {
+        let keepout_collider = Collider::Polygon(keepout.clone());
+        let (mut min, mut max) = (FloatVec2::new(f32::MAX, f32::MAX), FloatVec2::new(f32::MIN, f32::MIN));
+        for vertex in keepout.0.iter() {
+            min = FloatVec2::new(min.x.min(vertex.x), min.y.min(vertex.y));
+            max = FloatVec2::new(max.x.max(vertex.x), max.y.max(vertex.y));
+        }
+        let grow = FloatVec2::new(half_diagonal, half_diagonal);
+        for (column, row) in grid.cells_in((min - grow, max + grow)) {
+            let cell = Collider::Circle(CircleCollider {
+                position: grid.center(column, row),
+                diameter: (half_diagonal + POUR_CLEARANCE_MARGIN) * 2.0,
+            });
+            if cell.collides_with(&keepout_collider) {
+                grid.filled[row * grid.columns + column] = false;
+            }
+        }
+    }
+
+    // thermal reliefs: a gap around every pad of the net, bridged by four spokes along the axes of the pad
+    let pads_on_layer: Vec<&Pad> = net_info
+        .pads
+        .values()
+        .filter(|pad| pad.pad_layer.get_iter(problem.num_layers).any(|pad_layer| pad_layer == layer))
+        .collect();
+    for pad in pads_on_layer.iter() {
+        let copper = pad_copper(pad);
+        let mut relief = grid.filled.clone();
+        for piece in copper.iter() {
+            let keep_out = thermal_gap + half_diagonal + POUR_CLEARANCE_MARGIN;
+            for (column, row) in grid.cells_in(piece.bounding_box(keep_out)) {
+                if piece.distance(grid.center(column, row)) < keep_out {
+                    relief[row * grid.columns + column] = false;
+                }
+            }
+        }
+        let (half_width, half_height, rotation) = match pad.shape {
+            PadShape::Circle { diameter } => (diameter / 2.0, diameter / 2.0, 0.0),
+            PadShape::Rectangle { width, height } | PadShape::RoundRect { width, height, .. } => {
+                (width / 2.0, height / 2.0, pad.rotation.0.to_radians())
+            }
+        };
+        // the spokes reach a cell past the gap so they always meet the rest of the pour
+        let reach = thermal_gap + half_diagonal * 2.0 + POUR_CLEARANCE_MARGIN;
+        let spoke_half_width = (spoke_width + grid.cell_size) / 2.0;
+        let spoke_reach = half_width.max(half_height) + reach;
+        let grow = FloatVec2::new(spoke_reach, spoke_reach);
+        for (column, row) in grid.cells_in((pad.position - grow, pad.position + grow)) {
+            let local = to_local(grid.center(column, row) - pad.position, rotation);
+            let on_spoke = (local.y.abs() <= spoke_half_width && local.x.abs() <= half_width + reach)
+                || (local.x.abs() <= spoke_half_width && local.y.abs() <= half_height + reach);
+            let index = row * grid.columns + column;
+            if on_spoke && grid.filled[index] {
+                relief[index] = true;
+            }
+        }
+        grid.filled = relief;
+    }
+
+    // the copper of every other net, with the larger of the two clearances
+    for (net_name, other_net) in problem.nets.iter().filter(|(net_name, _)| **net_name != rule.net_name) {
+        for pad in other_net.pads.values() {
+            if !pad.pad_layer.get_iter(problem.num_layers).any(|pad_layer| pad_layer == layer) {
+                continue;
+            }
+            for piece in pad_copper(pad) {
+                grid.clear_around(&piece, clearance);
+            }
+            // the clearance shape of a rectangular pad keeps its sharp corners
+            for shape in pad.to_clearance_shapes().iter() {
+                grid.clear_around(&Copper::from_shape(shape), 0.0);
+            }
+        }
+        for fixed_trace in solution.determined_traces.values().filter(|trace| trace.net_name == *net_name) {
+            for segment in fixed_trace.trace_path.segments.iter().filter(|segment| segment.layer == layer) {
+                let piece = Copper::Capsule {
+                    start: segment.start.to_float(),
+                    end: segment.end.to_float(),
+                    radius: segment.width / 2.0,
+                };
+                grid.clear_around(&piece, clearance.max(segment.clearance));
+            }
+            for via in fixed_trace.trace_path.vias.iter() {
+                if via.min_layer <= layer && layer <= via.max_layer {
+                    let piece = Copper::Disc {
+                        center: via.position.to_float(),
+                        radius: via.diameter / 2.0,
+                    };
+                    grid.clear_around(&piece, clearance.max(via.clearance));
+                }
+            }
+        }
+    }
+    for earlier_pour in earlier_pours
+        .iter()
+        .filter(|pour| pour.layer == layer && pour.net_name != rule.net_name)
+    {
+        for polygon in earlier_pour.polygons.iter() {
+            let piece = Copper::Box {
+                center: (polygon[0] + polygon[2]) / 2.0,
+                half_width: (polygon[2].x - polygon[0].x) / 2.0,
+                half_height: (polygon[2].y - polygon[0].y) / 2.0,
+                rotation: 0.0,
+            };
+            grid.clear_around(&piece, clearance.max(earlier_pour.clearance));
+        }
+    }
+
+    // islands: only the cells connected to copper of the net are kept
+    let same_net_copper = same_net_copper(solution, &rule.net_name, &pads_on_layer, layer);
+    let mut connected = vec![false; grid.filled.len()];
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    for piece in same_net_copper.iter() {
+        for (column, row) in grid.cells_in(piece.bounding_box(0.0)) {
+            let index = row * grid.columns + column;
+            if grid.filled[index] && !connected[index] && piece.distance(grid.center(column, row)) <= 0.0 {
+                connected[index] = true;
+                queue.push_back((column, row));
+            }
+        }
+    }
+    while let Some((column, row)) = queue.pop_front() {
+        let neighbours = [
+            (column.wrapping_sub(1), row),
+            (column + 1, row),
+            (column, row.wrapping_sub(1)),
+            (column, row + 1),
+        ];
+        for (next_column, next_row) in neighbours {
+            if next_column >= grid.columns || next_row >= grid.rows {
+                continue;
+            }
+            let index = next_row * grid.columns + next_column;
+            if grid.filled[index] && !connected[index] {
+                connected[index] = true;
+                queue.push_back((next_column, next_row));
+            }
+        }
+    }
+    if grid.filled.iter().any(|filled| *filled) && connected.iter().all(|connected| !connected) {
+        println!(
+            "Warning: the copper pour of net {} on layer {} touches no copper of its net, nothing is filled",
+            rule.net_name.0, layer
+        );
+    }
+    grid.filled = connected;
+
+    CopperPour {
+        net_name: rule.net_name.clone(),
+        layer,
+        clearance,
+        polygons: to_rectangles(&grid),
+    }
+}
+
+// the copper of the net on the layer that the pour connects to
+fn same_net_copper(solution: &PcbSolution, net_name: &NetName, pads: &[&Pad], layer: usize) -> Vec<Copper> {
+    let mut copper: Vec<Copper> = pads.iter().flat_map(|pad| pad_copper(pad)).collect();
+    for fixed_trace in solution.determined_traces.values().filter(|trace| trace.net_name == *net_name) {
+        for segment in fixed_trace.trace_path.segments.iter().filter(|segment| segment.layer == layer) {
+            copper.push(Copper::Capsule {
+                start: segment.start.to_float(),
+                end: segment.end.to_float(),
+                radius: segment.width / 2.0,
+            });
+        }
+        for via in fixed_trace.trace_path.vias.iter() {
+            if via.min_layer <= layer && layer <= via.max_layer {
+                copper.push(Copper::Disc {
+                    center: via.position.to_float(),
+                    radius: via.diameter / 2.0,
+                });
+            }
+        }
+    }
+    copper
+}
+
+// the filled cells as rectangles, runs of a row are merged with the same runs of the rows above
+fn to_rectangles(grid: &PourGrid) -> Vec<Vec<FloatVec2>> {
+    let mut rectangles: Vec<(usize, usize, usize, usize)> = Vec::new(); // first row, last row, first column, last column
+    let mut open: BTreeMap<(usize, usize), usize> = BTreeMap::new(); // (first column, last column) to first row
+    for row in 0..=grid.rows {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        if row < grid.rows {
+            let mut column = 0;
+            while column < grid.columns {
+                if !grid.filled[row * grid.columns + column] {
+                    column += 1;
+                    continue;
+                }
+                let first = column;
+                while column < grid.columns && grid.filled[row * grid.columns + column] {
+                    column += 1;
+                }
+                runs.push((first, column - 1));
+            }
+        }
+        let closed: Vec<(usize, usize)> = open.keys().filter(|run| !runs.contains(run)).copied().collect();
+        for run in closed {
+            let first_row = open.remove(&run).unwrap();
+            rectangles.push((first_row, row - 1, run.0, run.1));
+        }
+        for run in runs {
+            open.entry(run).or_insert(row);
+        }
+    }
+    rectangles.sort();
+    rectangles
+        .into_iter()
+        .map(|(first_row, last_row, first_column, last_column)| {
+            let min = grid.origin
+                + FloatVec2::new(first_column as f32 * grid.cell_size, first_row as f32 * grid.cell_size);
+            let max = grid.origin
+                + FloatVec2::new(
+                    (last_column + 1) as f32 * grid.cell_size,
+                    (last_row + 1) as f32 * grid.cell_size,
+                );
+            vec![min, FloatVec2::new(max.x, min.y), max, FloatVec2::new(min.x, max.y)]
+        })
+        .collect()
+}
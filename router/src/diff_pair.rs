@@ -0,0 +1,848 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use shared::{
+    collider::Collider,
+    pad::{Pad, PadLayer},
+    pcb_problem::{
+        Connection, ConnectionID, DiffPairConstraint, FixedTrace, MeanderShape, NetInfo, NetName, PcbProblem,
+        RoutingConstraints, UncoupledDiffPair,
+    },
+    pcb_render_model::PcbRenderModel,
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, ViaType},
+    vec2::{FixedPoint, FixedVec2, FloatVec2, IntVec2},
+};
+
+use crate::{
+    astar::AStarModel,
+    astar_check_struct::AStarCheck,
+    cancellation::CancellationToken,
+    drc::bounding_box,
+    meander::{add_meanders, MeanderSide, MeanderStyle},
+    obstacles::{obstacles_of_other_nets, Obstacles},
+    pad_entry::PadEntry,
+    via_rules::ViaObstacles,
+};
+
+// number of fixed point bits per unit of length
+fn bits_per_unit() -> f64 {
+    (1i64 << FixedPoint::FRAC_NBITS) as f64
+}
+
+// an octilinear line a * x + b * y = k in fixed point bits, (a, b) is the left normal of its direction,
+// an even k makes the intersection of two diagonal lines exact and gives the line even-even points
+#[derive(Debug, Clone, Copy)]
+struct OctilinearLine {
+    direction: Direction,
+    a: i64,
+    b: i64,
+    k: i64,
+}
+
+impl OctilinearLine {
+    fn through(position: FixedVec2, direction: Direction) -> Self {
+        let int_vec = direction.to_int_vec2();
+        let (a, b) = (-(int_vec.y as i64), int_vec.x as i64);
+        OctilinearLine {
+            direction,
+            a,
+            b,
+            k: a * position.x.to_bits() as i64 + b * position.y.to_bits() as i64,
+        }
+    }
+    fn snap_k(k: f64) -> i64 {
+        2 * (k / 2.0).round() as i64
+    }
+    // a line through the position, moved by less than two bits so that k is even
+    fn snapped_through(position: FixedVec2, direction: Direction) -> Self {
+        let line = Self::through(position, direction);
+        OctilinearLine {
+            k: Self::snap_k(line.k as f64),
+            ..line
+        }
+    }
+    // the parallel line moved by distance to the left of the direction, negative distances move it to the right
+    fn offset(&self, distance: f64) -> Self {
+        let normal_length = ((self.a * self.a + self.b * self.b) as f64).sqrt();
+        OctilinearLine {
+            k: Self::snap_k(self.k as f64 + normal_length * distance * bits_per_unit()),
+            ..*self
+        }
+    }
+    fn intersect(&self, other: &OctilinearLine) -> Option<FixedVec2> {
+        let determinant = self.a * other.b - other.a * self.b;
+        if determinant == 0 {
+            return None;
+        }
+        let x = self.k * other.b - other.k * self.b;
+        let y = self.a * other.k - other.a * self.k;
+        if x % determinant != 0 || y % determinant != 0 {
+            return None;
+        }
+        Some(FixedVec2::new(
+            FixedPoint::from_bits((x / determinant) as i32),
+            FixedPoint::from_bits((y / determinant) as i32),
+        ))
+    }
+}
+
+// the part of the centre line between two vias, with collinear segments merged
+// and the tiny jogs left by the path optimization dropped
+struct CentreRun {
+    layer: usize,
+    points: Vec<FixedVec2>,
+    directions: Vec<Direction>,
+}
+
+fn centre_runs(trace_path: &TracePath, min_segment_length: f64) -> Vec<CentreRun> {
+    let mut runs: Vec<CentreRun> = Vec::new();
+    for segment in trace_path.segments.iter() {
+        let Ok(Some(direction)) = Direction::from_points(segment.start, segment.end) else {
+            continue;
+        };
+        let starts_run = runs.last().is_none_or(|run| run.layer != segment.layer);
+        if !starts_run && segment_length(segment.start, segment.end) < min_segment_length {
+            *runs.last_mut().unwrap().points.last_mut().unwrap() = segment.end;
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.layer == segment.layer => {
+                if *run.directions.last().unwrap() == direction {
+                    *run.points.last_mut().unwrap() = segment.end;
+                } else {
+                    run.points.push(segment.end);
+                    run.directions.push(direction);
+                }
+            }
+            _ => runs.push(CentreRun {
+                layer: segment.layer,
+                points: vec![segment.start, segment.end],
+                directions: vec![direction],
+            }),
+        }
+    }
+    for run in runs.iter_mut() {
+        if run.directions.len() > 1 && segment_length(run.points[0], run.points[1]) < min_segment_length {
+            run.points.remove(0);
+            run.directions.remove(0);
+        }
+    }
+    runs
+}
+
+/// one trace of a pair, following the centre line at `trace_distance` on one side from the first to the last point
+/// of the centre line, spreading to `via_distance` around the vias, None when the offset geometry folds onto itself
+fn coupled_trace(runs: &[CentreRun], on_left: bool, trace_distance: f64, via_distance: f64) -> Option<Vec<TraceAnchor>> {
+    let sign = if on_left { 1.0 } else { -1.0 };
+    // each vertex with the direction of the segment that leads to it
+    let mut vertices: Vec<(TraceAnchor, Option<Direction>)> = Vec::new();
+    let on_layer = |position: FixedVec2, layer: usize| TraceAnchor {
+        position,
+        start_layer: layer,
+        end_layer: layer,
+        via_type: None,
+    };
+
+    let first_run = runs.first()?;
+    let first_direction = first_run.directions[0];
+    let first_line = OctilinearLine::through(first_run.points[0], first_direction).offset(sign * trace_distance);
+    let first_point =
+        first_line.intersect(&OctilinearLine::snapped_through(first_run.points[0], first_direction.right_90_dir()))?;
+    vertices.push((on_layer(first_point, first_run.layer), None));
+
+    for (run_index, run) in runs.iter().enumerate() {
+        let lines: Vec<OctilinearLine> = run
+            .points
+            .iter()
+            .zip(run.directions.iter())
+            .map(|(point, direction)| OctilinearLine::through(*point, *direction).offset(sign * trace_distance))
+            .collect();
+        for i in 1..lines.len() {
+            let corner = lines[i - 1].intersect(&lines[i])?;
+            vertices.push((on_layer(corner, run.layer), Some(lines[i - 1].direction)));
+        }
+        let last_line = *lines.last().unwrap();
+        let Some(next_run) = runs.get(run_index + 1) else {
+            let last_point = *run.points.last().unwrap();
+            let last_direction = last_line.direction;
+            let end = last_line.intersect(&OctilinearLine::snapped_through(last_point, last_direction.right_90_dir()))?;
+            vertices.push((on_layer(end, run.layer), Some(last_direction)));
+            break;
+        };
+        // spread out around the via so the two vias of the pair keep their clearance
+        let via_centre = *run.points.last().unwrap();
+        let incoming = *run.directions.last().unwrap();
+        let outgoing = next_run.directions[0];
+        let next_line = OctilinearLine::through(next_run.points[0], outgoing).offset(sign * trace_distance);
+        let incoming_spread = OctilinearLine::through(via_centre, incoming).offset(sign * via_distance);
+        let via_position = if incoming == outgoing {
+            incoming_spread.intersect(&OctilinearLine::snapped_through(via_centre, incoming.right_90_dir()))?
+        } else {
+            let outgoing_spread = OctilinearLine::through(via_centre, outgoing).offset(sign * via_distance);
+            incoming_spread.intersect(&outgoing_spread)?
+        };
+        let (spread_out, spread_in) = if on_left {
+            (incoming.left_45_dir(), outgoing.right_45_dir())
+        } else {
+            (incoming.right_45_dir(), outgoing.left_45_dir())
+        };
+        let jog_out = OctilinearLine::through(via_position, spread_out);
+        let jog_in = OctilinearLine::through(via_position, spread_in);
+        vertices.push((on_layer(jog_out.intersect(&last_line)?, run.layer), Some(incoming)));
+        vertices.push((
+            // each net takes its own cheapest via spanning the layer change
+            TraceAnchor {
+                position: via_position,
+                start_layer: run.layer,
+                end_layer: next_run.layer,
+                via_type: None,
+            },
+            Some(spread_out),
+        ));
+        vertices.push((on_layer(jog_in.intersect(&next_line)?, next_run.layer), Some(spread_in)));
+    }
+
+    // drop the zero length segments, then every segment must run in the direction it was built for
+    let mut anchors: Vec<TraceAnchor> = Vec::new();
+    let mut expected_directions: Vec<Option<Direction>> = Vec::new();
+    for (anchor, expected_direction) in vertices {
+        match anchors.last_mut() {
+            Some(last) if last.position == anchor.position => last.end_layer = anchor.end_layer,
+            _ => {
+                anchors.push(anchor);
+                expected_directions.push(expected_direction);
+            }
+        }
+    }
+    for i in 1..anchors.len() {
+        let direction = Direction::from_points(anchors[i - 1].position, anchors[i].position).ok()??;
+        if expected_directions[i].is_some_and(|expected| expected != direction) {
+            return None;
+        }
+    }
+    Some(anchors)
+}
+
+fn segment_length(start: FixedVec2, end: FixedVec2) -> f64 {
+    (end.to_float() - start.to_float()).length() as f64
+}
+
+/// an even-even point on segment `index` about `distance` from its start, strictly inside the segment,
+/// so that an A* search can end there and the rest of the segment stays octilinear
+fn cut_point(anchors: &[TraceAnchor], index: usize, distance: f64) -> Option<FixedVec2> {
+    let (start, end) = (anchors[index].position, anchors[index + 1].position);
+    let direction = Direction::from_points(start, end).ok()??;
+    let line = OctilinearLine::through(start, direction);
+    if line.k % 2 != 0 {
+        return None;
+    }
+    let t = distance / segment_length(start, end);
+    let target_x = start.x.to_bits() as f64 + (end.x.to_bits() - start.x.to_bits()) as f64 * t;
+    let target_y = start.y.to_bits() as f64 + (end.y.to_bits() - start.y.to_bits()) as f64 * t;
+    let nearest_even = |value: f64| 2 * (value / 2.0).round() as i64;
+    let (x, y) = if line.b == 0 {
+        (line.k / line.a, nearest_even(target_y))
+    } else {
+        let x = nearest_even(target_x);
+        (x, (line.k - line.a * x) / line.b)
+    };
+    let point = FixedVec2::new(FixedPoint::from_bits(x as i32), FixedPoint::from_bits(y as i32));
+    let inside = Direction::from_points(start, point).ok()? == Some(direction)
+        && Direction::from_points(point, end).ok()? == Some(direction);
+    inside.then_some(point)
+}
+
+/// the part of the coupled trace that is more than `start_distance` from its start and `end_distance` from its end,
+/// the rest is left to the breakout routes of the pads
+fn trim_coupled_trace(anchors: &[TraceAnchor], start_distance: f64, end_distance: f64) -> Option<Vec<TraceAnchor>> {
+    let lengths: Vec<f64> = anchors
+        .windows(2)
+        .map(|pair| segment_length(pair[0].position, pair[1].position))
+        .collect();
+    let total_length: f64 = lengths.iter().sum();
+    // the first cut point at or after the given distance from the start
+    let find_cut = |distance: f64| -> Option<(usize, FixedVec2)> {
+        let mut walked = 0.0;
+        for (index, length) in lengths.iter().enumerate() {
+            if walked + length > distance {
+                let along = f64::max(distance - walked, 0.0);
+                if let Some(point) = cut_point(anchors, index, along) {
+                    return Some((index, point));
+                }
+            }
+            walked += length;
+        }
+        None
+    };
+    let (start_index, start_point) = find_cut(start_distance)?;
+    let (end_index, end_point) = find_cut(total_length - end_distance)?;
+    if (end_index, segment_length(anchors[end_index].position, end_point))
+        <= (start_index, segment_length(anchors[start_index].position, start_point))
+    {
+        return None;
+    }
+    let layer_of = |index: usize| anchors[index].end_layer;
+    let mut trimmed = vec![TraceAnchor {
+        position: start_point,
+        start_layer: layer_of(start_index),
+        end_layer: layer_of(start_index),
+        via_type: None,
+    }];
+    trimmed.extend(anchors[start_index + 1..=end_index].iter().cloned());
+    trimmed.push(TraceAnchor {
+        position: end_point,
+        start_layer: layer_of(end_index),
+        end_layer: layer_of(end_index),
+        via_type: None,
+    });
+    Some(trimmed)
+}
+
+// the distance from the midpoint of two pads to the far side of their clearance areas
+fn pad_region_radius(midpoint: FloatVec2, pads: [&Pad; 2]) -> f64 {
+    pads.iter()
+        .map(|pad| {
+            let clearance_colliders: Vec<Collider> = pad
+                .to_clearance_shapes()
+                .iter()
+                .map(Collider::from_prim_shape)
+                .collect();
+            let (min, max) = bounding_box(&clearance_colliders);
+            [min, max, FloatVec2::new(min.x, max.y), FloatVec2::new(max.x, min.y)]
+                .iter()
+                .map(|corner| (*corner - midpoint).length())
+                .fold(0.0, f32::max)
+        })
+        .fold(0.0, f32::max) as f64
+}
+
+fn reversed_anchors(anchors: &[TraceAnchor]) -> Vec<TraceAnchor> {
+    anchors
+        .iter()
+        .rev()
+        .map(|anchor| TraceAnchor {
+            position: anchor.position,
+            start_layer: anchor.end_layer,
+            end_layer: anchor.start_layer,
+            via_type: anchor.via_type,
+        })
+        .collect()
+}
+
+// pads of every net except the excluded ones and the given traces, as A* obstacles,
+// the via rules are those of the first excluded net, the nets of a pair share them
+pub(crate) struct PairObstacles {
+    other_nets: Obstacles,
+    via_obstacles: Rc<ViaObstacles>,
+}
+
+pub(crate) fn obstacles_excluding(problem: &PcbProblem, excluded_nets: &[&NetName], traces: &[&TracePath]) -> PairObstacles {
+    PairObstacles {
+        other_nets: obstacles_of_other_nets(problem, excluded_nets, traces.iter().copied()),
+        via_obstacles: Rc::new(ViaObstacles::new(problem, excluded_nets[0], traces.iter().copied())),
+    }
+}
+
+pub(crate) fn is_legal(problem: &PcbProblem, obstacles: &PairObstacles, trace_path: &TracePath) -> bool {
+    AStarCheck {
+        border_colliders: AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center),
+        obstacle_colliders: obstacles.other_nets.colliders.clone(),
+        obstacle_clearance_colliders: obstacles.other_nets.clearance_colliders.clone(),
+        solution_trace: trace_path.clone(),
+        num_layers: problem.num_layers,
+    }
+    .check()
+}
+
+fn common_layers(a: PadLayer, b: PadLayer) -> Option<PadLayer> {
+    match (a, b) {
+        (PadLayer::All, other) | (other, PadLayer::All) => Some(other),
+        (PadLayer::Front, PadLayer::Front) => Some(PadLayer::Front),
+        (PadLayer::Back, PadLayer::Back) => Some(PadLayer::Back),
+        _ => None,
+    }
+}
+
+// A* can only start and end on the outer layers
+fn outer_pad_layer(layer: usize, num_layers: usize) -> Option<PadLayer> {
+    if layer == 0 {
+        Some(PadLayer::Front)
+    } else if layer == num_layers - 1 {
+        Some(PadLayer::Back)
+    } else {
+        None
+    }
+}
+
+fn single_connection(problem: &PcbProblem, net_name: &NetName) -> Option<Rc<Connection>> {
+    let connections = &problem.nets[net_name].connections;
+    if connections.len() != 1 {
+        return None;
+    }
+    connections.values().next().cloned()
+}
+
+// the two ways to reach `end` from `start` with one diagonal and one straight segment
+fn direct_connections(start: FixedVec2, end: FixedVec2) -> [Vec<FixedVec2>; 2] {
+    let dx = end.x.to_bits() as i64 - start.x.to_bits() as i64;
+    let dy = end.y.to_bits() as i64 - start.y.to_bits() as i64;
+    let diagonal = i64::min(dx.abs(), dy.abs());
+    let diagonal_move = FixedVec2::new(
+        FixedPoint::from_bits((dx.signum() * diagonal) as i32),
+        FixedPoint::from_bits((dy.signum() * diagonal) as i32),
+    );
+    [start + diagonal_move, end - diagonal_move].map(|corner| {
+        let mut points = vec![start];
+        if corner != start && corner != end {
+            points.push(corner);
+        }
+        points.push(end);
+        points
+    })
+}
+
+// the layers and vias both nets allow, the length is checked on the finished traces
+// because the centre line is shorter or longer than either of them
+fn pair_routing_constraints(positive_info: &NetInfo, negative_info: &NetInfo) -> RoutingConstraints {
+    let positive = &positive_info.routing_constraints;
+    let negative = &negative_info.routing_constraints;
+    let allowed_layers = match (&positive.allowed_layers, &negative.allowed_layers) {
+        (Some(positive_layers), Some(negative_layers)) => Some(
+            positive_layers
+                .iter()
+                .filter(|layer| negative_layers.contains(layer))
+                .cloned()
+                .collect(),
+        ),
+        (Some(layers), None) | (None, Some(layers)) => Some(layers.clone()),
+        (None, None) => None,
+    };
+    let max_vias = match (positive.max_vias, negative.max_vias) {
+        (Some(positive_vias), Some(negative_vias)) => Some(usize::min(positive_vias, negative_vias)),
+        (max_vias, None) | (None, max_vias) => max_vias,
+    };
+    RoutingConstraints {
+        allowed_layers,
+        max_vias,
+        max_length: None,
+    }
+}
+
+// the vias both nets can place side by side, widened like the centre line trace,
+// a centre line via spans the layers of the vias of both nets
+fn pair_via_types(positive_info: &NetInfo, negative_info: &NetInfo, via_pitch: f32) -> Vec<ViaType> {
+    let mut via_types: Vec<ViaType> = positive_info
+        .via_types
+        .iter()
+        .filter_map(|positive_type| {
+            let negative_type = negative_info
+                .via_types
+                .iter()
+                .find(|negative_type| negative_type.spans(positive_type.min_layer, positive_type.max_layer))?;
+            Some(ViaType {
+                name: positive_type.name.clone(),
+                diameter: via_pitch + f32::max(positive_type.diameter, negative_type.diameter),
+                min_layer: usize::min(positive_type.min_layer, negative_type.min_layer),
+                max_layer: usize::max(positive_type.max_layer, negative_type.max_layer),
+                drill: None, // no single hole, the drill spacing is kept over the whole width
+            })
+        })
+        .collect();
+    via_types.sort_by(|a, b| {
+        a.num_spanned_layers()
+            .cmp(&b.num_spanned_layers())
+            .then(a.diameter.total_cmp(&b.diameter))
+    });
+    via_types
+}
+
+/// the short uncoupled route from a pad to one end of the coupled part of its trace,
+/// a direct connection when one is free, otherwise an A* search
+fn route_breakout(
+    problem: &PcbProblem,
+    pad: &Pad,
+    coupled_end: &TraceAnchor,
+    net_info: &NetInfo,
+    obstacles: &PairObstacles,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<TraceAnchor>, String> {
+    let start = pad.position.to_fixed().to_nearest_even_even();
+    if pad.pad_layer.get_iter(problem.num_layers).any(|layer| layer == coupled_end.start_layer) {
+        for points in direct_connections(start, coupled_end.position) {
+            let anchors: Vec<TraceAnchor> = points
+                .into_iter()
+                .map(|position| TraceAnchor {
+                    position,
+                    start_layer: coupled_end.start_layer,
+                    end_layer: coupled_end.start_layer,
+                    via_type: None,
+                })
+                .collect();
+            let trace_path = TracePath::from_anchors(
+                TraceAnchors(anchors.clone()),
+                net_info.trace_width,
+                net_info.trace_clearance,
+                &net_info.via_types,
+            );
+            if is_legal(problem, obstacles, &trace_path) {
+                return Ok(anchors);
+            }
+        }
+    }
+    let end_layers = outer_pad_layer(coupled_end.start_layer, problem.num_layers)
+        .ok_or("the coupled traces leave the pads on an inner layer".to_string())?;
+    let astar_model = AStarModel {
+        start: PadEntry::at(start),
+        end: PadEntry::at(coupled_end.position),
+        start_layers: pad.pad_layer,
+        end_layers,
+        num_layers: problem.num_layers,
+        trace_width: net_info.trace_width,
+        trace_clearance: net_info.trace_clearance,
+        via_types: net_info.via_types.clone(),
+        width: problem.width,
+        height: problem.height,
+        center: problem.center,
+        obstacle_shapes: obstacles.other_nets.shapes.clone(),
+        obstacle_clearance_shapes: obstacles.other_nets.clearance_shapes.clone(),
+        obstacle_colliders: obstacles.other_nets.colliders.clone(),
+        obstacle_clearance_colliders: obstacles.other_nets.clearance_colliders.clone(),
+        border_colliders_cache: RefCell::new(None),
+        border_shapes_cache: RefCell::new(None),
+        congestion_costs: None,
+        routing_constraints: net_info.routing_constraints.clone(),
+        via_obstacles: obstacles.via_obstacles.clone(),
+        layer_directions: problem.layer_directions.clone(),
+        blocking_traces: None,
+    };
+    let trace_path = astar_model
+        .run(pcb_render_model, cancellation_token)
+        .map_err(|err| format!("no breakout from pad {}: {}", pad.name.0, err))?
+        .trace_path;
+    Ok(trace_path.anchors.0)
+}
+
+/// the full trace of one net: breakout from the start pad, the coupled part, breakout to the end pad
+fn connect_to_pads(
+    problem: &PcbProblem,
+    pads: [&Pad; 2],
+    coupled: &[TraceAnchor],
+    net_info: &NetInfo,
+    obstacles: &PairObstacles,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    cancellation_token: &CancellationToken,
+) -> Result<TracePath, String> {
+    let start_breakout = route_breakout(
+        problem,
+        pads[0],
+        coupled.first().unwrap(),
+        net_info,
+        obstacles,
+        pcb_render_model.clone(),
+        cancellation_token,
+    )?;
+    let end_breakout = reversed_anchors(&route_breakout(
+        problem,
+        pads[1],
+        coupled.last().unwrap(),
+        net_info,
+        obstacles,
+        pcb_render_model,
+        cancellation_token,
+    )?);
+    let mut anchors: Vec<TraceAnchor> = start_breakout[..start_breakout.len() - 1].to_vec();
+    let mut coupled = coupled.to_vec();
+    coupled.first_mut().unwrap().start_layer = start_breakout.last().unwrap().start_layer;
+    coupled.last_mut().unwrap().end_layer = end_breakout.first().unwrap().end_layer;
+    anchors.extend(coupled);
+    anchors.extend(end_breakout[1..].iter().cloned());
+    Ok(TracePath::from_anchors(
+        TraceAnchors(anchors),
+        net_info.trace_width,
+        net_info.trace_clearance,
+        &net_info.via_types,
+    ))
+}
+
+/// routes one pair along a shared centre line, returns the positive and the negative trace
+fn route_pair(
+    problem: &PcbProblem,
+    positive_net: &NetName,
+    constraint: &DiffPairConstraint,
+    routed_traces: &[&TracePath],
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    cancellation_token: &CancellationToken,
+) -> Result<(FixedTrace, FixedTrace), String> {
+    let negative_net = &constraint.partner;
+    let (Some(positive_connection), Some(negative_connection)) =
+        (single_connection(problem, positive_net), single_connection(problem, negative_net))
+    else {
+        return Err("both nets of a pair need exactly one connection".to_string());
+    };
+    let positive_info = &problem.nets[positive_net];
+    let negative_info = &problem.nets[negative_net];
+    let positive_pads = [
+        &positive_info.pads[&positive_connection.start_pad],
+        &positive_info.pads[&positive_connection.end_pad],
+    ];
+    let mut negative_pads = [
+        &negative_info.pads[&negative_connection.start_pad],
+        &negative_info.pads[&negative_connection.end_pad],
+    ];
+    // pair up the pads that sit next to each other
+    let distance = |a: FloatVec2, b: FloatVec2| (a - b).length();
+    if distance(positive_pads[0].position, negative_pads[0].position)
+        + distance(positive_pads[1].position, negative_pads[1].position)
+        > distance(positive_pads[0].position, negative_pads[1].position)
+            + distance(positive_pads[1].position, negative_pads[0].position)
+    {
+        negative_pads.swap(0, 1);
+    }
+    let (Some(start_layers), Some(end_layers)) = (
+        common_layers(positive_pads[0].pad_layer, negative_pads[0].pad_layer),
+        common_layers(positive_pads[1].pad_layer, negative_pads[1].pad_layer),
+    ) else {
+        return Err("the pads of the pair are on different layers".to_string());
+    };
+
+    let trace_width = f32::max(positive_info.trace_width, negative_info.trace_width);
+    let trace_clearance = f32::max(positive_info.trace_clearance, negative_info.trace_clearance);
+    let via_diameter = positive_info
+        .via_types
+        .iter()
+        .chain(negative_info.via_types.iter())
+        .map(|via_type| via_type.diameter)
+        .fold(0.0, f32::max);
+    // the pair is checked like any two nets, so the gap cannot be below the clearance, the small margin
+    // keeps the traces from touching the clearance area after the offsets are rounded to fixed point
+    let gap = f32::max(constraint.gap, trace_clearance) + trace_width * 0.01;
+    let trace_pitch = trace_width + gap;
+    let via_pitch = f32::max(trace_pitch, via_diameter + trace_clearance);
+
+    // the centre line is searched as one wide trace covering both traces and the gap between them
+    let obstacles = obstacles_excluding(problem, &[positive_net, negative_net], routed_traces);
+    let midpoints = [0, 1].map(|end| {
+        ((positive_pads[end].position + negative_pads[end].position) / 2.0)
+            .to_fixed()
+            .to_nearest_even_even()
+    });
+    let astar_model = AStarModel {
+        start: PadEntry::at(midpoints[0]),
+        end: PadEntry::at(midpoints[1]),
+        start_layers,
+        end_layers,
+        num_layers: problem.num_layers,
+        trace_width: trace_pitch + trace_width,
+        trace_clearance,
+        via_types: pair_via_types(positive_info, negative_info, via_pitch),
+        width: problem.width,
+        height: problem.height,
+        center: problem.center,
+        obstacle_shapes: obstacles.other_nets.shapes.clone(),
+        obstacle_clearance_shapes: obstacles.other_nets.clearance_shapes.clone(),
+        obstacle_colliders: obstacles.other_nets.colliders.clone(),
+        obstacle_clearance_colliders: obstacles.other_nets.clearance_colliders.clone(),
+        border_colliders_cache: RefCell::new(None),
+        border_shapes_cache: RefCell::new(None),
+        congestion_costs: None,
+        routing_constraints: pair_routing_constraints(positive_info, negative_info),
+        via_obstacles: obstacles.via_obstacles.clone(),
+        layer_directions: problem.layer_directions.clone(),
+        blocking_traces: None,
+    };
+    let centre_line = astar_model
+        .run(pcb_render_model.clone(), cancellation_token)
+        .map_err(|err| format!("no path for the centre line: {}", err))?
+        .trace_path;
+    let runs = centre_runs(&centre_line, trace_width as f64);
+    if runs.is_empty() {
+        return Err("the centre line has no segments".to_string());
+    }
+
+    // the positive net keeps the side its pads are on, pads in line with the centre line do not tell the side
+    let first_direction = runs[0].directions[0].to_int_vec2();
+    let last_direction = runs.last().unwrap().directions.last().unwrap().to_int_vec2();
+    let cross = |direction: IntVec2, from: FloatVec2, to: FloatVec2| {
+        direction.x as f32 * (to.y - from.y) - direction.y as f32 * (to.x - from.x)
+    };
+    let start_side = cross(first_direction, negative_pads[0].position, positive_pads[0].position);
+    let end_side = cross(last_direction, negative_pads[1].position, positive_pads[1].position);
+    let is_inline = |side: f32| side.abs() < trace_width / 2.0;
+    if !is_inline(start_side) && !is_inline(end_side) && (start_side > 0.0) != (end_side > 0.0) {
+        return Err("the traces would have to cross between the pads".to_string());
+    }
+    let positive_on_left = if is_inline(start_side) { end_side >= 0.0 } else { start_side > 0.0 };
+
+    // the coupled part stops where the pads are, the pads are reached by short breakout routes
+    let breakouts = [0, 1].map(|end| {
+        pad_region_radius(midpoints[end].to_float(), [positive_pads[end], negative_pads[end]])
+            + (trace_width + trace_clearance) as f64
+    });
+    let coupled = |on_left: bool| {
+        coupled_trace(&runs, on_left, trace_pitch as f64 / 2.0, via_pitch as f64 / 2.0)
+            .and_then(|anchors| trim_coupled_trace(&anchors, breakouts[0], breakouts[1]))
+            .ok_or("the centre line is too short or turns too sharply to couple the traces".to_string())
+    };
+    let positive_coupled = coupled(positive_on_left)?;
+    let negative_coupled = coupled(!positive_on_left)?;
+    let coupled_path = |anchors: &[TraceAnchor], net_info: &NetInfo| {
+        TracePath::from_anchors(
+            TraceAnchors(anchors.to_vec()),
+            net_info.trace_width,
+            net_info.trace_clearance,
+            &net_info.via_types,
+        )
+    };
+
+    // each trace has to keep its clearance to the other one, to the partner pads and to everything else
+    let negative_coupled_path = coupled_path(&negative_coupled, negative_info);
+    let mut positive_obstacle_traces: Vec<&TracePath> = routed_traces.to_vec();
+    positive_obstacle_traces.push(&negative_coupled_path);
+    let positive_breakout_obstacles = obstacles_excluding(problem, &[positive_net], &positive_obstacle_traces);
+    let mut positive_trace = connect_to_pads(
+        problem,
+        positive_pads,
+        &positive_coupled,
+        positive_info,
+        &positive_breakout_obstacles,
+        pcb_render_model.clone(),
+        cancellation_token,
+    )?;
+    let mut negative_obstacle_traces: Vec<&TracePath> = routed_traces.to_vec();
+    negative_obstacle_traces.push(&positive_trace);
+    let negative_obstacles = obstacles_excluding(problem, &[negative_net], &negative_obstacle_traces);
+    let mut negative_trace = connect_to_pads(
+        problem,
+        negative_pads,
+        &negative_coupled,
+        negative_info,
+        &negative_obstacles,
+        pcb_render_model,
+        cancellation_token,
+    )?;
+    if !is_legal(problem, &negative_obstacles, &negative_trace) {
+        return Err("the negative trace collides with its surroundings".to_string());
+    }
+    let mut positive_obstacle_traces: Vec<&TracePath> = routed_traces.to_vec();
+    positive_obstacle_traces.push(&negative_trace);
+    let positive_obstacles = obstacles_excluding(problem, &[positive_net], &positive_obstacle_traces);
+    if !is_legal(problem, &positive_obstacles, &positive_trace) {
+        return Err("the positive trace collides with its surroundings".to_string());
+    }
+
+    // match the lengths with bumps on the outer side of the shorter trace
+    let skew = positive_trace.total_length - negative_trace.total_length;
+    if skew.abs() > constraint.max_skew as f64 {
+        let style = |on_left: bool| MeanderStyle {
+            shape: MeanderShape::Trombone,
+            side: if on_left { MeanderSide::Left } else { MeanderSide::Right },
+            max_height: 2.0 * trace_pitch,
+            spacing: trace_width + trace_clearance,
+        };
+        if skew > 0.0 {
+            negative_trace = add_meanders(
+                &negative_trace,
+                skew,
+                constraint.max_skew as f64,
+                style(!positive_on_left),
+                &negative_info.via_types,
+                |candidate| is_legal(problem, &negative_obstacles, candidate),
+            );
+        } else {
+            positive_trace = add_meanders(
+                &positive_trace,
+                -skew,
+                constraint.max_skew as f64,
+                style(positive_on_left),
+                &positive_info.via_types,
+                |candidate| is_legal(problem, &positive_obstacles, candidate),
+            );
+        }
+    }
+    let residual_skew = (positive_trace.total_length - negative_trace.total_length).abs();
+    if residual_skew > constraint.max_skew as f64 {
+        println!(
+            "Warning: differential pair {} / {} keeps a skew of {:.4}, the limit is {:.4}",
+            positive_net.0, negative_net.0, residual_skew, constraint.max_skew
+        );
+    }
+
+    Ok((
+        FixedTrace {
+            net_name: positive_net.clone(),
+            connection_id: positive_connection.connection_id,
+            trace_path: positive_trace,
+        },
+        FixedTrace {
+            net_name: negative_net.clone(),
+            connection_id: negative_connection.connection_id,
+            trace_path: negative_trace,
+        },
+    ))
+}
+
+/// routes the differential pairs of the problem before the other nets, around the given fixed traces,
+/// which are returned along with the pairs, a pair with a net that already has fixed traces is left as it is,
+/// a pair that cannot be routed as coupled traces is left to the solver like any other net and returned as uncoupled
+pub fn route_differential_pairs(
+    problem: &PcbProblem,
+    fixed_traces: &HashMap<ConnectionID, FixedTrace>,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    cancellation_token: &CancellationToken,
+) -> (HashMap<ConnectionID, FixedTrace>, Vec<UncoupledDiffPair>) {
+    let fixed_nets: HashSet<&NetName> = fixed_traces.values().map(|fixed_trace| &fixed_trace.net_name).collect();
+    let mut pairs: Vec<(&NetName, &DiffPairConstraint)> = problem
+        .nets
+        .iter()
+        .filter_map(|(net_name, net_info)| net_info.diff_pair.as_ref().map(|constraint| (net_name, constraint)))
+        .filter(|(_, constraint)| constraint.is_positive)
+        .filter(|(net_name, constraint)| !fixed_nets.contains(net_name) && !fixed_nets.contains(&constraint.partner))
+        .collect();
+    pairs.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+    let mut routed: HashMap<ConnectionID, FixedTrace> = fixed_traces.clone();
+    let mut uncoupled_pairs: Vec<UncoupledDiffPair> = Vec::new();
+    for (positive_net, constraint) in pairs {
+        if cancellation_token.is_cancelled() {
+            break;
+        }
+        let routed_traces: Vec<&TracePath> = routed.values().map(|fixed_trace| &fixed_trace.trace_path).collect();
+        match route_pair(
+            problem,
+            positive_net,
+            constraint,
+            &routed_traces,
+            pcb_render_model.clone(),
+            cancellation_token,
+        ) {
+            Ok((positive_trace, negative_trace)) => {
+                println!(
+                    "Routed differential pair {} / {}, lengths {:.4} and {:.4}",
+                    positive_net.0,
+                    constraint.partner.0,
+                    positive_trace.trace_path.total_length,
+                    negative_trace.trace_path.total_length
+                );
+                routed.insert(positive_trace.connection_id, positive_trace);
+                routed.insert(negative_trace.connection_id, negative_trace);
+            }
+            Err(err) => {
+                println!(
+                    "Cannot route differential pair {} / {} as coupled traces, routing the nets separately: {}",
+                    positive_net.0, constraint.partner.0, err
+                );
+                uncoupled_pairs.push(UncoupledDiffPair {
+                    positive_net: positive_net.clone(),
+                    negative_net: constraint.partner.clone(),
+                    reason: err,
+                });
+            }
+        }
+    }
+    (routed, uncoupled_pairs)
+}
@@ -0,0 +1,126 @@
+use std::num::NonZeroUsize;
+
+use shared::{
+    hyperparameters::{
+        DIVERSITY_LAYER_DISTANCE, DIVERSITY_MAX_ATTEMPTS, DIVERSITY_PENALTY, DIVERSITY_PENALTY_GROWTH,
+        ITERATION_TO_MIN_DISSIMILARITY,
+    },
+    trace_path::TracePath,
+    vec2::FloatVec2,
+};
+
+// the distance from one trace to the other is sampled at most this many times per segment
+const MAX_SAMPLES_PER_SEGMENT: usize = 64;
+
+/// how far apart the candidate traces of one connection are pushed when new candidates are sampled
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiversityConfig {
+    pub penalty: f64,        // extra cost per unit length through the cells of the earlier candidates, 0 turns the penalty off
+    pub penalty_growth: f64, // the penalty is multiplied by this after each candidate that is too similar
+    pub max_attempts: usize, // searches per new candidate before a too similar one is dropped, the first is not penalized
+    pub min_dissimilarity: Vec<f64>, // by iteration from 1, in trace pitches, later iterations use the last value
+    pub layer_distance: f64, // in trace pitches, added to the distance between parts of two traces on different layers
+}
+
+impl Default for DiversityConfig {
+    fn default() -> Self {
+        DiversityConfig {
+            penalty: DIVERSITY_PENALTY,
+            penalty_growth: DIVERSITY_PENALTY_GROWTH,
+            max_attempts: DIVERSITY_MAX_ATTEMPTS,
+            min_dissimilarity: ITERATION_TO_MIN_DISSIMILARITY.to_vec(),
+            layer_distance: DIVERSITY_LAYER_DISTANCE,
+        }
+    }
+}
+
+impl DiversityConfig {
+    /// the smallest dissimilarity, in trace pitches, between a new candidate of the iteration and the earlier ones
+    pub fn min_dissimilarity(&self, iteration: NonZeroUsize) -> f64 {
+        let index = (iteration.get() - 1).min(self.min_dissimilarity.len().saturating_sub(1));
+        self.min_dissimilarity.get(index).copied().unwrap_or(0.0)
+    }
+
+    /// the smallest dissimilarity between the trace and the given candidates, in float units,
+    /// infinite when there are no candidates
+    pub fn closest<'a>(
+        &self,
+        trace_path: &TracePath,
+        candidates: impl IntoIterator<Item = &'a TracePath>,
+        pitch: f32,
+    ) -> f64 {
+        let layer_distance = (self.layer_distance * pitch as f64) as f32;
+        candidates
+            .into_iter()
+            .map(|candidate| dissimilarity(trace_path, candidate, layer_distance))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// whether the trace is at least the minimum dissimilarity of the iteration away from every candidate
+    pub fn is_dissimilar<'a>(
+        &self,
+        trace_path: &TracePath,
+        candidates: impl IntoIterator<Item = &'a TracePath>,
+        pitch: f32,
+        iteration: NonZeroUsize,
+    ) -> bool {
+        let min_dissimilarity = self.min_dissimilarity(iteration) * pitch as f64;
+        min_dissimilarity <= 0.0 || self.closest(trace_path, candidates, pitch) >= min_dissimilarity
+    }
+}
+
+/// the area between two traces divided by the length of the longer one, the mean distance between them
+pub fn dissimilarity(a: &TracePath, b: &TracePath, layer_distance: f32) -> f64 {
+    let length = f64::max(a.total_length, b.total_length);
+    if length <= f64::EPSILON {
+        return 0.0;
+    }
+    area_between(a, b, layer_distance) / length
+}
+
+/// the area between two traces, the distance to the other trace integrated along each of them and averaged,
+/// parts of the traces on different layers are layer_distance farther apart than they look from above
+pub fn area_between(a: &TracePath, b: &TracePath, layer_distance: f32) -> f64 {
+    (one_sided_area(a, b, layer_distance) + one_sided_area(b, a, layer_distance)) / 2.0
+}
+
+fn one_sided_area(from: &TracePath, to: &TracePath, layer_distance: f32) -> f64 {
+    if to.segments.is_empty() {
+        return 0.0;
+    }
+    let mut area = 0.0;
+    for segment in from.segments.iter() {
+        let start = segment.start.to_float();
+        let end = segment.end.to_float();
+        let length = (end - start).length();
+        if length <= f32::EPSILON {
+            continue;
+        }
+        let num_samples = ((length / segment.width.max(f32::EPSILON)).ceil() as usize).clamp(1, MAX_SAMPLES_PER_SEGMENT);
+        let step_length = length / num_samples as f32;
+        for sample in 0..num_samples {
+            let t = (sample as f32 + 0.5) / num_samples as f32;
+            let point = start + (end - start) * t;
+            let distance = to
+                .segments
+                .iter()
+                .map(|other| {
+                    let planar = distance_to_segment(point, other.start.to_float(), other.end.to_float());
+                    if other.layer == segment.layer { planar } else { planar + layer_distance }
+                })
+                .fold(f32::MAX, f32::min);
+            area += (distance * step_length) as f64;
+        }
+    }
+    area
+}
+
+fn distance_to_segment(point: FloatVec2, start: FloatVec2, end: FloatVec2) -> f32 {
+    let segment = end - start;
+    let length2 = segment.magnitude2();
+    if length2 == 0.0 {
+        return (point - start).length();
+    }
+    let t = ((point - start).dot(segment) / length2).clamp(0.0, 1.0);
+    (point - (start + segment * t)).length()
+}
@@ -0,0 +1,786 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::Write,
+};
+
+use shared::{
+    collider::{BorderCollider, Collider},
+    pad::PadName,
+    pcb_problem::{ConnectionID, NetName, PcbProblem, PcbSolution},
+    pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable},
+    prim_shape::{CircleShape, PrimShape, RectangleShape},
+    trace_path::Via,
+    vec2::FloatVec2,
+};
+
+use crate::{
+    astar::AStarModel,
+    via_rules::{drill_gap, pad_blocks_vias, via_drill},
+};
+
+// numerical tolerance for width and distance comparisons
+const DRC_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DrcViolationKind {
+    Clearance,      // copper of two different nets closer than the clearance
+    Short,          // copper of two different nets overlapping
+    BoardEdge,      // copper closer to the board edge than the edge clearance
+    Keepout,        // copper inside a keepout polygon
+    MinTraceWidth,  // trace narrower than the width of its net
+    MinViaDiameter, // via smaller than the vias of its net spanning the same layers
+    ViaSpacing,     // holes of two vias closer than the drill spacing
+    ViaInPad,       // via inside a pad of its own net that does not allow vias
+    AnnularRing,    // via with less copper around its hole than the annular ring
+    ViaKeepout,     // via under a component that keeps vias out
+    UnknownNet,     // trace of a net the problem does not have, its rules cannot be checked
+}
+
+impl DrcViolationKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DrcViolationKind::Clearance => "clearance",
+            DrcViolationKind::Short => "short",
+            DrcViolationKind::BoardEdge => "board_edge",
+            DrcViolationKind::Keepout => "keepout",
+            DrcViolationKind::MinTraceWidth => "min_trace_width",
+            DrcViolationKind::MinViaDiameter => "min_via_diameter",
+            DrcViolationKind::ViaSpacing => "via_spacing",
+            DrcViolationKind::ViaInPad => "via_in_pad",
+            DrcViolationKind::AnnularRing => "annular_ring",
+            DrcViolationKind::ViaKeepout => "via_keepout",
+            DrcViolationKind::UnknownNet => "unknown_net",
+        }
+    }
+}
+
+/// a piece of the board involved in a violation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DrcObject {
+    Trace {
+        net_name: NetName,
+        connection_id: ConnectionID,
+    },
+    Via {
+        net_name: NetName,
+        connection_id: ConnectionID,
+    },
+    Pad {
+        net_name: NetName,
+        pad_name: PadName,
+    },
+    BoardEdge,
+    Keepout(usize), // index into PcbProblem::obstacle_polygons
+    Component(String), // a component that keeps vias out, by reference
+    Pour {
+        net_name: NetName,
+        layer: usize,
+    },
+}
+
+impl Display for DrcObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrcObject::Trace {
+                net_name,
+                connection_id,
+            } => write!(f, "trace({}, connection {})", net_name.0, connection_id.0),
+            DrcObject::Via {
+                net_name,
+                connection_id,
+            } => write!(f, "via({}, connection {})", net_name.0, connection_id.0),
+            DrcObject::Pad { net_name, pad_name } => {
+                write!(f, "pad({}, {})", net_name.0, pad_name.0)
+            }
+            DrcObject::BoardEdge => write!(f, "board_edge"),
+            DrcObject::Keepout(index) => write!(f, "keepout({})", index),
+            DrcObject::Component(reference) => write!(f, "component({})", reference),
+            DrcObject::Pour { net_name, layer } => write!(f, "pour({}, layer {})", net_name.0, layer),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DrcViolation {
+    pub kind: DrcViolationKind,
+    pub objects: Vec<DrcObject>,
+    pub location: FloatVec2, // approximate position of the violation, in problem units
+    pub layers: Vec<usize>,  // layers the violation occurs on, sorted
+    pub message: String,
+}
+
+impl Display for DrcViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| object.to_string())
+            .collect::<Vec<_>>()
+            .join(" and ");
+        write!(
+            f,
+            "{} at ({:.3}, {:.3}) on layers {:?}: {}, {}",
+            self.kind.name(),
+            self.location.x,
+            self.location.y,
+            self.layers,
+            objects,
+            self.message
+        )
+    }
+}
+
+pub const DRC_CSV_HEADER: &str = "kind,x,y,layers,objects,message";
+
+impl DrcViolation {
+    pub fn to_csv_row(&self) -> String {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| layer.to_string())
+            .collect::<Vec<_>>()
+            .join(";");
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| object.to_string())
+            .collect::<Vec<_>>()
+            .join(";");
+        format!(
+            "{},{},{},{},\"{}\",\"{}\"",
+            self.kind.name(),
+            self.location.x,
+            self.location.y,
+            layers,
+            objects.replace('"', "\"\""),
+            self.message.replace('"', "\"\"")
+        )
+    }
+    pub fn to_renderables(&self, marker_diameter: f32) -> Vec<ShapeRenderable> {
+        vec![ShapeRenderable {
+            shape: PrimShape::Circle(CircleShape {
+                position: self.location,
+                diameter: marker_diameter,
+            }),
+            color: [1.0, 0.0, 0.0, 0.6], // red marker
+        }]
+    }
+}
+
+pub struct DrcOptions {
+    pub board_edge_clearance: f32, // minimum distance from copper to the board edge
+}
+
+impl Default for DrcOptions {
+    fn default() -> Self {
+        DrcOptions {
+            board_edge_clearance: 0.0,
+        }
+    }
+}
+
+/// a trace segment, a via or a pad, flattened for the pairwise checks
+pub(crate) struct DrcItem {
+    pub(crate) object: DrcObject,
+    pub(crate) net_name: NetName,
+    pub(crate) layers: Vec<usize>,
+    pub(crate) colliders: Vec<Collider>,
+    pub(crate) clearance_colliders: Vec<Collider>,
+    pub(crate) skeleton: (FloatVec2, FloatVec2), // the centre line, start == end for vias and pads
+    pub(crate) bounding_box: (FloatVec2, FloatVec2), // min and max of the clearance colliders
+}
+
+impl DrcItem {
+    pub(crate) fn is_pad(&self) -> bool {
+        matches!(self.object, DrcObject::Pad { .. })
+    }
+    pub(crate) fn bounding_boxes_overlap(&self, other: &DrcItem) -> bool {
+        let (self_min, self_max) = self.bounding_box;
+        let (other_min, other_max) = other.bounding_box;
+        self_min.x <= other_max.x
+            && other_min.x <= self_max.x
+            && self_min.y <= other_max.y
+            && other_min.y <= self_max.y
+    }
+    pub(crate) fn common_layers(&self, other: &DrcItem) -> Vec<usize> {
+        self.layers
+            .iter()
+            .filter(|layer| other.layers.contains(layer))
+            .cloned()
+            .collect()
+    }
+}
+
+pub(crate) fn any_collision(colliders: &[Collider], other_colliders: &[Collider]) -> bool {
+    colliders.iter().any(|collider| {
+        other_colliders
+            .iter()
+            .any(|other_collider| collider.collides_with(other_collider))
+    })
+}
+
+pub(crate) fn bounding_box(colliders: &[Collider]) -> (FloatVec2, FloatVec2) {
+    let mut min = FloatVec2::new(f32::MAX, f32::MAX);
+    let mut max = FloatVec2::new(f32::MIN, f32::MIN);
+    let mut extend = |point: FloatVec2, radius: f32| {
+        min.x = min.x.min(point.x - radius);
+        min.y = min.y.min(point.y - radius);
+        max.x = max.x.max(point.x + radius);
+        max.y = max.y.max(point.y + radius);
+    };
+    for collider in colliders {
+        match collider {
+            Collider::Circle(circle) => extend(circle.position, circle.diameter / 2.0),
+            Collider::Polygon(polygon) => {
+                for vertex in polygon.0.iter() {
+                    extend(*vertex, 0.0);
+                }
+            }
+            Collider::Border(_) => panic!("Border colliders have no bounding box"),
+        }
+    }
+    (min, max)
+}
+
+pub(crate) fn closest_point_on_segment(point: FloatVec2, start: FloatVec2, end: FloatVec2) -> FloatVec2 {
+    let segment = end - start;
+    let length2 = segment.magnitude2();
+    if length2 <= f32::EPSILON {
+        return start;
+    }
+    let t = ((point - start).dot(segment) / length2).clamp(0.0, 1.0);
+    FloatVec2::new(start.x + segment.x * t, start.y + segment.y * t)
+}
+
+/// midpoint between the closest points of two centre lines, used as the violation location
+pub(crate) fn closest_location(
+    (a_start, a_end): (FloatVec2, FloatVec2),
+    (b_start, b_end): (FloatVec2, FloatVec2),
+) -> FloatVec2 {
+    let candidates = [
+        (a_start, closest_point_on_segment(a_start, b_start, b_end)),
+        (a_end, closest_point_on_segment(a_end, b_start, b_end)),
+        (closest_point_on_segment(b_start, a_start, a_end), b_start),
+        (closest_point_on_segment(b_end, a_start, a_end), b_end),
+    ];
+    let (a, b) = candidates
+        .iter()
+        .min_by(|(a1, b1), (a2, b2)| {
+            (*a1 - *b1)
+                .magnitude2()
+                .partial_cmp(&(*a2 - *b2).magnitude2())
+                .unwrap()
+        })
+        .cloned()
+        .unwrap();
+    (a + b) / 2.0
+}
+
+/// signed distance from a disc to a border, positive when the disc is inside the board
+fn distance_to_border(border: &BorderCollider, position: FloatVec2, radius: f32) -> f32 {
+    -(position - border.point_on_border).dot(border.normal.normalize()) - radius
+}
+
+pub(crate) fn collect_items(problem: &PcbProblem, solution: &PcbSolution) -> Vec<DrcItem> {
+    let mut items: Vec<DrcItem> = Vec::new();
+    for net_info in problem.nets.values() {
+        for pad in net_info.pads.values() {
+            let colliders: Vec<Collider> = pad
+                .to_shapes()
+                .iter()
+                .map(Collider::from_prim_shape)
+                .collect();
+            let clearance_colliders: Vec<Collider> = pad
+                .to_clearance_shapes()
+                .iter()
+                .map(Collider::from_prim_shape)
+                .collect();
+            items.push(DrcItem {
+                object: DrcObject::Pad {
+                    net_name: net_info.net_name.clone(),
+                    pad_name: pad.name.clone(),
+                },
+                net_name: net_info.net_name.clone(),
+                layers: pad.pad_layer.get_iter(problem.num_layers).collect(),
+                bounding_box: bounding_box(&clearance_colliders),
+                colliders,
+                clearance_colliders,
+                skeleton: (pad.position, pad.position),
+            });
+        }
+    }
+    for fixed_trace in solution.determined_traces.values() {
+        for segment in fixed_trace.trace_path.segments.iter() {
+            let clearance_colliders = segment.to_clearance_colliders();
+            items.push(DrcItem {
+                object: DrcObject::Trace {
+                    net_name: fixed_trace.net_name.clone(),
+                    connection_id: fixed_trace.connection_id,
+                },
+                net_name: fixed_trace.net_name.clone(),
+                layers: vec![segment.layer],
+                bounding_box: bounding_box(&clearance_colliders),
+                colliders: segment.to_colliders(),
+                clearance_colliders,
+                skeleton: (segment.start.to_float(), segment.end.to_float()),
+            });
+        }
+        for via in fixed_trace.trace_path.vias.iter() {
+            let clearance_colliders = vec![via.to_clearance_collider()];
+            items.push(DrcItem {
+                object: DrcObject::Via {
+                    net_name: fixed_trace.net_name.clone(),
+                    connection_id: fixed_trace.connection_id,
+                },
+                net_name: fixed_trace.net_name.clone(),
+                layers: (via.min_layer..=via.max_layer).collect(),
+                bounding_box: bounding_box(&clearance_colliders),
+                colliders: vec![via.to_collider()],
+                clearance_colliders,
+                skeleton: (via.position.to_float(), via.position.to_float()),
+            });
+        }
+    }
+    for pour in solution.copper_pours.iter() {
+        for shape in pour.to_shapes() {
+            let clearance_colliders = shape_clearance_colliders(&shape, pour.clearance);
+            let skeleton = match &shape {
+                PrimShape::Circle(circle) => (circle.position, circle.position),
+                PrimShape::Rectangle(rectangle) => (rectangle.position, rectangle.position),
+                PrimShape::Line(line) => (line.start, line.end),
+            };
+            items.push(DrcItem {
+                object: DrcObject::Pour {
+                    net_name: pour.net_name.clone(),
+                    layer: pour.layer,
+                },
+                net_name: pour.net_name.clone(),
+                layers: vec![pour.layer],
+                bounding_box: bounding_box(&clearance_colliders),
+                colliders: vec![Collider::from_prim_shape(&shape)],
+                clearance_colliders,
+                skeleton,
+            });
+        }
+    }
+    items
+}
+
+// an axis aligned rectangle grown by the clearance with round corners, as two rectangles and four discs
+fn rectangle_clearance_colliders(rectangle: &RectangleShape, clearance: f32) -> Vec<Collider> {
+    let (half_width, half_height) = (rectangle.width / 2.0, rectangle.height / 2.0);
+    let mut shapes = vec![
+        PrimShape::Rectangle(RectangleShape {
+            width: rectangle.width + clearance * 2.0,
+            ..rectangle.clone()
+        }),
+        PrimShape::Rectangle(RectangleShape {
+            height: rectangle.height + clearance * 2.0,
+            ..rectangle.clone()
+        }),
+    ];
+    for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        shapes.push(PrimShape::Circle(CircleShape {
+            position: rectangle.position + FloatVec2::new(x * half_width, y * half_height),
+            diameter: clearance * 2.0,
+        }));
+    }
+    shapes.iter().map(Collider::from_prim_shape).collect()
+}
+
+// the shape grown by the clearance, a line grows into a rectangle with round ends
+fn shape_clearance_colliders(shape: &PrimShape, clearance: f32) -> Vec<Collider> {
+    match shape {
+        PrimShape::Circle(circle) => vec![Collider::from_prim_shape(&PrimShape::Circle(CircleShape {
+            position: circle.position,
+            diameter: circle.diameter + clearance * 2.0,
+        }))],
+        PrimShape::Rectangle(rectangle) => rectangle_clearance_colliders(rectangle, clearance),
+        PrimShape::Line(line) => {
+            let direction = line.end - line.start;
+            let mut shapes = vec![
+                PrimShape::Circle(CircleShape {
+                    position: line.start,
+                    diameter: clearance * 2.0,
+                }),
+                PrimShape::Circle(CircleShape {
+                    position: line.end,
+                    diameter: clearance * 2.0,
+                }),
+            ];
+            if direction.length() > 0.0 {
+                shapes.push(PrimShape::Rectangle(RectangleShape {
+                    position: (line.start + line.end) / 2.0,
+                    width: direction.length(),
+                    height: clearance * 2.0,
+                    rotation: cgmath::Deg(direction.y.atan2(direction.x).to_degrees()),
+                }));
+            }
+            shapes.iter().map(Collider::from_prim_shape).collect()
+        }
+    }
+}
+
+/// collects violations, merging repeated ones between the same objects
+struct ViolationCollector {
+    violations: Vec<DrcViolation>,
+    index: HashMap<(DrcViolationKind, Vec<DrcObject>), usize>,
+}
+
+impl ViolationCollector {
+    fn push(&mut self, violation: DrcViolation) {
+        let key = (violation.kind, violation.objects.clone());
+        if let Some(&index) = self.index.get(&key) {
+            let existing = &mut self.violations[index];
+            for layer in violation.layers {
+                if !existing.layers.contains(&layer) {
+                    existing.layers.push(layer);
+                }
+            }
+            existing.layers.sort();
+            return;
+        }
+        self.index.insert(key, self.violations.len());
+        self.violations.push(violation);
+    }
+}
+
+/// checks a whole solution against the design rules of the problem
+pub fn check_pcb_solution(
+    problem: &PcbProblem,
+    solution: &PcbSolution,
+    options: &DrcOptions,
+) -> Vec<DrcViolation> {
+    let items = collect_items(problem, solution);
+    let mut collector = ViolationCollector {
+        violations: Vec::new(),
+        index: HashMap::new(),
+    };
+
+    // trace-trace, trace-pad, trace-via, via-via and pad-via between different nets
+    for (i, item) in items.iter().enumerate() {
+        for other in items[i + 1..].iter() {
+            if item.net_name == other.net_name || (item.is_pad() && other.is_pad()) {
+                continue;
+            }
+            if !item.bounding_boxes_overlap(other) {
+                continue;
+            }
+            let layers = item.common_layers(other);
+            if layers.is_empty() {
+                continue;
+            }
+            let kind = if any_collision(&item.colliders, &other.colliders) {
+                DrcViolationKind::Short
+            } else if any_collision(&item.clearance_colliders, &other.colliders)
+                || any_collision(&item.colliders, &other.clearance_colliders)
+            {
+                DrcViolationKind::Clearance
+            } else {
+                continue;
+            };
+            let message = match kind {
+                DrcViolationKind::Short => "copper of different nets overlaps".to_string(),
+                _ => "copper of different nets is closer than the clearance".to_string(),
+            };
+            collector.push(DrcViolation {
+                kind,
+                objects: vec![item.object.clone(), other.object.clone()],
+                location: closest_location(item.skeleton, other.skeleton),
+                layers,
+                message,
+            });
+        }
+    }
+
+    let mut borders: Vec<BorderCollider> =
+        AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center)
+            .iter()
+            .filter_map(|collider| match collider {
+                Collider::Border(border) => Some(border.clone()),
+                _ => None,
+            })
+            .collect();
+    borders.extend(problem.obstacle_borders.iter().cloned());
+
+    for fixed_trace in solution.determined_traces.values() {
+        let trace_object = DrcObject::Trace {
+            net_name: fixed_trace.net_name.clone(),
+            connection_id: fixed_trace.connection_id,
+        };
+        // a session file or an earlier solution may hold traces of nets the board no longer has
+        let Some(net_info) = problem.nets.get(&fixed_trace.net_name) else {
+            let mut layers: Vec<usize> = fixed_trace.trace_path.segments.iter().map(|segment| segment.layer).collect();
+            layers.sort();
+            layers.dedup();
+            collector.push(DrcViolation {
+                kind: DrcViolationKind::UnknownNet,
+                objects: vec![trace_object],
+                location: fixed_trace
+                    .trace_path
+                    .anchors
+                    .0
+                    .first()
+                    .map(|anchor| anchor.position.to_float())
+                    .unwrap_or(FloatVec2::new(0.0, 0.0)),
+                layers,
+                message: format!("net {} is not in the problem, the width and via rules are not checked", fixed_trace.net_name.0),
+            });
+            continue;
+        };
+        let via_object = DrcObject::Via {
+            net_name: fixed_trace.net_name.clone(),
+            connection_id: fixed_trace.connection_id,
+        };
+        // (object, position, radius, layers) of every copper disc on the edge of the trace
+        let mut discs: Vec<(DrcObject, FloatVec2, f32, Vec<usize>)> = Vec::new();
+        for segment in fixed_trace.trace_path.segments.iter() {
+            if segment.width < net_info.trace_width - DRC_EPSILON {
+                collector.push(DrcViolation {
+                    kind: DrcViolationKind::MinTraceWidth,
+                    objects: vec![trace_object.clone()],
+                    location: (segment.start.to_float() + segment.end.to_float()) / 2.0,
+                    layers: vec![segment.layer],
+                    message: format!(
+                        "trace width {} is less than the net width {}",
+                        segment.width, net_info.trace_width
+                    ),
+                });
+            }
+            for point in [segment.start, segment.end] {
+                discs.push((trace_object.clone(), point.to_float(), segment.width / 2.0, vec![segment.layer]));
+            }
+        }
+        for via in fixed_trace.trace_path.vias.iter() {
+            let layers: Vec<usize> = (via.min_layer..=via.max_layer).collect();
+            // the smallest via of the net that spans the same layers
+            let min_via_diameter = net_info
+                .via_types
+                .iter()
+                .filter(|via_type| via_type.spans(via.min_layer, via.max_layer))
+                .map(|via_type| via_type.diameter)
+                .reduce(f32::min)
+                .unwrap_or(net_info.via_diameter);
+            if via.diameter < min_via_diameter - DRC_EPSILON {
+                collector.push(DrcViolation {
+                    kind: DrcViolationKind::MinViaDiameter,
+                    objects: vec![via_object.clone()],
+                    location: via.position.to_float(),
+                    layers: layers.clone(),
+                    message: format!(
+                        "via diameter {} is less than the net via diameter {}",
+                        via.diameter, min_via_diameter
+                    ),
+                });
+            }
+            discs.push((via_object.clone(), via.position.to_float(), via.diameter / 2.0, layers));
+        }
+        // a trace is the union of discs swept along its segments, so checking the endpoints is enough
+        for (object, position, radius, layers) in discs.iter() {
+            for border in borders.iter() {
+                let distance = distance_to_border(border, *position, *radius);
+                if distance < options.board_edge_clearance - DRC_EPSILON {
+                    collector.push(DrcViolation {
+                        kind: DrcViolationKind::BoardEdge,
+                        objects: vec![object.clone(), DrcObject::BoardEdge],
+                        location: *position,
+                        layers: layers.clone(),
+                        message: format!(
+                            "copper is {:.4} from the board edge, minimum is {}",
+                            distance, options.board_edge_clearance
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    // keepouts apply to every layer
+    for (keepout_index, keepout) in problem.obstacle_polygons.iter().enumerate() {
+        let keepout_collider = Collider::Polygon(keepout.clone());
+        for item in items.iter().filter(|item| !item.is_pad()) {
+            if item
+                .colliders
+                .iter()
+                .any(|collider| collider.collides_with(&keepout_collider))
+            {
+                let (start, end) = item.skeleton;
+                collector.push(DrcViolation {
+                    kind: DrcViolationKind::Keepout,
+                    objects: vec![item.object.clone(), DrcObject::Keepout(keepout_index)],
+                    location: (start + end) / 2.0,
+                    layers: item.layers.clone(),
+                    message: "copper inside a keepout".to_string(),
+                });
+            }
+        }
+    }
+    check_via_rules(problem, solution, &mut collector);
+    collector.violations
+}
+
+/// the board wide via rules: annular ring, drill spacing, vias in pads of their own net and component keepouts
+fn check_via_rules(problem: &PcbProblem, solution: &PcbSolution, collector: &mut ViolationCollector) {
+    let rules = &problem.via_rules;
+    let mut vias: Vec<(DrcObject, &NetName, &Via)> = Vec::new();
+    for fixed_trace in solution.determined_traces.values() {
+        for via in fixed_trace.trace_path.vias.iter() {
+            let object = DrcObject::Via {
+                net_name: fixed_trace.net_name.clone(),
+                connection_id: fixed_trace.connection_id,
+            };
+            vias.push((object, &fixed_trace.net_name, via));
+        }
+    }
+    for (i, (object, net_name, via)) in vias.iter().enumerate() {
+        let position = via.position.to_float();
+        let layers: Vec<usize> = (via.min_layer..=via.max_layer).collect();
+        if let (Some(min_annular_ring), Some(drill)) = (rules.min_annular_ring, via.drill) {
+            let annular_ring = (via.diameter - drill) / 2.0;
+            if annular_ring < min_annular_ring - DRC_EPSILON {
+                collector.push(DrcViolation {
+                    kind: DrcViolationKind::AnnularRing,
+                    objects: vec![object.clone()],
+                    location: position,
+                    layers: layers.clone(),
+                    message: format!(
+                        "annular ring {} is less than the minimum {}",
+                        annular_ring, min_annular_ring
+                    ),
+                });
+            }
+        }
+        if let Some(min_drill_spacing) = rules.min_drill_spacing {
+            for (other_object, _, other) in vias[i + 1..].iter() {
+                if via.max_layer < other.min_layer || other.max_layer < via.min_layer {
+                    continue;
+                }
+                let other_position = other.position.to_float();
+                let gap = drill_gap(position, via_drill(via), other_position, via_drill(other));
+                if gap < min_drill_spacing - DRC_EPSILON {
+                    collector.push(DrcViolation {
+                        kind: DrcViolationKind::ViaSpacing,
+                        objects: vec![object.clone(), other_object.clone()],
+                        location: (position + other_position) / 2.0,
+                        layers: (usize::max(via.min_layer, other.min_layer)
+                            ..=usize::min(via.max_layer, other.max_layer))
+                            .collect(),
+                        message: format!(
+                            "drill holes are {:.4} apart, minimum is {}",
+                            gap, min_drill_spacing
+                        ),
+                    });
+                }
+            }
+        }
+        let via_collider = Collider::from_prim_shape(&PrimShape::Circle(CircleShape {
+            position,
+            diameter: via.diameter,
+        }));
+        if let Some(net_info) = problem.nets.get(*net_name) {
+            for pad in net_info.pads.values().filter(|pad| pad_blocks_vias(rules, pad)) {
+                let pad_layers: Vec<usize> = pad
+                    .pad_layer
+                    .get_iter(problem.num_layers)
+                    .filter(|layer| layers.contains(layer))
+                    .collect();
+                if pad_layers.is_empty() {
+                    continue;
+                }
+                if pad
+                    .to_shapes()
+                    .iter()
+                    .any(|shape| Collider::from_prim_shape(shape).collides_with(&via_collider))
+                {
+                    collector.push(DrcViolation {
+                        kind: DrcViolationKind::ViaInPad,
+                        objects: vec![
+                            object.clone(),
+                            DrcObject::Pad {
+                                net_name: (*net_name).clone(),
+                                pad_name: pad.name.clone(),
+                            },
+                        ],
+                        location: position,
+                        layers: pad_layers,
+                        message: "via inside a pad of its own net".to_string(),
+                    });
+                }
+            }
+        }
+        for keepout in rules.keepouts.iter() {
+            if layers.contains(&keepout.layer)
+                && Collider::from_prim_shape(&keepout.shape).collides_with(&via_collider)
+            {
+                collector.push(DrcViolation {
+                    kind: DrcViolationKind::ViaKeepout,
+                    objects: vec![object.clone(), DrcObject::Component(keepout.component.clone())],
+                    location: position,
+                    layers: vec![keepout.layer],
+                    message: format!("via under component {}", keepout.component),
+                });
+            }
+        }
+    }
+}
+
+pub fn write_drc_report(violations: &[DrcViolation], path: &str) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    writeln!(file, "{}", DRC_CSV_HEADER)?;
+    for violation in violations {
+        writeln!(file, "{}", violation.to_csv_row())?;
+    }
+    Ok(())
+}
+
+// grey for a net the problem does not have, see DrcViolationKind::UnknownNet
+fn net_color(problem: &PcbProblem, net_name: &NetName, alpha: f32) -> [f32; 4] {
+    match problem.nets.get(net_name) {
+        Some(net_info) => net_info.color.to_float4(alpha),
+        None => [0.5, 0.5, 0.5, alpha],
+    }
+}
+
+/// the solution with a red marker on every violation, for the viewer
+pub fn drc_to_pcb_render_model(
+    problem: &PcbProblem,
+    solution: &PcbSolution,
+    violations: &[DrcViolation],
+) -> PcbRenderModel {
+    let mut trace_shape_renderables: Vec<RenderableBatch> = Vec::new();
+    let mut pad_shape_renderables: Vec<ShapeRenderable> = Vec::new();
+    let mut other_shape_renderables: Vec<ShapeRenderable> = Vec::new();
+    for net_info in problem.nets.values() {
+        for pad in net_info.pads.values() {
+            pad_shape_renderables.extend(pad.to_renderables(net_info.color.to_float4(1.0)));
+            pad_shape_renderables.extend(pad.to_clearance_renderables(net_info.color.to_float4(0.5)));
+        }
+    }
+    for pour in solution.copper_pours.iter() {
+        let color = net_color(problem, &pour.net_name, 0.3);
+        trace_shape_renderables.push(RenderableBatch(pour.to_renderables(color)));
+    }
+    for fixed_trace in solution.determined_traces.values() {
+        let color = net_color(problem, &fixed_trace.net_name, 1.0);
+        trace_shape_renderables.extend(fixed_trace.trace_path.to_renderables(color));
+    }
+    for line in &problem.obstacle_border_outlines {
+        other_shape_renderables.push(ShapeRenderable {
+            shape: PrimShape::Line(line.clone()),
+            color: [1.0, 0.0, 1.0, 1.0], // magenta color for borders
+        });
+    }
+    let marker_diameter = f32::max(problem.width, problem.height) / 50.0;
+    for violation in violations {
+        other_shape_renderables.extend(violation.to_renderables(marker_diameter));
+    }
+    PcbRenderModel {
+        width: problem.width,
+        height: problem.height,
+        center: problem.center,
+        trace_shape_renderables,
+        pad_shape_renderables,
+        other_shape_renderables,
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::{BTreeSet, HashMap}, rc::Rc};
+
+use shared::{
+    hyperparameters::{EXACT_SELECTION_MAX_CANDIDATES, EXACT_SELECTION_MAX_NODES},
+    pcb_problem::{ConnectionID, PcbProblem},
+};
+
+use crate::proba_model::{ProbaModel, ProbaTrace, ProbaTraceID, Traces};
+
+/// the outcome of choosing one candidate trace for every connection that is not fixed yet
+#[derive(Debug)]
+pub enum ExactSelection {
+    Selected(HashMap<ConnectionID, Rc<ProbaTrace>>), // no two selected traces collide, the highest total score unless the search ran out of nodes
+    Insufficient(Vec<ConnectionID>), // no combination of the candidates works, these connections ran out of candidates
+    TooLarge,                        // too many candidates or search nodes to decide
+}
+
+struct Search {
+    variable_candidates: Vec<Vec<usize>>,     // by variable, its candidates sorted by posterior, highest first
+    conflicts: Vec<Vec<usize>>,               // by candidate, the candidates of other variables it collides with
+    scores: Vec<f64>,                         // by candidate
+    blocked: Vec<usize>,                      // by candidate, how many selected candidates collide with it
+    assignment: Vec<Option<usize>>,           // by variable
+    best: Option<(f64, Vec<usize>)>,          // total score and candidate of every variable
+    dead_ends: BTreeSet<usize>,               // variables left without a candidate somewhere in the search
+    nodes: usize,
+}
+
+impl Search {
+    fn open_candidates(&self, variable: usize) -> impl Iterator<Item = usize> + '_ {
+        self.variable_candidates[variable]
+            .iter()
+            .copied()
+            .filter(|&candidate| self.blocked[candidate] == 0)
+    }
+
+    /// false when the node budget runs out
+    fn run(&mut self, score: f64) -> bool {
+        self.nodes += 1;
+        if self.nodes > EXACT_SELECTION_MAX_NODES {
+            return false;
+        }
+        // branch on the variable with the fewest open candidates, it fails first
+        let mut branch_variable: Option<(usize, usize)> = None;
+        let mut bound = score;
+        for variable in 0..self.assignment.len() {
+            if self.assignment[variable].is_some() {
+                continue;
+            }
+            let open_count = self.open_candidates(variable).count();
+            if open_count == 0 {
+                self.dead_ends.insert(variable);
+                return true;
+            }
+            bound += self
+                .open_candidates(variable)
+                .map(|candidate| self.scores[candidate])
+                .fold(f64::MIN, f64::max);
+            if branch_variable.is_none_or(|(_, fewest)| open_count < fewest) {
+                branch_variable = Some((variable, open_count));
+            }
+        }
+        let Some((variable, _)) = branch_variable else {
+            if self.best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+                let selection = self.assignment.iter().map(|candidate| candidate.unwrap()).collect();
+                self.best = Some((score, selection));
+            }
+            return true;
+        };
+        if self.best.as_ref().is_some_and(|(best_score, _)| bound <= *best_score) {
+            return true;
+        }
+        let open: Vec<usize> = self.open_candidates(variable).collect();
+        for candidate in open {
+            self.assignment[variable] = Some(candidate);
+            for &other in self.conflicts[candidate].iter() {
+                self.blocked[other] += 1;
+            }
+            let within_budget = self.run(score + self.scores[candidate]);
+            for &other in self.conflicts[candidate].iter() {
+                self.blocked[other] -= 1;
+            }
+            self.assignment[variable] = None;
+            if !within_budget {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// chooses one candidate for every connection without a fixed trace, such that no two chosen candidates collide
+/// and their total score is the highest, by branch and bound over the candidates of the model
+pub fn select_exact(problem: &PcbProblem, proba_model: &ProbaModel) -> ExactSelection {
+    let mut connection_traces: Vec<(ConnectionID, Vec<Rc<ProbaTrace>>)> = proba_model
+        .connection_to_traces
+        .iter()
+        .filter_map(|(connection_id, traces)| match traces {
+            Traces::Probabilistic(trace_map) => {
+                let mut proba_traces: Vec<Rc<ProbaTrace>> = trace_map.values().cloned().collect();
+                proba_traces.sort_by(|a, b| {
+                    b.get_posterior_with_fallback()
+                        .total_cmp(&a.get_posterior_with_fallback())
+                        .then(a.proba_trace_id.cmp(&b.proba_trace_id))
+                });
+                Some((*connection_id, proba_traces))
+            }
+            Traces::Fixed(_) => None,
+        })
+        .collect();
+    connection_traces.sort_by_key(|(connection_id, _)| *connection_id);
+    let empty: Vec<ConnectionID> = connection_traces
+        .iter()
+        .filter(|(_, proba_traces)| proba_traces.is_empty())
+        .map(|(connection_id, _)| *connection_id)
+        .collect();
+    if !empty.is_empty() {
+        return ExactSelection::Insufficient(empty);
+    }
+    let num_candidates: usize = connection_traces.iter().map(|(_, proba_traces)| proba_traces.len()).sum();
+    if num_candidates > EXACT_SELECTION_MAX_CANDIDATES {
+        return ExactSelection::TooLarge;
+    }
+
+    let mut candidate_index: HashMap<ProbaTraceID, usize> = HashMap::new();
+    let mut candidates: Vec<Rc<ProbaTrace>> = Vec::new();
+    let mut candidate_variables: Vec<usize> = Vec::new();
+    let mut variable_candidates: Vec<Vec<usize>> = Vec::new();
+    for (variable, (_, proba_traces)) in connection_traces.iter().enumerate() {
+        let mut indices: Vec<usize> = Vec::new();
+        for proba_trace in proba_traces.iter() {
+            candidate_index.insert(proba_trace.proba_trace_id, candidates.len());
+            indices.push(candidates.len());
+            candidates.push(proba_trace.clone());
+            candidate_variables.push(variable);
+        }
+        variable_candidates.push(indices);
+    }
+    let conflicts: Vec<Vec<usize>> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, proba_trace)| {
+            let mut conflicts: Vec<usize> = proba_model
+                .collision_adjacency
+                .get(&proba_trace.proba_trace_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|adjacent_trace_id| candidate_index.get(adjacent_trace_id).copied())
+                .filter(|&other| candidate_variables[other] != candidate_variables[index])
+                .collect();
+            conflicts.sort();
+            conflicts
+        })
+        .collect();
+    let scores: Vec<f64> = candidates
+        .iter()
+        .map(|proba_trace| problem.trace_scorer.score(problem, &proba_trace.net_name, &proba_trace.trace_path))
+        .collect();
+
+    let mut search = Search {
+        blocked: vec![0; candidates.len()],
+        assignment: vec![None; variable_candidates.len()],
+        variable_candidates,
+        conflicts,
+        scores,
+        best: None,
+        dead_ends: BTreeSet::new(),
+        nodes: 0,
+    };
+    let finished = search.run(0.0);
+    println!(
+        "Exact selection over {} candidates of {} connections: {} nodes{}",
+        candidates.len(),
+        connection_traces.len(),
+        search.nodes,
+        if finished { "" } else { ", out of nodes" }
+    );
+    match search.best {
+        Some((_, selection)) => ExactSelection::Selected(
+            selection
+                .into_iter()
+                .map(|candidate| (candidates[candidate].connection_id, candidates[candidate].clone()))
+                .collect(),
+        ),
+        None if finished => ExactSelection::Insufficient(
+            search
+                .dead_ends
+                .iter()
+                .map(|&variable| connection_traces[variable].0)
+                .collect(),
+        ),
+        None => ExactSelection::TooLarge,
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::Deg;
+use shared::{
+    pad::{Pad, PadLayer, PadName, PadShape},
+    pcb_problem::{ConnectionID, FixedTrace, NetInfo, NetName, PcbProblem, PcbSolution},
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, Via},
+    vec2::{FixedPoint, FixedVec2, FloatVec2},
+};
+
+use crate::{
+    diff_pair::{is_legal, obstacles_excluding},
+    via_rules::ViaObstacles,
+};
+
+// a part needs a fanout when it has at least this many pads
+const FANOUT_MIN_PADS: usize = 16;
+// and its pads are at most this far apart, in float units
+const FANOUT_MAX_PITCH: f32 = 1.0;
+// and at least half of its pads have this many neighbours one pitch away, as in a grid array, rows of pins have two
+const FANOUT_MIN_GRID_NEIGHBOURS: usize = 3;
+// a dogbone via is tried up to this many half pitches away from its pad
+const FANOUT_MAX_HALF_PITCHES: usize = 4;
+
+/// the short route from an smd pad of a dense part to a through via, locked before the main solver runs
+#[derive(Debug, Clone)]
+pub struct Fanout {
+    pub pad: Pad,                   // the pad as it is in the problem
+    pub anchors: Vec<TraceAnchor>, // from the pad centre to the via, the last anchor is the via
+    pub trace_path: TracePath,      // the stub and its via, as copper
+}
+
+impl Fanout {
+    fn via_position(&self) -> FixedVec2 {
+        self.anchors.last().unwrap().position
+    }
+}
+
+/// the fanouts of every dense part, by net and pad
+#[derive(Debug, Clone, Default)]
+pub struct Fanouts {
+    pub fanouts: HashMap<(NetName, PadName), Fanout>,
+}
+
+// the pads of one part whose pins are too close together to route between them from the pad centres
+struct DensePart {
+    center: FloatVec2,
+    pitch: f32,
+    pads: Vec<(NetName, PadName)>,
+}
+
+// pads are named <component>-<pin>
+fn component_name(pad_name: &PadName) -> &str {
+    pad_name.0.rsplit_once('-').map_or(pad_name.0.as_str(), |(component, _)| component)
+}
+
+// the median distance from a pad to its nearest neighbour
+fn pitch(positions: &[FloatVec2]) -> f32 {
+    let mut nearest: Vec<f32> = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            positions
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| (*other - *position).length())
+                .fold(f32::MAX, f32::min)
+        })
+        .collect();
+    nearest.sort_by(|a, b| a.total_cmp(b));
+    nearest[nearest.len() / 2]
+}
+
+// whether the pads form a 2-d grid like a bga, not the rows of pins of a tssop or qfp
+fn is_grid_array(positions: &[FloatVec2], pitch: f32) -> bool {
+    let grid_pads = positions
+        .iter()
+        .filter(|position| {
+            let neighbours = positions
+                .iter()
+                .filter(|other| {
+                    let distance = (**other - **position).length();
+                    distance > 0.0 && distance < pitch * 1.2
+                })
+                .count();
+            neighbours >= FANOUT_MIN_GRID_NEIGHBOURS
+        })
+        .count();
+    grid_pads * 2 >= positions.len()
+}
+
+fn dense_parts(problem: &PcbProblem) -> Vec<DensePart> {
+    let mut components: HashMap<&str, Vec<(&NetName, &Pad)>> = HashMap::new();
+    for (net_name, net_info) in problem.nets.iter() {
+        for pad in net_info.pads.values() {
+            components.entry(component_name(&pad.name)).or_default().push((net_name, pad));
+        }
+    }
+    let mut component_names: Vec<&str> = components.keys().copied().collect();
+    component_names.sort();
+    let mut parts: Vec<DensePart> = Vec::new();
+    for component in component_names {
+        let pads = &components[component];
+        if pads.len() < FANOUT_MIN_PADS {
+            continue;
+        }
+        let positions: Vec<FloatVec2> = pads.iter().map(|(_, pad)| pad.position).collect();
+        let pitch = pitch(&positions);
+        if pitch > FANOUT_MAX_PITCH || !is_grid_array(&positions, pitch) {
+            continue;
+        }
+        let center = positions.iter().fold(FloatVec2::new(0.0, 0.0), |sum, position| sum + *position)
+            / positions.len() as f32;
+        // the outer pads escape first, the inner pads find their way out between the outer fanouts
+        let mut pads: Vec<(NetName, PadName)> =
+            pads.iter().map(|(net_name, pad)| ((*net_name).clone(), pad.name.clone())).collect();
+        let distance_from_center = |(net_name, pad_name): &(NetName, PadName)| {
+            (problem.nets[net_name].pads[pad_name].position - center).length()
+        };
+        pads.sort_by(|a, b| {
+            distance_from_center(b)
+                .total_cmp(&distance_from_center(a))
+                .then(a.1.cmp(&b.1))
+        });
+        println!(
+            "Fanout: part {} has {} pads at a pitch of {:.4}",
+            component,
+            pads.len(),
+            pitch
+        );
+        parts.push(DensePart { center, pitch, pads });
+    }
+    parts
+}
+
+// where a dogbone via may go from its pad, the ones pointing away from the part first, closer ones before farther ones,
+// each offset is a 90 or 45 degree step of even fixed point length, so the stub from the even pad position stays octilinear
+fn dogbone_offsets(pad: &Pad, part: &DensePart) -> Vec<FixedVec2> {
+    let outward = pad.position - part.center;
+    let outward = if outward.length() > 0.0 {
+        outward / outward.length()
+    } else {
+        FloatVec2::new(1.0, 0.0)
+    };
+    let mut offsets: Vec<(f32, FixedVec2)> = Vec::new();
+    for direction in [
+        Direction::Right,
+        Direction::TopRight,
+        Direction::Up,
+        Direction::TopLeft,
+        Direction::Left,
+        Direction::BottomLeft,
+        Direction::Down,
+        Direction::BottomRight,
+    ] {
+        let step = direction.to_int_vec2();
+        let step = FloatVec2::new(step.x as f32, step.y as f32);
+        let alignment = (step.x * outward.x + step.y * outward.y) / step.length();
+        for half_pitches in 1..=FANOUT_MAX_HALF_PITCHES {
+            // one diagonal step puts the via in the middle between four pads of a grid
+            let length = FixedPoint::from_num(half_pitches as f32 * part.pitch / 2.0);
+            let length = length - FixedPoint::from_bits(length.to_bits() & 1);
+            let offset = direction.to_fixed_vec2(length);
+            // sideways vias count double their distance, inward ones triple
+            offsets.push((offset.to_float().length() * (2.0 - alignment), offset));
+        }
+    }
+    offsets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    offsets.into_iter().map(|(_, offset)| offset).collect()
+}
+
+// the stub runs from the even pad position, the one the via position was offset from
+fn fanout_to(
+    net_info: &NetInfo,
+    pad: &Pad,
+    pad_position: FixedVec2,
+    via_position: FixedVec2,
+    via_type: usize,
+    num_layers: usize,
+) -> Fanout {
+    let pad_layer = match pad.pad_layer {
+        PadLayer::Back => num_layers - 1,
+        _ => 0,
+    };
+    let other_layer = num_layers - 1 - pad_layer;
+    // a via in the pad still gets its own anchor after the pad, so it stays inside the stitched trace
+    let mut anchors: Vec<TraceAnchor> = vec![TraceAnchor {
+        position: pad_position,
+        start_layer: pad_layer,
+        end_layer: pad_layer,
+        via_type: None,
+    }];
+    let via_anchor = TraceAnchor {
+        position: via_position,
+        start_layer: pad_layer,
+        end_layer: other_layer,
+        via_type: Some(via_type),
+    };
+    anchors.push(via_anchor.clone());
+    // the via is the last anchor, so from_anchors leaves it out
+    let mut trace_path = TracePath::from_anchors(
+        TraceAnchors(anchors.clone()),
+        net_info.trace_width,
+        net_info.trace_clearance,
+        &net_info.via_types,
+    );
+    trace_path
+        .vias
+        .push(Via::from_anchor(&via_anchor, net_info.trace_clearance, &net_info.via_types));
+    Fanout {
+        pad: pad.clone(),
+        anchors,
+        trace_path,
+    }
+}
+
+// the first legal fanout of a pad, via in pad when the rules allow it, otherwise a dogbone
+fn fanout_for_pad(
+    problem: &PcbProblem,
+    net_name: &NetName,
+    pad: &Pad,
+    via_type: usize,
+    part: &DensePart,
+    other_traces: &[&TracePath],
+    all_traces: &[&TracePath],
+) -> Option<Fanout> {
+    let net_info = &problem.nets[net_name];
+    let obstacles = obstacles_excluding(problem, &[net_name], other_traces);
+    let via_obstacles = ViaObstacles::new(problem, net_name, all_traces.iter().copied());
+    let pad_position = pad.position.to_fixed().to_nearest_even_even();
+    let mut via_positions: Vec<FixedVec2> = Vec::new();
+    if problem.via_rules.allow_via_in_smd_pad {
+        via_positions.push(pad_position);
+    }
+    via_positions.extend(
+        dogbone_offsets(pad, part)
+            .into_iter()
+            .map(|offset| pad_position + offset),
+    );
+    via_positions.into_iter().find_map(|via_position| {
+        let fanout = fanout_to(net_info, pad, pad_position, via_position, via_type, problem.num_layers);
+        (is_legal(problem, &obstacles, &fanout.trace_path) && via_obstacles.allows_vias(&fanout.trace_path))
+            .then_some(fanout)
+    })
+}
+
+/// finds the dense parts of the problem and fans out their smd pads to through vias,
+/// nets with prerouted traces, layer or via constraints, or without a through via are left alone
+pub fn plan_fanouts(problem: &PcbProblem, prerouted_traces: &HashMap<ConnectionID, FixedTrace>) -> Fanouts {
+    let mut fanouts = Fanouts::default();
+    let prerouted_nets: HashSet<&NetName> = prerouted_traces.values().map(|fixed_trace| &fixed_trace.net_name).collect();
+    for part in dense_parts(problem) {
+        for (net_name, pad_name) in part.pads.iter() {
+            let net_info = &problem.nets[net_name];
+            let pad = &net_info.pads[pad_name];
+            let is_connected = net_info
+                .connections
+                .values()
+                .any(|connection| &connection.start_pad == pad_name || &connection.end_pad == pad_name);
+            if !is_connected
+                || prerouted_nets.contains(net_name)
+                || net_info.routing_constraints.allowed_layers.is_some()
+                || net_info.routing_constraints.max_vias.is_some()
+                || matches!(pad.pad_layer, PadLayer::All)
+            {
+                continue;
+            }
+            let Some(via_type) = net_info
+                .via_types
+                .iter()
+                .position(|via_type| via_type.spans(0, problem.num_layers - 1))
+            else {
+                continue;
+            };
+            let mut other_traces: Vec<&TracePath> = prerouted_traces
+                .values()
+                .filter(|fixed_trace| &fixed_trace.net_name != net_name)
+                .map(|fixed_trace| &fixed_trace.trace_path)
+                .collect();
+            other_traces.extend(
+                fanouts
+                    .fanouts
+                    .iter()
+                    .filter(|((other_net, _), _)| other_net != net_name)
+                    .map(|(_, fanout)| &fanout.trace_path),
+            );
+            let mut all_traces: Vec<&TracePath> =
+                prerouted_traces.values().map(|fixed_trace| &fixed_trace.trace_path).collect();
+            all_traces.extend(fanouts.fanouts.values().map(|fanout| &fanout.trace_path));
+            match fanout_for_pad(problem, net_name, pad, via_type, &part, &other_traces, &all_traces) {
+                Some(fanout) => {
+                    fanouts.fanouts.insert((net_name.clone(), pad_name.clone()), fanout);
+                }
+                None => println!("Warning: no room for a fanout of pad {}, it is routed from the pad", pad_name.0),
+            }
+        }
+    }
+    if !fanouts.fanouts.is_empty() {
+        println!("Fanout: {} pads fanned out", fanouts.fanouts.len());
+    }
+    fanouts
+}
+
+impl Fanouts {
+    pub fn is_empty(&self) -> bool {
+        self.fanouts.is_empty()
+    }
+
+    /// the problem the main solver routes: each fanned out pad is replaced by a through pad at its via,
+    /// the pad itself and the stub stay as copper of the net under other names
+    pub fn apply(&self, problem: &PcbProblem) -> PcbProblem {
+        let mut problem = problem.clone();
+        for ((net_name, pad_name), fanout) in self.fanouts.iter() {
+            let net_info = problem.nets.get_mut(net_name).unwrap();
+            let via = fanout.trace_path.vias.last().unwrap();
+            net_info.pads.insert(
+                pad_name.clone(),
+                Pad {
+                    name: pad_name.clone(),
+                    position: fanout.via_position().to_float(),
+                    shape: PadShape::Circle { diameter: via.diameter },
+                    rotation: Deg(0.0),
+                    clearance: via.clearance,
+                    pad_layer: PadLayer::All,
+                },
+            );
+            let original_name = PadName(format!("{} fanout pad", pad_name.0));
+            net_info.pads.insert(
+                original_name.clone(),
+                Pad {
+                    name: original_name,
+                    ..fanout.pad.clone()
+                },
+            );
+            // the stub segment as a rectangular pad, its round ends are covered by the pad and the via
+            if let Some(segment) = fanout
+                .trace_path
+                .segments
+                .first()
+                .filter(|segment| segment.start != segment.end)
+            {
+                let start = segment.start.to_float();
+                let end = segment.end.to_float();
+                let delta = end - start;
+                let stub_name = PadName(format!("{} fanout stub", pad_name.0));
+                net_info.pads.insert(
+                    stub_name.clone(),
+                    Pad {
+                        name: stub_name,
+                        position: (start + end) / 2.0,
+                        shape: PadShape::Rectangle {
+                            width: delta.length(),
+                            height: segment.width,
+                        },
+                        rotation: Deg(delta.y.atan2(delta.x).to_degrees()),
+                        clearance: segment.clearance,
+                        pad_layer: fanout.pad.pad_layer,
+                    },
+                );
+            }
+        }
+        problem
+    }
+
+    /// puts the stubs back in front of and behind the traces the main solver routed between the fanout vias
+    pub fn stitch(&self, problem: &PcbProblem, mut solution: PcbSolution) -> PcbSolution {
+        for fixed_trace in solution.determined_traces.values_mut() {
+            let net_info = &problem.nets[&fixed_trace.net_name];
+            let Some(connection) = net_info.connections.get(&fixed_trace.connection_id) else {
+                continue;
+            };
+            let start_fanout = self.fanouts.get(&(fixed_trace.net_name.clone(), connection.start_pad.clone()));
+            let end_fanout = self.fanouts.get(&(fixed_trace.net_name.clone(), connection.end_pad.clone()));
+            if start_fanout.is_none() && end_fanout.is_none() {
+                continue;
+            }
+            let mut anchors: Vec<TraceAnchor> = fixed_trace.trace_path.anchors.0.clone();
+            if let Some(fanout) = start_fanout {
+                let via_anchor = fanout.anchors.last().unwrap();
+                let first = anchors.first_mut().unwrap();
+                first.position = via_anchor.position;
+                first.start_layer = via_anchor.start_layer;
+                first.via_type = via_anchor.via_type;
+                let mut stitched: Vec<TraceAnchor> = fanout.anchors[..fanout.anchors.len() - 1].to_vec();
+                stitched.extend(anchors);
+                anchors = stitched;
+            }
+            if let Some(fanout) = end_fanout {
+                let via_anchor = fanout.anchors.last().unwrap();
+                let last = anchors.last_mut().unwrap();
+                last.position = via_anchor.position;
+                last.end_layer = via_anchor.start_layer;
+                last.via_type = via_anchor.via_type;
+                anchors.extend(fanout.anchors[..fanout.anchors.len() - 1].iter().rev().cloned());
+            }
+            fixed_trace.trace_path = TracePath::from_anchors(
+                TraceAnchors(anchors),
+                net_info.trace_width,
+                net_info.trace_clearance,
+                &net_info.via_types,
+            );
+        }
+        solution
+    }
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, fmt::Display};
+
+use shared::hyperparameters::{
+    HALF_PROBABILITY_OPPORTUNITY_COST, INFERENCE_DAMPING, INFERENCE_MAX_ITERATIONS, INFERENCE_TOLERANCE,
+};
+
+// guards the division of a belief by a message, messages stay positive as long as the collision potential is
+const MIN_PROBABILITY: f64 = 1e-300;
+
+/// how the posteriors of the candidate traces are computed from their scores and their collisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InferenceEngine {
+    Heuristic,         // fixed rounds of prior * score * exp(-k * sum of the posteriors of the colliding traces)
+    MeanField,         // mean-field variational inference on the conflict graph
+    BeliefPropagation, // loopy belief propagation on the conflict graph
+}
+
+impl InferenceEngine {
+    pub fn from_name(name: &str) -> Result<InferenceEngine, String> {
+        match name.trim().to_lowercase().as_str() {
+            "heuristic" => Ok(InferenceEngine::Heuristic),
+            "mean_field" | "mean-field" | "meanfield" => Ok(InferenceEngine::MeanField),
+            "bp" | "belief_propagation" | "belief-propagation" => Ok(InferenceEngine::BeliefPropagation),
+            _ => Err(format!(
+                "Unknown inference engine: {}, expected one of: heuristic, mean_field, bp",
+                name
+            )),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            InferenceEngine::Heuristic => "heuristic",
+            InferenceEngine::MeanField => "mean_field",
+            InferenceEngine::BeliefPropagation => "bp",
+        }
+    }
+}
+
+impl Display for InferenceEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// the inference engine of the probabilistic model and when it stops iterating
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InferenceConfig {
+    pub engine: InferenceEngine,
+    pub damping: f64,          // share of the previous value kept at each update, 0 for undamped updates
+    pub tolerance: f64,        // converged when no marginal or message changes by more than this
+    pub max_iterations: usize, // sweeps over the graph before giving up on convergence
+}
+
+impl Default for InferenceConfig {
+    fn default() -> Self {
+        InferenceConfig {
+            engine: InferenceEngine::Heuristic,
+            damping: INFERENCE_DAMPING,
+            tolerance: INFERENCE_TOLERANCE,
+            max_iterations: INFERENCE_MAX_ITERATIONS,
+        }
+    }
+}
+
+/// a connection of the conflict graph, its states are its candidate traces followed by the state of no trace
+#[derive(Debug, Clone)]
+pub struct InferenceVariable {
+    pub unary: Vec<f64>, // unnormalized weight of every state, the last one is the state of no trace
+    pub conflicts: Vec<Vec<(usize, usize)>>, // by state, the (variable, state) pairs it collides with
+}
+
+impl InferenceVariable {
+    fn num_states(&self) -> usize {
+        self.unary.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InferenceResult {
+    pub marginals: Vec<Vec<f64>>, // by variable and state, every row sums to 1
+    pub iterations: usize,
+    pub converged: bool,
+    pub max_change: f64, // largest change in the last iteration
+}
+
+/// the weight of two colliding traces being chosen together, matching the penalty of the heuristic update
+pub fn collision_potential() -> f64 {
+    f64::exp(-f64::ln(2.0) / HALF_PROBABILITY_OPPORTUNITY_COST)
+}
+
+fn normalize(values: &mut [f64]) {
+    let sum: f64 = values.iter().sum();
+    if sum > 0.0 && sum.is_finite() {
+        values.iter_mut().for_each(|value| *value /= sum);
+    } else {
+        let uniform = 1.0 / values.len() as f64;
+        values.iter_mut().for_each(|value| *value = uniform);
+    }
+}
+
+fn damp(old: &[f64], new: &mut [f64], damping: f64) -> f64 {
+    let mut max_change: f64 = 0.0;
+    for (new_value, old_value) in new.iter_mut().zip(old.iter()) {
+        *new_value = (1.0 - damping) * *new_value + damping * old_value;
+        max_change = max_change.max((*new_value - old_value).abs());
+    }
+    max_change
+}
+
+fn unary_marginals(variables: &[InferenceVariable]) -> Vec<Vec<f64>> {
+    variables
+        .iter()
+        .map(|variable| {
+            let mut marginal = variable.unary.clone();
+            normalize(&mut marginal);
+            marginal
+        })
+        .collect()
+}
+
+/// the marginals of the Markov random field with the unary weights of the variables, the collision potential
+/// between colliding states and exactly one state per variable, by mean-field inference
+/// variables are updated one after the other, each update uses the latest marginals of the others
+pub fn mean_field(variables: &[InferenceVariable], config: &InferenceConfig) -> InferenceResult {
+    let log_potential = collision_potential().ln();
+    let mut marginals = unary_marginals(variables);
+    let mut max_change = 0.0;
+    for iteration in 1..=config.max_iterations {
+        max_change = 0.0;
+        for (index, variable) in variables.iter().enumerate() {
+            let mut updated: Vec<f64> = (0..variable.num_states())
+                .map(|state| {
+                    let expected_collisions: f64 = variable.conflicts[state]
+                        .iter()
+                        .map(|&(other, other_state)| marginals[other][other_state])
+                        .sum();
+                    variable.unary[state] * f64::exp(log_potential * expected_collisions)
+                })
+                .collect();
+            normalize(&mut updated);
+            let change = damp(&marginals[index], &mut updated, config.damping);
+            max_change = f64::max(max_change, change);
+            marginals[index] = updated;
+        }
+        if max_change <= config.tolerance {
+            return InferenceResult {
+                marginals,
+                iterations: iteration,
+                converged: true,
+                max_change,
+            };
+        }
+    }
+    InferenceResult {
+        marginals,
+        iterations: config.max_iterations,
+        converged: false,
+        max_change,
+    }
+}
+
+/// the marginals of the same Markov random field by loopy belief propagation,
+/// messages are sent along the edges between variables with colliding states, all at once in each iteration
+pub fn belief_propagation(variables: &[InferenceVariable], config: &InferenceConfig) -> InferenceResult {
+    let keep = 1.0 - collision_potential();
+    // for each directed edge (from, to), the states of `to` colliding with each state of `from`
+    let mut edges: HashMap<(usize, usize), Vec<Vec<usize>>> = HashMap::new();
+    for (from, variable) in variables.iter().enumerate() {
+        for (state, conflicts) in variable.conflicts.iter().enumerate() {
+            for &(to, to_state) in conflicts.iter() {
+                edges
+                    .entry((from, to))
+                    .or_insert_with(|| vec![Vec::new(); variable.num_states()])[state]
+                    .push(to_state);
+            }
+        }
+    }
+    let mut incoming: Vec<Vec<usize>> = vec![Vec::new(); variables.len()];
+    for &(from, to) in edges.keys() {
+        incoming[to].push(from);
+    }
+    incoming.iter_mut().for_each(|senders| senders.sort());
+    let mut messages: HashMap<(usize, usize), Vec<f64>> = edges
+        .keys()
+        .map(|&(from, to)| ((from, to), vec![1.0 / variables[to].num_states() as f64; variables[to].num_states()]))
+        .collect();
+
+    let beliefs = |messages: &HashMap<(usize, usize), Vec<f64>>| -> Vec<Vec<f64>> {
+        variables
+            .iter()
+            .enumerate()
+            .map(|(index, variable)| {
+                let mut belief = variable.unary.clone();
+                for from in incoming[index].iter() {
+                    let message = &messages[&(*from, index)];
+                    belief.iter_mut().zip(message.iter()).for_each(|(value, factor)| *value *= factor);
+                }
+                normalize(&mut belief);
+                belief
+            })
+            .collect()
+    };
+
+    let mut max_change = 0.0;
+    for iteration in 1..=config.max_iterations {
+        let current_beliefs = beliefs(&messages);
+        let mut new_messages: HashMap<(usize, usize), Vec<f64>> = HashMap::new();
+        max_change = 0.0;
+        for (&(from, to), colliding_states) in edges.iter() {
+            // the belief of the sender without what the receiver told it
+            let old_back_message = &messages[&(to, from)];
+            let cavity: Vec<f64> = current_beliefs[from]
+                .iter()
+                .zip(old_back_message.iter())
+                .map(|(belief, back)| belief / back.max(MIN_PROBABILITY))
+                .collect();
+            let total: f64 = cavity.iter().sum();
+            let mut message = vec![total; variables[to].num_states()];
+            for (state, states_to) in colliding_states.iter().enumerate() {
+                for &to_state in states_to.iter() {
+                    message[to_state] -= keep * cavity[state];
+                }
+            }
+            message.iter_mut().for_each(|value| *value = value.max(0.0));
+            normalize(&mut message);
+            let change = damp(&messages[&(from, to)], &mut message, config.damping);
+            max_change = f64::max(max_change, change);
+            new_messages.insert((from, to), message);
+        }
+        messages = new_messages;
+        if max_change <= config.tolerance {
+            return InferenceResult {
+                marginals: beliefs(&messages),
+                iterations: iteration,
+                converged: true,
+                max_change,
+            };
+        }
+    }
+    InferenceResult {
+        marginals: beliefs(&messages),
+        iterations: config.max_iterations,
+        converged: false,
+        max_change,
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, fmt::Display};
+
+use shared::{
+    pcb_problem::{ConnectionID, NetName, PcbProblem, PcbSolution},
+    trace_path::TracePath,
+};
+
+use crate::{
+    diff_pair::{is_legal, obstacles_excluding},
+    meander::{add_meanders, MeanderSide, MeanderStyle},
+};
+
+// bumps reach at most this many trace pitches away from the original segment
+const MAX_MEANDER_HEIGHT_PITCHES: f32 = 3.0;
+
+/// the length of one net of a length matching group
+#[derive(Debug, Clone)]
+pub struct NetLength {
+    pub net_name: NetName,
+    pub group: String,
+    pub routed_length: f64, // sum of the trace lengths of the net before tuning
+    pub length: f64,        // sum of the trace lengths of the net after tuning
+    pub target_length: f64,
+    pub tolerance: f64,
+}
+
+impl NetLength {
+    pub fn is_matched(&self) -> bool {
+        (self.length - self.target_length).abs() <= self.tolerance
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LengthMatchingReport {
+    pub net_lengths: Vec<NetLength>, // sorted by group, then by net name
+}
+
+impl LengthMatchingReport {
+    /// number of nets that are still too short or too long
+    pub fn violations(&self) -> usize {
+        self.net_lengths.iter().filter(|net_length| !net_length.is_matched()).count()
+    }
+}
+
+impl Display for LengthMatchingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.net_lengths.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            f,
+            "Length matching: {} of {} nets within tolerance",
+            self.net_lengths.len() - self.violations(),
+            self.net_lengths.len()
+        )?;
+        for net_length in self.net_lengths.iter() {
+            let status = if net_length.is_matched() {
+                "ok"
+            } else if net_length.length < net_length.target_length {
+                "too short"
+            } else {
+                "too long"
+            };
+            writeln!(
+                f,
+                "  group {} net {}: {:.4} -> {:.4}, target {:.4} +- {:.4}, {}",
+                net_length.group,
+                net_length.net_name.0,
+                net_length.routed_length,
+                net_length.length,
+                net_length.target_length,
+                net_length.tolerance,
+                status
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn net_lengths(solution: &PcbSolution) -> HashMap<NetName, f64> {
+    let mut lengths: HashMap<NetName, f64> = HashMap::new();
+    for fixed_trace in solution.determined_traces.values() {
+        *lengths.entry(fixed_trace.net_name.clone()).or_insert(0.0) += fixed_trace.trace_path.total_length;
+    }
+    lengths
+}
+
+// the explicit target of each group, or the length of its longest net
+fn group_targets(problem: &PcbProblem, lengths: &HashMap<NetName, f64>) -> HashMap<String, f64> {
+    let mut targets: HashMap<String, f64> = HashMap::new();
+    for (net_name, net_info) in problem.nets.iter() {
+        let Some(constraint) = &net_info.length_constraint else {
+            continue;
+        };
+        let target = match constraint.target_length {
+            Some(target_length) => target_length as f64,
+            None => lengths.get(net_name).copied().unwrap_or(0.0),
+        };
+        let entry = targets.entry(constraint.group.clone()).or_insert(target);
+        *entry = f64::max(*entry, target);
+    }
+    targets
+}
+
+fn build_report(
+    problem: &PcbProblem,
+    routed_lengths: &HashMap<NetName, f64>,
+    lengths: &HashMap<NetName, f64>,
+    targets: &HashMap<String, f64>,
+) -> LengthMatchingReport {
+    let mut net_lengths: Vec<NetLength> = problem
+        .nets
+        .iter()
+        .filter_map(|(net_name, net_info)| {
+            let constraint = net_info.length_constraint.as_ref()?;
+            Some(NetLength {
+                net_name: net_name.clone(),
+                group: constraint.group.clone(),
+                routed_length: routed_lengths.get(net_name).copied().unwrap_or(0.0),
+                length: lengths.get(net_name).copied().unwrap_or(0.0),
+                target_length: targets[&constraint.group],
+                tolerance: constraint.tolerance as f64,
+            })
+        })
+        .collect();
+    net_lengths.sort_by(|a, b| (&a.group, &a.net_name).cmp(&(&b.group, &b.net_name)));
+    LengthMatchingReport { net_lengths }
+}
+
+/// the lengths of the length matched nets of a solution, without changing it
+pub fn check_lengths(problem: &PcbProblem, solution: &PcbSolution) -> LengthMatchingReport {
+    let lengths = net_lengths(solution);
+    let targets = group_targets(problem, &lengths);
+    build_report(problem, &lengths, &lengths, &targets)
+}
+
+/// lengthens the nets of each length matching group with meanders until they are within tolerance of the group target,
+/// a meander is only kept when the trace stays clear of the pads and traces of every other net,
+/// nets that are too long are only reported, differential pair nets are left alone so they stay coupled
+pub fn tune_lengths(problem: &PcbProblem, solution: &mut PcbSolution) -> LengthMatchingReport {
+    let routed_lengths = net_lengths(solution);
+    let targets = group_targets(problem, &routed_lengths);
+    let mut tuned_nets: Vec<(&NetName, _)> = problem
+        .nets
+        .iter()
+        .filter_map(|(net_name, net_info)| net_info.length_constraint.as_ref().map(|constraint| (net_name, constraint)))
+        .collect();
+    tuned_nets.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (net_name, constraint) in tuned_nets {
+        let net_info = &problem.nets[net_name];
+        let target_length = targets[&constraint.group];
+        let mut missing = target_length - routed_lengths.get(net_name).copied().unwrap_or(0.0);
+        if missing <= constraint.tolerance as f64 {
+            continue;
+        }
+        if net_info.diff_pair.is_some() {
+            println!(
+                "Warning: net {} is a differential pair net, its length in group {} is not tuned",
+                net_name.0, constraint.group
+            );
+            continue;
+        }
+        let trace_pitch = net_info.trace_width + net_info.trace_clearance;
+        let style = MeanderStyle {
+            shape: constraint.meander_shape,
+            side: MeanderSide::Both,
+            max_height: MAX_MEANDER_HEIGHT_PITCHES * trace_pitch,
+            spacing: trace_pitch,
+        };
+        // the longest traces have the most room for meanders
+        let mut connection_ids: Vec<ConnectionID> = solution
+            .determined_traces
+            .iter()
+            .filter(|(_, fixed_trace)| &fixed_trace.net_name == net_name)
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        connection_ids.sort_by(|a, b| {
+            let length = |connection_id: &ConnectionID| solution.determined_traces[connection_id].trace_path.total_length;
+            length(b).total_cmp(&length(a)).then(a.cmp(b))
+        });
+        for connection_id in connection_ids {
+            if missing <= constraint.tolerance as f64 {
+                break;
+            }
+            let other_traces: Vec<&TracePath> = solution
+                .determined_traces
+                .values()
+                .filter(|fixed_trace| &fixed_trace.net_name != net_name)
+                .map(|fixed_trace| &fixed_trace.trace_path)
+                .collect();
+            let obstacles = obstacles_excluding(problem, &[net_name], &other_traces);
+            let trace_path = &solution.determined_traces[&connection_id].trace_path;
+            // meanders never push a trace over the maximum length of its net
+            let room = net_info
+                .routing_constraints
+                .max_length
+                .map_or(f64::INFINITY, |max_length| max_length as f64 - trace_path.total_length);
+            if room <= 0.0 {
+                continue;
+            }
+            let tuned_path = add_meanders(
+                trace_path,
+                f64::min(missing, room),
+                constraint.tolerance as f64,
+                style,
+                &net_info.via_types,
+                |candidate| is_legal(problem, &obstacles, candidate),
+            );
+            missing -= tuned_path.total_length - trace_path.total_length;
+            solution.determined_traces.get_mut(&connection_id).unwrap().trace_path = tuned_path;
+        }
+    }
+
+    let lengths = net_lengths(solution);
+    build_report(problem, &routed_lengths, &lengths, &targets)
+}
@@ -9,4 +9,28 @@ pub mod quad_tree;
 pub mod test_pcb_problem;
 pub mod astar_check_struct;
 pub mod naive_backtrack_algo;
-pub mod bayesian_backtrack_algo;
\ No newline at end of file
+pub mod bayesian_backtrack_algo;
+pub mod router_config;
+pub mod drc;
+pub mod connectivity;
+pub mod best_partial_solution;
+pub mod cancellation;
+pub mod congestion_map;
+pub mod negotiated_congestion_algo;
+
+pub mod meander;
+pub mod diff_pair;
+pub mod length_matching;
+pub mod routing_constraints;
+pub mod via_rules;
+pub mod fanout;
+pub mod pad_entry;
+pub mod copper_pour;
+pub mod inference;
+pub mod exact_selection;
+pub mod diversity;
+pub mod blocking_traces;
+pub mod connection_ordering;
+pub mod trace_cache_file;
+pub mod shove;
+pub mod obstacles;
\ No newline at end of file
@@ -0,0 +1,183 @@
+use shared::{
+    pcb_problem::MeanderShape,
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, ViaType},
+    vec2::{FixedPoint, FixedVec2},
+};
+
+// number of fixed point bits per unit of length
+fn bits_per_unit() -> f64 {
+    (1i64 << FixedPoint::FRAC_NBITS) as f64
+}
+
+fn offset_position(position: FixedVec2, direction: Direction, steps: i64) -> FixedVec2 {
+    let int_vec = direction.to_int_vec2();
+    FixedVec2::new(
+        FixedPoint::from_bits(position.x.to_bits() + (int_vec.x as i64 * steps) as i32),
+        FixedPoint::from_bits(position.y.to_bits() + (int_vec.y as i64 * steps) as i32),
+    )
+}
+
+fn steps_between(start: FixedVec2, end: FixedVec2) -> i64 {
+    let dx = (end.x.to_bits() as i64 - start.x.to_bits() as i64).abs();
+    let dy = (end.y.to_bits() as i64 - start.y.to_bits() as i64).abs();
+    i64::max(dx, dy)
+}
+
+/// the side of a segment a meander bulges to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeanderSide {
+    Left,
+    Right,
+    Both, // try left first, then right, accordions alternate
+}
+
+/// the shape of the bumps inserted into a trace
+#[derive(Debug, Clone, Copy)]
+pub struct MeanderStyle {
+    pub shape: MeanderShape,
+    pub side: MeanderSide,
+    pub max_height: f32, // distance from the original segment to the top of a bump
+    pub spacing: f32,    // length of the top of a bump, and distance between neighbouring bumps and corners
+}
+
+// the four corners of one bump, starting offset_steps after start
+fn bump_anchors(
+    start: FixedVec2,
+    direction: Direction,
+    layer: usize,
+    offset_steps: i64,
+    height_steps: i64,
+    top_steps: i64,
+    bulge_left: bool,
+) -> [TraceAnchor; 4] {
+    let (outward, inward) = if bulge_left {
+        (direction.left_45_dir(), direction.right_45_dir())
+    } else {
+        (direction.right_45_dir(), direction.left_45_dir())
+    };
+    let p1 = offset_position(start, direction, offset_steps);
+    let p2 = offset_position(p1, outward, height_steps);
+    let p3 = offset_position(p2, direction, top_steps);
+    let p4 = offset_position(p3, inward, height_steps);
+    [p1, p2, p3, p4].map(|position| TraceAnchor {
+        position,
+        start_layer: layer,
+        end_layer: layer,
+        via_type: None,
+    })
+}
+
+/// adds bumps to the straight segments of a trace, longest segments first,
+/// until the trace is within `tolerance` of `extra_length` longer or no bump fits anymore,
+/// each bump is kept only when `is_legal` accepts the resulting trace
+pub(crate) fn add_meanders(
+    trace_path: &TracePath,
+    extra_length: f64,
+    tolerance: f64,
+    style: MeanderStyle,
+    via_types: &[ViaType], // the vias of the net, the bumps keep the vias of the trace
+    is_legal: impl Fn(&TracePath) -> bool,
+) -> TracePath {
+    let Some(first_segment) = trace_path.segments.first() else {
+        return trace_path.clone();
+    };
+    let trace_width = first_segment.width;
+    let trace_clearance = first_segment.clearance;
+    let target_length = trace_path.total_length + extra_length;
+
+    let mut result = trace_path.clone();
+    let mut segments_by_length: Vec<(FixedVec2, FixedVec2, usize)> = trace_path
+        .segments
+        .iter()
+        .map(|segment| (segment.start, segment.end, segment.layer))
+        .collect();
+    segments_by_length.sort_by_key(|(start, end, _)| std::cmp::Reverse(steps_between(*start, *end)));
+    let mut prefer_left = style.side != MeanderSide::Right;
+
+    for (segment_start, segment_end, layer) in segments_by_length {
+        if target_length - result.total_length <= tolerance {
+            break;
+        }
+        let Ok(Some(direction)) = Direction::from_points(segment_start, segment_end) else {
+            continue;
+        };
+        // one step along a diagonal direction is sqrt(2) long
+        let step_length = if direction.is_diagonal() { std::f64::consts::SQRT_2 } else { 1.0 };
+        let spacing_steps = (style.spacing as f64 * bits_per_unit() / step_length).ceil() as i64;
+        // a bump with outward steps h is (2 * sqrt(2) - 2) * h longer on straight segments
+        // and (2 - sqrt(2)) * h longer on diagonal ones
+        let (extra_per_step, height_per_step, advance_per_step) = if direction.is_diagonal() {
+            (2.0 - std::f64::consts::SQRT_2, std::f64::consts::FRAC_1_SQRT_2, 1)
+        } else {
+            (2.0 * std::f64::consts::SQRT_2 - 2.0, 1.0, 2)
+        };
+        let max_height_steps = (style.max_height as f64 * bits_per_unit() / height_per_step).floor() as i64;
+
+        // the straight piece still free for bumps, it shrinks as bumps are added
+        let mut piece_start = segment_start;
+        let mut offset_steps = spacing_steps;
+        loop {
+            let missing = target_length - result.total_length;
+            if missing <= tolerance {
+                break;
+            }
+            let height_steps = i64::min(
+                max_height_steps,
+                (missing * bits_per_unit() / extra_per_step).ceil() as i64,
+            );
+            if height_steps <= 0 {
+                break;
+            }
+            let bump_steps = advance_per_step * height_steps + spacing_steps;
+            if offset_steps + bump_steps + spacing_steps > steps_between(piece_start, segment_end) {
+                break;
+            }
+            let Some(index) = result.anchors.0.windows(2).position(|pair| {
+                pair[0].position == piece_start && pair[1].position == segment_end && pair[0].end_layer == layer
+            }) else {
+                break;
+            };
+            let sides: &[bool] = match style.side {
+                MeanderSide::Left => &[true],
+                MeanderSide::Right => &[false],
+                MeanderSide::Both if prefer_left => &[true, false],
+                MeanderSide::Both => &[false, true],
+            };
+            let mut inserted = false;
+            for &bulge_left in sides {
+                let bump = bump_anchors(
+                    piece_start,
+                    direction,
+                    layer,
+                    offset_steps,
+                    height_steps,
+                    spacing_steps,
+                    bulge_left,
+                );
+                let mut anchors = result.anchors.0.clone();
+                anchors.splice(index + 1..index + 1, bump.iter().cloned());
+                let candidate = TracePath::from_anchors(
+                    TraceAnchors(anchors),
+                    trace_width,
+                    trace_clearance,
+                    via_types,
+                );
+                if is_legal(&candidate) {
+                    result = candidate;
+                    piece_start = bump[3].position;
+                    offset_steps = spacing_steps;
+                    inserted = true;
+                    if style.shape == MeanderShape::Accordion {
+                        prefer_left = !bulge_left;
+                    }
+                    break;
+                }
+            }
+            if !inserted {
+                // slide along the segment, the obstacle may only block part of it
+                offset_steps += bump_steps;
+            }
+        }
+    }
+    result
+}
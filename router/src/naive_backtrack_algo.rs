@@ -1,9 +1,8 @@
-use std::{cell::RefCell, cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}, hash::Hash, rc::Rc, sync::{atomic::Ordering, Arc, Mutex}, thread, time::Duration};
+use std::{cell::RefCell, collections::{BTreeSet, HashMap, HashSet, VecDeque}, fmt::Display, hash::Hash, rc::Rc, sync::{atomic::Ordering, Arc, Mutex}, thread, time::Duration};
 
-use ordered_float::NotNan;
-use shared::{binary_heap_item::BinaryHeapItem, collider::Collider, color_float3::ColorFloat3, hyperparameters::SAMPLE_CNT, pad::{Pad, PadName}, pcb_problem::{Connection, ConnectionID, FixedTrace, NetInfo, NetName, PcbProblem, PcbSolution}, pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable}, prim_shape::PrimShape, trace_path::{self, TracePath}};
+use shared::{color_float3::ColorFloat3, hyperparameters::SAMPLE_CNT, pad::{Pad, PadName}, pcb_problem::{Connection, ConnectionID, FixedTrace, NetInfo, NetName, PartialSolution, PcbProblem, PcbSolution}, pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable}, prim_shape::PrimShape, trace_path::{self, TracePath}};
 
-use crate::{astar::{self, AStarModel}, astar_check_struct::AStarCheck, bayesian_backtrack_algo::TraceCache, command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES}, quad_tree::QuadTreeNode};
+use crate::{astar::{self, AStarModel}, astar_check_struct::AStarCheck, bayesian_backtrack_algo::TraceCache, blocking_traces::BlockingTraces, best_partial_solution::BestPartialSolution, cancellation::CancellationToken, command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES}, obstacles::{self, ObstacleBuilder, Obstacles}, pad_entry::PadEntry, router_config::RouterConfig, shove::{shove_aside, ShoveConfig}, via_rules::ViaObstacles};
 
 
 
@@ -103,164 +102,189 @@ fn display_when_necessary(
         thread::sleep(Duration::from_millis(0));
     }
 }
+
+/// how the naive backtracker recovered from the connections it could not route
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackjumpStats {
+    pub failures: usize,       // A* runs that found no path
+    pub backjumps: usize,      // failures that went back to a blocking decision above the parent level
+    pub levels_skipped: usize, // levels between the failure and the blocking decision that were never revisited
+    pub restarted: bool,       // whether the search failed with backjumps and was restarted without them
+}
+
+impl Display for BackjumpStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed connections, {} backjumps, {} levels skipped{}",
+            self.failures,
+            self.backjumps,
+            self.levels_skipped,
+            if self.restarted { ", restarted chronologically" } else { "" }
+        )
+    }
+}
+
 pub fn naive_backtrack(problem: &PcbProblem, 
     pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
     trace_cache: &mut TraceCache,
     heuristics: Option<Vec<ConnectionID>>,
-) -> Result<PcbSolution, String> {
-    // prepare the obstacles for the first A* run    
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+    config: &RouterConfig,
+    cancellation_token: &CancellationToken,
+) -> Result<PcbSolution, PartialSolution> {
+    // the heuristics come from the bayesian backtracker, otherwise the ordering strategy decides
+    let ordered_connection_vec = if let Some(heuristics) = heuristics {
+        heuristics.into_iter().filter(|connection_id| !prerouted_traces.contains_key(connection_id)).collect()
+    } else {
+        let initial_lengths = initial_trace_lengths(problem, pcb_render_model.clone(), trace_cache, prerouted_traces, cancellation_token)?;
+        let ordered_connection_vec = config.ordering.strategy().order(problem, &initial_lengths);
+        println!("Connection order: {:?}", ordered_connection_vec.iter().map(|connection_id| connection_id.0).collect::<Vec<_>>());
+        ordered_connection_vec
+    };
+    let (result, backjump_stats) = naive_backtrack_with_stats(problem, pcb_render_model, trace_cache, ordered_connection_vec, prerouted_traces, &config.shove, cancellation_token);
+    println!("Naive backtrack: {}", backjump_stats);
+    result
+}
+
+/// routes every connection that is not prerouted around the pads of the other nets only, the traces go to the trace cache
+/// and their lengths feed the connection ordering
+fn initial_trace_lengths(problem: &PcbProblem,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    trace_cache: &mut TraceCache,
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+    cancellation_token: &CancellationToken,
+) -> Result<HashMap<ConnectionID, f64>, PartialSolution> {
     let border_colliders = AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center);
+    let mut connection_to_length: HashMap<ConnectionID, f64> = HashMap::new();
+    for (net_name, net_info) in problem.nets.iter() {
+        // obstacles are pads
+        let obstacles = obstacles::obstacles_of_other_nets(problem, &[net_name], std::iter::empty());
+        let via_obstacles = Rc::new(ViaObstacles::new(
+            problem,
+            net_name,
+            prerouted_traces.values().map(|fixed_trace| &fixed_trace.trace_path),
+        ));
         
-    let quad_tree_side_length = f32::max(problem.width as f32, problem.height as f32);
-        let quad_tree_x_min = problem.center.x as f32 - quad_tree_side_length / 2.0;
-        let quad_tree_x_max = problem.center.x as f32 + quad_tree_side_length / 2.0;
-        let quad_tree_y_min = problem.center.y as f32 - quad_tree_side_length / 2.0;
-        let quad_tree_y_max = problem.center.y as f32 + quad_tree_side_length / 2.0;
-    
-    let ordered_connection_vec = if let Some(heuristics) = heuristics {
-        heuristics
-    } else {        
-        let mut connection_to_length: HashMap<ConnectionID, NotNan<f32>> = HashMap::new();
-        for (net_name, net_info) in problem.nets.iter() {
-            // obstacles are pads
-            let mut obstacle_shapes: HashMap<usize, Vec<PrimShape>> = (0..problem.num_layers)
-                .map(|layer| (layer, Vec::new()))
-                .collect();
-            let mut obstacle_clearance_shapes: HashMap<usize, Vec<PrimShape>> = (0..problem
-                .num_layers)
-                .map(|layer| (layer, Vec::new()))
-                .collect();
-            let mut obstacle_colliders: HashMap<usize, QuadTreeNode> = (0..problem.num_layers)
-                .map(|layer| {
-                    (
-                        layer,
-                        QuadTreeNode::new(
-                            quad_tree_x_min,
-                            quad_tree_x_max,
-                            quad_tree_y_min,
-                            quad_tree_y_max,
-                            0,
-                        ),
-                    )
-                })
-                .collect();
-            let mut obstacle_clearance_colliders: HashMap<usize, QuadTreeNode> = (0..problem
-                .num_layers)
-                .map(|layer| {
-                    (
-                        layer,
-                        QuadTreeNode::new(
-                            quad_tree_x_min,
-                            quad_tree_x_max,
-                            quad_tree_y_min,
-                            quad_tree_y_max,
-                            0,
-                        ),
-                    )
-                })
-                .collect();
-            for (_, net_info) in problem
-                .nets
-                .iter()
-                .filter(|(other_net_id, _)| **other_net_id != *net_name)
-            {
-                for pad in net_info.pads.values(){
-                    let pad_layers = pad.pad_layer.get_iter(problem.num_layers);
-                    for layer in pad_layers{
-                        let pad_shapes = pad.to_shapes();
-                        let pad_clearance_shapes = pad.to_clearance_shapes();                    
-                        for pad_shape in pad_shapes.iter() {
-                            let pad_collider = Collider::from_prim_shape(pad_shape);
-                            obstacle_colliders.get_mut(&layer).unwrap().insert(pad_collider);
-                        }
-                        for pad_clearance_shape in pad_clearance_shapes.iter() {
-                            let pad_clearance_collider = Collider::from_prim_shape(pad_clearance_shape);
-                            obstacle_clearance_colliders.get_mut(&layer).unwrap().insert(pad_clearance_collider);
-                        }
-                        obstacle_shapes.get_mut(&layer).unwrap().extend(pad_shapes);
-                        obstacle_clearance_shapes.get_mut(&layer).unwrap().extend(pad_clearance_shapes);
-                    }
+        for connection in net_info.connections.values() {
+            if prerouted_traces.contains_key(&connection.connection_id) {
+                continue; // already routed, e.g. as part of a differential pair
+            }
+            let mut trace_path: Option<TracePath> = None;
+            let current_connection_trace_cache = trace_cache.traces.get_mut(&connection.connection_id).unwrap();
+            for cache_trace_path in current_connection_trace_cache.iter() {
+                let astar_check = AStarCheck{
+                    border_colliders: border_colliders.clone(),
+                    obstacle_colliders: obstacles.colliders.clone(),
+                    obstacle_clearance_colliders: obstacles.clearance_colliders.clone(),
+                    solution_trace: cache_trace_path.clone(),
+                    num_layers: problem.num_layers,
+                };
+                if astar_check.check() && via_obstacles.allows_vias(cache_trace_path) {
+                    println!("Cache Hit!");                            
+                    trace_path = Some(cache_trace_path.clone());
+                    break; // we found a trace that satisfies the constraints, no need to generate a new one
+                }else{
+                    println!("Cache Miss!");
                 }
             }
-            let obstacle_shapes = Rc::new(obstacle_shapes);
-            let obstacle_clearance_shapes = Rc::new(obstacle_clearance_shapes);
-            let obstacle_colliders = Rc::new(obstacle_colliders);
-            let obstacle_clearance_colliders = Rc::new(obstacle_clearance_colliders);
-            
-            for connection in net_info.connections.values() {
-                let mut trace_path: Option<TracePath> = None;
-                let current_connection_trace_cache = trace_cache.traces.get_mut(&connection.connection_id).unwrap();
-                for cache_trace_path in current_connection_trace_cache.iter() {
-                    let astar_check = AStarCheck{
-                        border_colliders: border_colliders.clone(),
-                        obstacle_colliders: obstacle_colliders.clone(),
-                        obstacle_clearance_colliders: obstacle_clearance_colliders.clone(),
-                        solution_trace: cache_trace_path.clone(),
-                        num_layers: problem.num_layers,
-                    };
-                    if astar_check.check() {
-                        println!("Cache Hit!");                            
-                        trace_path = Some(cache_trace_path.clone());
-                        break; // we found a trace that satisfies the constraints, no need to generate a new one
-                    }else{
-                        println!("Cache Miss!");
+            let trace_path = if let Some(trace_path) = trace_path{
+                trace_path
+            }else{
+                // run A* algorithm
+                let start_pad = net_info.pads.get(&connection.start_pad).unwrap();
+                let end_pad = net_info.pads.get(&connection.end_pad).unwrap();
+                let start = PadEntry::from_pad(start_pad, net_info.trace_width, &problem.pad_entry);
+                let end = PadEntry::from_pad(end_pad, net_info.trace_width, &problem.pad_entry);
+                let start_layers = start_pad.pad_layer;
+                let end_layers = end_pad.pad_layer;                    
+                let astar_model = AStarModel {
+                    start,
+                    end,
+                    start_layers,
+                    end_layers,
+                    num_layers: problem.num_layers,
+                    trace_width: net_info.trace_width,
+                    trace_clearance: net_info.trace_clearance,
+                    via_types: net_info.via_types.clone(),
+                    width: problem.width,
+                    height: problem.height,
+                    center: problem.center,
+                    obstacle_shapes: obstacles.shapes.clone(),
+                    obstacle_clearance_shapes: obstacles.clearance_shapes.clone(),
+                    obstacle_colliders: obstacles.colliders.clone(),
+                    obstacle_clearance_colliders: obstacles.clearance_colliders.clone(),
+                    border_colliders_cache: RefCell::new(None),
+                    border_shapes_cache: RefCell::new(None),
+                    congestion_costs: None,
+                    routing_constraints: net_info.routing_constraints.clone(),
+                    via_obstacles: via_obstacles.clone(),
+                    layer_directions: problem.layer_directions.clone(),
+                    blocking_traces: None,
+                };
+                let result = astar_model.run(pcb_render_model.clone(), cancellation_token);
+                let result = match result{
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!("A star algorithm failed");
+                        let reason = if cancellation_token.is_cancelled() {
+                            cancellation_token.reason()
+                        } else {
+                            format!(
+                                "A* algorithm failed in initial heuristic calculation, connection {} of net {} is unroutable: {}",
+                                connection.connection_id.0, net_name.0, e
+                            )
+                        };
+                        return Err(PartialSolution::from_fixed_traces(problem, HashMap::new(), reason));
                     }
-                }
-                let trace_path = if let Some(trace_path) = trace_path{
-                    trace_path
-                }else{
-                    // run A* algorithm
-                    let start_pad = net_info.pads.get(&connection.start_pad).unwrap();
-                    let end_pad = net_info.pads.get(&connection.end_pad).unwrap();
-                    let start = start_pad.position.to_fixed().to_nearest_even_even();
-                    let end = end_pad.position.to_fixed().to_nearest_even_even();
-                    let start_layers = start_pad.pad_layer;
-                    let end_layers = end_pad.pad_layer;                    
-                    let astar_model = AStarModel {
-                        start,
-                        end,
-                        start_layers,
-                        end_layers,
-                        num_layers: problem.num_layers,
-                        trace_width: net_info.trace_width,
-                        trace_clearance: net_info.trace_clearance,
-                        via_diameter: net_info.via_diameter,
-                        width: problem.width,
-                        height: problem.height,
-                        center: problem.center,
-                        obstacle_shapes: obstacle_shapes.clone(),
-                        obstacle_clearance_shapes: obstacle_clearance_shapes.clone(),
-                        obstacle_colliders: obstacle_colliders.clone(),
-                        obstacle_clearance_colliders: obstacle_clearance_colliders.clone(),
-                        border_colliders_cache: RefCell::new(None),
-                        border_shapes_cache: RefCell::new(None),
-                    };
-                    let result = astar_model.run(pcb_render_model.clone());
-                    let result = match result{
-                        Ok(result) => result,
-                        Err(e) => {
-                            println!("A star algorithm failed");
-                            return Err("A* algorithm failed in initial heuristic calculation".to_string());
-                        }
-                    };
-                    current_connection_trace_cache.push(result.trace_path.clone());
-                    result.trace_path
                 };
-                connection_to_length.insert(connection.connection_id, NotNan::new(trace_path.total_length as f32).unwrap());
-            }
-        }
-        let mut connection_heap: BinaryHeap<BinaryHeapItem<Reverse<NotNan<f32>>, ConnectionID>> = BinaryHeap::new();
-        for (connection_id, length) in connection_to_length.iter() {
-            connection_heap.push(BinaryHeapItem::new(Reverse(*length), *connection_id));
+                current_connection_trace_cache.push(result.trace_path.clone());
+                result.trace_path
+            };
+            connection_to_length.insert(connection.connection_id, trace_path.total_length);
         }
-        let ordered_connection_vec: Vec<ConnectionID> = connection_heap.drain().map(|item| item.value).collect();
-        ordered_connection_vec
-    };
+    }
+    Ok(connection_to_length)
+}
+
+/// on a connection that cannot be routed, goes back to the latest decision that fixed a trace in its way
+/// and routes the connection before that trace, instead of only going back one level
+fn naive_backtrack_with_stats(problem: &PcbProblem,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    trace_cache: &mut TraceCache,
+    ordered_connection_vec: Vec<ConnectionID>,
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+    shove: &ShoveConfig,
+    cancellation_token: &CancellationToken,
+) -> (Result<PcbSolution, PartialSolution>, BackjumpStats) {
+    // prepare the obstacles for the first A* run    
+    let border_colliders = AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center);
+    
     // SAMPLE_CNT.store(0, Ordering::Relaxed);
+    let mut backjump_stats = BackjumpStats::default();
     let mut backtrack_stack: Vec<NaiveBacktrackNode> = Vec::new();
+    let mut best_partial_solution = BestPartialSolution::new();
 
-    let root_node = NaiveBacktrackNode::new_empty(&ordered_connection_vec);
+    if ordered_connection_vec.is_empty() {
+        return (Ok(PcbSolution{
+            determined_traces: prerouted_traces.clone(),
+            copper_pours: Vec::new(),
+            uncoupled_diff_pairs: Vec::new(),
+            scale_down_factor: problem.scale_down_factor,
+        }), backjump_stats);
+    }
+    let new_root_node = || {
+        let mut root_node = NaiveBacktrackNode::new_empty(&ordered_connection_vec);
+        // the prerouted traces are fixed from the start and never backtracked
+        root_node.fixed_connections = prerouted_traces.clone();
+        root_node
+    };
+    let root_node = new_root_node();
+    best_partial_solution.consider(&root_node.fixed_connections);
     backtrack_stack.push(root_node);
+    // a backjump skips the levels between a failure and the decision that blocked it without trying their alternatives,
+    // so a search that fails after backjumps may have skipped a solution, it is then searched again chronologically
+    let mut backjump = true;
 
     let connections: HashMap<ConnectionID, Rc<Connection>> = problem.nets.values()
         .flat_map(|net_info| net_info.connections.iter())
@@ -292,7 +316,24 @@ pub fn naive_backtrack(problem: &PcbProblem,
         println!();
     }
 
-    while !backtrack_stack.is_empty() {
+    loop {
+        let search_failed = backtrack_stack.is_empty()
+            || backtrack_stack.last().is_some_and(|top_node| {
+                top_node.alternative_connections.is_empty() && !top_node.failed_connections.is_empty()
+            });
+        if search_failed && backjump && backjump_stats.backjumps > 0 {
+            println!("Search failed after {} backjumps, restarting it with chronological backtracking", backjump_stats.backjumps);
+            backjump = false;
+            backjump_stats.restarted = true;
+            backtrack_stack.clear();
+            backtrack_stack.push(new_root_node());
+        }
+        if backtrack_stack.is_empty() {
+            break;
+        }
+        if cancellation_token.is_cancelled() {
+            return (Err(best_partial_solution.into_partial_solution(problem, cancellation_token.reason())), backjump_stats);
+        }
         // Get the top node from the stack
         
         let top_node = backtrack_stack.last_mut().unwrap();
@@ -303,7 +344,10 @@ pub fn naive_backtrack(problem: &PcbProblem,
         if top_node.alternative_connections.is_empty() {
             if !top_node.failed_connections.is_empty() {
                 println!("No more alternative connections but have failed connections, fail to solve");
-                return Err("Failed to solve PCB problem: No more alternative connections but have failed connections".to_string());
+                return (Err(best_partial_solution.into_partial_solution(
+                    problem,
+                    "Failed to solve PCB problem: No more alternative connections but have failed connections".to_string(),
+                )), backjump_stats);
             }
             // is solution
             let fixed_connections = std::mem::take(&mut top_node.fixed_connections);
@@ -315,9 +359,11 @@ pub fn naive_backtrack(problem: &PcbProblem,
                 .collect();
             let pcb_solution = PcbSolution{
                 determined_traces: fixed_traces,
+                copper_pours: Vec::new(),
+                uncoupled_diff_pairs: Vec::new(),
                 scale_down_factor: problem.scale_down_factor,
             };
-            return Ok(pcb_solution);
+            return (Ok(pcb_solution), backjump_stats);
         }
         // select the next connection
         top_node.current_connection = Some(top_node.alternative_connections.pop_front().unwrap());
@@ -327,89 +373,12 @@ pub fn naive_backtrack(problem: &PcbProblem,
 
         // here: prepare the obstacles for current connection with fixed traces
         let current_net_name = connections.get(&current_connection).unwrap().net_name.clone();
-        let mut obstacle_shapes: HashMap<usize, Vec<PrimShape>> = (0..problem.num_layers)
-            .map(|layer| (layer, Vec::new()))
-            .collect();
-        let mut obstacle_clearance_shapes: HashMap<usize, Vec<PrimShape>> = (0..problem
-            .num_layers)
-            .map(|layer| (layer, Vec::new()))
-            .collect();
-        let mut obstacle_colliders: HashMap<usize, QuadTreeNode> = (0..problem.num_layers)
-            .map(|layer| {
-                (
-                    layer,
-                    QuadTreeNode::new(
-                        quad_tree_x_min,
-                        quad_tree_x_max,
-                        quad_tree_y_min,
-                        quad_tree_y_max,
-                        0,
-                    ),
-                )
-            })
-            .collect();
-        let mut obstacle_clearance_colliders: HashMap<usize, QuadTreeNode> = (0..problem
-            .num_layers)
-            .map(|layer| {
-                (
-                    layer,
-                    QuadTreeNode::new(
-                        quad_tree_x_min,
-                        quad_tree_x_max,
-                        quad_tree_y_min,
-                        quad_tree_y_max,
-                        0,
-                    ),
-                )
-            })
-            .collect();
-        // add all pads from other nets
-        for (_, net_info) in problem
-            .nets
-            .iter()
-            .filter(|(other_net_id, _)| **other_net_id != current_net_name)
-        {
-            for pad in net_info.pads.values(){
-                let pad_layers = pad.pad_layer.get_iter(problem.num_layers);
-                for layer in pad_layers{
-                    let pad_shapes = pad.to_shapes();
-                    let pad_clearance_shapes = pad.to_clearance_shapes();    
-                    let pad_colliders = pad_shapes.iter()
-                        .map(|shape| Collider::from_prim_shape(shape));
-                    let pad_clearance_colliders = pad_clearance_shapes.iter()
-                        .map(|shape| Collider::from_prim_shape(shape));
-                    obstacle_colliders.get_mut(&layer).unwrap().extend(pad_colliders);
-                    obstacle_clearance_colliders.get_mut(&layer).unwrap().extend(pad_clearance_colliders);
-                    obstacle_shapes.get_mut(&layer).unwrap().extend(pad_shapes);
-                    obstacle_clearance_shapes.get_mut(&layer).unwrap().extend(pad_clearance_shapes);                    
-                }
-            }
-        }
-        // add fixed traces
-        for (connection_id, fixed_trace) in top_node.fixed_connections.iter(){
-            let trace_path = &fixed_trace.trace_path;
-            let connection_net_name = connections.get(connection_id).unwrap().net_name.clone();
-            if current_net_name != connection_net_name {
-                let trace_shapes = trace_path.to_shapes(problem.num_layers);
-                let trace_clearance_shapes = trace_path.to_clearance_shapes(problem.num_layers);
-                let trace_colliders = trace_path.to_colliders(problem.num_layers);
-                let trace_clearance_colliders = trace_path.to_clearance_colliders(problem.num_layers);
-                for layer in (0..problem.num_layers).into_iter() {
-                    let shapes = trace_shapes.get(&layer).unwrap();
-                    let clearance_shapes = trace_clearance_shapes.get(&layer).unwrap();
-                    let colliders = trace_colliders.get(&layer).unwrap();
-                    let clearance_colliders = trace_clearance_colliders.get(&layer).unwrap();
-                    obstacle_shapes.get_mut(&layer).unwrap().extend(shapes.iter().cloned());
-                    obstacle_clearance_shapes.get_mut(&layer).unwrap().extend(clearance_shapes.iter().cloned());
-                    obstacle_colliders.get_mut(&layer).unwrap().extend(colliders.iter().cloned());
-                    obstacle_clearance_colliders.get_mut(&layer).unwrap().extend(clearance_colliders.iter().cloned());
-                }
-            }            
-        }
-        let obstacle_shapes = Rc::new(obstacle_shapes);
-        let obstacle_clearance_shapes = Rc::new(obstacle_clearance_shapes);
-        let obstacle_colliders = Rc::new(obstacle_colliders);
-        let obstacle_clearance_colliders = Rc::new(obstacle_clearance_colliders);
+        let obstacles = obstacles_of_other_nets(problem, &current_net_name, &top_node.fixed_connections, &BTreeSet::new());
+        let via_obstacles = Rc::new(ViaObstacles::new(
+            problem,
+            &current_net_name,
+            top_node.fixed_connections.values().map(|fixed_trace| &fixed_trace.trace_path),
+        ));
 
 
         // check cache first
@@ -418,12 +387,12 @@ pub fn naive_backtrack(problem: &PcbProblem,
         for cache_trace_path in current_connection_trace_cache.iter() {
             let astar_check = AStarCheck{
                 border_colliders: border_colliders.clone(),
-                obstacle_colliders: obstacle_colliders.clone(),
-                obstacle_clearance_colliders: obstacle_clearance_colliders.clone(),
+                obstacle_colliders: obstacles.colliders.clone(),
+                obstacle_clearance_colliders: obstacles.clearance_colliders.clone(),
                 solution_trace: cache_trace_path.clone(),
                 num_layers: problem.num_layers,
             };
-            if astar_check.check() {
+            if astar_check.check() && via_obstacles.allows_vias(cache_trace_path) {
                 println!("Cache Hit!");                            
                 trace_path = Some(cache_trace_path.clone());
                 break; // we found a trace that satisfies the constraints, no need to generate a new one
@@ -432,54 +401,172 @@ pub fn naive_backtrack(problem: &PcbProblem,
             }
         }
         let connection = connections.get(&current_connection).unwrap();
+        // the fixed traces of other nets shoved aside for the trace of the connection
+        let mut shoved_traces: HashMap<ConnectionID, FixedTrace> = HashMap::new();
         let trace_path = if let Some(trace_path) = trace_path{
             trace_path
         }else{            
             let start_pad = pads.get(&connection.start_pad).unwrap();
             let end_pad = pads.get(&connection.end_pad).unwrap();
-            let start = start_pad.position.to_fixed().to_nearest_even_even();
-            let end = end_pad.position.to_fixed().to_nearest_even_even();
             let start_layers = start_pad.pad_layer;
             let end_layers = end_pad.pad_layer;
             let net_info = connection_to_net_info.get(&connection.connection_id).unwrap();
-            let astar_model = AStarModel {
-                start,
-                end,
+            let start = PadEntry::from_pad(start_pad, net_info.trace_width, &problem.pad_entry);
+            let end = PadEntry::from_pad(end_pad, net_info.trace_width, &problem.pad_entry);
+            let blocking_traces = Rc::new(BlockingTraces::new(
+                problem,
+                top_node
+                    .fixed_connections
+                    .iter()
+                    .filter(|(connection_id, _)| connections[connection_id].net_name != current_net_name)
+                    .map(|(connection_id, fixed_trace)| (*connection_id, &fixed_trace.trace_path)),
+            ));
+            let astar_model = |obstacles: &Obstacles, blocking_traces: Option<Rc<BlockingTraces>>| AStarModel {
+                start: start.clone(),
+                end: end.clone(),
                 start_layers,
                 end_layers,
                 num_layers: problem.num_layers,
                 trace_width: net_info.trace_width,
                 trace_clearance: net_info.trace_clearance,
-                via_diameter: net_info.via_diameter,
+                via_types: net_info.via_types.clone(),
                 width: problem.width,
                 height: problem.height,
                 center: problem.center,
-                obstacle_shapes: obstacle_shapes.clone(),
-                obstacle_clearance_shapes: obstacle_clearance_shapes.clone(),
-                obstacle_colliders: obstacle_colliders.clone(),
-                obstacle_clearance_colliders: obstacle_clearance_colliders.clone(),
+                obstacle_shapes: obstacles.shapes.clone(),
+                obstacle_clearance_shapes: obstacles.clearance_shapes.clone(),
+                obstacle_colliders: obstacles.colliders.clone(),
+                obstacle_clearance_colliders: obstacles.clearance_colliders.clone(),
                 border_colliders_cache: RefCell::new(None),
                 border_shapes_cache: RefCell::new(None),
+                congestion_costs: None,
+                routing_constraints: net_info.routing_constraints.clone(),
+                via_obstacles: via_obstacles.clone(),
+                layer_directions: problem.layer_directions.clone(),
+                blocking_traces,
             };
-            let result = astar_model.run(pcb_render_model.clone());
-            let result = match result {
-                Ok(result) => result,
-                Err(e) => {
-                    println!("Cannot find a path for connection {:?}, popping node", connection.connection_id);
-                    backtrack_stack.pop();
-                    continue;
+            // routes the connection through the traces that were in the way of the search and pushes them aside
+            let try_shove = || -> Option<(TracePath, HashMap<ConnectionID, FixedTrace>)> {
+                // the prerouted traces never move, the search goes around them
+                let blocking_connections: BTreeSet<ConnectionID> = blocking_traces
+                    .blocking_connections()
+                    .into_iter()
+                    .filter(|connection_id| !prerouted_traces.contains_key(connection_id))
+                    .collect();
+                if !shove.is_enabled() || blocking_connections.is_empty() {
+                    return None;
                 }
+                let obstacles = obstacles_of_other_nets(problem, &current_net_name, &top_node.fixed_connections, &blocking_connections);
+                let result = astar_model(&obstacles, None).run(pcb_render_model.clone(), cancellation_token).ok()?;
+                let new_trace = FixedTrace {
+                    net_name: connection.net_name.clone(),
+                    connection_id: connection.connection_id,
+                    trace_path: result.trace_path,
+                };
+                let rigid: HashSet<ConnectionID> = prerouted_traces.keys().cloned().collect();
+                let shoved = shove_aside(problem, &top_node.fixed_connections, &rigid, &new_trace, shove)?;
+                Some((new_trace.trace_path, shoved))
             };
-            current_connection_trace_cache.push(result.trace_path.clone());
-            result.trace_path
+            let result = astar_model(&obstacles, Some(blocking_traces.clone())).run(pcb_render_model.clone(), cancellation_token);
+            match result {
+                Ok(result) => {
+                    current_connection_trace_cache.push(result.trace_path.clone());
+                    // a long way around the traces in the way may get shorter with them shoved aside
+                    let detour_limit = shove.detour_ratio * (start_pad.position - end_pad.position).length() as f64;
+                    if result.trace_path.total_length > detour_limit
+                        && let Some((trace_path, shoved)) = try_shove()
+                        && trace_path.total_length < result.trace_path.total_length
+                    {
+                        println!(
+                            "Connection {:?} takes a detour, shoved {} traces aside for a shorter one",
+                            connection.connection_id,
+                            shoved.len()
+                        );
+                        shoved_traces = shoved;
+                        trace_path
+                    } else {
+                        result.trace_path
+                    }
+                }
+                Err(e) => {
+                    if let Some((trace_path, shoved)) = try_shove() {
+                        println!(
+                            "Cannot find a path for connection {:?} around the fixed traces, shoved {} traces aside",
+                            connection.connection_id,
+                            shoved.len()
+                        );
+                        shoved_traces = shoved;
+                        trace_path
+                    } else {
+                        backjump_stats.failures += 1;
+                        let blocking_connections = blocking_traces.blocking_connections();
+                        // the level of a node is where it chose the last of its failed connections, the one its child fixed
+                        let top_level = backtrack_stack.len() - 1;
+                        let jump_level = (0..top_level).rev().find(|level| {
+                            backtrack_stack[*level]
+                                .failed_connections
+                                .last()
+                                .is_some_and(|connection_id| blocking_connections.contains(connection_id))
+                        });
+                        // without a decision known to be in the way, e.g. when the pads alone block it,
+                        // or when the parent made it, go back one level as a chronological backtrack would
+                        let Some(jump_level) = jump_level.filter(|jump_level| backjump && *jump_level + 1 < top_level) else {
+                            println!("Cannot find a path for connection {:?}, popping node: {}", connection.connection_id, e);
+                            backtrack_stack.pop();
+                            continue;
+                        };
+                        let levels_skipped = top_level - 1 - jump_level;
+                        backjump_stats.backjumps += 1;
+                        backjump_stats.levels_skipped += levels_skipped;
+                        println!(
+                            "Cannot find a path for connection {:?}, blocked by {:?}, jumping back to the decision of connection {:?}, {} levels skipped",
+                            connection.connection_id,
+                            blocking_connections,
+                            backtrack_stack[jump_level].failed_connections.last().unwrap(),
+                            levels_skipped
+                        );
+                        backtrack_stack.truncate(jump_level + 1);
+                        // route the failed connection before the trace that blocked it
+                        let jump_node = backtrack_stack.last_mut().unwrap();
+                        if let Some(index) = jump_node.alternative_connections.iter().position(|connection_id| *connection_id == current_connection) {
+                            jump_node.alternative_connections.remove(index);
+                            jump_node.alternative_connections.push_front(current_connection);
+                        }
+                        continue;
+                    }
+                }
+            }
         };
         let fixed_trace = FixedTrace{
             net_name: connection.net_name.clone(),
             connection_id: connection.connection_id,
             trace_path,
         };
-        let new_node = top_node.push_node(current_connection, fixed_trace);
+        let mut new_node = top_node.push_node(current_connection, fixed_trace);
+        // the shoved traces stay at their new places below the new node, backtracking over it puts them back
+        new_node.fixed_connections.extend(shoved_traces);
+        best_partial_solution.consider(&new_node.fixed_connections);
         backtrack_stack.push(new_node);  
     }
-    Err("No solution found".to_string())
-}
\ No newline at end of file
+    (Err(best_partial_solution.into_partial_solution(problem, "No solution found".to_string())), backjump_stats)
+}
+
+/// the pads and the fixed traces of the nets other than the given one, as obstacles of its searches,
+/// the excluded traces can be shoved aside, a search may pass them closer than their clearance but not cross them
+fn obstacles_of_other_nets(
+    problem: &PcbProblem,
+    net_name: &NetName,
+    fixed_connections: &HashMap<ConnectionID, FixedTrace>,
+    excluded: &BTreeSet<ConnectionID>,
+) -> Obstacles {
+    let mut builder = ObstacleBuilder::new(problem);
+    builder.add_pads_of_other_nets(problem, &[net_name]);
+    for (connection_id, fixed_trace) in fixed_connections.iter().filter(|(_, fixed_trace)| fixed_trace.net_name != *net_name) {
+        if excluded.contains(connection_id) {
+            builder.add_trace_to_cross(&fixed_trace.trace_path);
+        } else {
+            builder.add_trace(&fixed_trace.trace_path);
+        }
+    }
+    builder.build()
+}
@@ -0,0 +1,371 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use shared::{
+    collider::Collider,
+    hyperparameters::{
+        HISTORY_CONGESTION_FACTOR, NEGOTIATED_CONGESTION_MAX_ITERATIONS, PRESENT_CONGESTION_FACTOR,
+        PRESENT_CONGESTION_GROWTH,
+    },
+    pcb_problem::{Connection, ConnectionID, FixedTrace, NetName, PartialSolution, PcbProblem, PcbSolution},
+    pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable},
+    prim_shape::PrimShape,
+    trace_path::TracePath,
+    vec2::FloatVec2,
+};
+
+use crate::{
+    astar::AStarModel,
+    best_partial_solution::BestPartialSolution,
+    cancellation::CancellationToken,
+    command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES},
+    congestion_map::CongestionMap,
+    obstacles::{obstacles_of_other_nets, Obstacles},
+    pad_entry::PadEntry,
+    via_rules::ViaObstacles,
+};
+
+// the hard obstacles of one net: the pads and the prerouted traces of all other nets, traces of other nets only cost extra
+fn hard_obstacles_for_net(
+    problem: &PcbProblem,
+    net_name: &NetName,
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+) -> Obstacles {
+    obstacles_of_other_nets(
+        problem,
+        &[net_name],
+        prerouted_traces
+            .values()
+            .filter(|fixed_trace| fixed_trace.net_name != *net_name)
+            .map(|fixed_trace| &fixed_trace.trace_path),
+    )
+}
+
+// the copper and clearance colliders of a trace per layer, with a bounding box of its clearance area
+struct TraceGeometry {
+    colliders: HashMap<usize, Vec<Collider>>,
+    clearance_colliders: HashMap<usize, Vec<Collider>>,
+    min_corner: FloatVec2,
+    max_corner: FloatVec2,
+}
+
+impl TraceGeometry {
+    fn new(trace_path: &TracePath, num_layers: usize) -> Self {
+        let mut min_corner = FloatVec2::new(f32::MAX, f32::MAX);
+        let mut max_corner = FloatVec2::new(f32::MIN, f32::MIN);
+        let mut extend = |position: FloatVec2, radius: f32| {
+            min_corner = FloatVec2::new(min_corner.x.min(position.x - radius), min_corner.y.min(position.y - radius));
+            max_corner = FloatVec2::new(max_corner.x.max(position.x + radius), max_corner.y.max(position.y + radius));
+        };
+        for segment in trace_path.segments.iter() {
+            let radius = segment.width / 2.0 + segment.clearance;
+            extend(segment.start.to_float(), radius);
+            extend(segment.end.to_float(), radius);
+        }
+        for via in trace_path.vias.iter() {
+            extend(via.position.to_float(), via.diameter / 2.0 + via.clearance);
+        }
+        TraceGeometry {
+            colliders: trace_path.to_colliders(num_layers),
+            clearance_colliders: trace_path.to_clearance_colliders(num_layers),
+            min_corner,
+            max_corner,
+        }
+    }
+    fn overlaps(&self, other: &TraceGeometry) -> bool {
+        if self.max_corner.x < other.min_corner.x
+            || other.max_corner.x < self.min_corner.x
+            || self.max_corner.y < other.min_corner.y
+            || other.max_corner.y < self.min_corner.y
+        {
+            return false;
+        }
+        let any_collision = |colliders: &Vec<Collider>, others: &Vec<Collider>| {
+            colliders
+                .iter()
+                .any(|collider| others.iter().any(|other| collider.collides_with(other)))
+        };
+        self.colliders.iter().any(|(layer, colliders)| {
+            any_collision(colliders, &other.clearance_colliders[layer])
+                || any_collision(&self.clearance_colliders[layer], &other.colliders[layer])
+        })
+    }
+}
+
+/// pairs of routed connections of different nets whose traces violate the clearance, sorted
+fn find_overlaps(problem: &PcbProblem, routes: &HashMap<ConnectionID, FixedTrace>) -> Vec<(ConnectionID, ConnectionID)> {
+    let mut routed: Vec<(&ConnectionID, &FixedTrace)> = routes.iter().collect();
+    routed.sort_by_key(|(connection_id, _)| **connection_id);
+    let geometries: Vec<TraceGeometry> = routed
+        .iter()
+        .map(|(_, fixed_trace)| TraceGeometry::new(&fixed_trace.trace_path, problem.num_layers))
+        .collect();
+    let mut overlaps: Vec<(ConnectionID, ConnectionID)> = Vec::new();
+    for i in 0..routed.len() {
+        for j in (i + 1)..routed.len() {
+            if routed[i].1.net_name != routed[j].1.net_name && geometries[i].overlaps(&geometries[j]) {
+                overlaps.push((*routed[i].0, *routed[j].0));
+            }
+        }
+    }
+    overlaps
+}
+
+/// drops the connections with the most overlaps until the remaining routes are legal
+fn legal_subset(
+    routes: &HashMap<ConnectionID, FixedTrace>,
+    overlaps: &[(ConnectionID, ConnectionID)],
+) -> HashMap<ConnectionID, FixedTrace> {
+    let mut remaining_overlaps: Vec<(ConnectionID, ConnectionID)> = overlaps.to_vec();
+    let mut dropped: HashSet<ConnectionID> = HashSet::new();
+    while !remaining_overlaps.is_empty() {
+        let mut num_overlaps: HashMap<ConnectionID, usize> = HashMap::new();
+        for (connection_a, connection_b) in remaining_overlaps.iter() {
+            *num_overlaps.entry(*connection_a).or_insert(0) += 1;
+            *num_overlaps.entry(*connection_b).or_insert(0) += 1;
+        }
+        let (worst, _) = num_overlaps
+            .into_iter()
+            .max_by_key(|(connection_id, count)| (*count, *connection_id))
+            .unwrap();
+        dropped.insert(worst);
+        remaining_overlaps.retain(|(connection_a, connection_b)| *connection_a != worst && *connection_b != worst);
+    }
+    routes
+        .iter()
+        .filter(|(connection_id, _)| !dropped.contains(connection_id))
+        .map(|(connection_id, fixed_trace)| (*connection_id, fixed_trace.clone()))
+        .collect()
+}
+
+fn routes_to_pcb_render_model(problem: &PcbProblem, routes: &HashMap<ConnectionID, FixedTrace>) -> PcbRenderModel {
+    let mut trace_shape_renderables: Vec<RenderableBatch> = Vec::new();
+    let mut pad_shape_renderables: Vec<ShapeRenderable> = Vec::new();
+    let mut other_shape_renderables: Vec<ShapeRenderable> = Vec::new();
+    for net_info in problem.nets.values() {
+        for pad in net_info.pads.values() {
+            pad_shape_renderables.extend(pad.to_renderables(net_info.color.to_float4(1.0)));
+            pad_shape_renderables.extend(pad.to_clearance_renderables(net_info.color.to_float4(0.5)));
+        }
+    }
+    for fixed_trace in routes.values() {
+        let color = problem.nets[&fixed_trace.net_name].color;
+        trace_shape_renderables.extend(fixed_trace.trace_path.to_renderables(color.to_float4(1.0)));
+    }
+    for line in &problem.obstacle_border_outlines {
+        other_shape_renderables.push(ShapeRenderable {
+            shape: PrimShape::Line(line.clone()),
+            color: [1.0, 0.0, 1.0, 1.0], // magenta color for borders
+        });
+    }
+    PcbRenderModel {
+        width: problem.width,
+        height: problem.height,
+        center: problem.center,
+        trace_shape_renderables,
+        pad_shape_renderables,
+        other_shape_renderables,
+    }
+}
+
+fn display_when_necessary(
+    problem: &PcbProblem,
+    routes: &HashMap<ConnectionID, FixedTrace>,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+) {
+    let command_level = COMMAND_LEVEL.load(Ordering::Relaxed);
+    {
+        let mut pcb_render_model = pcb_render_model.lock().unwrap();
+        if pcb_render_model.is_some() {
+            return; // already rendered, no need to update
+        }
+        *pcb_render_model = Some(routes_to_pcb_render_model(problem, routes));
+    }
+    if command_level <= CommandFlag::ProbaModelResult.get_level() {
+        // block the thread until the user clicks a button
+        let mutex_guard = COMMAND_MUTEXES[3].lock().unwrap();
+        let _unused = COMMAND_CVS[3].wait(mutex_guard).unwrap();
+    } else {
+        thread::sleep(Duration::from_millis(0));
+    }
+}
+
+/// PathFinder-style router: every connection is routed with A* while traces of other nets may overlap at a cost,
+/// then the overlapping connections are ripped up and rerouted with growing present and history congestion costs
+/// until no overlaps remain, prerouted traces are kept as they are and are hard obstacles for the other nets
+pub fn negotiated_congestion(
+    problem: &PcbProblem,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    prerouted_traces: &HashMap<ConnectionID, FixedTrace>,
+    cancellation_token: &CancellationToken,
+) -> Result<PcbSolution, PartialSolution> {
+    let connections: HashMap<ConnectionID, Rc<Connection>> = problem
+        .nets
+        .values()
+        .flat_map(|net_info| net_info.connections.iter())
+        .map(|(connection_id, connection)| (*connection_id, connection.clone()))
+        .collect();
+    // route the short connections first, they have the least freedom to go around others
+    let pad_distance = |connection: &Connection| -> f32 {
+        let pads = &problem.nets[&connection.net_name].pads;
+        (pads[&connection.start_pad].position - pads[&connection.end_pad].position).length()
+    };
+    let mut ordered_connections: Vec<ConnectionID> = connections
+        .keys()
+        .filter(|connection_id| !prerouted_traces.contains_key(connection_id))
+        .cloned()
+        .collect();
+    ordered_connections.sort_by(|a, b| {
+        pad_distance(&connections[a])
+            .total_cmp(&pad_distance(&connections[b]))
+            .then(a.cmp(b))
+    });
+    let net_obstacles: HashMap<NetName, Obstacles> = problem
+        .nets
+        .keys()
+        .map(|net_name| (net_name.clone(), hard_obstacles_for_net(problem, net_name, prerouted_traces)))
+        .collect();
+
+    let mut congestion_map = CongestionMap::new(problem);
+    let mut routes: HashMap<ConnectionID, FixedTrace> = prerouted_traces.clone();
+    let mut best_partial_solution = BestPartialSolution::new();
+    let mut present_factor = PRESENT_CONGESTION_FACTOR;
+    let mut connections_to_route = ordered_connections.clone();
+    let mut failure_reason = format!(
+        "Overlaps remain after {} negotiated congestion iterations",
+        NEGOTIATED_CONGESTION_MAX_ITERATIONS
+    );
+    // the last search error of each connection, so unroutable connections can be reported with a reason
+    let mut search_failures: HashMap<ConnectionID, String> = HashMap::new();
+
+    for iteration in 0..NEGOTIATED_CONGESTION_MAX_ITERATIONS {
+        println!(
+            "Negotiated congestion iteration {}: rerouting {} connections, present factor {:.2}",
+            iteration + 1,
+            connections_to_route.len(),
+            present_factor
+        );
+        for connection_id in connections_to_route.iter() {
+            if cancellation_token.is_cancelled() {
+                break;
+            }
+            let connection = &connections[connection_id];
+            let net_info = &problem.nets[&connection.net_name];
+            // rip up the old route so the connection does not pay for its own congestion
+            congestion_map.remove_trace(*connection_id);
+            let congestion_costs = congestion_map.costs_for_net(&connection.net_name, present_factor);
+            let obstacles = &net_obstacles[&connection.net_name];
+            let start_pad = &net_info.pads[&connection.start_pad];
+            let end_pad = &net_info.pads[&connection.end_pad];
+            let via_obstacles = ViaObstacles::new(
+                problem,
+                &connection.net_name,
+                routes
+                    .iter()
+                    .filter(|(routed_id, _)| *routed_id != connection_id)
+                    .map(|(_, fixed_trace)| &fixed_trace.trace_path),
+            );
+            let astar_model = AStarModel {
+                start: PadEntry::from_pad(start_pad, net_info.trace_width, &problem.pad_entry),
+                end: PadEntry::from_pad(end_pad, net_info.trace_width, &problem.pad_entry),
+                start_layers: start_pad.pad_layer,
+                end_layers: end_pad.pad_layer,
+                num_layers: problem.num_layers,
+                trace_width: net_info.trace_width,
+                trace_clearance: net_info.trace_clearance,
+                via_types: net_info.via_types.clone(),
+                width: problem.width,
+                height: problem.height,
+                center: problem.center,
+                obstacle_shapes: obstacles.shapes.clone(),
+                obstacle_clearance_shapes: obstacles.clearance_shapes.clone(),
+                obstacle_colliders: obstacles.colliders.clone(),
+                obstacle_clearance_colliders: obstacles.clearance_colliders.clone(),
+                border_colliders_cache: RefCell::new(None),
+                border_shapes_cache: RefCell::new(None),
+                congestion_costs: Some(Rc::new(congestion_costs)),
+                routing_constraints: net_info.routing_constraints.clone(),
+                via_obstacles: Rc::new(via_obstacles),
+                layer_directions: problem.layer_directions.clone(),
+                blocking_traces: None,
+            };
+            match astar_model.run(pcb_render_model.clone(), cancellation_token) {
+                Ok(result) => {
+                    search_failures.remove(connection_id);
+                    congestion_map.add_trace(*connection_id, &connection.net_name, &result.trace_path);
+                    routes.insert(
+                        *connection_id,
+                        FixedTrace {
+                            net_name: connection.net_name.clone(),
+                            connection_id: *connection_id,
+                            trace_path: result.trace_path,
+                        },
+                    );
+                }
+                Err(err) => {
+                    println!("Cannot find a path for connection {:?}: {}", connection_id, err);
+                    search_failures.insert(*connection_id, err);
+                    // keep the previous route, if any, it is still better than nothing
+                    if let Some(fixed_trace) = routes.get(connection_id) {
+                        congestion_map.add_trace(*connection_id, &connection.net_name, &fixed_trace.trace_path);
+                    }
+                }
+            }
+        }
+        if cancellation_token.is_cancelled() {
+            failure_reason = cancellation_token.reason();
+            break;
+        }
+
+        let overlaps = find_overlaps(problem, &routes);
+        best_partial_solution.consider(&legal_subset(&routes, &overlaps));
+        display_when_necessary(problem, &routes, pcb_render_model.clone());
+        let num_unrouted = connections.len() - routes.len();
+        println!(
+            "Negotiated congestion iteration {}: {} overlapping pairs, {} unrouted connections, {} overused cells",
+            iteration + 1,
+            overlaps.len(),
+            num_unrouted,
+            congestion_map.num_overused_cells()
+        );
+        if overlaps.is_empty() && num_unrouted == 0 {
+            println!("Negotiated congestion converged after {} iterations", iteration + 1);
+            return Ok(PcbSolution {
+                determined_traces: routes,
+                copper_pours: Vec::new(),
+                uncoupled_diff_pairs: Vec::new(),
+                scale_down_factor: problem.scale_down_factor,
+            });
+        }
+
+        congestion_map.update_history(&overlaps, HISTORY_CONGESTION_FACTOR);
+        present_factor *= PRESENT_CONGESTION_GROWTH;
+        let overlapping: HashSet<ConnectionID> = overlaps
+            .iter()
+            .flat_map(|(connection_a, connection_b)| [*connection_a, *connection_b])
+            .collect();
+        connections_to_route = ordered_connections
+            .iter()
+            .filter(|connection_id| overlapping.contains(connection_id) || !routes.contains_key(connection_id))
+            .cloned()
+            .collect();
+    }
+    let mut unroutable: Vec<(&ConnectionID, &String)> = search_failures
+        .iter()
+        .filter(|(connection_id, _)| !routes.contains_key(connection_id))
+        .collect();
+    unroutable.sort();
+    for (connection_id, err) in unroutable {
+        failure_reason.push_str(&format!(
+            ", connection {} of net {} is unroutable: {}",
+            connection_id.0, connections[connection_id].net_name.0, err
+        ));
+    }
+    Err(best_partial_solution.into_partial_solution(problem, failure_reason))
+}
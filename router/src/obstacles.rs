@@ -0,0 +1,143 @@
+use std::{collections::HashMap, rc::Rc};
+
+use shared::{
+    collider::Collider,
+    pcb_problem::{NetName, PcbProblem},
+    prim_shape::PrimShape,
+    trace_path::TracePath,
+};
+
+use crate::quad_tree::QuadTreeNode;
+
+// the copper of other nets a trace has to keep clear of, per layer, shared with A* and AStarCheck
+pub(crate) struct Obstacles {
+    pub(crate) shapes: Rc<HashMap<usize, Vec<PrimShape>>>,
+    pub(crate) clearance_shapes: Rc<HashMap<usize, Vec<PrimShape>>>,
+    pub(crate) colliders: Rc<HashMap<usize, QuadTreeNode>>,
+    pub(crate) clearance_colliders: Rc<HashMap<usize, QuadTreeNode>>,
+}
+
+// collects the obstacles layer by layer, build() hands them out
+pub(crate) struct ObstacleBuilder {
+    num_layers: usize,
+    shapes: HashMap<usize, Vec<PrimShape>>,
+    clearance_shapes: HashMap<usize, Vec<PrimShape>>,
+    colliders: HashMap<usize, QuadTreeNode>,
+    clearance_colliders: HashMap<usize, QuadTreeNode>,
+}
+
+impl ObstacleBuilder {
+    pub(crate) fn new(problem: &PcbProblem) -> Self {
+        let quad_tree_side_length = f32::max(problem.width, problem.height);
+        let quad_tree_x_min = problem.center.x - quad_tree_side_length / 2.0;
+        let quad_tree_x_max = problem.center.x + quad_tree_side_length / 2.0;
+        let quad_tree_y_min = problem.center.y - quad_tree_side_length / 2.0;
+        let quad_tree_y_max = problem.center.y + quad_tree_side_length / 2.0;
+        let new_quad_trees = || -> HashMap<usize, QuadTreeNode> {
+            (0..problem.num_layers)
+                .map(|layer| {
+                    (
+                        layer,
+                        QuadTreeNode::new(quad_tree_x_min, quad_tree_x_max, quad_tree_y_min, quad_tree_y_max, 0),
+                    )
+                })
+                .collect()
+        };
+        ObstacleBuilder {
+            num_layers: problem.num_layers,
+            shapes: (0..problem.num_layers).map(|layer| (layer, Vec::new())).collect(),
+            clearance_shapes: (0..problem.num_layers).map(|layer| (layer, Vec::new())).collect(),
+            colliders: new_quad_trees(),
+            clearance_colliders: new_quad_trees(),
+        }
+    }
+
+    /// adds the pads of every net except the given ones
+    pub(crate) fn add_pads_of_other_nets(&mut self, problem: &PcbProblem, own_nets: &[&NetName]) {
+        for (_, net_info) in problem
+            .nets
+            .iter()
+            .filter(|(net_name, _)| !own_nets.contains(net_name))
+        {
+            for pad in net_info.pads.values() {
+                for layer in pad.pad_layer.get_iter(self.num_layers) {
+                    let pad_shapes = pad.to_shapes();
+                    let pad_clearance_shapes = pad.to_clearance_shapes();
+                    self.colliders
+                        .get_mut(&layer)
+                        .unwrap()
+                        .extend(pad_shapes.iter().map(Collider::from_prim_shape));
+                    self.clearance_colliders
+                        .get_mut(&layer)
+                        .unwrap()
+                        .extend(pad_clearance_shapes.iter().map(Collider::from_prim_shape));
+                    self.shapes.get_mut(&layer).unwrap().extend(pad_shapes);
+                    self.clearance_shapes.get_mut(&layer).unwrap().extend(pad_clearance_shapes);
+                }
+            }
+        }
+    }
+
+    /// adds the copper and the clearance area of a trace
+    pub(crate) fn add_trace(&mut self, trace_path: &TracePath) {
+        let trace_shapes = trace_path.to_shapes(self.num_layers);
+        let trace_clearance_shapes = trace_path.to_clearance_shapes(self.num_layers);
+        let trace_colliders = trace_path.to_colliders(self.num_layers);
+        let trace_clearance_colliders = trace_path.to_clearance_colliders(self.num_layers);
+        for layer in 0..self.num_layers {
+            self.shapes.get_mut(&layer).unwrap().extend(trace_shapes[&layer].iter().cloned());
+            self.clearance_shapes
+                .get_mut(&layer)
+                .unwrap()
+                .extend(trace_clearance_shapes[&layer].iter().cloned());
+            self.colliders
+                .get_mut(&layer)
+                .unwrap()
+                .extend(trace_colliders[&layer].iter().cloned());
+            self.clearance_colliders
+                .get_mut(&layer)
+                .unwrap()
+                .extend(trace_clearance_colliders[&layer].iter().cloned());
+        }
+    }
+
+    /// adds a trace that may be pushed aside: its vias do not move and keep their clearance,
+    /// its segments only may not be crossed
+    pub(crate) fn add_trace_to_cross(&mut self, trace_path: &TracePath) {
+        for via in trace_path.vias.iter() {
+            for layer in via.min_layer..=via.max_layer {
+                self.shapes.get_mut(&layer).unwrap().push(via.to_shape());
+                self.clearance_shapes.get_mut(&layer).unwrap().push(via.to_clearance_shape());
+                self.colliders.get_mut(&layer).unwrap().insert(via.to_collider());
+                self.clearance_colliders.get_mut(&layer).unwrap().insert(via.to_clearance_collider());
+            }
+        }
+        for segment in trace_path.segments.iter() {
+            self.clearance_shapes.get_mut(&segment.layer).unwrap().extend(segment.to_shapes());
+            self.clearance_colliders.get_mut(&segment.layer).unwrap().extend(segment.to_colliders().into_iter());
+        }
+    }
+
+    pub(crate) fn build(self) -> Obstacles {
+        Obstacles {
+            shapes: Rc::new(self.shapes),
+            clearance_shapes: Rc::new(self.clearance_shapes),
+            colliders: Rc::new(self.colliders),
+            clearance_colliders: Rc::new(self.clearance_colliders),
+        }
+    }
+}
+
+/// the pads of every net except the own ones and the given traces
+pub(crate) fn obstacles_of_other_nets<'a>(
+    problem: &PcbProblem,
+    own_nets: &[&NetName],
+    traces: impl IntoIterator<Item = &'a TracePath>,
+) -> Obstacles {
+    let mut builder = ObstacleBuilder::new(problem);
+    builder.add_pads_of_other_nets(problem, own_nets);
+    for trace_path in traces {
+        builder.add_trace(trace_path);
+    }
+    builder.build()
+}
@@ -0,0 +1,201 @@
+use shared::{
+    hyperparameters::{ASTAR_STRIDE, OFF_CENTER_ENTRY_COST},
+    octile_distance::octile_distance_fixed,
+    pad::{Pad, PadShape},
+    pcb_problem::PadEntryRules,
+    trace_path::Direction,
+    vec2::{FixedPoint, FixedVec2, FloatVec2},
+};
+
+const MAX_PAD_ENTRY_POINTS: usize = 9; // the centre, the two ends of the long axis and the nearest grid crossings
+const AXIS_ANGLE_TOLERANCE: f32 = 0.5; // degrees a long axis may be off a routing direction and still count as one
+
+/// where a search may start or end on a pad: a few points inside the pad copper,
+/// and optionally the directions the trace has to leave or reach them in
+#[derive(Debug, Clone)]
+pub struct PadEntry {
+    pub points: Vec<FixedVec2>, // even-even points the trace may end at, the snapped centre first
+    pub exit_directions: Vec<Direction>, // planar moves from or to the points run along one of these, any direction if empty
+    exit_reach: f32, // half the length of the pad along the exit directions, moves along them have to leave the copper
+}
+
+impl PadEntry {
+    /// a single point, for searches that do not end on a pad
+    pub fn at(position: FixedVec2) -> Self {
+        PadEntry {
+            points: vec![position],
+            exit_directions: Vec::new(),
+            exit_reach: 0.0,
+        }
+    }
+
+    pub fn from_pad(pad: &Pad, trace_width: f32, rules: &PadEntryRules) -> Self {
+        let center = pad.position.to_fixed().to_nearest_even_even();
+        // round pads are vias and through holes, landing off their centre gains nothing
+        let (width, height, corner_radius) = match pad.shape {
+            PadShape::Circle { .. } => return PadEntry::at(center),
+            PadShape::Rectangle { width, height } => (width, height, 0.0),
+            PadShape::RoundRect {
+                width,
+                height,
+                corner_radius,
+            } => (width, height, corner_radius),
+        };
+        let exit_directions = if rules.long_axis_exit && width != height {
+            let axis_angle = pad.rotation.0 + if width > height { 0.0 } else { 90.0 };
+            axis_directions(axis_angle)
+        } else {
+            Vec::new()
+        };
+        let exit_reach = width.max(height) / 2.0;
+        let mut points = vec![center];
+        if !rules.off_center {
+            return PadEntry { points, exit_directions, exit_reach };
+        }
+
+        let (cos, sin) = (pad.rotation.0.to_radians().cos(), pad.rotation.0.to_radians().sin());
+        let to_world = |local: FloatVec2| {
+            pad.position + FloatVec2::new(local.x * cos - local.y * sin, local.x * sin + local.y * cos)
+        };
+        let to_local = |world: FloatVec2| {
+            let offset = world - pad.position;
+            FloatVec2::new(offset.x * cos + offset.y * sin, -offset.x * sin + offset.y * cos)
+        };
+        // the round end of the trace has to stay inside the copper
+        let half_width = width / 2.0 - trace_width / 2.0;
+        let half_height = height / 2.0 - trace_width / 2.0;
+        let inner_radius = corner_radius - trace_width / 2.0;
+        if half_width < 0.0 || half_height < 0.0 {
+            return PadEntry { points, exit_directions, exit_reach };
+        }
+        // snapping to the even grid may move a point out by a couple of fixed point steps
+        let tolerance = FixedPoint::DELTA.to_num::<f32>() * 2.0;
+        let inside = |local: FloatVec2| {
+            let (x, y) = (local.x.abs(), local.y.abs());
+            if x > half_width + tolerance || y > half_height + tolerance {
+                return false;
+            }
+            // in a rounded corner, measure from the centre of the corner arc
+            let corner_x = width / 2.0 - corner_radius;
+            let corner_y = height / 2.0 - corner_radius;
+            if corner_radius > 0.0 && x > corner_x && y > corner_y {
+                return FloatVec2::new(x - corner_x, y - corner_y).length() <= inner_radius.max(0.0) + tolerance;
+            }
+            true
+        };
+        let long_axis_ends = if width >= height {
+            [FloatVec2::new(half_width, 0.0), FloatVec2::new(-half_width, 0.0)]
+        } else {
+            [FloatVec2::new(0.0, half_height), FloatVec2::new(0.0, -half_height)]
+        };
+        let mut candidates: Vec<FloatVec2> = long_axis_ends.iter().map(|end| to_world(*end)).collect();
+
+        // where the grid lines of the search cross the pad, so pads off the grid can be reached along a grid line
+        let stride = ASTAR_STRIDE.to_num::<f32>();
+        let reach = half_width.max(half_height);
+        let grid_lines = |center: f32| {
+            let first = ((center - reach) / stride).ceil() as i32;
+            let last = ((center + reach) / stride).floor() as i32;
+            (first..=last).map(move |index| index as f32 * stride)
+        };
+        let mut grid_candidates: Vec<FloatVec2> = Vec::new();
+        for x in grid_lines(pad.position.x) {
+            grid_candidates.push(FloatVec2::new(x, pad.position.y));
+            for y in grid_lines(pad.position.y) {
+                grid_candidates.push(FloatVec2::new(x, y));
+            }
+        }
+        for y in grid_lines(pad.position.y) {
+            grid_candidates.push(FloatVec2::new(pad.position.x, y));
+        }
+        grid_candidates.sort_by(|a, b| {
+            (*a - pad.position)
+                .length()
+                .total_cmp(&(*b - pad.position).length())
+        });
+        candidates.extend(grid_candidates);
+
+        for candidate in candidates {
+            if points.len() >= MAX_PAD_ENTRY_POINTS {
+                break;
+            }
+            let point = candidate.to_fixed().to_nearest_even_even();
+            if !points.contains(&point) && inside(to_local(point.to_float())) {
+                points.push(point);
+            }
+        }
+        PadEntry { points, exit_directions, exit_reach }
+    }
+
+    /// the snapped centre of the pad
+    pub fn center(&self) -> FixedVec2 {
+        self.points[0]
+    }
+
+    pub fn contains(&self, position: FixedVec2) -> bool {
+        self.points.contains(&position)
+    }
+
+    pub fn allows_direction(&self, direction: Direction) -> bool {
+        self.exit_directions.is_empty() || self.exit_directions.contains(&direction)
+    }
+
+    /// how far a move from the point in the direction has to go to leave the pad copper
+    pub fn exit_length(&self, point: FixedVec2, direction: Direction) -> f32 {
+        let step = direction.to_fixed_vec2(FixedPoint::ONE).to_float();
+        let along = (point.to_float() - self.center().to_float()).dot(step / step.length());
+        (self.exit_reach - along).max(0.0)
+    }
+
+    /// whether a straight segment obeys the exit directions, only segments touching one of the points are restricted,
+    /// they have to run along an exit direction until they are out of the pad
+    pub fn allows_segment(&self, start: FixedVec2, end: FixedVec2) -> bool {
+        if self.exit_directions.is_empty() || start == end {
+            return true;
+        }
+        let length = (end - start).to_float().length();
+        let allows_exit = |point: FixedVec2, other: FixedVec2| match Direction::from_points(point, other) {
+            Ok(Some(direction)) => self.allows_direction(direction) && length >= self.exit_length(point, direction),
+            _ => false,
+        };
+        (!self.contains(start) || allows_exit(start, end)) && (!self.contains(end) || allows_exit(end, start))
+    }
+
+    /// the extra cost of ending a trace at the point instead of the centre,
+    /// so that the centre is still preferred unless reaching it takes a detour
+    pub fn landing_cost(&self, point: FixedVec2) -> f64 {
+        octile_distance_fixed(point, self.center()) * OFF_CENTER_ENTRY_COST
+    }
+
+    /// octile distance to the nearest point, a lower bound of the remaining length
+    pub fn distance(&self, position: FixedVec2) -> f64 {
+        self.points
+            .iter()
+            .map(|point| octile_distance_fixed(position, *point))
+            .fold(f64::MAX, f64::min)
+    }
+
+    /// lower bound of the remaining cost, the distance to each point with its landing cost
+    pub fn estimated_cost(&self, position: FixedVec2) -> f64 {
+        self.points
+            .iter()
+            .map(|point| octile_distance_fixed(position, *point) + self.landing_cost(*point))
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+/// both routing directions along an axis at the angle, none if the axis is not one of the eight directions
+fn axis_directions(angle: f32) -> Vec<Direction> {
+    let angle = angle.rem_euclid(180.0);
+    let step = (angle / 45.0).round();
+    if (angle - step * 45.0).abs() > AXIS_ANGLE_TOLERANCE {
+        return Vec::new();
+    }
+    let direction = match step as i32 % 4 {
+        0 => Direction::Right,
+        1 => Direction::TopRight,
+        2 => Direction::Up,
+        _ => Direction::TopLeft,
+    };
+    vec![direction, direction.opposite()]
+}
@@ -1,48 +1,231 @@
-use std::sync::{atomic::Ordering, Arc, Mutex};
+use std::{collections::{HashMap, HashSet}, sync::{atomic::Ordering, Arc, Mutex}};
 
-use shared::{hyperparameters::SAMPLE_CNT, pcb_problem::{ConnectionID, PcbProblem, PcbSolution}, pcb_render_model::PcbRenderModel};
+use shared::{hyperparameters::SAMPLE_CNT, pcb_problem::{ConnectionID, FixedTrace, NetName, PartialSolution, PcbProblem, PcbSolution}, pcb_render_model::PcbRenderModel};
 
-use crate::{bayesian_backtrack_algo::{bayesian_backtrack, TraceCache}, naive_backtrack_algo::naive_backtrack};
+use crate::{connectivity::check_connectivity, copper_pour::fill_copper_pours, bayesian_backtrack_algo::{bayesian_backtrack, TraceCache}, naive_backtrack_algo::naive_backtrack, negotiated_congestion_algo::negotiated_congestion, diff_pair::route_differential_pairs, fanout::{plan_fanouts, Fanouts}, length_matching::tune_lengths, routing_constraints::check_routing_constraints, router_config::{RouterConfig, RoutingAlgorithm}};
 
 
 
-/// this either calls naive backtrack or bayesian backtrack
+/// the traces of an existing solution to rip up and route again
+#[derive(Debug, Clone, Default)]
+pub struct RipUp {
+    pub nets: HashSet<NetName>,             // every connection of these nets is routed again
+    pub connections: HashSet<ConnectionID>, // single connections routed again, the other traces of their nets are kept
+}
+
+impl RipUp {
+    pub fn contains(&self, fixed_trace: &FixedTrace) -> bool {
+        self.nets.contains(&fixed_trace.net_name) || self.connections.contains(&fixed_trace.connection_id)
+    }
+}
+
+/// this calls naive backtrack, bayesian backtrack or the negotiated congestion router, depending on config.algorithm
+/// when the problem cannot be fully routed, the best partial solution found is returned as the error
 pub fn solve_pcb_problem(
     pcb_problem: &PcbProblem,
     pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
-    bayesian: bool,
-) -> Result<PcbSolution, String> {
+    config: &RouterConfig,
+) -> Result<PcbSolution, PartialSolution> {
+    solve_around_fixed_traces(pcb_problem, HashMap::new(), pcb_render_model, config)
+}
+
+/// rips up the selected traces of an existing solution and routes their connections again with the solver of config.algorithm,
+/// every other trace of the solution is kept as it is and the new traces go around it,
+/// connections the solution does not route are routed as well, length tuning and copper pours are redone for the whole board
+pub fn reroute_pcb_solution(
+    pcb_problem: &PcbProblem,
+    solution: &PcbSolution,
+    rip_up: &RipUp,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    config: &RouterConfig,
+) -> Result<PcbSolution, PartialSolution> {
+    for net_name in rip_up.nets.iter().filter(|net_name| !pcb_problem.nets.contains_key(*net_name)) {
+        println!("Warning: net {} is not on the board, nothing to rip up", net_name.0);
+    }
+    let connection_nets: HashMap<ConnectionID, &NetName> = pcb_problem
+        .nets
+        .iter()
+        .flat_map(|(net_name, net_info)| net_info.connections.keys().map(move |connection_id| (*connection_id, net_name)))
+        .collect();
+    for connection_id in rip_up.connections.iter().filter(|connection_id| !connection_nets.contains_key(*connection_id)) {
+        println!("Warning: connection {} is not on the board, nothing to rip up", connection_id.0);
+    }
+    let mut fixed_traces: HashMap<ConnectionID, FixedTrace> = HashMap::new();
+    for (connection_id, fixed_trace) in solution.determined_traces.iter() {
+        if connection_nets.get(connection_id) != Some(&&fixed_trace.net_name) {
+            println!(
+                "Warning: the trace of connection {} does not belong to a connection of net {} on the board, it is dropped",
+                connection_id.0, fixed_trace.net_name.0
+            );
+            continue;
+        }
+        if !rip_up.contains(fixed_trace) {
+            fixed_traces.insert(*connection_id, fixed_trace.clone());
+        }
+    }
+    println!(
+        "Keeping {} traces, routing {} connections again",
+        fixed_traces.len(),
+        connection_nets.len() - fixed_traces.len()
+    );
+    solve_around_fixed_traces(pcb_problem, fixed_traces, pcb_render_model, config)
+}
+
+/// routes every connection without a fixed trace, the fixed traces are part of the solution and obstacles for the others
+fn solve_around_fixed_traces(
+    pcb_problem: &PcbProblem,
+    fixed_traces: HashMap<ConnectionID, FixedTrace>,
+    pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+    config: &RouterConfig,
+) -> Result<PcbSolution, PartialSolution> {
     let connections: Vec<ConnectionID> = pcb_problem.nets.iter().flat_map(|(_, net_info)| net_info.connections.keys().cloned()).collect::<Vec<_>>();
     let mut trace_cache = TraceCache{
         traces: connections.iter().map(|&connection_id| (connection_id, Vec::new())).collect(),
     };
 
-    let result = if bayesian {
+    // the budget starts counting here, the cancel flag stays shared with the caller's token
+    let cancellation_token = config.cancellation_token.with_budget(config.time_budget);
+    // differential pairs are routed first as coupled traces, the solvers keep them fixed
+    let (prerouted_traces, uncoupled_diff_pairs) = route_differential_pairs(pcb_problem, &fixed_traces, pcb_render_model.clone(), &cancellation_token);
+    // the pads of dense parts are fanned out to vias, the solvers route between the vias of the fanned out problem
+    let fanouts = if config.fanout { plan_fanouts(pcb_problem, &prerouted_traces) } else { Fanouts::default() };
+    let fanout_problem = if fanouts.is_empty() { None } else { Some(fanouts.apply(pcb_problem)) };
+    let solver_problem = fanout_problem.as_ref().unwrap_or(pcb_problem);
+    // the traces of an earlier run on the same board are reused where they are still legal
+    if let Some(trace_cache_path) = config.trace_cache_path.as_ref().filter(|path| path.exists())
+        && let Err(e) = trace_cache.load(solver_problem, trace_cache_path)
+    {
+        println!("Warning: {}", e);
+    }
+    let result = match config.algorithm {
         // Call the Bayesian backtrack function
-        bayesian_backtrack(pcb_problem, pcb_render_model, &mut trace_cache)
-    } else {
+        RoutingAlgorithm::Bayesian => bayesian_backtrack(solver_problem, pcb_render_model, &mut trace_cache, &prerouted_traces, config, &cancellation_token),
         // Call the naive backtrack function
-        naive_backtrack(pcb_problem, pcb_render_model, &mut trace_cache, None)
+        RoutingAlgorithm::Naive => naive_backtrack(solver_problem, pcb_render_model, &mut trace_cache, None, &prerouted_traces, config, &cancellation_token),
+        // Call the negotiated congestion router, it does not reuse cached traces because its costs change every iteration
+        RoutingAlgorithm::NegotiatedCongestion => negotiated_congestion(solver_problem, pcb_render_model, &prerouted_traces, &cancellation_token),
     };
+    if let Some(trace_cache_path) = config.trace_cache_path.as_ref()
+        && let Err(e) = trace_cache.save(solver_problem, trace_cache_path)
+    {
+        println!("Warning: {}", e);
+    }
+    // the stubs join the routed traces to the pads again
+    let result = match result {
+        Ok(solution) => Ok(fanouts.stitch(pcb_problem, solution)),
+        Err(partial_solution) => Err(PartialSolution {
+            solution: Box::new(fanouts.stitch(pcb_problem, *partial_solution.solution)),
+            ..partial_solution
+        }),
+    };
+    // the pairs that fell back to separate nets are reported with the solution, complete or not
+    if !uncoupled_diff_pairs.is_empty() {
+        println!(
+            "Warning: {} differential pairs were routed as separate nets: {}",
+            uncoupled_diff_pairs.len(),
+            uncoupled_diff_pairs
+                .iter()
+                .map(|pair| format!("{} / {} ({})", pair.positive_net.0, pair.negative_net.0, pair.reason))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+    }
+    match check_solution(pcb_problem, result, connections.len()) {
+        Ok(mut solution) => {
+            solution.uncoupled_diff_pairs = uncoupled_diff_pairs;
+            Ok(solution)
+        }
+        Err(mut partial_solution) => {
+            partial_solution.solution.uncoupled_diff_pairs = uncoupled_diff_pairs;
+            Err(partial_solution)
+        }
+    }
+}
+
+/// checks that a solution of the solver routes every connection correctly and within the constraints of its net,
+/// the connections that do not are reported as unrouted, the pours of a correct solution are filled
+fn check_solution(
+    pcb_problem: &PcbProblem,
+    result: Result<PcbSolution, PartialSolution>,
+    num_connections: usize,
+) -> Result<PcbSolution, PartialSolution> {
     match result{
-        Ok(solution) => {
+        Ok(mut solution) => {
             println!("PCB problem solved successfully");
             println!("Sample Count: {}", SAMPLE_CNT.load(Ordering::SeqCst));
-            if solution.determined_traces.len() < connections.len() {
+            if solution.determined_traces.len() < num_connections {
                 let err_msg = format!(
                     "Not all connections were solved. Expected: {}, Found: {}",
-                    connections.len(),
+                    num_connections,
                     solution.determined_traces.len()
                 );
                 println!("{}", err_msg);
-                return Err(err_msg);
+                return Err(PartialSolution::from_fixed_traces(pcb_problem, solution.determined_traces, err_msg));
+            }
+            // counting traces is not enough, the copper has to connect every pad of each net
+            let connectivity_report = check_connectivity(pcb_problem, &solution);
+            print!("{}", connectivity_report);
+            if !connectivity_report.is_connected() {
+                let err_msg = format!(
+                    "The routed copper does not match the netlist: {} open nets, {} net merges, {} trace endpoints off pad",
+                    connectivity_report.open_nets.len(),
+                    connectivity_report.net_merges.len(),
+                    connectivity_report.endpoints_off_pad.len()
+                );
+                println!("{}", err_msg);
+                // only the nets that are routed correctly are kept
+                let mut broken_nets: HashSet<NetName> = connectivity_report
+                    .open_nets
+                    .iter()
+                    .map(|open_net| open_net.net_name.clone())
+                    .collect();
+                broken_nets.extend(
+                    connectivity_report
+                        .net_merges
+                        .iter()
+                        .flat_map(|net_merge| net_merge.net_names.iter().cloned()),
+                );
+                broken_nets.extend(
+                    connectivity_report
+                        .endpoints_off_pad
+                        .iter()
+                        .map(|endpoint| solution.determined_traces[&endpoint.connection_id].net_name.clone()),
+                );
+                let mut determined_traces = solution.determined_traces;
+                determined_traces.retain(|_, fixed_trace| !broken_nets.contains(&fixed_trace.net_name));
+                return Err(PartialSolution::from_fixed_traces(pcb_problem, determined_traces, err_msg));
+            }
+            // meanders only lengthen traces between their pads, so the connectivity stays intact
+            let length_report = tune_lengths(pcb_problem, &mut solution);
+            print!("{}", length_report);
+            // a trace that breaks the constraints of its net is not a solution, its connection is reported as unrouted
+            let violations = check_routing_constraints(pcb_problem, &solution);
+            if !violations.is_empty() {
+                let err_msg = format!(
+                    "{} routed connections break the routing constraints of their nets: {}",
+                    violations.len(),
+                    violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("; ")
+                );
+                println!("{}", err_msg);
+                let mut determined_traces = solution.determined_traces;
+                for violation in violations.iter() {
+                    determined_traces.remove(&violation.connection_id);
+                }
+                return Err(PartialSolution::from_fixed_traces(pcb_problem, determined_traces, err_msg));
             }
+            // the pours go around the final copper, so they are filled last
+            solution.copper_pours = fill_copper_pours(pcb_problem, &solution);
             Ok(solution)
         }
-        Err(e) => {
-            println!("Failed to solve PCB problem: {}", e);
+        Err(partial_solution) => {
+            println!("Failed to solve PCB problem: {}", partial_solution.reason);
+            println!(
+                "Routed {} of {} connections",
+                partial_solution.solution.determined_traces.len(),
+                num_connections
+            );
             println!("Sample Count: {}", SAMPLE_CNT.load(Ordering::SeqCst));
-            Err(e)
+            Err(partial_solution)
         }
     }
 }
@@ -2,10 +2,10 @@ use fixed::traits::Fixed;
 // use crate::block_or_sleep::{block_or_sleep, block_thread};
 use shared::{
     binary_heap_item::BinaryHeapItem,
-    hyperparameters::{ASTAR_STRIDE, DISPLAY_OPTIMIZATION, OPTIMIZATION_PRO},
+    hyperparameters::{ASTAR_STRIDE, DISPLAY_OPTIMIZATION, OPTIMIZATION_OBSTACLE_MARGIN, OPTIMIZATION_PRO},
     pcb_render_model::{PcbRenderModel, RenderableBatch, ShapeRenderable, UpdatePcbRenderModel},
     prim_shape::{CircleShape, PrimShape, RectangleShape},
-    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, TraceSegment, Via},
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, TraceSegment, Via, ViaType},
     vec2::{FixedPoint, FixedVec2, FloatVec2, IntVec2},
 };
 
@@ -183,6 +183,9 @@ pub fn binary_approach_to_obstacles(
 )->FixedPoint{
     println!("Called binary approach to obstacles");
     assert!(start_length < end_length, "start_length should be less than end_length");
+    // the trace stops where it touches the obstacle, the check of its later pieces may round the other way,
+    // a wider trace keeps a margin on both its copper and its clearance
+    let trace_width = trace_width + 2.0 * OPTIMIZATION_OBSTACLE_MARGIN;
     let mut lower_bound = start_length;
     let mut upper_bound = end_length;
     while lower_bound + FixedPoint::DELTA < upper_bound {
@@ -644,7 +647,7 @@ pub fn optimize_path(
     // check_collision_for_via: &dyn Fn(FixedVec2, f32, f32, usize, usize) -> bool, // min, max
     trace_width: f32,
     trace_clearance: f32,
-    via_diameter: f32,
+    via_types: &[ViaType],
 ) -> TracePath {    
     let path = &trace_path.anchors.0;
     let mut optimized = path.clone();    
@@ -750,6 +753,6 @@ pub fn optimize_path(
         // return (TracePath::from_anchors(TraceAnchors(optimized), trace_width, trace_clearance, via_diameter), true);
     }
     let result_trace_anchors = TraceAnchors(optimized);
-    let result_trace_path = TracePath::from_anchors(result_trace_anchors, trace_width, trace_clearance, via_diameter);
+    let result_trace_path = TracePath::from_anchors(result_trace_anchors, trace_width, trace_clearance, via_types);
     result_trace_path
 }
@@ -21,7 +21,7 @@ use shared::{
 };
 
 use crate::{
-    astar::AStarModel, astar_check_struct::AStarCheck, bayesian_backtrack_algo::TraceCache, command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES}, quad_tree::{self, QuadTreeNode}
+    astar::AStarModel, astar_check_struct::AStarCheck, bayesian_backtrack_algo::TraceCache, cancellation::CancellationToken, command_flags::{CommandFlag, COMMAND_CVS, COMMAND_LEVEL, COMMAND_MUTEXES}, congestion_map::CongestionCosts, diversity::DiversityConfig, exact_selection::{select_exact, ExactSelection}, inference::{belief_propagation, mean_field, InferenceConfig, InferenceEngine, InferenceVariable}, pad_entry::PadEntry, quad_tree::{self, QuadTreeNode}, router_config::RouterConfig, via_rules::ViaObstacles
 };
 
 #[derive(Copy, Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
@@ -78,6 +78,8 @@ impl ProbaModel {
         fix_sequence: Vec<ConnectionID>,
         pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
         trace_cache: &mut TraceCache,
+        config: &RouterConfig,
+        cancellation_token: &CancellationToken,
     ) -> Self {
         let mut connection_ids: Vec<ConnectionID> = Vec::new();
         for net_info in problem.nets.values() {
@@ -129,26 +131,102 @@ impl ProbaModel {
         // sample and then update posterior
         // to do: specify iteration number
         for j in 0..SAMPLE_ITERATIONS {
+            if cancellation_token.is_cancelled() {
+                println!("Stopping sampling early: {}", cancellation_token.reason());
+                break;
+            }
             println!("Sampling new traces for iteration {}", j + 1);
-            proba_model.sample_new_traces(problem, pcb_render_model.clone(), trace_cache);
-            println!("Done sampling new traces");
-            display_when_necessary(&proba_model, CommandFlag::UpdatePosteriorResult);
-
-            for i in 0..10 {
-                println!("Updating posterior for the {}th time", i + 1);
-                proba_model.update_posterior();
-                display_when_necessary(&proba_model, CommandFlag::AstarFrontierOrUpdatePosterior);
+            proba_model.sample_and_infer(problem, pcb_render_model.clone(), trace_cache, config, cancellation_token, &display_when_necessary);
+        }
+
+        // small candidate sets are solved exactly, while the candidates provably cannot be combined more are sampled
+        while proba_model.has_probabilistic_connections() && !cancellation_token.is_cancelled() {
+            match select_exact(problem, &proba_model) {
+                ExactSelection::Selected(selection) => {
+                    println!("Exact selection fixed the traces of the {} remaining connections", selection.len());
+                    proba_model.fix_selection(selection);
+                    display_when_necessary(&proba_model, CommandFlag::UpdatePosteriorResult);
+                    break;
+                }
+                ExactSelection::Insufficient(connection_ids) => {
+                    if !ITERATION_TO_PRIOR_PROBABILITY.contains_key(&proba_model.next_iteration) {
+                        println!("The candidates of connections {:?} cannot be combined and no sampling iterations are left", connection_ids);
+                        break;
+                    }
+                    println!("The candidates of connections {:?} cannot be combined, sampling more traces", connection_ids);
+                    proba_model.sample_and_infer(problem, pcb_render_model.clone(), trace_cache, config, cancellation_token, &display_when_necessary);
+                }
+                ExactSelection::TooLarge => break,
             }
-            display_when_necessary(&proba_model, CommandFlag::UpdatePosteriorResult);
         }
         proba_model
     }
 
+    fn sample_and_infer(
+        &mut self,
+        problem: &PcbProblem,
+        pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
+        trace_cache: &mut TraceCache,
+        config: &RouterConfig,
+        cancellation_token: &CancellationToken,
+        display_when_necessary: &impl Fn(&ProbaModel, CommandFlag),
+    ) {
+        self.sample_new_traces(problem, pcb_render_model, trace_cache, &config.diversity, cancellation_token);
+        println!("Done sampling new traces");
+        display_when_necessary(self, CommandFlag::UpdatePosteriorResult);
+
+        match config.inference.engine {
+            InferenceEngine::Heuristic => {
+                for i in 0..10 {
+                    if cancellation_token.is_cancelled() {
+                        break;
+                    }
+                    println!("Updating posterior for the {}th time", i + 1);
+                    self.update_posterior(problem, cancellation_token);
+                    display_when_necessary(self, CommandFlag::AstarFrontierOrUpdatePosterior);
+                }
+            }
+            InferenceEngine::MeanField | InferenceEngine::BeliefPropagation => {
+                if !cancellation_token.is_cancelled() {
+                    self.infer_posterior(problem, &config.inference);
+                    display_when_necessary(self, CommandFlag::AstarFrontierOrUpdatePosterior);
+                }
+            }
+        }
+        display_when_necessary(self, CommandFlag::UpdatePosteriorResult);
+    }
+
+    fn has_probabilistic_connections(&self) -> bool {
+        self.connection_to_traces
+            .values()
+            .any(|traces| matches!(traces, Traces::Probabilistic(_)))
+    }
+
+    /// fixes the selected trace of every connection, in the order of the connection ids
+    fn fix_selection(&mut self, selection: HashMap<ConnectionID, Rc<ProbaTrace>>) {
+        let mut connection_ids: Vec<ConnectionID> = selection.keys().copied().collect();
+        connection_ids.sort();
+        for connection_id in connection_ids {
+            let proba_trace = &selection[&connection_id];
+            self.connection_to_traces.insert(
+                connection_id,
+                Traces::Fixed(FixedTrace {
+                    net_name: proba_trace.net_name.clone(),
+                    connection_id,
+                    trace_path: proba_trace.trace_path.clone(),
+                }),
+            );
+            self.fix_sequence.push(connection_id);
+        }
+    }
+
     fn sample_new_traces(
         &mut self,
         problem: &PcbProblem,
         pcb_render_model: Arc<Mutex<Option<PcbRenderModel>>>,
         trace_cache: &mut TraceCache,
+        diversity: &DiversityConfig,
+        cancellation_token: &CancellationToken,
     ) {
         let mut rng = create_deterministic_rng();
         let mut new_proba_traces: Vec<Rc<ProbaTrace>> = Vec::new();
@@ -239,6 +317,10 @@ impl ProbaModel {
         
         // the outer loop for generating the dijkstra model
         for (net_name, net_info) in problem.nets.iter() {
+            // keep the traces sampled so far, they are still added to the model below
+            if cancellation_token.is_cancelled() {
+                break;
+            }
             // collect connections that are not in this net
             let obstacle_connections: HashSet<ConnectionID> = problem
                 .nets
@@ -295,6 +377,9 @@ impl ProbaModel {
                     .any(|&count| count < max_num_traces)
             {
                 // println!("Generation attempt: {}", num_generation_attempts + 1);
+                if cancellation_token.is_cancelled() {
+                    break;
+                }
                 num_generation_attempts += 1;
                 let mut sampled_obstacle_traces: HashMap<ConnectionID, Option<ProbaTraceID>> =
                     HashMap::new();
@@ -488,6 +573,14 @@ impl ProbaModel {
                     Rc::new(obstacle_colliders);
                 let obstacle_clearance_colliders: Rc<HashMap<usize, QuadTreeNode>> =
                     Rc::new(obstacle_clearance_colliders);
+                let via_obstacles = Rc::new(ViaObstacles::new(
+                    problem,
+                    net_name,
+                    self.connection_to_traces.values().filter_map(|traces| match traces {
+                        Traces::Fixed(fixed_trace) => Some(&fixed_trace.trace_path),
+                        Traces::Probabilistic(_) => None,
+                    }),
+                ));
                 // to do: reuse the obstacle shapes and obstacle clearance shapes
 
                 let connections = &problem
@@ -548,7 +641,7 @@ impl ProbaModel {
                             solution_trace: trace_path.clone(),
                             num_layers: problem.num_layers,
                         };
-                        if astar_check.check() {
+                        if astar_check.check() && via_obstacles.allows_vias(trace_path) {
                             found_satisfying_trace = true; // the trace satisfies the constraints
                             println!("OK: Stored trace path {} satisfies the constraints", i);
                             break; // we found a trace that satisfies the constraints, no need to generate a new one
@@ -567,6 +660,7 @@ impl ProbaModel {
                     // if not, check if the trace cache contains a trace that satisfies the constraints
                     // if not, we will generate a new trace
                     // in both cases, the trace will be added to proba traces
+                    let pitch = net_info.trace_width + net_info.trace_clearance;
                     let mut cached_trace: Option<TracePath> = None;
                     let current_connection_trace_cache = trace_cache.traces.get_mut(connection_id).unwrap();
                     for trace_path in current_connection_trace_cache.iter() {                        
@@ -577,7 +671,10 @@ impl ProbaModel {
                             solution_trace: trace_path.clone(),
                             num_layers: problem.num_layers,
                         };
-                        if astar_check.check() {
+                        if astar_check.check()
+                            && via_obstacles.allows_vias(trace_path)
+                            && diversity.is_dissimilar(trace_path, current_connection_visited_traces.iter(), pitch, self.next_iteration)
+                        {
                             println!("Cache Hit!");                            
                             cached_trace = Some(trace_path.clone());
                             
@@ -593,11 +690,11 @@ impl ProbaModel {
                         // prepare for the a star model
                         let start_pad = net_info.pads.get(&connection.start_pad).unwrap();
                         let end_pad = net_info.pads.get(&connection.end_pad).unwrap();
-                        let start = start_pad.position.to_fixed().to_nearest_even_even();
-                        let end = end_pad.position.to_fixed().to_nearest_even_even();
+                        let start = PadEntry::from_pad(start_pad, net_info.trace_width, &problem.pad_entry);
+                        let end = PadEntry::from_pad(end_pad, net_info.trace_width, &problem.pad_entry);
                         let start_layers = start_pad.pad_layer;
                         let end_layers = end_pad.pad_layer;
-                        let astar_model = AStarModel {
+                        let mut astar_model = AStarModel {
                             width: problem.width,
                             height: problem.height,
                             center: problem.center,
@@ -612,24 +709,70 @@ impl ProbaModel {
                             num_layers: problem.num_layers,
                             trace_width: net_info.trace_width,
                             trace_clearance: net_info.trace_clearance,
-                            via_diameter: net_info.via_diameter,
+                            via_types: net_info.via_types.clone(),
                             border_colliders_cache: RefCell::new(None), // Cache for border points, initialized to None
                             border_shapes_cache: RefCell::new(None), // Cache for border shapes, initialized to None
+                            congestion_costs: None,
+                            routing_constraints: net_info.routing_constraints.clone(),
+                            via_obstacles: via_obstacles.clone(),
+                            layer_directions: problem.layer_directions.clone(),
+                            blocking_traces: None,
                         };
-                        // run A* algorithm to find a path
-                        let astar_result = astar_model.run(pcb_render_model.clone());
-                        let astar_result = match astar_result {
-                            Ok(result) => result,
-                            Err(err) => {
-                                println!("A* algorithm failed: {}", err);
-                                continue; // Skip this connection if A* fails
+                        // run A* until the path is far enough from the earlier candidates of the connection,
+                        // the cells they use cost more after each path that is too similar
+                        let mut penalty = 0.0;
+                        let mut num_attempts: usize = 0;
+                        let trace_path = loop {
+                            num_attempts += 1;
+                            if penalty > 0.0 {
+                                astar_model.congestion_costs = Some(Rc::new(CongestionCosts::around_traces(
+                                    problem,
+                                    current_connection_visited_traces.iter(),
+                                    penalty,
+                                )));
+                            }
+                            let astar_result = astar_model.run(pcb_render_model.clone(), cancellation_token);
+                            let astar_result = match astar_result {
+                                Ok(result) => result,
+                                Err(err) => {
+                                    println!("A* algorithm failed: {}", err);
+                                    break None;
+                                }
+                            };
+                            let trace_path = astar_result.trace_path;
+                            if diversity.is_dissimilar(&trace_path, current_connection_visited_traces.iter(), pitch, self.next_iteration) {
+                                break Some(trace_path);
                             }
+                            if num_attempts >= diversity.max_attempts || diversity.penalty <= 0.0 || cancellation_token.is_cancelled() {
+                                println!(
+                                    "Trace path for ConnectionID {:?} is too similar to its other candidates after {} attempts, dropping it",
+                                    connection_id, num_attempts
+                                );
+                                break None;
+                            }
+                            penalty = if penalty > 0.0 { penalty * diversity.penalty_growth } else { diversity.penalty };
+                            println!(
+                                "Trace path for ConnectionID {:?} is too similar to its other candidates, searching again with penalty {}",
+                                connection_id, penalty
+                            );
+                        };
+                        let Some(trace_path) = trace_path else {
+                            continue; // Skip this connection if A* fails or only finds similar paths
                         };
-                        let trace_path = astar_result.trace_path;
                         trace_path
                     };
 
-                    assert!(!visited_traces.contains(&trace_path.anchors), "Trace path is supposed to be a new one generated by A*");       
+                    if visited_traces.contains(&trace_path.anchors) {
+                        // this used to be an assert, a candidate that fits the sampled obstacles is reused above before A* runs,
+                        // but when the exact selection samples again because the candidates cannot be combined,
+                        // the posteriors barely changed, the same obstacles come up and A* with its post processing finds
+                        // the same path again, whose copy among the candidates failed the stricter checks above
+                        println!(
+                            "Trace path for ConnectionID {:?} is already a candidate, skipping",
+                            connection_id
+                        );
+                        continue;
+                    }
 
                     visited_traces.insert(trace_path.anchors.clone());
                     current_connection_visited_traces.push(trace_path.clone());
@@ -829,7 +972,88 @@ impl ProbaModel {
         }
     }
 
-    pub fn update_posterior(&mut self) {
+    /// sets the posterior of every candidate trace to its marginal in the conflict graph,
+    /// where each connection takes one of its candidates or none of them and colliding candidates repel each other
+    pub fn infer_posterior(&mut self, problem: &PcbProblem, inference: &InferenceConfig) {
+        // the connections with candidates, sorted so the inference is deterministic
+        let mut connection_traces: Vec<(ConnectionID, Vec<Rc<ProbaTrace>>)> = self
+            .connection_to_traces
+            .iter()
+            .filter_map(|(connection_id, traces)| match traces {
+                Traces::Probabilistic(trace_map) if !trace_map.is_empty() => {
+                    let mut proba_traces: Vec<Rc<ProbaTrace>> = trace_map.values().cloned().collect();
+                    proba_traces.sort_by_key(|proba_trace| proba_trace.proba_trace_id);
+                    Some((*connection_id, proba_traces))
+                }
+                _ => None,
+            })
+            .collect();
+        connection_traces.sort_by_key(|(connection_id, _)| *connection_id);
+        let mut trace_to_state: HashMap<ProbaTraceID, (usize, usize)> = HashMap::new();
+        for (variable, (_, proba_traces)) in connection_traces.iter().enumerate() {
+            for (state, proba_trace) in proba_traces.iter().enumerate() {
+                trace_to_state.insert(proba_trace.proba_trace_id, (variable, state));
+            }
+        }
+        // the state of no trace stands for the traces not sampled yet, it gets the probability left for later iterations
+        let no_trace_weight = *NEXT_ITERATION_TO_REMAINING_PROBABILITY
+            .get(&self.next_iteration)
+            .unwrap_or_else(|| panic!("No remaining probability for iteration {:?}", self.next_iteration));
+        let variables: Vec<InferenceVariable> = connection_traces
+            .iter()
+            .map(|(_, proba_traces)| {
+                let mut unary: Vec<f64> = proba_traces
+                    .iter()
+                    .map(|proba_trace| {
+                        let score = problem
+                            .trace_scorer
+                            .score(problem, &proba_trace.net_name, &proba_trace.trace_path);
+                        proba_trace.get_normalized_prior() * score
+                    })
+                    .collect();
+                unary.push(no_trace_weight);
+                let mut conflicts: Vec<Vec<(usize, usize)>> = proba_traces
+                    .iter()
+                    .map(|proba_trace| {
+                        let mut conflicts: Vec<(usize, usize)> = self
+                            .collision_adjacency
+                            .get(&proba_trace.proba_trace_id)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|adjacent_trace_id| trace_to_state.get(adjacent_trace_id).copied())
+                            .collect();
+                        conflicts.sort();
+                        conflicts
+                    })
+                    .collect();
+                conflicts.push(Vec::new());
+                InferenceVariable { unary, conflicts }
+            })
+            .collect();
+        let result = match inference.engine {
+            InferenceEngine::MeanField => mean_field(&variables, inference),
+            // the heuristic engine runs update_posterior instead, belief propagation stands in for it when asked here
+            InferenceEngine::BeliefPropagation | InferenceEngine::Heuristic => belief_propagation(&variables, inference),
+        };
+        if result.converged {
+            println!("Inference ({}) converged after {} iterations", inference.engine, result.iterations);
+        } else {
+            println!(
+                "Warning: inference ({}) did not converge after {} iterations, largest change {:e}",
+                inference.engine, result.iterations, result.max_change
+            );
+        }
+        for ((_, proba_traces), marginals) in connection_traces.iter().zip(result.marginals.iter()) {
+            for (proba_trace, marginal) in proba_traces.iter().zip(marginals.iter()) {
+                *proba_trace.posterior.borrow_mut() = Some(*marginal);
+            }
+        }
+    }
+
+    pub fn update_posterior(&mut self, problem: &PcbProblem, cancellation_token: &CancellationToken) {
+        if cancellation_token.is_cancelled() {
+            return;
+        }
         let proba_traces: HashMap<ProbaTraceID, Rc<ProbaTrace>> = self
             .connection_to_traces
             .values()
@@ -868,7 +1092,9 @@ impl ProbaModel {
             let current_posterior = proba_trace.get_posterior_with_fallback();
             // let opportunity_cost = target_posterior / current_posterior;
 
-            let score = proba_trace.trace_path.get_score();
+            let score = problem
+                .trace_scorer
+                .score(problem, &proba_trace.net_name, &proba_trace.trace_path);
             assert!(score >= 0.0 && score <= 1.0,
                 "Score must be between 0 and 1, got: {}",
                 score
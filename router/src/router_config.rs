@@ -0,0 +1,81 @@
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use crate::{
+    bayesian_backtrack_algo::BacktrackBudget, cancellation::CancellationToken, connection_ordering::OrderingStrategy,
+    diversity::DiversityConfig, inference::InferenceConfig, shove::ShoveConfig,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingAlgorithm {
+    Naive,
+    Bayesian,
+    NegotiatedCongestion, // PathFinder-style rip-up and reroute
+}
+
+impl RoutingAlgorithm {
+    pub fn from_name(name: &str) -> Result<RoutingAlgorithm, String> {
+        match name.trim().to_lowercase().as_str() {
+            "naive" => Ok(RoutingAlgorithm::Naive),
+            "bayesian" => Ok(RoutingAlgorithm::Bayesian),
+            "pathfinder" | "negotiated" | "negotiated_congestion" => Ok(RoutingAlgorithm::NegotiatedCongestion),
+            _ => Err(format!(
+                "Unknown routing algorithm: {}, expected one of: naive, bayesian, pathfinder",
+                name
+            )),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoutingAlgorithm::Naive => "naive",
+            RoutingAlgorithm::Bayesian => "bayesian",
+            RoutingAlgorithm::NegotiatedCongestion => "pathfinder",
+        }
+    }
+}
+
+impl Display for RoutingAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// options that select how a pcb problem is routed, passed to solve_pcb_problem
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    pub algorithm: RoutingAlgorithm,
+    pub time_budget: Option<Duration>, // wall-clock limit, the best result so far is returned when it runs out
+    pub cancellation_token: CancellationToken, // cancel it from another thread to stop the solver early
+    pub fanout: bool, // fan out the pads of grid array parts to vias before the main solver runs, off by default
+    pub ordering: OrderingStrategy, // the order the naive backtracker routes the connections in when it has no heuristics
+    pub inference: InferenceConfig, // how the bayesian backtracker computes the posteriors of its candidate traces
+    pub diversity: DiversityConfig, // how different the candidate traces sampled by the bayesian backtracker must be
+    pub backtrack_budget: BacktrackBudget, // how far the bayesian backtracker searches before it falls back to the naive one
+    pub trace_cache_path: Option<PathBuf>, // the candidate traces are loaded from this file before routing and saved to it after
+    pub shove: ShoveConfig, // how the backtrackers push fixed traces of other nets aside for a connection they block
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        RouterConfig {
+            algorithm: RoutingAlgorithm::Naive,
+            time_budget: None,
+            cancellation_token: CancellationToken::new(),
+            fanout: false,
+            ordering: OrderingStrategy::default(),
+            inference: InferenceConfig::default(),
+            diversity: DiversityConfig::default(),
+            backtrack_budget: BacktrackBudget::default(),
+            trace_cache_path: None,
+            shove: ShoveConfig::default(),
+        }
+    }
+}
+
+impl RouterConfig {
+    pub fn new(algorithm: RoutingAlgorithm) -> Self {
+        RouterConfig {
+            algorithm,
+            ..Default::default()
+        }
+    }
+}
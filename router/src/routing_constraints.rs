@@ -0,0 +1,63 @@
+use std::fmt::Display;
+
+use shared::pcb_problem::{ConnectionID, NetName, PcbProblem, PcbSolution};
+
+/// a routed connection that breaks the routing constraints of its net
+#[derive(Debug, Clone)]
+pub struct ConstraintViolation {
+    pub connection_id: ConnectionID,
+    pub net_name: NetName,
+    pub message: String,
+}
+
+impl Display for ConstraintViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "connection {} of net {}: {}",
+            self.connection_id.0, self.net_name.0, self.message
+        )
+    }
+}
+
+/// checks every trace of the solution against the layers, vias and length its net allows,
+/// A* already keeps to them, this catches cached, prerouted and tuned traces
+pub fn check_routing_constraints(problem: &PcbProblem, solution: &PcbSolution) -> Vec<ConstraintViolation> {
+    let mut violations: Vec<ConstraintViolation> = Vec::new();
+    for (connection_id, fixed_trace) in solution.determined_traces.iter() {
+        let constraints = &problem.nets[&fixed_trace.net_name].routing_constraints;
+        let trace_path = &fixed_trace.trace_path;
+        let mut messages: Vec<String> = Vec::new();
+        let mut forbidden_layers: Vec<usize> = trace_path
+            .segments
+            .iter()
+            .map(|segment| segment.layer)
+            .filter(|layer| !constraints.allows_layer(*layer))
+            .collect();
+        forbidden_layers.sort();
+        forbidden_layers.dedup();
+        if !forbidden_layers.is_empty() {
+            messages.push(format!("uses the forbidden layers {:?}", forbidden_layers));
+        }
+        if let Some(max_vias) = constraints.max_vias
+            && trace_path.vias.len() > max_vias
+        {
+            messages.push(format!("has {} vias, at most {} are allowed", trace_path.vias.len(), max_vias));
+        }
+        if let Some(max_length) = constraints.max_length
+            && trace_path.total_length > max_length as f64
+        {
+            messages.push(format!(
+                "is {:.4} long, at most {:.4} is allowed",
+                trace_path.total_length, max_length
+            ));
+        }
+        violations.extend(messages.into_iter().map(|message| ConstraintViolation {
+            connection_id: *connection_id,
+            net_name: fixed_trace.net_name.clone(),
+            message,
+        }));
+    }
+    violations.sort_by_key(|violation| violation.connection_id);
+    violations
+}
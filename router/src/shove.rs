@@ -0,0 +1,446 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use shared::{
+    collider::Collider,
+    hyperparameters::{SHOVE_DETOUR_RATIO, SHOVE_MAX_DEPTH, SHOVE_MAX_OFFSET, SHOVE_MAX_TRACES, SHOVE_STEP},
+    pcb_problem::{ConnectionID, FixedTrace, NetName, PcbProblem},
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath, ViaType},
+    vec2::{FixedPoint, FixedVec2, FloatVec2},
+};
+
+use crate::{astar::AStarModel, drc::closest_point_on_segment, post_process::Line};
+
+/// how the fixed traces of other nets are pushed aside when they are in the way of a new trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShoveConfig {
+    pub max_depth: usize,  // a shoved trace may shove the traces in its own way, longer chains are undone, 0 turns shoving off
+    pub max_traces: usize, // a shove that moves more fixed traces than this for one new trace is undone
+    pub step: f32,         // in float units, the offset of a shoved segment grows by this until the segment is out of the way
+    pub max_offset: f32,   // in float units, segments are not shoved farther than this
+    pub detour_ratio: f64, // a route this many times longer than the distance between its pads is too costly for the naive backtracker
+}
+
+impl Default for ShoveConfig {
+    // shoving moves traces that are already fixed and changes the routing of every board, so it is opt-in
+    fn default() -> Self {
+        ShoveConfig {
+            max_depth: 0,
+            max_traces: SHOVE_MAX_TRACES,
+            step: SHOVE_STEP,
+            max_offset: SHOVE_MAX_OFFSET,
+            detour_ratio: SHOVE_DETOUR_RATIO,
+        }
+    }
+}
+
+impl ShoveConfig {
+    pub fn enabled() -> Self {
+        ShoveConfig {
+            max_depth: SHOVE_MAX_DEPTH,
+            ..Default::default()
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.max_depth > 0 && self.max_traces > 0 && self.step > 0.0
+    }
+}
+
+// the copper of a pad on one layer, the shoved traces of other nets keep their clearance to it
+struct PadObstacle<'a> {
+    net_name: &'a NetName,
+    layer: usize,
+    colliders: Vec<Collider>,
+    clearance_colliders: Vec<Collider>,
+}
+
+struct Shover<'a> {
+    problem: &'a PcbProblem,
+    config: &'a ShoveConfig,
+    fixed_traces: &'a HashMap<ConnectionID, FixedTrace>,
+    rigid: &'a HashSet<ConnectionID>,
+    new_trace: &'a FixedTrace,
+    pads: Vec<PadObstacle<'a>>,
+    border_colliders: Rc<Vec<Collider>>,
+}
+
+/// pushes the fixed traces of other nets that collide with the new trace aside, so it can be fixed among them,
+/// returns the traces that moved, they replace the fixed traces of their connections,
+/// a shoved trace keeps its clearances and pushes the traces in its own way in turn,
+/// the rigid traces, the prerouted and kept ones, never move,
+/// None when a trace in the way cannot move or the shove cascades past the limits of the config, nothing moves then
+pub fn shove_aside(
+    problem: &PcbProblem,
+    fixed_traces: &HashMap<ConnectionID, FixedTrace>,
+    rigid: &HashSet<ConnectionID>,
+    new_trace: &FixedTrace,
+    config: &ShoveConfig,
+) -> Option<HashMap<ConnectionID, FixedTrace>> {
+    if !config.is_enabled() {
+        return None;
+    }
+    let mut pads: Vec<PadObstacle> = Vec::new();
+    for (net_name, net_info) in problem.nets.iter() {
+        for pad in net_info.pads.values() {
+            let colliders: Vec<Collider> = pad.to_shapes().iter().map(Collider::from_prim_shape).collect();
+            let clearance_colliders: Vec<Collider> =
+                pad.to_clearance_shapes().iter().map(Collider::from_prim_shape).collect();
+            for layer in pad.pad_layer.get_iter(problem.num_layers) {
+                pads.push(PadObstacle {
+                    net_name,
+                    layer,
+                    colliders: colliders.clone(),
+                    clearance_colliders: clearance_colliders.clone(),
+                });
+            }
+        }
+    }
+    let shover = Shover {
+        problem,
+        config,
+        fixed_traces,
+        rigid,
+        new_trace,
+        pads,
+        border_colliders: AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center),
+    };
+    let mut moved: HashMap<ConnectionID, FixedTrace> = HashMap::new();
+    if shover.push_away(new_trace, 1, &mut moved) {
+        if !moved.is_empty() {
+            println!("Shoved {} traces aside for connection {}", moved.len(), new_trace.connection_id.0);
+        }
+        Some(moved)
+    } else {
+        println!("Cannot shove the traces in the way of connection {} aside", new_trace.connection_id.0);
+        None
+    }
+}
+
+impl Shover<'_> {
+    /// the fixed trace of the connection after the moves so far
+    fn current<'b>(&'b self, connection_id: &ConnectionID, moved: &'b HashMap<ConnectionID, FixedTrace>) -> &'b FixedTrace {
+        moved.get(connection_id).unwrap_or(&self.fixed_traces[connection_id])
+    }
+
+    /// moves every trace of another net out of the way of the pusher, the moves are added to moved only when all succeed
+    fn push_away(&self, pusher: &FixedTrace, depth: usize, moved: &mut HashMap<ConnectionID, FixedTrace>) -> bool {
+        let mut in_the_way: Vec<ConnectionID> = self
+            .fixed_traces
+            .keys()
+            .filter(|connection_id| **connection_id != self.new_trace.connection_id)
+            .filter(|connection_id| {
+                let fixed_trace = self.current(connection_id, moved);
+                fixed_trace.net_name != pusher.net_name && fixed_trace.trace_path.collides_with(&pusher.trace_path)
+            })
+            .cloned()
+            .collect();
+        if in_the_way.is_empty() {
+            return true;
+        }
+        if depth > self.config.max_depth {
+            println!("Shove cascades past {} traces in a chain, undone", self.config.max_depth);
+            return false;
+        }
+        in_the_way.sort();
+        let mut attempt = moved.clone();
+        for connection_id in in_the_way {
+            // each trace moves once per shove, an earlier move of the chain may have taken it out of the way already
+            let victim = self.current(&connection_id, &attempt).clone();
+            if !victim.trace_path.collides_with(&pusher.trace_path) {
+                continue;
+            }
+            if attempt.contains_key(&connection_id) || !self.shove(&victim, &pusher.trace_path, depth, &mut attempt) {
+                return false;
+            }
+        }
+        *moved = attempt;
+        true
+    }
+
+    /// moves the victim out of the way of the pusher by the smallest offset that keeps its clearances,
+    /// the traces in its new way are pushed away in turn
+    fn shove(
+        &self,
+        victim: &FixedTrace,
+        pusher: &TracePath,
+        depth: usize,
+        moved: &mut HashMap<ConnectionID, FixedTrace>,
+    ) -> bool {
+        let net_info = &self.problem.nets[&victim.net_name];
+        // the prerouted and kept traces stay where they are,
+        // the traces of a differential pair stay coupled, so the skew of the pair never changes
+        if self.rigid.contains(&victim.connection_id) || net_info.diff_pair.is_some() {
+            return false;
+        }
+        if moved.len() >= self.config.max_traces {
+            println!("Shove moves more than {} traces, undone", self.config.max_traces);
+            return false;
+        }
+        for trace_path in self.offsets(&victim.trace_path, pusher, &net_info.via_types) {
+            if !self.keeps_clear(&victim.net_name, &trace_path, moved)
+                || !self.keeps_group_length(victim, &trace_path, moved)
+            {
+                continue;
+            }
+            let shoved = FixedTrace {
+                net_name: victim.net_name.clone(),
+                connection_id: victim.connection_id,
+                trace_path,
+            };
+            let mut attempt = moved.clone();
+            attempt.insert(victim.connection_id, shoved.clone());
+            if self.push_away(&shoved, depth + 1, &mut attempt) {
+                *moved = attempt;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// the victim with the segments in the way of the pusher moved away from it, by growing offsets,
+    /// only the offsets that clear the pusher, the segments next to vias stay
+    fn offsets(&self, victim: &TracePath, pusher: &TracePath, via_types: &[ViaType]) -> Vec<TracePath> {
+        let Some(segment) = victim.segments.first() else {
+            return Vec::new();
+        };
+        let (width, clearance) = (segment.width, segment.clearance);
+        let anchors = &victim.anchors.0;
+        let max_steps = (self.config.max_offset / self.config.step).floor() as usize;
+        let mut sides: HashMap<usize, Direction> = HashMap::new(); // by segment of the victim, the way it moves
+        let mut current = victim.clone();
+        let mut jog_shift = 0; // the segments of current come after the jog from the start pad once the first segment moves
+        let mut candidates: Vec<TracePath> = Vec::new();
+        for step in 1..=max_steps {
+            // the segments that got in the way as their neighbours moved join the moving ones
+            for index in 0..anchors.len() - 1 {
+                if sides.contains_key(&index) || !is_movable(anchors, index) {
+                    continue;
+                }
+                if let Some(side) = away_from(&current, index + jog_shift, pusher) {
+                    sides.insert(index, side);
+                }
+            }
+            if sides.is_empty() {
+                break;
+            }
+            // an even offset keeps the moved anchors on the even grid the crossings of 45 degree lines need
+            let offset = FixedPoint::from_num(self.config.step * step as f32);
+            let offset = offset - FixedPoint::from_bits(offset.to_bits() & 1);
+            let moved_anchors = loop {
+                match offset_segments(anchors, &sides, offset) {
+                    Ok(moved_anchors) => break Some(moved_anchors),
+                    // a neighbour that would turn around or vanish moves along with the segment next to it
+                    Err(Some(index)) if !sides.contains_key(&index) && is_movable(anchors, index) => {
+                        let neighbour_side = index.checked_sub(1).and_then(|previous| sides.get(&previous));
+                        let Some(side) = neighbour_side.or(sides.get(&(index + 1))).copied() else {
+                            break None;
+                        };
+                        sides.insert(index, side);
+                    }
+                    Err(_) => break None,
+                }
+            };
+            let Some(moved_anchors) = moved_anchors else {
+                break;
+            };
+            current = TracePath::from_anchors(TraceAnchors(moved_anchors), width, clearance, via_types);
+            jog_shift = usize::from(sides.contains_key(&0));
+            if !current.collides_with(pusher) {
+                candidates.push(current.clone());
+            }
+        }
+        candidates
+    }
+
+    /// whether the shoved trace of the net keeps its clearances to the board border, the pads of the other nets,
+    /// the new trace and the traces moved so far, and stays within the length limit of its net
+    fn keeps_clear(&self, net_name: &NetName, trace_path: &TracePath, moved: &HashMap<ConnectionID, FixedTrace>) -> bool {
+        let net_info = &self.problem.nets[net_name];
+        if net_info
+            .routing_constraints
+            .max_length
+            .is_some_and(|max_length| trace_path.total_length > max_length as f64)
+        {
+            return false;
+        }
+        let colliders = trace_path.to_colliders(self.problem.num_layers);
+        let clearance_colliders = trace_path.to_clearance_colliders(self.problem.num_layers);
+        let touches_border = colliders.values().flatten().any(|collider| {
+            self.border_colliders
+                .iter()
+                .any(|border_collider| border_collider.collides_with(collider))
+        });
+        if touches_border {
+            return false;
+        }
+        let touches_pad = self.pads.iter().filter(|pad| pad.net_name != net_name).any(|pad| {
+            colliders.get(&pad.layer).is_some_and(|colliders| {
+                colliders
+                    .iter()
+                    .any(|collider| pad.clearance_colliders.iter().any(|pad_collider| collider.collides_with(pad_collider)))
+            }) || clearance_colliders.get(&pad.layer).is_some_and(|clearance_colliders| {
+                clearance_colliders
+                    .iter()
+                    .any(|clearance_collider| pad.colliders.iter().any(|pad_collider| clearance_collider.collides_with(pad_collider)))
+            })
+        });
+        if touches_pad {
+            return false;
+        }
+        std::iter::once(self.new_trace)
+            .chain(moved.values())
+            .filter(|fixed_trace| fixed_trace.net_name != *net_name)
+            .all(|fixed_trace| !trace_path.collides_with(&fixed_trace.trace_path))
+    }
+
+    /// whether the net of the shoved trace stays within the target length of its length matching group,
+    /// the explicit target or the length of the longest net of the group, as length tuning only lengthens nets
+    fn keeps_group_length(&self, victim: &FixedTrace, trace_path: &TracePath, moved: &HashMap<ConnectionID, FixedTrace>) -> bool {
+        let Some(constraint) = &self.problem.nets[&victim.net_name].length_constraint else {
+            return true;
+        };
+        let net_length = |net_name: &NetName| -> f64 {
+            self.fixed_traces
+                .keys()
+                .map(|connection_id| self.current(connection_id, moved))
+                .filter(|fixed_trace| fixed_trace.net_name == *net_name)
+                .map(|fixed_trace| fixed_trace.trace_path.total_length)
+                .sum()
+        };
+        let target_length = match constraint.target_length {
+            Some(target_length) => target_length as f64,
+            None => self
+                .problem
+                .nets
+                .iter()
+                .filter(|(_, net_info)| {
+                    net_info.length_constraint.as_ref().is_some_and(|other| other.group == constraint.group)
+                })
+                .map(|(net_name, _)| net_length(net_name))
+                .fold(0.0, f64::max),
+        };
+        let shoved_length = net_length(&victim.net_name) - self.current(&victim.connection_id, moved).trace_path.total_length
+            + trace_path.total_length;
+        shoved_length <= target_length + constraint.tolerance as f64
+    }
+}
+
+// a segment moves when its bends are on one layer, at an end of the trace it jogs away from the pad instead
+fn is_movable(anchors: &[TraceAnchor], index: usize) -> bool {
+    [index, index + 1]
+        .into_iter()
+        .all(|anchor| anchor == 0 || anchor + 1 == anchors.len() || anchors[anchor].start_layer == anchors[anchor].end_layer)
+}
+
+/// the way the segment of the path has to move to get away from the pusher, None when it is not in the way
+fn away_from(path: &TracePath, index: usize, pusher: &TracePath) -> Option<Direction> {
+    let segment = &path.segments[index];
+    let piece = TracePath::from_anchors(
+        TraceAnchors(path.anchors.0[index..=index + 1].to_vec()),
+        segment.width,
+        segment.clearance,
+        &[],
+    );
+    if !piece.collides_with(pusher) {
+        return None;
+    }
+    let middle = (segment.start.to_float() + segment.end.to_float()) / 2.0;
+    // the copper of the pusher nearest to the middle of the segment
+    let nearest = pusher
+        .segments
+        .iter()
+        .filter(|other| other.layer == segment.layer)
+        .map(|other| closest_point_on_segment(middle, other.start.to_float(), other.end.to_float()))
+        .chain(
+            pusher
+                .vias
+                .iter()
+                .filter(|via| via.min_layer <= segment.layer && segment.layer <= via.max_layer)
+                .map(|via| via.position.to_float()),
+        )
+        .min_by(|a, b| (*a - middle).length().total_cmp(&(*b - middle).length()))?;
+    let direction = segment.get_direction();
+    let left = direction.left_90_dir().to_int_vec2();
+    if (middle - nearest).dot(FloatVec2::new(left.x as f32, left.y as f32)) >= 0.0 {
+        Some(direction.left_90_dir())
+    } else {
+        Some(direction.right_90_dir())
+    }
+}
+
+/// the anchors with the given segments moved by the offset to their sides, the anchors between a moved segment and
+/// its neighbours slide along the neighbours, a moved segment at an end of the trace leaves its pad with a 45 degree jog,
+/// unlike post_process::try_parallel_shift, which only slides the middle of a Z shape towards its start to drop a bend,
+/// the segments move away from the pusher, may have neighbours in any direction and may end at a pad,
+/// Err with the segment that would turn around or vanish, Err(None) when the anchors would leave the 45 degree grid
+fn offset_segments(
+    anchors: &[TraceAnchor],
+    sides: &HashMap<usize, Direction>,
+    offset: FixedPoint,
+) -> Result<Vec<TraceAnchor>, Option<usize>> {
+    let directions: Vec<Direction> = anchors
+        .windows(2)
+        .map(|pair| Direction::from_points(pair[0].position, pair[1].position).ok().flatten())
+        .collect::<Option<_>>()
+        .ok_or(None)?;
+    let lines: Vec<(FixedVec2, Direction)> = directions
+        .iter()
+        .enumerate()
+        .map(|(index, direction)| match sides.get(&index) {
+            Some(side) => (anchors[index].position + side.to_fixed_vec2(offset), *direction),
+            None => (anchors[index].position, *direction),
+        })
+        .collect();
+    let mut result = anchors.to_vec();
+    for index in 1..anchors.len() - 1 {
+        if sides.contains_key(&(index - 1)) || sides.contains_key(&index) {
+            result[index].position = intersection(lines[index - 1], lines[index]).ok_or(None)?;
+        }
+    }
+    let last = anchors.len() - 1;
+    let first_jog = sides.get(&0).map(|side| TraceAnchor {
+        position: result[0].position + directions[0].to_fixed_vec2(offset) + side.to_fixed_vec2(offset),
+        start_layer: result[0].end_layer,
+        end_layer: result[0].end_layer,
+        via_type: None,
+    });
+    let last_jog = sides.get(&(last - 1)).map(|side| TraceAnchor {
+        position: result[last].position - directions[last - 1].to_fixed_vec2(offset) + side.to_fixed_vec2(offset),
+        start_layer: result[last].start_layer,
+        end_layer: result[last].start_layer,
+        via_type: None,
+    });
+    let turned = directions.iter().enumerate().position(|(index, direction)| {
+        let start = match (index, &first_jog) {
+            (0, Some(jog)) => jog.position,
+            _ => result[index].position,
+        };
+        let end = match (index + 1 == last, &last_jog) {
+            (true, Some(jog)) => jog.position,
+            _ => result[index + 1].position,
+        };
+        Direction::from_points(start, end) != Ok(Some(*direction))
+    });
+    if let Some(index) = turned {
+        return Err(Some(index));
+    }
+    if let Some(jog) = last_jog {
+        result.insert(last, jog);
+    }
+    if let Some(jog) = first_jog {
+        result.insert(1, jog);
+    }
+    Ok(result)
+}
+
+/// where the two lines through the points in the directions cross, None for parallel lines,
+/// or for points off the even grid, where the crossing of two 45 degree lines would be rounded
+fn intersection((point, direction): (FixedVec2, Direction), (other_point, other_direction): (FixedVec2, Direction)) -> Option<FixedVec2> {
+    let is_even = |point: FixedVec2| point.to_nearest_even_even() == point;
+    if direction == other_direction || direction == other_direction.opposite() || !is_even(point) || !is_even(other_point) {
+        return None;
+    }
+    Some(Line::new(point, direction.to_int_vec2()).intersection(&Line::new(other_point, other_direction.to_int_vec2())))
+}
@@ -0,0 +1,341 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    path::Path,
+    rc::Rc,
+};
+
+use shared::{
+    collider::Collider,
+    pad::{Pad, PadLayer, PadShape},
+    pcb_problem::{Connection, NetInfo, NetName, PcbProblem},
+    trace_path::{Direction, TraceAnchor, TraceAnchors, TracePath},
+    vec2::{FixedPoint, FixedVec2},
+};
+
+use crate::{
+    astar::AStarModel, astar_check_struct::AStarCheck, bayesian_backtrack_algo::TraceCache, connectivity::anchor_on_pad,
+    obstacles::{obstacles_of_other_nets, Obstacles}, via_rules::ViaObstacles,
+};
+
+// the first line of a trace cache file, bumped whenever the format or the key changes
+const TRACE_CACHE_HEADER: &str = "trace_cache 1";
+// pads of other nets this many trace pitches around the pads of a connection are part of its key
+const NEARBY_PAD_MARGIN_PITCHES: f32 = 10.0;
+
+/// 64 bit FNV-1a, unlike the hashers of std its values stay the same from one build to the next
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+    fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn hash_pad(hasher: &mut StableHasher, pad: &Pad) {
+    hasher.write_f32(pad.position.x);
+    hasher.write_f32(pad.position.y);
+    match pad.shape {
+        PadShape::Circle { diameter } => {
+            hasher.write_u64(0);
+            hasher.write_f32(diameter);
+        }
+        PadShape::Rectangle { width, height } => {
+            hasher.write_u64(1);
+            hasher.write_f32(width);
+            hasher.write_f32(height);
+        }
+        PadShape::RoundRect { width, height, corner_radius } => {
+            hasher.write_u64(2);
+            hasher.write_f32(width);
+            hasher.write_f32(height);
+            hasher.write_f32(corner_radius);
+        }
+    }
+    hasher.write_f32(pad.rotation.0);
+    hasher.write_f32(pad.clearance);
+    hasher.write_u64(match pad.pad_layer {
+        PadLayer::Front => 0,
+        PadLayer::Back => 1,
+        PadLayer::All => 2,
+    });
+}
+
+fn pad_hash(pad: &Pad) -> u64 {
+    let mut hasher = StableHasher::new();
+    hash_pad(&mut hasher, pad);
+    hasher.finish()
+}
+
+/// a hash of what a trace of the connection depends on: its pads, the trace and via rules of its net
+/// and the pads of other nets around it, equal keys on two boards mean the cached traces are worth checking,
+/// the key does not depend on which pad the connection starts from
+pub fn connection_cache_key(problem: &PcbProblem, net_info: &NetInfo, connection: &Connection) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_u64(problem.num_layers as u64);
+    let start_pad = &net_info.pads[&connection.start_pad];
+    let end_pad = &net_info.pads[&connection.end_pad];
+    let pad_hashes = [pad_hash(start_pad), pad_hash(end_pad)];
+    hasher.write_u64(pad_hashes[0].min(pad_hashes[1]));
+    hasher.write_u64(pad_hashes[0].max(pad_hashes[1]));
+    hasher.write_f32(net_info.trace_width);
+    hasher.write_f32(net_info.trace_clearance);
+    for via_type in net_info.via_types.iter() {
+        hasher.write_str(&via_type.name);
+        hasher.write_f32(via_type.diameter);
+        hasher.write_f32(via_type.drill.unwrap_or(0.0));
+        hasher.write_u64(via_type.min_layer as u64);
+        hasher.write_u64(via_type.max_layer as u64);
+    }
+    hasher.write_str(&format!("{:?}", net_info.routing_constraints));
+    // the nearby pads are hashed on their own and sorted, so the order of the nets does not matter
+    let margin = NEARBY_PAD_MARGIN_PITCHES * (net_info.trace_width + net_info.trace_clearance);
+    let x_min = start_pad.position.x.min(end_pad.position.x) - margin;
+    let x_max = start_pad.position.x.max(end_pad.position.x) + margin;
+    let y_min = start_pad.position.y.min(end_pad.position.y) - margin;
+    let y_max = start_pad.position.y.max(end_pad.position.y) + margin;
+    let nearby_pads: BTreeSet<u64> = problem
+        .nets
+        .values()
+        .filter(|other_net_info| other_net_info.net_name != net_info.net_name)
+        .flat_map(|other_net_info| other_net_info.pads.values())
+        .filter(|pad| {
+            x_min <= pad.position.x && pad.position.x <= x_max && y_min <= pad.position.y && pad.position.y <= y_max
+        })
+        .map(pad_hash)
+        .collect();
+    for pad_hash in nearby_pads {
+        hasher.write_u64(pad_hash);
+    }
+    hasher.finish()
+}
+
+fn anchor_to_string(anchor: &TraceAnchor) -> String {
+    let via_type = match anchor.via_type {
+        Some(index) => index.to_string(),
+        None => "-".to_string(),
+    };
+    format!(
+        "{},{},{},{},{}",
+        anchor.position.x.to_bits(),
+        anchor.position.y.to_bits(),
+        anchor.start_layer,
+        anchor.end_layer,
+        via_type
+    )
+}
+
+fn anchor_from_str(text: &str) -> Result<TraceAnchor, String> {
+    let fields: Vec<&str> = text.split(',').collect();
+    if fields.len() != 5 {
+        return Err(format!("Invalid trace anchor: {}", text));
+    }
+    let parse = |field: &str| field.parse::<i64>().map_err(|e| format!("Invalid trace anchor {}: {}", text, e));
+    let x = i32::try_from(parse(fields[0])?).map_err(|e| e.to_string())?;
+    let y = i32::try_from(parse(fields[1])?).map_err(|e| e.to_string())?;
+    let start_layer = usize::try_from(parse(fields[2])?).map_err(|e| e.to_string())?;
+    let end_layer = usize::try_from(parse(fields[3])?).map_err(|e| e.to_string())?;
+    let via_type = match fields[4] {
+        "-" => None,
+        index => Some(usize::try_from(parse(index)?).map_err(|e| e.to_string())?),
+    };
+    Ok(TraceAnchor {
+        position: FixedVec2::new(FixedPoint::from_bits(x), FixedPoint::from_bits(y)),
+        start_layer,
+        end_layer,
+        via_type,
+    })
+}
+
+/// the pads of the other nets and the board border, what a cached trace has to keep clear of to be reused
+struct NetObstacles {
+    border_colliders: Rc<Vec<Collider>>,
+    pads: Obstacles,
+    via_obstacles: ViaObstacles,
+}
+
+impl NetObstacles {
+    fn new(problem: &PcbProblem, net_name: &NetName, border_colliders: Rc<Vec<Collider>>) -> Self {
+        NetObstacles {
+            border_colliders,
+            pads: obstacles_of_other_nets(problem, &[net_name], std::iter::empty()),
+            via_obstacles: ViaObstacles::new(problem, net_name, std::iter::empty()),
+        }
+    }
+    fn allows(&self, problem: &PcbProblem, trace_path: &TracePath) -> bool {
+        let astar_check = AStarCheck {
+            border_colliders: self.border_colliders.clone(),
+            obstacle_colliders: self.pads.colliders.clone(),
+            obstacle_clearance_colliders: self.pads.clearance_colliders.clone(),
+            solution_trace: trace_path.clone(),
+            num_layers: problem.num_layers,
+        };
+        astar_check.check() && self.via_obstacles.allows_vias(trace_path)
+    }
+}
+
+/// the anchors from the end to the start, each anchor enters on the layer it used to leave on
+fn reversed(anchors: &TraceAnchors) -> TraceAnchors {
+    TraceAnchors(
+        anchors
+            .0
+            .iter()
+            .rev()
+            .map(|anchor| TraceAnchor {
+                start_layer: anchor.end_layer,
+                end_layer: anchor.start_layer,
+                ..anchor.clone()
+            })
+            .collect(),
+    )
+}
+
+/// the segments of a trace run in one of the 8 directions and have a length, as TracePath expects of them
+fn check_octilinear(anchors: &TraceAnchors) -> Result<(), String> {
+    if anchors.0.len() < 2 {
+        return Err("A trace needs at least two anchors".to_string());
+    }
+    for pair in anchors.0.windows(2) {
+        if Direction::from_points(pair[0].position, pair[1].position)?.is_none() {
+            return Err(format!("Trace segment of no length at {:?}", pair[0].position.to_float()));
+        }
+    }
+    Ok(())
+}
+
+/// the traces of a connection must run on layers that exist and from the copper of its start pad to that of its end pad
+fn is_well_formed(problem: &PcbProblem, net_info: &NetInfo, connection: &Connection, anchors: &TraceAnchors) -> bool {
+    let (Some(first), Some(last)) = (anchors.0.first(), anchors.0.last()) else {
+        return false;
+    };
+    anchors.0.iter().all(|anchor| {
+        anchor.start_layer < problem.num_layers
+            && anchor.end_layer < problem.num_layers
+            && anchor.via_type.is_none_or(|index| index < net_info.via_types.len())
+    }) && anchor_on_pad(first, &net_info.pads[&connection.start_pad], problem.num_layers)
+        && anchor_on_pad(last, &net_info.pads[&connection.end_pad], problem.num_layers)
+}
+
+impl TraceCache {
+    /// adds the traces of the file whose connection key matches a connection of the problem,
+    /// each one is checked against the pads of the other nets and the via rules first and dropped when it is no longer legal,
+    /// returns the number of traces added
+    pub fn load(&mut self, problem: &PcbProblem, path: &Path) -> Result<usize, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read trace cache {}: {}", path.display(), e))?;
+        let mut lines = content.lines();
+        match lines.next() {
+            Some(header) if header.trim() == TRACE_CACHE_HEADER => {}
+            _ => return Err(format!("{} is not a trace cache file of this version", path.display())),
+        }
+        let mut key_to_connections: HashMap<u64, Vec<(&NetInfo, &Connection)>> = HashMap::new();
+        for net_info in problem.nets.values() {
+            for connection in net_info.connections.values() {
+                key_to_connections
+                    .entry(connection_cache_key(problem, net_info, connection))
+                    .or_default()
+                    .push((net_info, connection));
+            }
+        }
+        let border_colliders = AStarModel::calculate_border_colliders(problem.width, problem.height, problem.center);
+        let mut net_obstacles: HashMap<NetName, NetObstacles> = HashMap::new();
+        let mut num_loaded = 0;
+        let mut num_illegal = 0;
+        for (line_index, line) in lines.enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            let key = fields
+                .next()
+                .and_then(|key| u64::from_str_radix(key, 16).ok())
+                .ok_or_else(|| format!("Invalid key on line {} of {}", line_index + 2, path.display()))?;
+            let anchors = TraceAnchors(fields.map(anchor_from_str).collect::<Result<Vec<_>, String>>()?);
+            check_octilinear(&anchors)
+                .map_err(|e| format!("Invalid trace on line {} of {}: {}", line_index + 2, path.display(), e))?;
+            let Some(connections) = key_to_connections.get(&key) else {
+                continue; // a connection that changed or is not on this board
+            };
+            for (net_info, connection) in connections.iter() {
+                // the key does not depend on which pad the connection starts from, so the trace may run the other way
+                let anchors = if is_well_formed(problem, net_info, connection, &anchors) {
+                    anchors.clone()
+                } else if is_well_formed(problem, net_info, connection, &reversed(&anchors)) {
+                    reversed(&anchors)
+                } else {
+                    num_illegal += 1;
+                    continue;
+                };
+                let cached_traces = self.traces.entry(connection.connection_id).or_default();
+                if cached_traces.iter().any(|trace_path| trace_path.anchors == anchors) {
+                    continue;
+                }
+                let trace_path = TracePath::from_anchors(
+                    anchors,
+                    net_info.trace_width,
+                    net_info.trace_clearance,
+                    &net_info.via_types,
+                );
+                let obstacles = net_obstacles
+                    .entry(net_info.net_name.clone())
+                    .or_insert_with(|| NetObstacles::new(problem, &net_info.net_name, border_colliders.clone()));
+                if obstacles.allows(problem, &trace_path) {
+                    cached_traces.push(trace_path);
+                    num_loaded += 1;
+                } else {
+                    num_illegal += 1;
+                }
+            }
+        }
+        println!(
+            "Loaded {} traces from the trace cache {}, {} are no longer legal",
+            num_loaded,
+            path.display(),
+            num_illegal
+        );
+        Ok(num_loaded)
+    }
+
+    /// writes the traces of every connection of the problem under its connection key
+    pub fn save(&self, problem: &PcbProblem, path: &Path) -> Result<(), String> {
+        let write = || -> std::io::Result<usize> {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            writeln!(file, "{}", TRACE_CACHE_HEADER)?;
+            let mut num_saved = 0;
+            for net_info in problem.nets.values() {
+                for connection in net_info.connections.values() {
+                    let Some(cached_traces) = self.traces.get(&connection.connection_id) else {
+                        continue;
+                    };
+                    let key = connection_cache_key(problem, net_info, connection);
+                    for trace_path in cached_traces.iter() {
+                        let anchors: Vec<String> = trace_path.anchors.0.iter().map(anchor_to_string).collect();
+                        writeln!(file, "{:016x} {}", key, anchors.join(" "))?;
+                        num_saved += 1;
+                    }
+                }
+            }
+            file.flush()?;
+            Ok(num_saved)
+        };
+        let num_saved = write().map_err(|e| format!("Failed to write trace cache {}: {}", path.display(), e))?;
+        println!("Saved {} traces to the trace cache {}", num_saved, path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use shared::{
+    collider::Collider,
+    pad::{Pad, PadLayer},
+    pcb_problem::{NetName, PcbProblem, ViaRules},
+    prim_shape::{CircleShape, PrimShape},
+    trace_path::{TracePath, Via, ViaType},
+    vec2::{FixedVec2, FloatVec2},
+};
+
+/// the distance between the edges of the holes of two vias, a via without a known drill counts as all hole
+pub(crate) fn drill_gap(position: FloatVec2, drill: f32, other_position: FloatVec2, other_drill: f32) -> f32 {
+    (position - other_position).length() - (drill + other_drill) / 2.0
+}
+
+pub(crate) fn via_drill(via: &Via) -> f32 {
+    via.drill.unwrap_or(via.diameter)
+}
+
+/// vias never overlap through hole pads, and overlap smd pads of their own net only when the rules allow it
+pub(crate) fn pad_blocks_vias(rules: &ViaRules, pad: &Pad) -> bool {
+    match pad.pad_layer {
+        PadLayer::All => true,
+        PadLayer::Front | PadLayer::Back => !rules.allow_via_in_smd_pad,
+    }
+}
+
+fn via_collider(position: FloatVec2, diameter: f32) -> Collider {
+    Collider::from_prim_shape(&PrimShape::Circle(CircleShape { position, diameter }))
+}
+
+/// what limits where the vias of one net may go besides the copper of the other nets,
+/// the routed vias of every net for the drill spacing, the pads of the net itself and the component keepouts
+pub struct ViaObstacles {
+    min_drill_spacing: Option<f32>,
+    min_annular_ring: Option<f32>,
+    drills: Vec<(FloatVec2, f32, usize, usize)>, // position, drill, min layer and max layer of the routed vias
+    pads: Vec<(Vec<usize>, Vec<Collider>)>,      // layers and colliders of the pads a via must not overlap
+    keepouts: Vec<(usize, Collider)>,
+}
+
+impl ViaObstacles {
+    pub fn new<'a>(
+        problem: &PcbProblem,
+        net_name: &NetName,
+        routed_traces: impl IntoIterator<Item = &'a TracePath>,
+    ) -> Self {
+        let rules = &problem.via_rules;
+        let drills = match rules.min_drill_spacing {
+            Some(_) => routed_traces
+                .into_iter()
+                .flat_map(|trace_path| trace_path.vias.iter())
+                .map(|via| (via.position.to_float(), via_drill(via), via.min_layer, via.max_layer))
+                .collect(),
+            None => Vec::new(),
+        };
+        let pads = problem
+            .nets
+            .get(net_name)
+            .map(|net_info| {
+                net_info
+                    .pads
+                    .values()
+                    .filter(|pad| pad_blocks_vias(rules, pad))
+                    .map(|pad| {
+                        let layers: Vec<usize> = pad.pad_layer.get_iter(problem.num_layers).collect();
+                        let colliders: Vec<Collider> =
+                            pad.to_shapes().iter().map(Collider::from_prim_shape).collect();
+                        (layers, colliders)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let keepouts = rules
+            .keepouts
+            .iter()
+            .map(|keepout| (keepout.layer, Collider::from_prim_shape(&keepout.shape)))
+            .collect();
+        ViaObstacles {
+            min_drill_spacing: rules.min_drill_spacing,
+            min_annular_ring: rules.min_annular_ring,
+            drills,
+            pads,
+            keepouts,
+        }
+    }
+
+    /// no rule at all, for searches that never place vias of a real net
+    pub fn none() -> Self {
+        ViaObstacles {
+            min_drill_spacing: None,
+            min_annular_ring: None,
+            drills: Vec::new(),
+            pads: Vec::new(),
+            keepouts: Vec::new(),
+        }
+    }
+
+    /// whether a via of the type may be placed at the position, the copper clearance is checked separately
+    pub fn allows(&self, position: FixedVec2, via_type: &ViaType) -> bool {
+        let via = Via {
+            position,
+            diameter: via_type.diameter,
+            drill: via_type.drill,
+            clearance: 0.0,
+            min_layer: via_type.min_layer,
+            max_layer: via_type.max_layer,
+            padstack_name: String::new(),
+        };
+        self.allows_via(&via)
+    }
+
+    /// whether every via of a trace found earlier, e.g. in a trace cache, is still allowed
+    pub fn allows_vias(&self, trace_path: &TracePath) -> bool {
+        trace_path.vias.iter().all(|via| self.allows_via(via))
+    }
+
+    fn allows_via(&self, via: &Via) -> bool {
+        if let (Some(min_annular_ring), Some(drill)) = (self.min_annular_ring, via.drill)
+            && (via.diameter - drill) / 2.0 < min_annular_ring
+        {
+            return false;
+        }
+        let position = via.position.to_float();
+        if let Some(min_drill_spacing) = self.min_drill_spacing {
+            let drill = via_drill(via);
+            let too_close = self.drills.iter().any(|(other_position, other_drill, min_layer, max_layer)| {
+                via.min_layer <= *max_layer
+                    && *min_layer <= via.max_layer
+                    && drill_gap(position, drill, *other_position, *other_drill) < min_drill_spacing
+            });
+            if too_close {
+                return false;
+            }
+        }
+        let collider = via_collider(position, via.diameter);
+        let spans = |layer: usize| via.min_layer <= layer && layer <= via.max_layer;
+        let overlaps_pad = self.pads.iter().any(|(layers, colliders)| {
+            layers.iter().any(|layer| spans(*layer)) && colliders.iter().any(|pad| pad.collides_with(&collider))
+        });
+        if overlaps_pad {
+            return false;
+        }
+        !self
+            .keepouts
+            .iter()
+            .any(|(layer, keepout)| spans(*layer) && keepout.collides_with(&collider))
+    }
+}
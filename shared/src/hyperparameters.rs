@@ -24,6 +24,7 @@ pub const FOURTH_ITERATION_NUM_TRACES: usize = 2;
 // pub const DISPLAY_ASTAR: bool = true; // Whether to display the A* search process
 pub const DISPLAY_OPTIMIZATION: bool = false; // Whether to display the optimization process
 pub const OPTIMIZATION_PRO: bool = true;
+pub const OPTIMIZATION_OBSTACLE_MARGIN: f32 = 0.005; // Extra distance the optimization keeps when it moves a trace up to an obstacle, so float rounding never brings it into the clearance
 pub const DISPLAY_PERIOD_MILLIS: u64 = 10;
 
 pub const MAX_ITERATION: NonZeroUsize =
@@ -40,12 +41,56 @@ pub const ESTIMATE_COEFFICIENT: f64 = 1.0;
 
 pub const VIA_COST: f64 = 5.0; // Cost of placing a via
 
+pub const OFF_DIRECTION_PENALTY: f64 = 0.5; // Extra cost per unit of length moved perpendicular to the preferred direction of a layer
+
+// weights of the features of a trace in its raw score, see TraceScoreWeights
+pub const LENGTH_SCORE_WEIGHT: f64 = 1.0; // per unit of length
+pub const DETOUR_SCORE_WEIGHT: f64 = 4.0; // per unit of length over the octile lower bound, as a ratio
+pub const VIA_SCORE_WEIGHT: f64 = 0.4; // per VIA_COST of each via
+pub const BEND_SCORE_WEIGHT: f64 = 0.2; // per change of direction
+pub const BEND_ANGLE_SCORE_WEIGHT: f64 = 0.5; // per radian turned
+pub const OFF_DIRECTION_SCORE_WEIGHT: f64 = 1.0; // per unit of off direction cost
+pub const PAD_PROXIMITY_SCORE_WEIGHT: f64 = 1.0; // per pad of another net touched by the trace
+pub const PAD_PROXIMITY_DISTANCE: f64 = 0.5; // Pads of other nets farther than this from a trace do not lower its score
+
+pub const OFF_CENTER_ENTRY_COST: f64 = 1.0; // Extra cost per unit of distance between the end of a trace and the centre of its pad
+
+pub const INFERENCE_DAMPING: f64 = 0.5; // Share of the previous marginal or message kept at each update of the inference
+pub const INFERENCE_TOLERANCE: f64 = 1e-4; // The inference stops when no marginal or message changes by more than this
+pub const INFERENCE_MAX_ITERATIONS: usize = 100; // The inference stops after this many sweeps even when it has not converged
+
+pub const EXACT_SELECTION_MAX_CANDIDATES: usize = 256; // Candidate sets up to this size are searched for an exact compatible selection
+pub const EXACT_SELECTION_MAX_NODES: usize = 100_000; // The exact selection gives up after this many search nodes
+
+pub const DIVERSITY_PENALTY: f64 = 1.0; // Extra cost per unit length through the cells of the earlier candidates of a connection
+pub const DIVERSITY_PENALTY_GROWTH: f64 = 2.0; // The diversity penalty is multiplied by this after each candidate that is too similar
+pub const DIVERSITY_MAX_ATTEMPTS: usize = 3; // Searches per new candidate before a too similar one is dropped, only the first is not penalized
+pub const ITERATION_TO_MIN_DISSIMILARITY: [f64; 4] = [0.0, 1.0, 1.0, 0.5]; // Mean distance in trace pitches between a new candidate of each iteration and the earlier ones
+pub const DIVERSITY_LAYER_DISTANCE: f64 = 2.0; // In trace pitches, how much farther apart two parts of traces on different layers count
+
 pub const NUM_TOP_RANKED_TO_TRY: usize = 3; // Number of top-ranked traces to try fixing in each iteration
 
 pub const SAMPLE_ITERATIONS: usize = 2;
 
 pub const UPDATE_PROBA_SKIP_STRIDE: usize = 2;
 
+pub const BAYESIAN_MAX_BACKTRACKS: usize = 16; // Dead ends the bayesian backtracker re-samples or pops before it falls back to the naive backtracker
+pub const BAYESIAN_MIN_BRANCH_MASS: f64 = 0.01; // Branches less probable than this, relative to fixing the most probable candidate of every connection, are not explored
+
+pub const ORDERING_FREE_CELL_RADIUS: usize = 3; // Cells of one trace pitch counted on each side of a pad by the most constrained first connection ordering
+
+pub const SHOVE_MAX_DEPTH: usize = 2; // When shoving is turned on, a shoved trace may shove the traces in its own way, chains longer than this are undone
+pub const SHOVE_MAX_TRACES: usize = 6; // A shove that moves more fixed traces than this for one new trace is undone
+pub const SHOVE_STEP: f32 = 0.05; // In float units, the offset of a shoved segment grows by this until the segment is out of the way
+pub const SHOVE_MAX_OFFSET: f32 = 1.0; // In float units, segments are not shoved farther than this
+pub const SHOVE_DETOUR_RATIO: f64 = 2.0; // A route this many times longer than the distance between its pads is too costly, the naive backtracker then tries shoving
+
+pub const NEGOTIATED_CONGESTION_MAX_ITERATIONS: usize = 30; // Maximum number of rip-up and reroute iterations
+pub const PRESENT_CONGESTION_FACTOR: f64 = 0.5; // Initial cost factor for every other net already using a cell
+pub const PRESENT_CONGESTION_GROWTH: f64 = 1.6; // The present congestion factor is multiplied by this after each iteration
+pub const HISTORY_CONGESTION_FACTOR: f64 = 0.3; // Added to the history cost of the cells shared by overlapping connections after each iteration
+pub const CONGESTION_GRID_MAX_CELLS_PER_SIDE: usize = 256; // Upper bound of the congestion grid resolution
+
 pub const LAYER_TO_TRACE_COLOR: [ColorFloat3; 4] = [
     ColorFloat3::new(1.0, 0.0, 0.0), // Red for front layer
     ColorFloat3::new(0.0, 0.0, 1.0), // Blue for back layer
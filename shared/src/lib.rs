@@ -10,4 +10,5 @@ pub mod pcb_render_model;
 pub mod prim_shape;
 pub mod trace_path;
 pub mod vec2;
-pub mod octile_distance;
\ No newline at end of file
+pub mod octile_distance;
+pub mod trace_score;
\ No newline at end of file
@@ -1,12 +1,14 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, f64::consts::FRAC_1_SQRT_2, fmt::Display, rc::Rc};
 
 use crate::{
     collider::{BorderCollider, PolygonCollider},
     color_float3::ColorFloat3,
     distinct_color_generator::DistinctColorGenerator,
     pad::{Pad, PadName},
-    prim_shape::Line,
-    trace_path::TracePath,
+    pcb_render_model::ShapeRenderable,
+    prim_shape::{Line, PrimShape, RectangleShape},
+    trace_path::{TracePath, ViaType},
+    trace_score::TraceScorer,
     vec2::FloatVec2,
 };
 
@@ -25,12 +27,239 @@ pub struct Connection {
 #[derive(Debug, Clone)]
 pub struct NetInfo {
     pub net_name: NetName,
+    pub net_class_name: NetClassName, // The netclass the net belongs to
     pub color: ColorFloat3,
     pub pads: HashMap<PadName, Pad>,
     pub trace_width: f32,     // Width of the trace from the source pad
     pub trace_clearance: f32, // Clearance around the trace from the source pad
-    pub via_diameter: f32, // Diameter of the via, obtained from via name and accessed through padstacks
+    pub via_diameter: f32, // Diameter of the default via, obtained from via name and accessed through padstacks
+    pub via_types: Vec<ViaType>, // Vias the net may use, sorted from the cheapest, never empty
     pub connections: HashMap<ConnectionID, Rc<Connection>>, // List of connections in the net, the source pad is the same
+    pub diff_pair: Option<DiffPairConstraint>, // Set when the net is routed together with a partner net
+    pub length_constraint: Option<LengthConstraint>, // Set when the net belongs to a length matching group
+    pub routing_constraints: RoutingConstraints,
+}
+
+/// a component body no via may be placed under, on the outer layer the component is mounted on
+#[derive(Debug, Clone)]
+pub struct ViaKeepout {
+    pub component: String,
+    pub layer: usize,
+    pub shape: PrimShape, // bounding rectangle of the pads of the component
+}
+
+/// board wide rules for placing vias, in float units
+#[derive(Debug, Clone, Default)]
+pub struct ViaRules {
+    pub min_drill_spacing: Option<f32>, // minimum distance between the edges of the holes of two vias
+    pub allow_via_in_smd_pad: bool,     // vias may sit inside the smd pads of their own net
+    pub min_annular_ring: Option<f32>,  // minimum copper width between the hole and the edge of a via
+    pub keepouts: Vec<ViaKeepout>,
+}
+
+/// where traces may land on the pads they connect
+#[derive(Debug, Clone, Copy)]
+pub struct PadEntryRules {
+    pub off_center: bool,     // traces may end anywhere inside the copper of rectangular pads, not only at the centre
+    pub long_axis_exit: bool, // traces leave and reach elongated pads along their long axis
+}
+
+impl Default for PadEntryRules {
+    fn default() -> Self {
+        PadEntryRules {
+            off_center: true,
+            long_axis_exit: false,
+        }
+    }
+}
+
+/// a net to fill with copper after routing, as declared by a plane in the dsn file or in the extra info, in float units
+#[derive(Debug, Clone)]
+pub struct CopperPourRule {
+    pub net_name: NetName,
+    pub layers: Vec<usize>,
+    pub outline: Option<Vec<FloatVec2>>, // the area to fill, the whole board when None
+    pub thermal_gap: Option<f32>,        // gap between the pour and the pads of its net, defaults to the clearance of the net
+    pub spoke_width: Option<f32>,        // width of the thermal relief spokes, defaults to the trace width of the net
+}
+
+/// the copper filled for a pour rule on one layer, in float units,
+/// written to the SES file, which also brings it into KiCad, the tree has no SVG or Gerber writer to emit it yet
+#[derive(Debug, Clone)]
+pub struct CopperPour {
+    pub net_name: NetName,
+    pub layer: usize,
+    pub clearance: f32,                // kept to the copper of every other net
+    pub polygons: Vec<Vec<FloatVec2>>, // counterclockwise rectangles that do not overlap, convex and without holes
+}
+
+impl CopperPour {
+    pub fn to_shapes(&self) -> Vec<PrimShape> {
+        self.polygons
+            .iter()
+            .map(|polygon| {
+                let (min, max) = (polygon[0], polygon[2]);
+                PrimShape::Rectangle(RectangleShape {
+                    position: (min + max) / 2.0,
+                    width: max.x - min.x,
+                    height: max.y - min.y,
+                    rotation: cgmath::Deg(0.0),
+                })
+            })
+            .collect()
+    }
+    pub fn to_renderables(&self, color: [f32; 4]) -> Vec<ShapeRenderable> {
+        self.to_shapes()
+            .into_iter()
+            .map(|shape| ShapeRenderable { shape, color })
+            .collect()
+    }
+}
+
+/// the direction the traces on a layer should run in, in problem coordinates with y up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreferredDirection {
+    Horizontal,
+    Vertical,
+    Diagonal45,  // rising to the right
+    Diagonal135, // rising to the left
+    #[default]
+    None,
+}
+
+impl PreferredDirection {
+    /// how much a move of (dx, dy) goes across the preferred direction, 0 along it and 1 perpendicular to it
+    pub fn misalignment(&self, dx: f64, dy: f64) -> f64 {
+        let length = (dx * dx + dy * dy).sqrt();
+        let (px, py) = match self {
+            PreferredDirection::Horizontal => (1.0, 0.0),
+            PreferredDirection::Vertical => (0.0, 1.0),
+            PreferredDirection::Diagonal45 => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            PreferredDirection::Diagonal135 => (-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            PreferredDirection::None => return 0.0,
+        };
+        if length == 0.0 {
+            return 0.0;
+        }
+        (dx * py - dy * px).abs() / length
+    }
+}
+
+/// the preferred direction of every layer and what moving against it costs
+#[derive(Debug, Clone, Default)]
+pub struct LayerDirections {
+    pub directions: Vec<PreferredDirection>, // by layer index, layers past the end have no preference
+    pub penalty: f64,                        // extra cost per unit of length moved perpendicular to the preference
+}
+
+impl LayerDirections {
+    pub fn direction(&self, layer: usize) -> PreferredDirection {
+        self.directions.get(layer).copied().unwrap_or_default()
+    }
+
+    /// the extra cost of a move of (dx, dy) on the layer
+    pub fn off_direction_cost(&self, layer: usize, dx: f64, dy: f64) -> f64 {
+        let length = (dx * dx + dy * dy).sqrt();
+        self.penalty * length * self.direction(layer).misalignment(dx, dy)
+    }
+
+    /// the extra cost of all segments of a trace
+    pub fn trace_cost(&self, trace_path: &TracePath) -> f64 {
+        trace_path
+            .segments
+            .iter()
+            .map(|segment| {
+                let delta = segment.end.to_float() - segment.start.to_float();
+                self.off_direction_cost(segment.layer, delta.x as f64, delta.y as f64)
+            })
+            .sum()
+    }
+}
+
+/// limits on how the traces of a net may be routed, they apply to each connection of the net
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutingConstraints {
+    pub allowed_layers: Option<Vec<usize>>, // None allows every layer
+    pub max_vias: Option<usize>,            // Some(0) for nets that must not use vias
+    pub max_length: Option<f32>,            // in float units
+}
+
+impl RoutingConstraints {
+    pub fn allows_layer(&self, layer: usize) -> bool {
+        self.allowed_layers
+            .as_ref()
+            .is_none_or(|allowed_layers| allowed_layers.contains(&layer))
+    }
+    pub fn is_unconstrained(&self) -> bool {
+        self.allowed_layers.is_none() && self.max_vias.is_none() && self.max_length.is_none()
+    }
+}
+
+impl Display for RoutingConstraints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits: Vec<String> = Vec::new();
+        if let Some(allowed_layers) = &self.allowed_layers {
+            limits.push(format!("layers {:?}", allowed_layers));
+        }
+        match self.max_vias {
+            Some(0) => limits.push("no vias".to_string()),
+            Some(max_vias) => limits.push(format!("at most {} vias", max_vias)),
+            None => {}
+        }
+        if let Some(max_length) = self.max_length {
+            limits.push(format!("at most {:.4} long", max_length));
+        }
+        if limits.is_empty() {
+            write!(f, "no routing constraints")
+        } else {
+            write!(f, "{}", limits.join(", "))
+        }
+    }
+}
+
+/// a differential pair as declared in the dsn file or in the extra info, gap and skew in specctra dsn units
+#[derive(Debug, Clone)]
+pub struct DiffPair {
+    pub positive_net: NetName,
+    pub negative_net: NetName,
+    pub gap: Option<f32>,      // Edge to edge distance of the coupled traces, defaults to the trace clearance
+    pub max_skew: Option<f32>, // Allowed length difference of the two traces, defaults to the trace width
+}
+
+/// the pair constraint of one net of a differential pair, in float units
+#[derive(Debug, Clone)]
+pub struct DiffPairConstraint {
+    pub partner: NetName,
+    pub is_positive: bool,
+    pub gap: f32,
+    pub max_skew: f32,
+}
+
+/// the shape of the meanders that lengthen a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeanderShape {
+    Accordion, // many bumps alternating around the original segment
+    Trombone,  // bumps on one side of the original segment
+}
+
+/// a group of nets that must have the same length, as declared in the dsn file or in the extra info,
+/// lengths in specctra dsn units
+#[derive(Debug, Clone)]
+pub struct LengthGroup {
+    pub name: String,
+    pub nets: Vec<NetName>,
+    pub target_length: Option<f32>, // defaults to the length of the longest routed net of the group
+    pub tolerance: Option<f32>,     // defaults to the trace width
+    pub meander_shape: Option<MeanderShape>, // defaults to accordion
+}
+
+/// the length matching constraint of one net, in float units
+#[derive(Debug, Clone)]
+pub struct LengthConstraint {
+    pub group: String,
+    pub target_length: Option<f32>,
+    pub tolerance: f32,
+    pub meander_shape: MeanderShape,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
@@ -48,15 +277,22 @@ pub struct ConnectionID(pub usize);
 // separate the problem, the probabilistic model, and the solution
 
 // (0, 0) center, up, right
+#[derive(Clone)]
 pub struct PcbProblem {
     pub width: f32,
     pub height: f32,
     pub center: FloatVec2,
+    pub board_outline: Vec<FloatVec2>,         // The boundary of the board, width, height and center are its bounding box
     pub num_layers: usize,                     // 0: front, num_layers - 1: back
     pub obstacle_borders: Vec<BorderCollider>, // Borders that represent obstacles in the PCB
     pub obstacle_border_outlines: Vec<Line>,   // Outlines of the borders, used for rendering
     pub obstacle_polygons: Vec<PolygonCollider>, // Polygons that represent obstacles in the PCB
     pub nets: HashMap<NetName, NetInfo>,       // NetID to NetInfo
+    pub via_rules: ViaRules,                   // Where vias may be placed, for every net
+    pub layer_directions: LayerDirections,     // Preferred trace direction of every layer
+    pub pad_entry: PadEntryRules,              // Where traces may land on their pads
+    pub copper_pours: Vec<CopperPourRule>,     // Nets filled with copper after routing, in order, earlier pours are obstacles for later ones
+    pub trace_scorer: Rc<dyn TraceScorer>,     // Rates the candidate traces of the probabilistic model
     pub scale_down_factor: f32, // Scale down factor to convert specctra dsn units to float units
 }
 
@@ -69,9 +305,54 @@ pub struct FixedTrace {
 
 pub struct PcbSolution {
     pub determined_traces: HashMap<ConnectionID, FixedTrace>, // NetID to ConnectionID to FixedTrace
+    pub copper_pours: Vec<CopperPour>, // filled once the traces are final, empty until then
+    pub uncoupled_diff_pairs: Vec<UncoupledDiffPair>, // declared pairs whose nets were routed separately
     pub scale_down_factor: f32, // Scale down factor to convert specctra dsn units to float units
 }
 
+/// a declared differential pair that could not be routed as coupled traces, its nets were routed like any other net
+#[derive(Debug, Clone)]
+pub struct UncoupledDiffPair {
+    pub positive_net: NetName,
+    pub negative_net: NetName,
+    pub reason: String, // why the coupled routing failed
+}
+
+/// the best incomplete routing found when the solver gives up, so the rest can be finished by hand
+pub struct PartialSolution {
+    pub solution: Box<PcbSolution>,            // the connections that were routed
+    pub unrouted_connections: Vec<Connection>, // sorted by connection id
+    pub reason: String,                        // why the solver stopped
+}
+
+impl PartialSolution {
+    /// keeps the given traces and lists every other connection of the problem as unrouted
+    pub fn from_fixed_traces(
+        problem: &PcbProblem,
+        determined_traces: HashMap<ConnectionID, FixedTrace>,
+        reason: String,
+    ) -> Self {
+        let mut unrouted_connections: Vec<Connection> = problem
+            .nets
+            .values()
+            .flat_map(|net_info| net_info.connections.values())
+            .filter(|connection| !determined_traces.contains_key(&connection.connection_id))
+            .map(|connection| connection.as_ref().clone())
+            .collect();
+        unrouted_connections.sort_by_key(|connection| connection.connection_id);
+        PartialSolution {
+            solution: Box::new(PcbSolution {
+                determined_traces,
+                copper_pours: Vec::new(),
+                uncoupled_diff_pairs: Vec::new(),
+                scale_down_factor: problem.scale_down_factor,
+            }),
+            unrouted_connections,
+            reason,
+        }
+    }
+}
+
 impl PcbProblem {
     // pub fn new(
     //     width: f32,
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::{
     collider::Collider,
-    hyperparameters::{HALF_PROBABILITY_RAW_SCORE, LAYER_TO_TRACE_COLOR},
+    hyperparameters::LAYER_TO_TRACE_COLOR,
     pcb_render_model::{RenderableBatch, ShapeRenderable},
     prim_shape::{CircleShape, PrimShape, RectangleShape},
     vec2::{FixedPoint, FixedVec2, FloatVec2, IntVec2},
@@ -400,6 +400,25 @@ impl TraceSegment {
     }
 }
 
+/// a via padstack a net may use, through, blind, buried or micro depending on the layers it spans
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViaType {
+    pub name: String,     // Name of the padstack, written back to the session file
+    pub diameter: f32,    // Diameter of the via
+    pub drill: Option<f32>, // Diameter of the hole, None when the padstack does not tell
+    pub min_layer: usize, // Inclusive, the first layer the via spans
+    pub max_layer: usize, // Inclusive, the last layer the via spans
+}
+
+impl ViaType {
+    pub fn spans(&self, layer_a: usize, layer_b: usize) -> bool {
+        self.min_layer <= usize::min(layer_a, layer_b) && usize::max(layer_a, layer_b) <= self.max_layer
+    }
+    pub fn num_spanned_layers(&self) -> usize {
+        self.max_layer - self.min_layer + 1
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Via {
     pub position: FixedVec2, // Position of the via
@@ -407,9 +426,45 @@ pub struct Via {
     pub clearance: f32,      // Clearance around the via
     pub min_layer: usize,    // Inclusive, the layer where the via starts
     pub max_layer: usize,    // Inclusive, the layer where the via ends
+    pub padstack_name: String, // Empty when the via type is unknown
+    pub drill: Option<f32>,  // Diameter of the hole, None when the via type does not tell
 }
 
 impl Via {
+    /// the via that makes the layer change of an anchor, of the type the anchor chose
+    /// or else of the cheapest type spanning the change, via_types are sorted from the cheapest
+    pub fn from_anchor(anchor: &TraceAnchor, clearance: f32, via_types: &[ViaType]) -> Self {
+        let via_type = anchor
+            .via_type
+            .and_then(|index| via_types.get(index))
+            .filter(|via_type| via_type.spans(anchor.start_layer, anchor.end_layer))
+            .or_else(|| {
+                via_types
+                    .iter()
+                    .find(|via_type| via_type.spans(anchor.start_layer, anchor.end_layer))
+            });
+        match via_type {
+            Some(via_type) => Via {
+                position: anchor.position,
+                diameter: via_type.diameter,
+                clearance,
+                min_layer: via_type.min_layer,
+                max_layer: via_type.max_layer,
+                padstack_name: via_type.name.clone(),
+                drill: via_type.drill,
+            },
+            // no padstack spans the change, keep the largest via on the changed layers only
+            None => Via {
+                position: anchor.position,
+                diameter: via_types.iter().map(|via_type| via_type.diameter).fold(0.0, f32::max),
+                clearance,
+                min_layer: usize::min(anchor.start_layer, anchor.end_layer),
+                max_layer: usize::max(anchor.start_layer, anchor.end_layer),
+                padstack_name: String::new(),
+                drill: None,
+            },
+        }
+    }
     pub fn to_collider(&self) -> Collider {
         let shape = PrimShape::Circle(CircleShape {
             position: self.position.to_float(),
@@ -468,6 +523,7 @@ pub struct TraceAnchor {
     pub position: FixedVec2,
     pub start_layer: usize, // Inclusive, the layer where the trace starts
     pub end_layer: usize,   // Inclusive, the layer where the trace ends
+    pub via_type: Option<usize>, // Index into the via types of the net for a layer change, None for the cheapest
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -487,7 +543,7 @@ impl TracePath {
         anchors: TraceAnchors,
         trace_width: f32,
         trace_clearance: f32,
-        via_diameter: f32,
+        via_types: &[ViaType],
     ) -> Self{
         let anchors_vec = &anchors.0;
         let mut segments = Vec::new();
@@ -514,16 +570,7 @@ impl TracePath {
         for i in 1..anchors_vec.len() - 1{
             let anchor = &anchors_vec[i];            
             if anchor.start_layer != anchor.end_layer {
-                let min_layer = usize::min(anchor.start_layer, anchor.end_layer);
-                let max_layer = usize::max(anchor.start_layer, anchor.end_layer);
-                let via = Via {
-                    position: anchor.position,
-                    diameter: via_diameter,
-                    clearance: trace_clearance,
-                    min_layer,
-                    max_layer,
-                };
-                vias.push(via);
+                vias.push(Via::from_anchor(anchor, trace_clearance, via_types));
             }
         }
         Self {
@@ -616,21 +663,47 @@ impl TracePath {
                 }
             }
         }
+        if self.vias_collide_with(other) || other.vias_collide_with(self) {
+            return true;
+        }
+        for via_self in &self.vias {
+            for via_other in &other.vias {
+                if via_self.min_layer <= via_other.max_layer
+                    && via_other.min_layer <= via_self.max_layer
+                    && (via_self.to_collider().collides_with(&via_other.to_clearance_collider())
+                        || via_self.to_clearance_collider().collides_with(&via_other.to_collider()))
+                {
+                    return true;
+                }
+            }
+        }
         false
     }
 
-    pub fn get_score(&self) -> f64 {
-        // to do
-        let score_raw = self.total_length; // placeholder for actual score calculation
-        let k = f64::ln(2.0) / HALF_PROBABILITY_RAW_SCORE;
-        let score = f64::exp(-k * score_raw);
-        println!("total length: {}, score: {}", self.total_length, score);
-        assert!(
-            score >= 0.0 && score <= 1.0,
-            "Score must be between 0 and 1, got: {}",
-            score
-        );
-        score
+    // the vias of this path against the segments of the other path on the layers the vias span
+    fn vias_collide_with(&self, other: &TracePath) -> bool {
+        for via in &self.vias {
+            let collider = via.to_collider();
+            let clearance_collider = via.to_clearance_collider();
+            for segment in other
+                .segments
+                .iter()
+                .filter(|segment| (via.min_layer..=via.max_layer).contains(&segment.layer))
+            {
+                if segment
+                    .to_clearance_colliders()
+                    .iter()
+                    .any(|segment_clearance_collider| collider.collides_with(segment_clearance_collider))
+                    || segment
+                        .to_colliders()
+                        .iter()
+                        .any(|segment_collider| clearance_collider.collides_with(segment_collider))
+                {
+                    return true;
+                }
+            }
+        }
+        false
     }
 
     pub fn to_renderables(&self, color: [f32; 4]) -> [RenderableBatch; 2] {
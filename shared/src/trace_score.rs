@@ -0,0 +1,207 @@
+use std::fmt::Display;
+
+use crate::{
+    hyperparameters::{
+        BEND_ANGLE_SCORE_WEIGHT, BEND_SCORE_WEIGHT, DETOUR_SCORE_WEIGHT, HALF_PROBABILITY_RAW_SCORE,
+        LENGTH_SCORE_WEIGHT, OFF_DIRECTION_SCORE_WEIGHT, PAD_PROXIMITY_DISTANCE, PAD_PROXIMITY_SCORE_WEIGHT,
+        VIA_COST, VIA_SCORE_WEIGHT,
+    },
+    octile_distance::octile_distance_fixed,
+    pad::{Pad, PadShape},
+    pcb_problem::{NetName, PcbProblem},
+    trace_path::TracePath,
+    vec2::FloatVec2,
+};
+
+/// how much each feature of a trace adds to its raw score, a raw score of HALF_PROBABILITY_RAW_SCORE halves the score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceScoreWeights {
+    pub length: f64,                 // per unit of length
+    pub detour: f64,                 // per unit of the length ratio above 1
+    pub vias: f64,                   // per VIA_COST of each via
+    pub bends: f64,                  // per change of direction
+    pub bend_angle: f64,             // per radian turned
+    pub off_direction: f64,          // per unit of the off direction cost of the layers
+    pub pad_proximity: f64,          // per pad of another net, 1 when touching and 0 at the proximity distance
+    pub pad_proximity_distance: f64, // in float units, pads of other nets farther than this from the trace do not count
+}
+
+impl Default for TraceScoreWeights {
+    fn default() -> Self {
+        TraceScoreWeights {
+            length: LENGTH_SCORE_WEIGHT,
+            detour: DETOUR_SCORE_WEIGHT,
+            vias: VIA_SCORE_WEIGHT,
+            bends: BEND_SCORE_WEIGHT,
+            bend_angle: BEND_ANGLE_SCORE_WEIGHT,
+            off_direction: OFF_DIRECTION_SCORE_WEIGHT,
+            pad_proximity: PAD_PROXIMITY_SCORE_WEIGHT,
+            pad_proximity_distance: PAD_PROXIMITY_DISTANCE,
+        }
+    }
+}
+
+/// the features of a trace that its score is made of, before weighting
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFeatures {
+    pub length: f64,
+    pub lower_bound: f64,   // octile distance between the ends of the trace
+    pub length_ratio: f64,  // length over the lower bound, 1 for the shortest trace
+    pub via_cost: f64,      // number of vias times VIA_COST
+    pub bends: usize,       // number of changes of direction
+    pub bend_angle: f64,    // sum of the angles turned, in radians
+    pub off_direction: f64, // cost of moving against the preferred direction of the layers
+    pub pad_proximity: f64, // sum over the nearby pads of other nets, each between 0 and 1
+}
+
+impl TraceFeatures {
+    pub fn new(problem: &PcbProblem, net_name: &NetName, trace_path: &TracePath, proximity_distance: f64) -> Self {
+        let anchors = &trace_path.anchors.0;
+        let lower_bound = match (anchors.first(), anchors.last()) {
+            (Some(first), Some(last)) => octile_distance_fixed(first.position, last.position),
+            _ => 0.0,
+        };
+        let length_ratio = if lower_bound > f64::EPSILON {
+            (trace_path.total_length / lower_bound).max(1.0)
+        } else {
+            1.0
+        };
+
+        let mut bends = 0;
+        let mut bend_angle = 0.0;
+        let directions: Vec<FloatVec2> = trace_path
+            .segments
+            .iter()
+            .map(|segment| segment.end.to_float() - segment.start.to_float())
+            .filter(|delta| delta.length() > f32::EPSILON)
+            .collect();
+        for pair in directions.windows(2) {
+            let (before, after) = (pair[0], pair[1]);
+            let cos = (before.dot(after) / (before.length() * after.length())).clamp(-1.0, 1.0);
+            let angle = (cos as f64).acos();
+            if angle > 1e-3 {
+                bends += 1;
+                bend_angle += angle;
+            }
+        }
+
+        let mut pad_proximity = 0.0;
+        if proximity_distance > 0.0 {
+            for other_net in problem.nets.values().filter(|net_info| net_info.net_name != *net_name) {
+                for pad in other_net.pads.values() {
+                    let distance = trace_path
+                        .segments
+                        .iter()
+                        .filter(|segment| pad.pad_layer.get_iter(problem.num_layers).any(|layer| layer == segment.layer))
+                        .map(|segment| {
+                            segment_distance(pad.position, segment.start.to_float(), segment.end.to_float())
+                                - segment.width / 2.0
+                                - pad_radius(pad)
+                        })
+                        .fold(f32::MAX, f32::min) as f64;
+                    if distance < proximity_distance {
+                        pad_proximity += 1.0 - distance.max(0.0) / proximity_distance;
+                    }
+                }
+            }
+        }
+
+        TraceFeatures {
+            length: trace_path.total_length,
+            lower_bound,
+            length_ratio,
+            via_cost: trace_path.vias.len() as f64 * VIA_COST,
+            bends,
+            bend_angle,
+            off_direction: problem.layer_directions.trace_cost(trace_path),
+            pad_proximity,
+        }
+    }
+
+    /// the weighted contribution of every feature to the raw score, by name
+    pub fn breakdown(&self, weights: &TraceScoreWeights) -> [(&'static str, f64); 7] {
+        [
+            ("length", weights.length * self.length),
+            ("detour", weights.detour * (self.length_ratio - 1.0)),
+            ("vias", weights.vias * self.via_cost),
+            ("bends", weights.bends * self.bends as f64),
+            ("bend angle", weights.bend_angle * self.bend_angle),
+            ("off direction", weights.off_direction * self.off_direction),
+            ("pad proximity", weights.pad_proximity * self.pad_proximity),
+        ]
+    }
+
+    pub fn raw_score(&self, weights: &TraceScoreWeights) -> f64 {
+        self.breakdown(weights).iter().map(|(_, contribution)| contribution).sum()
+    }
+}
+
+impl Display for TraceFeatures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "length {:.3} (lower bound {:.3}, ratio {:.3}), via cost {:.1}, {} bends ({:.2} rad), off direction {:.3}, pad proximity {:.3}",
+            self.length,
+            self.lower_bound,
+            self.length_ratio,
+            self.via_cost,
+            self.bends,
+            self.bend_angle,
+            self.off_direction,
+            self.pad_proximity
+        )
+    }
+}
+
+/// the quality of a candidate trace, between 0 and 1, higher is better
+pub trait TraceScorer {
+    fn score(&self, problem: &PcbProblem, net_name: &NetName, trace_path: &TracePath) -> f64;
+}
+
+/// the default scorer, a weighted sum of the features of the trace mapped through an exponential
+#[derive(Debug, Clone, Default)]
+pub struct WeightedTraceScorer {
+    pub weights: TraceScoreWeights,
+}
+
+impl WeightedTraceScorer {
+    pub fn new(weights: TraceScoreWeights) -> Self {
+        WeightedTraceScorer { weights }
+    }
+
+    pub fn features(&self, problem: &PcbProblem, net_name: &NetName, trace_path: &TracePath) -> TraceFeatures {
+        TraceFeatures::new(problem, net_name, trace_path, self.weights.pad_proximity_distance)
+    }
+}
+
+impl TraceScorer for WeightedTraceScorer {
+    fn score(&self, problem: &PcbProblem, net_name: &NetName, trace_path: &TracePath) -> f64 {
+        let features = self.features(problem, net_name, trace_path);
+        let score_raw = features.raw_score(&self.weights);
+        let k = f64::ln(2.0) / HALF_PROBABILITY_RAW_SCORE;
+        let score = f64::exp(-k * score_raw.max(0.0));
+        println!("{}, raw score: {}, score: {}", features, score_raw, score);
+        score
+    }
+}
+
+fn segment_distance(point: FloatVec2, start: FloatVec2, end: FloatVec2) -> f32 {
+    let segment = end - start;
+    let length2 = segment.dot(segment);
+    let t = if length2 <= f32::EPSILON {
+        0.0
+    } else {
+        ((point - start).dot(segment) / length2).clamp(0.0, 1.0)
+    };
+    (point - (start + segment * t)).length()
+}
+
+// the radius of the circle around the pad, so a nearby pad is never missed
+fn pad_radius(pad: &Pad) -> f32 {
+    match pad.shape {
+        PadShape::Circle { diameter } => diameter / 2.0,
+        PadShape::Rectangle { width, height } | PadShape::RoundRect { width, height, .. } => {
+            (width * width + height * height).sqrt() / 2.0
+        }
+    }
+}
@@ -177,6 +177,17 @@ impl Sub for FloatVec2 {
     }
 }
 
+impl Mul<f32> for FloatVec2 {
+    type Output = FloatVec2;
+
+    fn mul(self, scalar: f32) -> FloatVec2 {
+        FloatVec2 {
+            x: self.x * scalar,
+            y: self.y * scalar,
+        }
+    }
+}
+
 impl Div<f32> for FloatVec2 {
     type Output = FloatVec2;
 